@@ -0,0 +1,569 @@
+//! Bit rate detection on a running bus.
+//!
+//! [AutoBaud] opens a channel in listen-only mode at one candidate [Baudrate] after the
+//! other and listens for a short while. A bit rate is detected once enough valid frames
+//! and no error frames were received: at a wrong bit rate the controller sees stuff, form
+//! and CRC errors instead of frames. Listening never acknowledges or disturbs the traffic
+//! of the other nodes.
+//!
+//! CAN FD buses are detected with [AutoBaud::detect_fd], which tries the common data phase
+//! bit rates for each nominal candidate. The data bit rate is only confirmed when frames
+//! with bit rate switch were received.
+//!
+//! [AutoBaud::detect_usb] first asks the driver through
+//! [SetBitrateAdapting](crate::special::SetBitrateAdapting): when another application
+//! already initialized the channel, its bit rate is taken over without probing.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::autobaud::AutoBaud;
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use std::time::Duration;
+//! let detector = AutoBaud::new()
+//!     .candidates(&[Baudrate::Baud500K, Baudrate::Baud250K, Baudrate::Baud125K])
+//!     .dwell(Duration::from_millis(300));
+//!
+//! match detector.detect_usb(UsbBus::USB1)? {
+//!     Some(detection) => {
+//!         println!("bus runs at {:?}", detection.baudrate);
+//!         let socket = UsbCanSocket::open(UsbBus::USB1, detection.baudrate)?;
+//!     }
+//!     None => println!("no traffic at any candidate bit rate"),
+//! }
+//! # Ok::<(), peak_can::error::CanError>(())
+//! ```
+
+use crate::bus::UsbBus;
+use crate::df::SetAllowErrorFrames;
+use crate::error::CanError;
+use crate::info::NominalBusSpeed;
+use crate::socket::usb::UsbCanSocket;
+use crate::socket::{
+    self, Baudrate, CANFD_CLOCK_HZ, CANFD_TIMING_BOUNDARIES, CanFdBitTiming, RecvCan, RecvCanFd,
+};
+use crate::special::{ListenOnly, SetBitrateAdapting, SetListenOnly};
+use crate::status::ErrorState;
+
+use std::time::{Duration, Instant};
+
+/// Candidates in the order they are tried, the most common bit rates first.
+pub const DEFAULT_CANDIDATES: [Baudrate; 14] = [
+    Baudrate::Baud500K,
+    Baudrate::Baud250K,
+    Baudrate::Baud125K,
+    Baudrate::Baud1M,
+    Baudrate::Baud100K,
+    Baudrate::Baud83K,
+    Baudrate::Baud50K,
+    Baudrate::Baud33K,
+    Baudrate::Baud20K,
+    Baudrate::Baud10K,
+    Baudrate::Baud800K,
+    Baudrate::Baud95K,
+    Baudrate::Baud47K,
+    Baudrate::Baud5K,
+];
+
+/// CAN FD data phase bit rates tried for each nominal candidate.
+pub const DEFAULT_FD_DATA_RATES: [u32; 5] = [2_000_000, 4_000_000, 5_000_000, 8_000_000, 1_000_000];
+
+const DEFAULT_DWELL: Duration = Duration::from_millis(500);
+const DEFAULT_MIN_FRAMES: usize = 3;
+
+/// Listening stops early after this many errors without a single valid frame.
+const ERROR_LIMIT: usize = 5;
+
+/// Frames received while listening at one bit rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Probe {
+    /// Valid data and remote frames.
+    pub frames: usize,
+    /// Valid CAN FD frames sent with bit rate switch.
+    pub fast_frames: usize,
+    /// Error frames and status frames reporting a bus error state.
+    pub error_frames: usize,
+}
+
+/// Detected bit rate of a bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detection {
+    pub baudrate: Baudrate,
+    /// Data phase bit rate, only set by [AutoBaud::detect_fd] after frames with bit rate
+    /// switch were received.
+    pub data_bps: Option<u32>,
+    pub probe: Probe,
+    /// The bit rate was taken over from an initialized channel, nothing was probed.
+    pub adapted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AutoBaud {
+    candidates: Vec<Baudrate>,
+    fd_data_rates: Vec<u32>,
+    dwell: Duration,
+    min_frames: usize,
+}
+
+impl Default for AutoBaud {
+    fn default() -> Self {
+        AutoBaud::new()
+    }
+}
+
+impl AutoBaud {
+    pub fn new() -> AutoBaud {
+        AutoBaud {
+            candidates: DEFAULT_CANDIDATES.to_vec(),
+            fd_data_rates: DEFAULT_FD_DATA_RATES.to_vec(),
+            dwell: DEFAULT_DWELL,
+            min_frames: DEFAULT_MIN_FRAMES,
+        }
+    }
+
+    /// Bit rates to try, in order.
+    pub fn candidates(mut self, candidates: &[Baudrate]) -> Self {
+        self.candidates = candidates.to_vec();
+        self
+    }
+
+    /// CAN FD data phase bit rates in bits per second, in order.
+    pub fn fd_data_rates(mut self, rates: &[u32]) -> Self {
+        self.fd_data_rates = rates.to_vec();
+        self
+    }
+
+    /// Longest time to listen at each bit rate, 500 ms by default.
+    pub fn dwell(mut self, dwell: Duration) -> Self {
+        self.dwell = dwell;
+        self
+    }
+
+    /// Valid frames needed to accept a bit rate, 3 by default.
+    pub fn min_frames(mut self, min_frames: usize) -> Self {
+        self.min_frames = min_frames.max(1);
+        self
+    }
+
+    fn accepts(&self, probe: &Probe) -> bool {
+        probe.frames >= self.min_frames && probe.error_frames == 0
+    }
+
+    fn gives_up(&self, probe: &Probe) -> bool {
+        probe.frames == 0 && probe.error_frames >= ERROR_LIMIT
+    }
+
+    /* Probing */
+
+    /// Listens on an opened socket until enough frames arrived, the errors make a match
+    /// impossible or the dwell time elapsed.
+    pub fn probe<S: RecvCan + ?Sized>(&self, socket: &S) -> Result<Probe, CanError> {
+        let deadline = Instant::now() + self.dwell;
+        let mut probe = Probe::default();
+        let mut status = StatusChanges::default();
+        while let Some(frame) = socket::poll_until(deadline, || status.received(socket.recv()))? {
+            match frame {
+                Some(frame) if frame.is_status_frame() && !reports_error(frame.data()) => {}
+                Some(frame) if !frame.is_error_frame() && !frame.is_status_frame() => {
+                    if !frame.is_echo_frame() {
+                        probe.frames += 1;
+                    }
+                }
+                _ => probe.error_frames += 1,
+            }
+            if self.gives_up(&probe) || (probe.error_frames > 0 && probe.frames >= self.min_frames)
+            {
+                break;
+            }
+            if self.accepts(&probe) {
+                break;
+            }
+        }
+        Ok(probe)
+    }
+
+    /// Like [AutoBaud::probe], but keeps listening for a frame with bit rate switch.
+    pub fn probe_fd<S: RecvCanFd + ?Sized>(&self, socket: &S) -> Result<Probe, CanError> {
+        let deadline = Instant::now() + self.dwell;
+        let mut probe = Probe::default();
+        let mut status = StatusChanges::default();
+        while let Some(frame) = socket::poll_until(deadline, || status.received(socket.recv_fd()))?
+        {
+            match frame {
+                Some(frame) if frame.is_status_frame() && !reports_error(frame.data()) => {}
+                Some(frame) if !frame.is_error_frame() && !frame.is_status_frame() => {
+                    if !frame.is_echo_frame() {
+                        probe.frames += 1;
+                        if frame.is_brs_frame() {
+                            probe.fast_frames += 1;
+                        }
+                    }
+                }
+                _ => probe.error_frames += 1,
+            }
+            if self.gives_up(&probe) || (probe.error_frames > 0 && probe.frames >= self.min_frames)
+            {
+                break;
+            }
+            if self.accepts(&probe) && probe.fast_frames > 0 {
+                break;
+            }
+        }
+        Ok(probe)
+    }
+
+    /* Detection */
+
+    /// Tries every candidate with a socket from `open`, which initializes the channel in
+    /// listen-only mode with error frames enabled. Each socket is dropped before the next
+    /// one is opened.
+    ///
+    /// Returns `None` if no candidate matched.
+    pub fn detect<S, F>(&self, mut open: F) -> Result<Option<Detection>, CanError>
+    where
+        S: RecvCan,
+        F: FnMut(Baudrate) -> Result<S, CanError>,
+    {
+        for baudrate in &self.candidates {
+            let probe = self.probe(&open(*baudrate)?)?;
+            if self.accepts(&probe) {
+                return Ok(Some(Detection {
+                    baudrate: *baudrate,
+                    data_bps: None,
+                    probe,
+                    adapted: false,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Tries every candidate with each data bit rate not below it. `open` initializes the
+    /// channel for CAN FD with the given timing, see [fd_bit_timing].
+    pub fn detect_fd<S, F>(&self, mut open: F) -> Result<Option<Detection>, CanError>
+    where
+        S: RecvCanFd,
+        F: FnMut(&CanFdBitTiming) -> Result<S, CanError>,
+    {
+        for baudrate in &self.candidates {
+            let nominal = baudrate.bits_per_second();
+            for data in self.fd_data_rates.iter().filter(|data| **data >= nominal) {
+                let Some(timing) = fd_bit_timing(nominal, *data) else {
+                    continue;
+                };
+                let probe = self.probe_fd(&open(&timing)?)?;
+                if self.accepts(&probe) {
+                    return Ok(Some(Detection {
+                        baudrate: *baudrate,
+                        data_bps: (probe.fast_frames > 0).then_some(*data),
+                        probe,
+                        adapted: false,
+                    }));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Detects the bit rate of a PCAN-USB channel.
+    ///
+    /// The channel is switched to listen-only before every initialization so that no
+    /// acknowledge or error flag is sent at a wrong bit rate, and the previous listen-only
+    /// setting is restored afterwards.
+    pub fn detect_usb(&self, bus: UsbBus) -> Result<Option<Detection>, CanError> {
+        let listen_only = bus.listen_only().unwrap_or(false);
+        bus.set_listen_only(true)?;
+        let result = match self.adapted(bus) {
+            Ok(Some(detection)) => Ok(Some(detection)),
+            _ => self.detect(|baudrate| {
+                let socket = UsbCanSocket::open(bus, baudrate)?;
+                socket.set_listen_only(true)?;
+                socket.allow_error_frames(true)?;
+                Ok(socket)
+            }),
+        };
+        let _ = bus.set_listen_only(listen_only);
+        result
+    }
+
+    /// Connects with bitrate adapting, the driver answers [CanError::Caution] when the
+    /// channel already runs at another bit rate.
+    fn adapted(&self, bus: UsbBus) -> Result<Option<Detection>, CanError> {
+        bus.set_bitrate_adapting(true)?;
+        let first = self
+            .candidates
+            .first()
+            .copied()
+            .unwrap_or(Baudrate::Baud500K);
+        let result = match UsbCanSocket::open(bus, first) {
+            Err(CanError::Caution) => {
+                let socket = UsbCanSocket::open_with_usb_bus(bus);
+                socket.nominal_bus_speed().map(|bps| {
+                    baudrate_of(bps).map(|baudrate| Detection {
+                        baudrate,
+                        data_bps: None,
+                        probe: Probe::default(),
+                        adapted: true,
+                    })
+                })
+            }
+            Ok(_) => Ok(None),
+            Err(err) => Err(err),
+        };
+        let _ = bus.set_bitrate_adapting(false);
+        result
+    }
+}
+
+/// Tracks the bus error status a read returns instead of a frame when listening at a wrong
+/// bit rate. The driver keeps reporting a status until it changes, so only changes count.
+#[derive(Default)]
+struct StatusChanges {
+    last: Option<u32>,
+}
+
+impl StatusChanges {
+    /// The frame of a read, `None` for a changed bus error status. An unchanged status
+    /// reads as an empty queue.
+    fn received<F, T>(&mut self, read: Result<(F, T), CanError>) -> Result<Option<F>, CanError> {
+        match read {
+            Ok((frame, _)) => Ok(Some(frame)),
+            Err(
+                err @ (CanError::BusLight
+                | CanError::BusHeavy
+                | CanError::BusPassive
+                | CanError::BusOff
+                | CanError::AnyBusErr),
+            ) => {
+                let status = u32::from(err);
+                if self.last.replace(status) == Some(status) {
+                    Err(CanError::QrcvEmpty)
+                } else {
+                    Ok(None)
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Status frames carry the PCAN status big endian in the first four bytes.
+fn reports_error(data: &[u8]) -> bool {
+    data.first_chunk::<4>()
+        .and_then(|status| ErrorState::from_status(u32::from_be_bytes(*status)))
+        .is_some_and(|state| state != ErrorState::Active)
+}
+
+/// Standard bit rate within 1 % of `bps`.
+pub(crate) fn baudrate_of(bps: u32) -> Option<Baudrate> {
+    DEFAULT_CANDIDATES
+        .into_iter()
+        .find(|baudrate| baudrate.bits_per_second().abs_diff(bps) * 100 <= bps)
+}
+
+/// Prescaler, time segment 1 and time segment 2 with the sample point closest to
+/// `sample_permille`, using as many time quanta as the segments allow.
+fn segments(
+    bps: u32,
+    max_prescaler: u16,
+    max_tseg1: u32,
+    max_tseg2: u32,
+    sample_permille: u32,
+) -> Option<(u16, u32, u32)> {
+    (1..=max_prescaler).find_map(|prescaler| {
+        let divider = prescaler as u32 * bps;
+        if !CANFD_CLOCK_HZ.is_multiple_of(divider) {
+            return None;
+        }
+        let quanta = CANFD_CLOCK_HZ / divider;
+        let tseg1 = ((quanta * sample_permille + 500) / 1000).checked_sub(1)?;
+        let tseg2 = quanta.checked_sub(1 + tseg1)?;
+        (tseg1 >= 1 && tseg1 <= max_tseg1 && tseg2 >= 1 && tseg2 <= max_tseg2)
+            .then_some((prescaler, tseg1, tseg2))
+    })
+}
+
+/// CAN FD timing at the 80 MHz controller clock with an 80 % nominal and a 75 % data
+/// sample point, `None` if the bit rates cannot be reached exactly.
+pub fn fd_bit_timing(nominal_bps: u32, data_bps: u32) -> Option<CanFdBitTiming> {
+    let bounds = &CANFD_TIMING_BOUNDARIES;
+    let (nom_prescaler, nom_tseg1, nom_tseg2) = segments(
+        nominal_bps,
+        bounds.nom_prescaler_max,
+        bounds.nom_tseg1_max as u32,
+        bounds.nom_tseg2_max as u32,
+        800,
+    )?;
+    let (data_prescaler, data_tseg1, data_tseg2) = segments(
+        data_bps,
+        bounds.data_prescaler_max,
+        bounds.data_tseg1_max as u32,
+        bounds.data_tseg2_max as u32,
+        750,
+    )?;
+    CanFdBitTiming::new(
+        nom_prescaler,
+        nom_tseg2.min(bounds.nom_sjw_max as u32) as u8,
+        nom_tseg1 as u16,
+        nom_tseg2 as u8,
+        data_prescaler,
+        data_tseg2.min(bounds.data_sjw_max as u32) as u8,
+        data_tseg1 as u8,
+        data_tseg2 as u8,
+    )
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::{MockEnd, mock_bus};
+    use crate::socket::{CanFdFrame, CanFrame, MessageType, SendCan, SendCanFd, Timestamp};
+
+    fn bus_with(frames: &[CanFrame]) -> MockEnd {
+        let (listener, bus) = mock_bus();
+        for frame in frames {
+            bus.send(*frame).unwrap();
+        }
+        listener
+    }
+
+    #[test]
+    fn autobaud_detects_first_clean_candidate() {
+        let data = CanFrame::new(0x123, MessageType::Standard, &[1, 2, 3]).unwrap();
+        let error = CanFrame::new_error(1, &[0, 0, 0, 8]).unwrap();
+        let detector = AutoBaud::new()
+            .candidates(&[Baudrate::Baud500K, Baudrate::Baud250K, Baudrate::Baud125K])
+            .dwell(Duration::from_millis(20));
+
+        let mut opened = Vec::new();
+        let detection = detector
+            .detect(|baudrate| {
+                opened.push(baudrate);
+                Ok(match baudrate {
+                    Baudrate::Baud500K => bus_with(&[error; 6]),
+                    Baudrate::Baud250K => bus_with(&[data, error, data, data, data]),
+                    _ => bus_with(&[data; 4]),
+                })
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(detection.baudrate, Baudrate::Baud125K);
+        assert_eq!(detection.probe.frames, 3);
+        assert!(!detection.adapted);
+        assert_eq!(opened.len(), 3);
+
+        // Silence matches nothing
+        let silent = detector.detect(|_| Ok(bus_with(&[]))).unwrap();
+        assert_eq!(silent, None);
+    }
+
+    /// Reads a bus error status while empty, as a channel at a wrong bit rate does.
+    struct Erroring(MockEnd);
+
+    impl RecvCan for Erroring {
+        fn recv(&self) -> Result<(CanFrame, Timestamp), CanError> {
+            match self.0.recv() {
+                Err(CanError::QrcvEmpty) => Err(CanError::BusLight),
+                read => read,
+            }
+        }
+
+        fn recv_frame(&self) -> Result<CanFrame, CanError> {
+            self.recv().map(|(frame, _)| frame)
+        }
+    }
+
+    #[test]
+    fn autobaud_counts_bus_error_status() {
+        let data = CanFrame::new(0x123, MessageType::Standard, &[1, 2, 3]).unwrap();
+        let detector = AutoBaud::new()
+            .candidates(&[Baudrate::Baud500K, Baudrate::Baud250K])
+            .dwell(Duration::from_millis(20));
+
+        let detection = detector
+            .detect(|baudrate| {
+                Ok(match baudrate {
+                    Baudrate::Baud500K => Erroring(bus_with(&[])),
+                    _ => Erroring(bus_with(&[data; 3])),
+                })
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(detection.baudrate, Baudrate::Baud250K);
+        assert_eq!(detection.probe.frames, 3);
+        assert_eq!(detection.probe.error_frames, 0);
+
+        // A status the driver keeps reporting counts once
+        let start = Instant::now();
+        let probe = detector.probe(&Erroring(bus_with(&[]))).unwrap();
+        assert_eq!(probe.error_frames, 1);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let mut status = StatusChanges::default();
+        let read = |err| Err::<(CanFrame, Timestamp), _>(err);
+        assert!(matches!(
+            status.received(read(CanError::BusLight)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            status.received(read(CanError::BusLight)),
+            Err(CanError::QrcvEmpty)
+        ));
+        assert!(matches!(
+            status.received(read(CanError::BusHeavy)),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn autobaud_detects_fd_data_rate() {
+        let slow = CanFdFrame::new(0x10, MessageType::Standard, &[0; 12], true, false).unwrap();
+        let fast = CanFdFrame::new(0x10, MessageType::Standard, &[0; 12], true, true).unwrap();
+        let error = CanFdFrame::new_error(1, &[0, 0, 0, 8]).unwrap();
+        let detector = AutoBaud::new()
+            .candidates(&[Baudrate::Baud500K])
+            .fd_data_rates(&[2_000_000, 4_000_000])
+            .dwell(Duration::from_millis(20));
+
+        let detection = detector
+            .detect_fd(|timing| {
+                let (listener, bus) = mock_bus();
+                bus.send_fd(slow).unwrap();
+                bus.send_fd(slow).unwrap();
+                if timing.data_bits_per_second() == 4_000_000 {
+                    bus.send_fd(fast).unwrap();
+                } else {
+                    bus.send_fd(error).unwrap();
+                }
+                bus.send_fd(slow).unwrap();
+                Ok(listener)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(detection.baudrate, Baudrate::Baud500K);
+        assert_eq!(detection.data_bps, Some(4_000_000));
+        assert_eq!(detection.probe.fast_frames, 1);
+    }
+
+    #[test]
+    fn autobaud_fd_timing() {
+        for (nominal, data) in [
+            (500_000, 2_000_000),
+            (1_000_000, 8_000_000),
+            (125_000, 1_000_000),
+            (250_000, 5_000_000),
+        ] {
+            let timing = fd_bit_timing(nominal, data).unwrap();
+            assert_eq!(timing.nominal_bits_per_second(), nominal);
+            assert_eq!(timing.data_bits_per_second(), data);
+        }
+        // 95.238 kbit/s is not a divisor of the CAN FD clock
+        assert!(fd_bit_timing(Baudrate::Baud95K.bits_per_second(), 2_000_000).is_none());
+        assert_eq!(baudrate_of(83_333), Some(Baudrate::Baud83K));
+        assert_eq!(baudrate_of(83_000), Some(Baudrate::Baud83K));
+        assert_eq!(baudrate_of(300_000), None);
+    }
+}
@@ -0,0 +1,31 @@
+//! `pcan-log convert`: rewrites a log in another format.
+
+use crate::files::{self, Format};
+
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Log to read, `-` for standard input
+    input: PathBuf,
+    /// Log to write, `-` for standard output
+    output: PathBuf,
+    /// Format of the input instead of the one its extension names
+    #[arg(short, long, value_enum)]
+    from: Option<Format>,
+    /// Format of the output instead of the one its extension names
+    #[arg(short, long, value_enum)]
+    to: Option<Format>,
+    /// Shift the timestamps so the first event is at zero
+    #[arg(short, long)]
+    zero: bool,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let mut events = files::read(&args.input, args.from)?;
+    if args.zero {
+        files::zero(&mut events);
+    }
+    files::write(&args.output, args.to, &events)
+}
@@ -0,0 +1,35 @@
+//! `pcan-log cut`: keeps a time range and a set of identifiers of a log.
+
+use crate::files::{self, Format};
+use crate::select::Selection;
+
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Log to read, `-` for standard input
+    input: PathBuf,
+    /// Log to write, `-` for standard output
+    output: PathBuf,
+    #[command(flatten)]
+    selection: Selection,
+    /// Format of the input instead of the one its extension names
+    #[arg(short, long, value_enum)]
+    from: Option<Format>,
+    /// Format of the output instead of the one its extension names
+    #[arg(short, long, value_enum)]
+    to: Option<Format>,
+    /// Shift the timestamps so the first kept event is at zero
+    #[arg(short, long)]
+    zero: bool,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let events = files::read(&args.input, args.from)?;
+    let mut events = args.selection.apply(events);
+    if args.zero {
+        files::zero(&mut events);
+    }
+    files::write(&args.output, args.to, &events)
+}
@@ -0,0 +1,91 @@
+//! Reading and writing logs given on the command line, `-` being standard input or output.
+
+use peak_can::logfile::{self, LogFormat};
+use peak_can::mdf::BusEvent;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Log formats by their command line names.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// PEAK-System trace, version 2.1 when writing
+    Trc,
+    /// Vector ASCII log
+    Asc,
+    /// Vector binary log
+    Blf,
+    /// SocketCAN `candump -l` log
+    Candump,
+    /// Comma separated values with a header line
+    Csv,
+}
+
+impl From<Format> for LogFormat {
+    fn from(value: Format) -> Self {
+        match value {
+            Format::Trc => LogFormat::Trc,
+            Format::Asc => LogFormat::Asc,
+            Format::Blf => LogFormat::Blf,
+            Format::Candump => LogFormat::Candump,
+            Format::Csv => LogFormat::Csv,
+        }
+    }
+}
+
+fn format_of(path: &Path, format: Option<Format>, option: &str) -> Result<LogFormat, String> {
+    match format {
+        Some(format) => Ok(format.into()),
+        None if path == Path::new("-") => Err(format!("standard streams need {option}")),
+        None => LogFormat::from_path(path).ok_or_else(|| {
+            format!(
+                "cannot tell the format of `{}` from its extension, use {option}",
+                path.display()
+            )
+        }),
+    }
+}
+
+/// Reads all events of `path`, in `format` or the one its extension names.
+pub fn read(path: &Path, format: Option<Format>) -> Result<Vec<BusEvent>, Box<dyn Error>> {
+    let log_format = format_of(path, format, "--from")?;
+    let result = if path == Path::new("-") {
+        logfile::parse(log_format, io::stdin().lock())
+    } else {
+        let file = File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        logfile::parse(log_format, BufReader::new(file))
+    };
+    result.map_err(|err| format!("{}: {err}", path.display()).into())
+}
+
+/// Writes `events` to `path`, in `format` or the one its extension names.
+pub fn write(
+    path: &Path,
+    format: Option<Format>,
+    events: &[BusEvent],
+) -> Result<(), Box<dyn Error>> {
+    let log_format = format_of(path, format, "--to")?;
+    let writer: Box<dyn Write> = if path == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        let file = File::create(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        Box::new(file)
+    };
+
+    match logfile::write(log_format, events, BufWriter::new(writer)) {
+        Err(logfile::LogFileError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(|err| format!("{}: {err}", path.display()).into()),
+    }
+}
+
+/// Shifts the timestamps so the first event is at zero.
+pub fn zero(events: &mut [BusEvent]) {
+    let Some(first) = events.iter().map(|event| event.timestamp_us).min() else {
+        return;
+    };
+    for event in events {
+        event.timestamp_us -= first;
+    }
+}
@@ -0,0 +1,101 @@
+//! `pcan-log`: converts, merges, cuts and replays CAN logs.
+//!
+//! Built with the `cli` feature. Formats follow the file extensions (`.trc`, `.asc`, `.blf`,
+//! `.log` for candump and `.csv`) unless given with `--from` and `--to`, `-` reads standard
+//! input or writes standard output.
+//!
+//! ```text
+//! pcan-log convert drive.blf drive.trc
+//! pcan-log merge body.log chassis.log --renumber -o vehicle.blf
+//! pcan-log cut drive.trc - --start 10 --end 20 --id 7E0-7EF --to candump
+//! pcan-log replay drive.trc USB1 -b 500K --speed 2 --skip-tx
+//! ```
+
+mod convert;
+mod cut;
+mod files;
+mod merge;
+mod replay;
+mod select;
+
+use clap::{Parser, Subcommand};
+
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "pcan-log",
+    version,
+    about = "Converts, merges, cuts and replays CAN logs"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rewrite a log in another format
+    Convert(convert::Args),
+    /// Combine logs in timestamp order
+    Merge(merge::Args),
+    /// Keep a time range and a set of identifiers
+    Cut(cut::Args),
+    /// Play a log onto a channel
+    Replay(replay::Args),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Convert(args) => convert::run(args),
+        Command::Merge(args) => merge::run(args),
+        Command::Cut(args) => cut::run(args),
+        Command::Replay(args) => replay::run(args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("pcan-log: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn command_line() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from([
+            "pcan-log", "cut", "a.trc", "-", "-t", "csv", "-i", "7E0-7EF",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Command::Cut(_)));
+        let cli =
+            Cli::try_parse_from(["pcan-log", "replay", "a.blf", "USB1", "-d", "2M", "-s", "0"])
+                .unwrap();
+        assert!(matches!(cli.command, Command::Replay(_)));
+        assert!(Cli::try_parse_from(["pcan-log", "merge", "a.trc", "-o", "b.trc"]).is_err());
+        assert!(
+            Cli::try_parse_from(["pcan-log", "convert", "a.trc", "b.xyz", "-t", "mf4"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from([
+                "pcan-log",
+                "replay",
+                "a.trc",
+                "USB1",
+                "-r",
+                "2",
+                "--forever"
+            ])
+            .is_err()
+        );
+    }
+}
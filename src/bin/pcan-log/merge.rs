@@ -0,0 +1,54 @@
+//! `pcan-log merge`: combines several logs into one in timestamp order.
+
+use crate::files::{self, Format};
+
+use peak_can::logfile;
+
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Logs to combine; their timestamps must share a time base, e.g. candump, CSV or BLF
+    /// logs of the same machine
+    #[arg(required = true, num_args = 2..)]
+    inputs: Vec<PathBuf>,
+    /// Log to write, `-` for standard output
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Format of the inputs instead of the ones their extensions name
+    #[arg(short, long, value_enum)]
+    from: Option<Format>,
+    /// Format of the output instead of the one its extension names
+    #[arg(short, long, value_enum)]
+    to: Option<Format>,
+    /// Number the channels of each input after those of the previous inputs instead of
+    /// keeping their numbers
+    #[arg(short, long)]
+    renumber: bool,
+    /// Shift the timestamps so the first event is at zero
+    #[arg(short, long)]
+    zero: bool,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let mut logs = Vec::with_capacity(args.inputs.len());
+    let mut offset = 0u8;
+    for input in &args.inputs {
+        let mut events = files::read(input, args.from)?;
+        if args.renumber {
+            let channels = events.iter().map(|e| e.bus_channel).max().unwrap_or(0);
+            for event in &mut events {
+                event.bus_channel = event.bus_channel.saturating_add(offset);
+            }
+            offset = offset.saturating_add(channels);
+        }
+        logs.push(events);
+    }
+
+    let mut events = logfile::merge(logs);
+    if args.zero {
+        files::zero(&mut events);
+    }
+    files::write(&args.output, args.to, &events)
+}
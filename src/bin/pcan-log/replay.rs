@@ -0,0 +1,63 @@
+//! `pcan-log replay`: plays a log onto a channel with its original timing.
+
+use crate::files::{self, Format};
+use crate::select::Selection;
+
+use peak_can::cli::{BitrateArgs, Bus, Channel};
+use peak_can::replay::{Repeat, Replayer};
+
+use std::error::Error;
+use std::path::PathBuf;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Log to replay, `-` for standard input
+    input: PathBuf,
+    /// Channel such as USB1, PCI2, LAN1, a handle such as 0x51 or USB@7 by device ID;
+    /// receives the events of every log channel unless --log-channel picks one
+    channel: Bus,
+    #[command(flatten)]
+    bitrate: BitrateArgs,
+    #[command(flatten)]
+    selection: Selection,
+    /// Format of the input instead of the one its extension names
+    #[arg(short, long, value_enum)]
+    from: Option<Format>,
+    /// Time scale: 2 plays twice as fast, 0.5 half as fast and 0 sends back to back
+    #[arg(short, long, default_value_t = 1.0)]
+    speed: f64,
+    /// Number of passes over the log
+    #[arg(short, long, default_value_t = 1, conflicts_with = "forever")]
+    repeat: u32,
+    /// Loop until interrupted
+    #[arg(long)]
+    forever: bool,
+    /// Skip the frames recorded as transmitted by the logging node
+    #[arg(long)]
+    skip_tx: bool,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let events = files::read(&args.input, args.from)?;
+    let mut events = args.selection.apply(events);
+    events.sort_by_key(|event| event.timestamp_us);
+
+    let channel = Channel::open(args.channel, &args.bitrate)?;
+    let repeat = if args.forever {
+        Repeat::Forever
+    } else {
+        Repeat::Times(args.repeat)
+    };
+
+    let report = Replayer::new(&events)
+        .speed(args.speed)
+        .repeat(repeat)
+        .skip_tx(args.skip_tx)
+        .target(1, channel.replay_target())
+        .run()?;
+    eprintln!(
+        "{}: sent {} frames, skipped {}",
+        channel.bus, report.sent, report.skipped
+    );
+    Ok(())
+}
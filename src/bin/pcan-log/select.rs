@@ -0,0 +1,163 @@
+//! Time range, identifier and channel selection shared by `cut` and `replay`.
+
+use peak_can::mdf::{BusEvent, BusEventKind};
+
+/// Identifier filter: a single identifier, an inclusive range or a code and mask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdFilter {
+    Id(u32),
+    Range(u32, u32),
+    Mask { code: u32, mask: u32 },
+}
+
+impl IdFilter {
+    pub fn matches(&self, can_id: u32) -> bool {
+        match *self {
+            IdFilter::Id(id) => can_id == id,
+            IdFilter::Range(first, last) => (first..=last).contains(&can_id),
+            IdFilter::Mask { code, mask } => can_id & mask == code & mask,
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("invalid identifier `{s}`"))
+}
+
+pub fn parse_id_filter(s: &str) -> Result<IdFilter, String> {
+    if let Some((first, last)) = s.split_once('-') {
+        let (first, last) = (parse_hex(first)?, parse_hex(last)?);
+        if first > last {
+            return Err(format!("empty identifier range `{s}`"));
+        }
+        return Ok(IdFilter::Range(first, last));
+    }
+    if let Some((code, mask)) = s.split_once(':') {
+        let mask = u32::from_str_radix(mask, 16).map_err(|_| format!("invalid mask `{mask}`"))?;
+        return Ok(IdFilter::Mask {
+            code: parse_hex(code)?,
+            mask,
+        });
+    }
+    parse_hex(s).map(IdFilter::Id)
+}
+
+/// Decimal seconds as microseconds.
+pub fn parse_seconds(s: &str) -> Result<u64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds >= 0.0 && seconds.is_finite())
+        .map(|seconds| (seconds * 1_000_000.0).round() as u64)
+        .ok_or_else(|| format!("invalid time `{s}`"))
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Selection {
+    /// Drop events before this many seconds after the first event
+    #[arg(long, value_parser = parse_seconds)]
+    start: Option<u64>,
+    /// Drop events after this many seconds after the first event
+    #[arg(long, value_parser = parse_seconds)]
+    end: Option<u64>,
+    /// Take --start and --end as timestamps of the log rather than offsets from its first
+    /// event
+    #[arg(long)]
+    absolute: bool,
+    /// Keep only these identifiers: `123`, a range `100-1FF` or `<id>:<mask>`; may be
+    /// repeated, error frames are dropped
+    #[arg(short, long = "id", value_parser = parse_id_filter)]
+    ids: Vec<IdFilter>,
+    /// Drop these identifiers, same notation as --id; may be repeated
+    #[arg(short = 'x', long, value_parser = parse_id_filter)]
+    exclude: Vec<IdFilter>,
+    /// Keep only the events recorded on this log channel (numbered from 1)
+    #[arg(long)]
+    log_channel: Option<u8>,
+}
+
+impl Selection {
+    fn keeps_id(&self, event: &BusEvent) -> bool {
+        if event.kind == BusEventKind::ErrorFrame {
+            return self.ids.is_empty();
+        }
+        (self.ids.is_empty() || self.ids.iter().any(|f| f.matches(event.can_id)))
+            && !self.exclude.iter().any(|f| f.matches(event.can_id))
+    }
+
+    /// The selected events, in their original order.
+    pub fn apply(&self, events: Vec<BusEvent>) -> Vec<BusEvent> {
+        let origin = if self.absolute {
+            0
+        } else {
+            events.iter().map(|e| e.timestamp_us).min().unwrap_or(0)
+        };
+        let start = self.start.map_or(0, |start| origin + start);
+        let end = self.end.map_or(u64::MAX, |end| origin.saturating_add(end));
+
+        events
+            .into_iter()
+            .filter(|event| (start..=end).contains(&event.timestamp_us))
+            .filter(|event| self.log_channel.is_none_or(|c| event.bus_channel == c))
+            .filter(|event| self.keeps_id(event))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp_us: u64, can_id: u32) -> BusEvent {
+        BusEvent {
+            timestamp_us,
+            bus_channel: 1,
+            kind: BusEventKind::DataFrame,
+            can_id,
+            extended: false,
+            dlc: 0,
+            data: Vec::new(),
+            tx: false,
+            edl: false,
+            brs: false,
+            esi: false,
+            error_type: 0,
+        }
+    }
+
+    #[test]
+    fn selection() {
+        assert_eq!(
+            parse_id_filter("7E0-7EF"),
+            Ok(IdFilter::Range(0x7E0, 0x7EF))
+        );
+        assert!(
+            parse_id_filter("18DAF100:1FFFFF00")
+                .unwrap()
+                .matches(0x18DA_F1AB)
+        );
+        assert!(parse_id_filter("200-100").is_err());
+        assert_eq!(parse_seconds("1.5"), Ok(1_500_000));
+
+        let events = vec![
+            event(10_000_000, 0x100),
+            event(11_000_000, 0x7E8),
+            event(12_000_000, 0x200),
+            event(13_000_000, 0x7E0),
+        ];
+        let selection = Selection {
+            start: Some(1_000_000),
+            end: Some(3_000_000),
+            absolute: false,
+            ids: vec![IdFilter::Range(0x700, 0x7FF), IdFilter::Id(0x200)],
+            exclude: vec![IdFilter::Id(0x7E0)],
+            log_channel: None,
+        };
+        let ids: Vec<u32> = selection
+            .apply(events)
+            .iter()
+            .map(|event| event.can_id)
+            .collect();
+        assert_eq!(ids, vec![0x7E8, 0x200]);
+    }
+}
@@ -0,0 +1,136 @@
+//! `pcan device`: finding adapters and giving them device IDs that survive replugging.
+
+use crate::list::condition_name;
+
+use peak_can::bus::{LanBus, PciBus, UsbBus};
+use peak_can::cli::{Bus, ResolvedBus, parse_device_id};
+use peak_can::device::{self, AssignDeviceId, FindByDeviceId};
+
+use std::error::Error;
+use std::time::Duration;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// List the attached adapters with their device IDs, marking IDs in use more than once
+    List,
+    /// Blink the LED of a USB adapter
+    Identify {
+        /// USB channel such as USB3
+        channel: Bus,
+        /// How long to blink
+        #[arg(short, long, default_value_t = 10)]
+        seconds: u64,
+    },
+    /// Store a device ID in an adapter, refusing IDs another adapter of the family has
+    Assign {
+        /// USB, PCI or LAN channel such as USB3
+        channel: Bus,
+        /// Device ID in decimal or, with 0x, in hex
+        #[arg(value_parser = parse_device_id)]
+        device_id: u32,
+    },
+    /// Print the channel of the adapter with a device ID
+    Find {
+        /// Device ID in decimal or, with 0x, in hex
+        #[arg(value_parser = parse_device_id)]
+        device_id: u32,
+        /// Adapter family
+        #[arg(short = 't', long = "type", value_enum, default_value_t = Family::Usb)]
+        family: Family,
+    },
+}
+
+/// Families storing a device ID.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    Usb,
+    Pci,
+    Lan,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    match args.command {
+        Command::List => list(),
+        Command::Identify { channel, seconds } => {
+            let ResolvedBus::Usb(usb) = channel.resolve()? else {
+                return Err(format!("{channel}: only USB adapters can blink").into());
+            };
+            println!("{channel} blinks for {seconds} s");
+            Ok(device::identify(usb, Duration::from_secs(seconds))?)
+        }
+        Command::Assign { channel, device_id } => {
+            match channel.resolve()? {
+                ResolvedBus::Usb(bus) => bus.assign_device_id(device_id),
+                ResolvedBus::Pci(bus) => bus.assign_device_id(device_id),
+                ResolvedBus::Lan(bus) => bus.assign_device_id(device_id),
+                _ => return Err(format!("{channel}: the adapter has no device ID").into()),
+            }
+            .map_err(|err| format!("{channel}: {err}"))?;
+            println!("{channel}: device ID {device_id}");
+            Ok(())
+        }
+        Command::Find { device_id, family } => {
+            let bus = match family {
+                Family::Usb => UsbBus::by_device_id(device_id).map(Bus::Usb),
+                Family::Pci => PciBus::by_device_id(device_id).map(Bus::Pci),
+                Family::Lan => LanBus::by_device_id(device_id).map(Bus::Lan),
+            }?;
+            println!("{bus}");
+            Ok(())
+        }
+    }
+}
+
+fn list() -> Result<(), Box<dyn Error>> {
+    let adapters = device::adapters()?;
+    if adapters.is_empty() {
+        println!("no channels attached");
+        return Ok(());
+    }
+
+    let duplicates = device::duplicates(&adapters);
+    println!(
+        "{:<8} {:<8} {:<20} {:>4}  {:<12} CONDITION",
+        "CHANNEL", "TYPE", "DEVICE", "CTRL", "DEVICE ID"
+    );
+    for adapter in &adapters {
+        let name = Bus::from_handle(adapter.channel)
+            .map_or_else(|| format!("{:#X}", adapter.channel), |bus| bus.to_string());
+        let device_type = adapter
+            .device_type
+            .map_or_else(|| String::from("?"), |t| t.to_string());
+        let device_id = if !adapter.has_device_id() {
+            String::from("-")
+        } else if duplicates
+            .iter()
+            .any(|(_, _, channels)| channels.contains(&adapter.channel))
+        {
+            format!("{} *", adapter.device_id)
+        } else {
+            adapter.device_id.to_string()
+        };
+
+        println!(
+            "{:<8} {:<8} {:<20} {:>4}  {:<12} {}",
+            name,
+            device_type,
+            adapter.device_name,
+            adapter.controller_number,
+            device_id,
+            adapter
+                .condition
+                .map_or("unknown", |condition| condition_name(&condition)),
+        );
+    }
+
+    if !duplicates.is_empty() {
+        println!("\n* shared with another adapter, assign unique IDs with `pcan device assign`");
+    }
+    Ok(())
+}
@@ -0,0 +1,349 @@
+//! `pcan dump`: prints received frames, like `candump`.
+
+use peak_can::cli::{BitrateArgs, Bus, Channel};
+use peak_can::error::CanError;
+use peak_can::filter::{AnyFrame, Rule};
+use peak_can::status::ErrorFrame;
+
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::thread;
+use std::time::Duration;
+
+const IDLE_SLEEP: Duration = Duration::from_micros(500);
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel such as USB1, PCI2, LAN1, a handle such as 0x51 or USB@7 by device ID
+    channel: Bus,
+    /// Filters as `<id>:<mask>` to accept or `<id>~<mask>` to reject matching identifiers, a
+    /// plain `<id>` accepts that identifier; identifiers of more than three hex digits are
+    /// extended
+    #[arg(value_parser = parse_filter)]
+    filters: Vec<Filter>,
+    #[command(flatten)]
+    bitrate: BitrateArgs,
+    /// Timestamps: (a)bsolute, (d)elta to the previous frame or (z)ero at the first frame
+    #[arg(short, long, value_enum)]
+    timestamp: Option<TimestampMode>,
+    /// Show error and status frames
+    #[arg(short, long)]
+    errors: bool,
+    /// Exit after this many frames
+    #[arg(short = 'n', long)]
+    count: Option<u64>,
+    /// Color the output
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto)]
+    color: ColorMode,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampMode {
+    #[value(name = "a")]
+    Absolute,
+    #[value(name = "d")]
+    Delta,
+    #[value(name = "z")]
+    Zero,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Colored when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorMode::Auto => io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/* Filters */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Accept(Rule),
+    Reject(Rule),
+}
+
+fn parse_id(s: &str) -> Result<(u32, bool), String> {
+    let id = u32::from_str_radix(s, 16).map_err(|_| format!("invalid identifier `{s}`"))?;
+    Ok((id, s.len() > 3))
+}
+
+pub fn parse_filter(s: &str) -> Result<Filter, String> {
+    let (id, mask, reject) = match s.split_once([':', '~']) {
+        Some((id, mask)) => {
+            let mask =
+                u32::from_str_radix(mask, 16).map_err(|_| format!("invalid mask `{mask}`"))?;
+            (id, mask, s.contains('~'))
+        }
+        None => (s, u32::MAX, false),
+    };
+    let (code, extended) = parse_id(id)?;
+    let width = if extended {
+        Rule::Extended
+    } else {
+        !Rule::Extended
+    };
+    let rule = Rule::mask(code, mask).and(width);
+    Ok(if reject {
+        Filter::Reject(rule)
+    } else {
+        Filter::Accept(rule)
+    })
+}
+
+/// Frames matching any accepting filter and no rejecting one, every frame without
+/// accepting filters.
+pub fn rule(filters: &[Filter]) -> Rule {
+    let accepted: Vec<Rule> = filters
+        .iter()
+        .filter_map(|filter| match filter {
+            Filter::Accept(rule) => Some(rule.clone()),
+            Filter::Reject(_) => None,
+        })
+        .collect();
+    let rejected: Vec<Rule> = filters
+        .iter()
+        .filter_map(|filter| match filter {
+            Filter::Reject(rule) => Some(rule.clone()),
+            Filter::Accept(_) => None,
+        })
+        .collect();
+
+    let rule = if accepted.is_empty() {
+        Rule::Always
+    } else {
+        Rule::Any(accepted)
+    };
+    if rejected.is_empty() {
+        rule
+    } else {
+        rule.and(!Rule::Any(rejected))
+    }
+}
+
+/* Output */
+
+/// Turns driver timestamps into the selected representation.
+pub struct Clock {
+    mode: Option<TimestampMode>,
+    first: Option<u64>,
+    previous: Option<u64>,
+}
+
+impl Clock {
+    pub fn new(mode: Option<TimestampMode>) -> Clock {
+        Clock {
+            mode,
+            first: None,
+            previous: None,
+        }
+    }
+
+    /// `(seconds.micros) ` for the timestamp in microseconds, empty without a mode.
+    pub fn stamp(&mut self, timestamp: u64) -> String {
+        let first = *self.first.get_or_insert(timestamp);
+        let previous = self.previous.replace(timestamp).unwrap_or(timestamp);
+        let micros = match self.mode {
+            None => return String::new(),
+            Some(TimestampMode::Absolute) => timestamp,
+            Some(TimestampMode::Delta) => timestamp.saturating_sub(previous),
+            Some(TimestampMode::Zero) => timestamp.saturating_sub(first),
+        };
+        format!("({}.{:06}) ", micros / 1_000_000, micros % 1_000_000)
+    }
+}
+
+const RED: &str = "31";
+const YELLOW: &str = "33";
+const MAGENTA: &str = "35";
+const BOLD: &str = "1";
+const DIM: &str = "2";
+
+pub fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{style}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// One line in `candump` layout: channel, identifier, length and payload, followed by
+/// the CAN FD flags and `TX` for echo frames.
+pub fn format_frame(channel: &str, frame: &AnyFrame, color: bool) -> String {
+    let mut line = format!("{channel:>5}  ");
+
+    if frame.is_error_frame() {
+        let _ = write!(line, "{}", paint("ERROR   ", RED, color));
+        let _ = write!(line, "[{}]  {}", frame.data().len(), hex(frame.data()));
+        let error = match frame {
+            AnyFrame::Can(frame) => ErrorFrame::from_frame(frame),
+            AnyFrame::Fd(frame) => ErrorFrame::from_fd_frame(frame),
+        };
+        if let Some(error) = error {
+            let direction = if error.rx { "rx" } else { "tx" };
+            let description = format!(
+                "  {:?} error ({direction}), TEC {} REC {}",
+                error.kind, error.tec, error.rec
+            );
+            line.push_str(&paint(&description, RED, color));
+        }
+        return line;
+    }
+    if frame.is_status_frame() {
+        let _ = write!(line, "{}", paint("STATUS  ", YELLOW, color));
+        let _ = write!(line, "[{}]  {}", frame.data().len(), hex(frame.data()));
+        return line;
+    }
+
+    let id = if frame.is_extended_frame() {
+        format!("{:08X}", frame.can_id())
+    } else {
+        format!("{:03X}     ", frame.can_id())
+    };
+    line.push_str(&paint(&id, BOLD, color));
+
+    match frame {
+        AnyFrame::Can(can) if can.is_remote_frame() => {
+            let _ = write!(line, "   [{}]  remote request", can.dlc());
+        }
+        AnyFrame::Can(_) => {
+            let _ = write!(line, "   [{}]  {}", frame.data().len(), hex(frame.data()));
+        }
+        AnyFrame::Fd(_) if !frame.is_fd_frame() => {
+            let _ = write!(line, "   [{}]  {}", frame.data().len(), hex(frame.data()));
+        }
+        AnyFrame::Fd(fd) => {
+            let _ = write!(line, "  [{:02}]  {}", frame.data().len(), hex(frame.data()));
+            let mut flags = String::from("FD");
+            if fd.is_brs_frame() {
+                flags.push_str(" BRS");
+            }
+            if fd.is_esi_frame() {
+                flags.push_str(" ESI");
+            }
+            let _ = write!(line, "  {}", paint(&flags, MAGENTA, color));
+        }
+    }
+
+    if frame.is_echo_frame() {
+        let _ = write!(line, "  {}", paint("TX", DIM, color));
+    }
+    line
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let channel = Channel::open(args.channel, &args.bitrate)?;
+    if args.errors {
+        channel.allow_error_frames(true)?;
+    }
+
+    let rule = rule(&args.filters);
+    let color = args.color.enabled();
+    let name = channel.bus.to_string();
+    let mut clock = Clock::new(args.timestamp);
+    let mut shown = 0;
+    let mut stdout = io::stdout().lock();
+
+    while args.count != Some(shown) {
+        let (frame, timestamp) = match channel.recv() {
+            Ok(received) => received,
+            Err(CanError::QrcvEmpty) => {
+                thread::sleep(IDLE_SLEEP);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let special = frame.is_error_frame() || frame.is_status_frame();
+        if (special && !args.errors) || (!special && !rule.matches(&frame)) {
+            continue;
+        }
+
+        let line = format!(
+            "{}{}",
+            clock.stamp(timestamp),
+            format_frame(&name, &frame, color)
+        );
+        match writeln!(stdout, "{line}") {
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+        shown += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peak_can::socket::{CanFdFrame, CanFrame, MessageType};
+
+    #[test]
+    fn filters() {
+        let standard = AnyFrame::Can(CanFrame::new(0x123, MessageType::Standard, &[1]).unwrap());
+        let extended = AnyFrame::Can(CanFrame::new(0x123, MessageType::Extended, &[1]).unwrap());
+        let other = AnyFrame::Can(CanFrame::new(0x124, MessageType::Standard, &[1]).unwrap());
+
+        let accept = rule(&[parse_filter("123").unwrap()]);
+        assert!(accept.matches(&standard));
+        assert!(!accept.matches(&extended) && !accept.matches(&other));
+
+        let masked = rule(&[parse_filter("00000120:1FFFFFF0").unwrap()]);
+        assert!(masked.matches(&extended) && !masked.matches(&standard));
+
+        let reject = rule(&[parse_filter("120~7F0").unwrap()]);
+        assert!(!reject.matches(&standard) && !reject.matches(&other));
+        assert!(reject.matches(&extended));
+
+        assert!(rule(&[]).matches(&other));
+        assert!(parse_filter("12G").is_err());
+    }
+
+    #[test]
+    fn candump_layout() {
+        let frame =
+            AnyFrame::Can(CanFrame::new(0x123, MessageType::Standard, &[0xDE, 0xAD]).unwrap());
+        assert_eq!(
+            format_frame("USB1", &frame, false),
+            " USB1  123        [2]  DE AD"
+        );
+
+        let remote =
+            AnyFrame::Can(CanFrame::new_remote(0x1ABCDE, MessageType::Extended, 4).unwrap());
+        assert_eq!(
+            format_frame("USB1", &remote, false),
+            " USB1  001ABCDE   [4]  remote request"
+        );
+
+        let fd = AnyFrame::Fd(
+            CanFdFrame::new(0x7FF, MessageType::Standard, &[0; 12], true, true).unwrap(),
+        );
+        assert!(
+            format_frame("USB1", &fd, false)
+                .ends_with("[12]  00 00 00 00 00 00 00 00 00 00 00 00  FD BRS")
+        );
+        assert_eq!(paint("123", BOLD, true), "\x1b[1m123\x1b[0m");
+
+        let mut clock = Clock::new(Some(TimestampMode::Delta));
+        assert_eq!(clock.stamp(5_000_000), "(0.000000) ");
+        assert_eq!(clock.stamp(6_250_000), "(1.250000) ");
+    }
+}
@@ -0,0 +1,147 @@
+//! `pcan info`: every hardware and driver parameter of a channel.
+
+use crate::list::condition_name;
+
+use peak_can::cli::{BitrateArgs, Bus, Channel, ResolvedBus};
+use peak_can::error::CanError;
+use peak_can::hw::{
+    self, ChannelCondition, ChannelIdentifying, ControllerNumber, DeviceId, DevicePartNumber,
+    HardwareName, IpAddress,
+};
+use peak_can::info::{self, BitrateInfo, BitrateInfoFd, ChannelFeatures, ChannelVersion};
+use peak_can::special::{BitrateAdapting, FiveVoltsPower, ListenOnly};
+
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel such as USB1, PCI2, LAN1, a handle such as 0x51 or USB@7 by device ID;
+    /// driver information only when omitted
+    channel: Option<Bus>,
+    /// Initialize the channel to also read the firmware version, bus speeds and status
+    #[arg(short, long)]
+    open: bool,
+    #[command(flatten)]
+    bitrate: BitrateArgs,
+}
+
+fn row<T: Display>(label: &str, value: Result<T, CanError>) {
+    match value {
+        Ok(value) => println!("  {label:<24}{value}"),
+        Err(err) => println!("  {label:<24}({err})"),
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value { "yes" } else { "no" }
+}
+
+fn driver_rows() {
+    println!("Driver");
+    row("API version", info::api_version());
+    row("Attached channels", hw::attached_channels_count());
+    row(
+        "LAN service running",
+        info::lan_service_is_running().map(yes_no),
+    );
+}
+
+/// Parameters every family reports before the channel is initialized.
+fn bus_rows<B>(bus: &B)
+where
+    B: ChannelCondition
+        + HardwareName
+        + ControllerNumber
+        + DevicePartNumber
+        + ChannelVersion
+        + ChannelFeatures
+        + BitrateInfo
+        + BitrateInfoFd,
+{
+    row(
+        "Condition",
+        bus.channel_condition().map(|c| condition_name(&c)),
+    );
+    row("Hardware name", bus.hardware_name());
+    row("Controller number", bus.controller_number());
+    row("Part number", bus.device_part_number());
+    match bus.channel_version() {
+        Ok(version) => {
+            row("Driver", Ok(version.device_driver_name_and_version));
+            row("Copyright", Ok(version.year_of_copyright));
+            row("Company", Ok(version.company_name_and_city));
+        }
+        Err(err) => row::<String>("Driver", Err(err)),
+    }
+    row("CAN FD capable", bus.is_fd_capable().map(yes_no));
+    row("Interframe delay", bus.is_delay_capable().map(yes_no));
+    row("Digital I/O", bus.is_io_capable().map(yes_no));
+    row(
+        "Bit rate (BTR0BTR1)",
+        bus.bitrate_info()
+            .map(|(btr0, btr1)| format!("{btr0:#06X} {btr1:#06X}")),
+    );
+    row("Bit rate (FD)", bus.bitrate_info_fd());
+}
+
+fn channel_rows(bus: ResolvedBus) {
+    println!("Channel {bus} ({:#X})", bus.handle());
+    match bus {
+        ResolvedBus::Usb(bus) => {
+            bus_rows(&bus);
+            row("Device ID", bus.device_id().map(|id| format!("{id:#010X}")));
+            row("Identifying", bus.is_channel_identifying().map(yes_no));
+            row("5 V power", bus.five_volts().map(yes_no));
+            row("Listen only", bus.listen_only().map(yes_no));
+            row("Bit rate adapting", bus.bitrate_adapting().map(yes_no));
+        }
+        ResolvedBus::Lan(bus) => {
+            bus_rows(&bus);
+            row("Device ID", bus.device_id().map(|id| format!("{id:#010X}")));
+            row("IP address", bus.ip_address());
+        }
+        ResolvedBus::Pci(bus) => {
+            bus_rows(&bus);
+            row("Device ID", bus.device_id().map(|id| format!("{id:#010X}")));
+        }
+        ResolvedBus::Pcc(bus) => {
+            bus_rows(&bus);
+            row("5 V power", bus.five_volts().map(yes_no));
+        }
+        ResolvedBus::Isa(bus) => bus_rows(&bus),
+        ResolvedBus::Dng(bus) => bus_rows(&bus),
+    }
+}
+
+/// Parameters only available on initialized channels.
+fn socket_rows(channel: &Channel) {
+    println!("Initialized");
+    row("Firmware version", channel.firmware_version());
+    row(
+        "Nominal bus speed",
+        channel
+            .nominal_bus_speed()
+            .map(|bps| format!("{bps} bit/s")),
+    );
+    row(
+        "Data bus speed",
+        channel.data_bus_speed().map(|bps| format!("{bps} bit/s")),
+    );
+    row("Bus status", channel.bus_status());
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    driver_rows();
+    let Some(bus) = args.channel else {
+        return Ok(());
+    };
+    let bus = bus.resolve()?;
+
+    channel_rows(bus);
+    if args.open {
+        let channel = Channel::open(bus.into(), &args.bitrate)?;
+        socket_rows(&channel);
+    }
+    Ok(())
+}
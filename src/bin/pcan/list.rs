@@ -0,0 +1,86 @@
+//! `pcan list`: attached channels as reported by the driver.
+
+use peak_can::capability::DeviceType;
+use peak_can::cli::{BitrateArgs, Bus, Channel, parse_baudrate};
+use peak_can::hw::{self, ChannelConditionStatus};
+use peak_can::socket::Baudrate;
+use peak_can::special::SetListenOnly;
+
+use std::error::Error;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Read the firmware versions by briefly initializing every available channel, USB
+    /// channels in listen-only mode
+    #[arg(short, long)]
+    firmware: bool,
+    /// Bit rate the channels are initialized with to read the firmware version
+    #[arg(short, long, default_value = "500K", value_parser = parse_baudrate)]
+    bitrate: Baudrate,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let channels = hw::attached_channels()?;
+    if channels.is_empty() {
+        println!("no channels attached");
+        return Ok(());
+    }
+
+    println!(
+        "{:<8} {:<6} {:<8} {:<20} {:>4}  {:<10}  {:<11} {:<3}  FIRMWARE",
+        "CHANNEL", "HANDLE", "TYPE", "DEVICE", "CTRL", "DEVICE ID", "CONDITION", "FD"
+    );
+    for channel in &channels {
+        let info = &channel.channel_information;
+        let bus = Bus::from_handle(info.channel_handle);
+        let name = bus.map_or_else(|| String::from("?"), |bus| bus.to_string());
+        let device_type = DeviceType::try_from(u32::from(info.device_type))
+            .map_or_else(|_| format!("{:#X}", info.device_type), |t| t.to_string());
+        let condition = ChannelConditionStatus::try_from(info.channel_condition);
+        let firmware = match (bus, &condition) {
+            (Some(bus), Ok(ChannelConditionStatus::Available)) if args.firmware => {
+                firmware_version(bus, args.bitrate).unwrap_or_else(|err| format!("({err})"))
+            }
+            _ => String::from("-"),
+        };
+
+        println!(
+            "{:<8} {:<6} {:<8} {:<20} {:>4}  {:<10}  {:<11} {:<3}  {}",
+            name,
+            format!("{:#X}", info.channel_handle),
+            device_type,
+            channel.device_name(),
+            info.controller_number,
+            format!("{:#010X}", info.device_id),
+            condition.map_or("unknown", |condition| condition_name(&condition)),
+            if channel.is_fd_capable() { "yes" } else { "no" },
+            firmware,
+        );
+    }
+    Ok(())
+}
+
+pub fn condition_name(condition: &ChannelConditionStatus) -> &'static str {
+    match condition {
+        ChannelConditionStatus::Unavailable => "unavailable",
+        ChannelConditionStatus::Available => "available",
+        ChannelConditionStatus::Occupied => "occupied",
+        ChannelConditionStatus::CanView => "PCAN-View",
+    }
+}
+
+/// Only available on initialized channels.
+fn firmware_version(bus: Bus, bitrate: Baudrate) -> Result<String, Box<dyn Error>> {
+    let args = BitrateArgs {
+        bitrate,
+        data_bitrate: None,
+    };
+    if let Bus::Usb(usb) = bus {
+        usb.set_listen_only(true)?;
+    }
+    let version = Channel::open(bus, &args).and_then(|channel| Ok(channel.firmware_version()?));
+    if let Bus::Usb(usb) = bus {
+        usb.set_listen_only(false)?;
+    }
+    version
+}
@@ -0,0 +1,95 @@
+//! `pcan`: command line tools for PEAK-System CAN adapters in the spirit of can-utils.
+//!
+//! Built with the `cli` feature.
+//!
+//! ```text
+//! pcan list --firmware
+//! pcan dump USB1 -b 500K -t z 123:7FF 18DAF100:1FFFFF00
+//! pcan send USB1 -b 500K 123#DEADBEEF
+//! pcan send USB1 -b 500K -d 2M 123##1000102030405060708090A0B --interval 100
+//! pcan info USB1 --open
+//! pcan device identify USB3
+//! pcan device assign USB3 7
+//! pcan dump USB@7
+//! pcan monitor USB1 -b 500K
+//! ```
+
+mod device;
+mod dump;
+mod info;
+mod list;
+#[cfg(feature = "monitor")]
+mod monitor;
+mod send;
+
+use clap::{Parser, Subcommand};
+
+use std::process::ExitCode;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "pcan",
+    version,
+    about = "Command line tools for PEAK-System CAN adapters"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the attached channels
+    List(list::Args),
+    /// Print received frames
+    Dump(dump::Args),
+    /// Send frames once or cyclically
+    Send(send::Args),
+    /// Show driver and channel parameters
+    Info(info::Args),
+    /// List, blink and assign device IDs of adapters
+    Device(device::Args),
+    /// Live per-identifier view of the bus
+    #[cfg(feature = "monitor")]
+    Monitor(monitor::Args),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::List(args) => list::run(args),
+        Command::Dump(args) => dump::run(args),
+        Command::Send(args) => send::run(args),
+        Command::Info(args) => info::run(args),
+        Command::Device(args) => device::run(args),
+        #[cfg(feature = "monitor")]
+        Command::Monitor(args) => monitor::run(args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("pcan: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn command_line() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["pcan", "send", "usb1", "-d", "2M", "123##1AA"]).unwrap();
+        assert!(matches!(cli.command, Command::Send(_)));
+        assert!(Cli::try_parse_from(["pcan", "dump", "USB99"]).is_err());
+        assert!(Cli::try_parse_from(["pcan", "send", "USB1", "-n", "3", "123#00"]).is_err());
+        assert!(Cli::try_parse_from(["pcan", "device", "assign", "USB3", "0x1F"]).is_ok());
+        assert!(Cli::try_parse_from(["pcan", "device", "assign", "USB3", "seven"]).is_err());
+        assert!(Cli::try_parse_from(["pcan", "dump", "CAN@7"]).is_err());
+    }
+}
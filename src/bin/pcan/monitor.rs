@@ -0,0 +1,592 @@
+//! `pcan monitor`: live per-identifier view of the bus, like the receive list of PCAN-View.
+//!
+//! Built with the `monitor` feature. Keys: `p` or space pauses the table, `f` edits the
+//! filters, `s` sends a frame, `c` clears the table, arrows scroll and `q` quits.
+
+use crate::dump::{Filter, hex, parse_filter, rule};
+use crate::send::parse_frame;
+
+use peak_can::cli::{BitrateArgs, Bus, Channel};
+use peak_can::error::CanError;
+use peak_can::filter::{AnyFrame, Rule};
+use peak_can::stats::{BusStatistics, Direction};
+use peak_can::status::{ErrorMonitor, ErrorState};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Interval between screen updates.
+const REFRESH: Duration = Duration::from_millis(100);
+/// Longest wait for a key press before reading frames again.
+const KEY_POLL: Duration = Duration::from_millis(2);
+/// Frames read before checking the keyboard again.
+const MAX_BATCH: usize = 4096;
+/// How long a changed byte stays highlighted.
+const HIGHLIGHT: Duration = Duration::from_secs(1);
+/// Without frames for this long the bus counts as idle.
+const IDLE: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel such as USB1, PCI2, LAN1, a handle such as 0x51 or USB@7 by device ID
+    channel: Bus,
+    /// Display filters as in `pcan dump`, editable with `f`
+    #[arg(value_parser = check_filter)]
+    filters: Vec<String>,
+    #[command(flatten)]
+    bitrate: BitrateArgs,
+}
+
+fn check_filter(s: &str) -> Result<String, String> {
+    parse_filter(s).map(|_| s.to_string())
+}
+
+fn parse_filters(text: &str) -> Result<Rule, String> {
+    let filters = text
+        .split_whitespace()
+        .map(parse_filter)
+        .collect::<Result<Vec<Filter>, _>>()?;
+    Ok(rule(&filters))
+}
+
+/* Traffic */
+
+/// Newest frame of one identifier.
+struct Entry {
+    frame: AnyFrame,
+    count: u64,
+    timestamp: u64,
+    /// Interval between the two newest frames in microseconds.
+    period: Option<u64>,
+    /// When each payload byte last changed.
+    changed: Vec<Option<Instant>>,
+}
+
+/// Per-identifier table, bus load and error counters.
+struct Traffic {
+    entries: BTreeMap<(bool, u32), Entry>,
+    stats: BusStatistics,
+    errors: ErrorMonitor,
+    last_frame: Option<Instant>,
+}
+
+impl Traffic {
+    fn new(stats: BusStatistics) -> Traffic {
+        Traffic {
+            entries: BTreeMap::new(),
+            stats,
+            errors: ErrorMonitor::new(),
+            last_frame: None,
+        }
+    }
+
+    /// Counts the frame in the bus load and, unless `paused`, shows it in the table.
+    fn record(&mut self, frame: AnyFrame, timestamp: u64, paused: bool) {
+        let now = Instant::now();
+        self.last_frame = Some(now);
+        match &frame {
+            AnyFrame::Can(can) => {
+                self.stats.record(can, timestamp, Direction::Rx);
+                self.errors.process(can, timestamp);
+            }
+            AnyFrame::Fd(fd) => {
+                self.stats.record_fd(fd, timestamp, Direction::Rx);
+                self.errors.process_fd(fd, timestamp);
+            }
+        }
+        if paused || frame.is_error_frame() || frame.is_status_frame() {
+            return;
+        }
+
+        let key = (frame.is_extended_frame(), frame.can_id());
+        let Some(entry) = self.entries.get_mut(&key) else {
+            let changed = vec![None; frame.data().len()];
+            self.entries.insert(
+                key,
+                Entry {
+                    frame,
+                    count: 1,
+                    timestamp,
+                    period: None,
+                    changed,
+                },
+            );
+            return;
+        };
+
+        let previous = entry.frame.data();
+        let changed = frame
+            .data()
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| match previous.get(i) {
+                Some(old) if old == byte => entry.changed.get(i).copied().flatten(),
+                _ => Some(now),
+            })
+            .collect();
+        entry.changed = changed;
+        entry.period = Some(timestamp.saturating_sub(entry.timestamp));
+        entry.timestamp = timestamp;
+        entry.count += 1;
+        entry.frame = frame;
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.stats.reset();
+    }
+
+    fn is_idle(&self) -> bool {
+        self.last_frame.is_none_or(|last| last.elapsed() > IDLE)
+    }
+}
+
+/* Keyboard */
+
+enum Prompt {
+    Filter(String),
+    Send(String),
+}
+
+enum Action {
+    None,
+    Quit,
+    Send(AnyFrame),
+}
+
+struct Monitor {
+    name: String,
+    traffic: Traffic,
+    rule: Rule,
+    filter_text: String,
+    send_text: String,
+    paused: bool,
+    prompt: Option<Prompt>,
+    message: Option<(String, bool)>,
+    state: Option<ErrorState>,
+    sent: u64,
+    table: TableState,
+}
+
+impl Monitor {
+    fn new(name: String, stats: BusStatistics, filter_text: String) -> Result<Monitor, String> {
+        Ok(Monitor {
+            name,
+            traffic: Traffic::new(stats),
+            rule: parse_filters(&filter_text)?,
+            filter_text,
+            send_text: String::new(),
+            paused: false,
+            prompt: None,
+            message: None,
+            state: None,
+            sent: 0,
+            table: TableState::default(),
+        })
+    }
+
+    fn info(&mut self, text: String) {
+        self.message = Some((text, false));
+    }
+
+    fn error(&mut self, text: String) {
+        self.message = Some((text, true));
+    }
+
+    fn key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> Action {
+        if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+            return Action::Quit;
+        }
+        match self.prompt.take() {
+            Some(prompt) => self.prompt_key(prompt, code),
+            None => self.command_key(code),
+        }
+    }
+
+    fn command_key(&mut self, code: KeyCode) -> Action {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('p') | KeyCode::Char(' ') => self.paused = !self.paused,
+            KeyCode::Char('f') => self.prompt = Some(Prompt::Filter(self.filter_text.clone())),
+            KeyCode::Char('s') => self.prompt = Some(Prompt::Send(self.send_text.clone())),
+            KeyCode::Char('c') => {
+                self.traffic.clear();
+                self.table.select(None);
+            }
+            KeyCode::Down => self.table.scroll_down_by(1),
+            KeyCode::Up => self.table.scroll_up_by(1),
+            KeyCode::PageDown => self.table.scroll_down_by(20),
+            KeyCode::PageUp => self.table.scroll_up_by(20),
+            KeyCode::Home => self.table.select_first(),
+            KeyCode::End => self.table.select_last(),
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn prompt_key(&mut self, prompt: Prompt, code: KeyCode) -> Action {
+        let (mut text, filter) = match prompt {
+            Prompt::Filter(text) => (text, true),
+            Prompt::Send(text) => (text, false),
+        };
+        match code {
+            KeyCode::Esc => return Action::None,
+            KeyCode::Enter if filter => {
+                match parse_filters(&text) {
+                    Ok(rule) => {
+                        self.rule = rule;
+                        self.filter_text = text.trim().to_string();
+                        self.message = None;
+                    }
+                    Err(err) => self.error(err),
+                }
+                return Action::None;
+            }
+            KeyCode::Enter => {
+                let text = text.trim().to_string();
+                let result = parse_frame(&text);
+                self.send_text = text;
+                return match result {
+                    Ok(frame) => Action::Send(frame),
+                    Err(err) => {
+                        self.error(err);
+                        Action::None
+                    }
+                };
+            }
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Char(c) => text.push(c),
+            _ => {}
+        }
+        self.prompt = Some(if filter {
+            Prompt::Filter(text)
+        } else {
+            Prompt::Send(text)
+        });
+        Action::None
+    }
+
+    fn sent(&mut self, result: Result<(), CanError>) {
+        match result {
+            Ok(()) => {
+                self.sent += 1;
+                self.info(format!("sent {}", self.send_text));
+            }
+            Err(err) => self.error(format!("sending {} failed: {err}", self.send_text)),
+        }
+    }
+
+    /* Drawing */
+
+    fn header(&self) -> Line<'_> {
+        let load = if self.traffic.is_idle() {
+            0.0
+        } else {
+            self.traffic.stats.bus_load()
+        };
+        let errors = &self.traffic.errors;
+        let state = self.state.unwrap_or(errors.state());
+        let state_style = match state {
+            ErrorState::Active => Style::new().fg(Color::Green),
+            ErrorState::Warning => Style::new().fg(Color::Yellow),
+            ErrorState::Passive | ErrorState::BusOff => Style::new().fg(Color::Red),
+        };
+
+        let mut spans = vec![
+            Span::styled(format!(" {} ", self.name), Modifier::BOLD),
+            Span::raw(format!(
+                " load {load:5.1} %  {:6.0} frames/s  rx {} tx {}  ",
+                self.traffic.stats.frames_per_second(),
+                self.traffic.stats.rx_frames(),
+                self.sent
+            )),
+            Span::styled(state.to_string(), state_style),
+            Span::raw(format!(
+                "  TEC {} REC {}  errors {}",
+                errors.tec(),
+                errors.rec(),
+                errors.error_frames()
+            )),
+        ];
+        if self.paused {
+            spans.push(Span::styled("  PAUSED", Style::new().fg(Color::Yellow)));
+        }
+        if !self.filter_text.is_empty() {
+            spans.push(Span::raw(format!("  filter {}", self.filter_text)));
+        }
+        Line::from(spans)
+    }
+
+    fn row<'a>(&self, entry: &'a Entry) -> Row<'a> {
+        let frame = &entry.frame;
+        let id = if frame.is_extended_frame() {
+            format!("{:08X}", frame.can_id())
+        } else {
+            format!("{:03X}", frame.can_id())
+        };
+        let mut flags = Vec::new();
+        if frame.is_fd_frame() {
+            flags.push("FD");
+        }
+        if frame.is_brs_frame() {
+            flags.push("BRS");
+        }
+        if frame.is_remote_frame() {
+            flags.push("RTR");
+        }
+        if frame.is_echo_frame() {
+            flags.push("TX");
+        }
+        let period = entry
+            .period
+            .map(|micros| format!("{:.1}", micros as f64 / 1_000.0))
+            .unwrap_or_default();
+
+        let highlight = Style::new().fg(Color::Black).bg(Color::Yellow);
+        let mut data = Vec::with_capacity(frame.data().len() * 2);
+        for (i, byte) in frame.data().iter().enumerate() {
+            if i > 0 {
+                data.push(Span::raw(" "));
+            }
+            let changed = entry.changed[i].is_some_and(|at| at.elapsed() < HIGHLIGHT);
+            let text = hex(&[*byte]);
+            data.push(if changed {
+                Span::styled(text, highlight)
+            } else {
+                Span::raw(text)
+            });
+        }
+
+        Row::new([
+            Cell::from(id),
+            Cell::from(flags.join(" ")),
+            Cell::from(frame.data().len().to_string()),
+            Cell::from(entry.count.to_string()),
+            Cell::from(period),
+            Cell::from(Line::from(data)),
+        ])
+    }
+
+    fn footer(&self) -> Line<'_> {
+        match &self.prompt {
+            Some(Prompt::Filter(text)) => Line::from(format!(
+                "filter (<id>:<mask> accepts, <id>~<mask> rejects): {text}_"
+            )),
+            Some(Prompt::Send(text)) => {
+                Line::from(format!("send (123#DEADBEEF, 123#R, 123##1AABB): {text}_"))
+            }
+            None => match &self.message {
+                Some((text, true)) => Line::styled(text.as_str(), Style::new().fg(Color::Red)),
+                Some((text, false)) => Line::from(text.as_str()),
+                None => Line::styled(
+                    "q quit  p pause  f filter  s send  c clear  \u{2191}\u{2193} scroll",
+                    Modifier::DIM,
+                ),
+            },
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, table, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(Paragraph::new(self.header()), header);
+
+        let rows: Vec<Row> = self
+            .traffic
+            .entries
+            .values()
+            .filter(|entry| self.rule.matches(&entry.frame))
+            .map(|entry| self.row(entry))
+            .collect();
+        let widths = [
+            Constraint::Length(9),
+            Constraint::Length(10),
+            Constraint::Length(4),
+            Constraint::Length(10),
+            Constraint::Length(11),
+            Constraint::Min(23),
+        ];
+        let titles = ["ID", "TYPE", "LEN", "COUNT", "PERIOD ms", "DATA"];
+        let widget = Table::new(rows, widths)
+            .header(Row::new(titles).style(Modifier::BOLD | Modifier::REVERSED))
+            .row_highlight_style(Modifier::REVERSED);
+        frame.render_stateful_widget(widget, table, &mut self.table);
+
+        frame.render_widget(Paragraph::new(self.footer()), footer);
+    }
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    channel: &Channel,
+    monitor: &mut Monitor,
+) -> Result<(), Box<dyn Error>> {
+    let mut next_draw = Instant::now();
+    loop {
+        for _ in 0..MAX_BATCH {
+            match channel.recv() {
+                Ok((frame, timestamp)) => monitor.traffic.record(frame, timestamp, monitor.paused),
+                Err(CanError::QrcvEmpty) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if Instant::now() >= next_draw {
+            monitor.state = channel.bus_status().ok();
+            terminal.draw(|frame| monitor.draw(frame))?;
+            next_draw = Instant::now() + REFRESH;
+        }
+
+        if !event::poll(KEY_POLL)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match monitor.key(key.code, key.modifiers) {
+            Action::Quit => return Ok(()),
+            Action::Send(frame) => monitor.sent(channel.send(&frame)),
+            Action::None => {}
+        }
+        next_draw = Instant::now();
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let channel = Channel::open(args.channel, &args.bitrate)?;
+    channel.allow_error_frames(true)?;
+
+    let mut stats = BusStatistics::from_baudrate(&args.bitrate.bitrate);
+    let mut name = format!("{} {}", channel.bus, args.bitrate.bitrate);
+    if let Some(data_bps) = args.bitrate.data_bitrate {
+        stats = stats.data_rate(data_bps);
+        name.push_str(&format!("/{}K", data_bps / 1_000));
+    }
+    let mut monitor = Monitor::new(name, stats, args.filters.join(" "))?;
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &channel, &mut monitor);
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use peak_can::socket::{Baudrate, CanFrame, MessageType};
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+
+    fn frame(id: u32, data: &[u8]) -> AnyFrame {
+        AnyFrame::Can(CanFrame::new(id, MessageType::Standard, data).unwrap())
+    }
+
+    fn monitor() -> Monitor {
+        let stats = BusStatistics::from_baudrate(&Baudrate::Baud500K);
+        Monitor::new(String::from("USB1"), stats, String::from("123")).unwrap()
+    }
+
+    #[test]
+    fn table_entries() {
+        let mut traffic = Traffic::new(BusStatistics::from_baudrate(&Baudrate::Baud500K));
+        traffic.record(frame(0x123, &[1, 2, 3]), 1_000, false);
+        traffic.record(frame(0x123, &[1, 9, 3, 4]), 11_000, false);
+        traffic.record(frame(0x200, &[0]), 12_000, true);
+
+        let entry = &traffic.entries[&(false, 0x123)];
+        assert_eq!(entry.count, 2);
+        assert_eq!(entry.period, Some(10_000));
+        let changed: Vec<bool> = entry.changed.iter().map(Option::is_some).collect();
+        assert_eq!(changed, vec![false, true, false, true]);
+
+        // Paused frames count in the statistics only
+        assert_eq!(traffic.entries.len(), 1);
+        assert_eq!(traffic.stats.rx_frames(), 3);
+        assert!(!traffic.is_idle());
+    }
+
+    #[test]
+    fn keys() {
+        let mut monitor = monitor();
+        assert!(monitor.rule.matches(&frame(0x123, &[])));
+        assert!(!monitor.rule.matches(&frame(0x124, &[])));
+
+        monitor.key(KeyCode::Char(' '), KeyModifiers::NONE);
+        assert!(monitor.paused);
+
+        monitor.key(KeyCode::Char('f'), KeyModifiers::NONE);
+        monitor.key(KeyCode::Backspace, KeyModifiers::NONE);
+        monitor.key(KeyCode::Char('4'), KeyModifiers::NONE);
+        monitor.key(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(monitor.filter_text, "124");
+        assert!(monitor.rule.matches(&frame(0x124, &[])));
+
+        monitor.key(KeyCode::Char('f'), KeyModifiers::NONE);
+        monitor.key(KeyCode::Char('G'), KeyModifiers::NONE);
+        monitor.key(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(monitor.filter_text, "124");
+        assert!(matches!(monitor.message, Some((_, true))));
+
+        monitor.key(KeyCode::Char('s'), KeyModifiers::NONE);
+        for c in "7DF#0201".chars() {
+            monitor.key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        let Action::Send(sent) = monitor.key(KeyCode::Enter, KeyModifiers::NONE) else {
+            panic!("enter sends the frame");
+        };
+        assert_eq!((sent.can_id(), sent.data()), (0x7DF, &[2, 1][..]));
+        monitor.sent(Ok(()));
+        assert_eq!(monitor.sent, 1);
+
+        assert!(matches!(
+            monitor.key(KeyCode::Char('q'), KeyModifiers::NONE),
+            Action::Quit
+        ));
+    }
+
+    #[test]
+    fn screen() {
+        let mut monitor = monitor();
+        monitor
+            .traffic
+            .record(frame(0x123, &[0xDE, 0xAD]), 0, false);
+        monitor
+            .traffic
+            .record(frame(0x124, &[0xBE, 0xEF]), 0, false);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 5)).unwrap();
+        terminal.draw(|frame| monitor.draw(frame)).unwrap();
+        let screen: Vec<String> = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(100)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect())
+            .collect();
+        assert!(screen[0].starts_with(" USB1 "));
+        assert!(screen[0].contains("error active"));
+        assert!(screen[1].starts_with("ID"));
+        assert!(screen[2].starts_with("123") && screen[2].contains("DE AD"));
+        // 124 is filtered out
+        assert!(screen[3].trim().is_empty());
+        assert!(screen[4].starts_with("q quit"));
+    }
+}
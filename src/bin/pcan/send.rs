@@ -0,0 +1,175 @@
+//! `pcan send`: sends frames once or cyclically, like `cansend` and `cangen -g`.
+
+use peak_can::cli::{BitrateArgs, Bus, Channel};
+use peak_can::filter::AnyFrame;
+use peak_can::socket::{CanFdFrame, CanFrame, MessageType};
+
+use std::error::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Payload lengths a CAN FD frame can carry.
+const FD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Channel such as USB1, PCI2, LAN1, a handle such as 0x51 or USB@7 by device ID
+    channel: Bus,
+    /// Frames in `cansend` notation: `123#DEADBEEF` (classic), `123#R` or `123#R4` (remote),
+    /// `123##1DEADBEEF` (CAN FD, the digit after `##` is the flags nibble, 1 for bit rate
+    /// switch); identifiers of more than three hex digits are extended
+    #[arg(required = true, value_parser = parse_frame)]
+    frames: Vec<AnyFrame>,
+    #[command(flatten)]
+    bitrate: BitrateArgs,
+    /// Resend the frames every this many milliseconds until interrupted
+    #[arg(short, long, value_parser = parse_millis)]
+    interval: Option<Duration>,
+    /// Number of cycles when sending cyclically
+    #[arg(short = 'n', long, requires = "interval")]
+    count: Option<u64>,
+}
+
+fn parse_millis(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|millis| *millis > 0.0 && millis.is_finite())
+        .map(|millis| Duration::from_secs_f64(millis / 1_000.0))
+        .ok_or_else(|| format!("invalid interval `{s}`"))
+}
+
+fn parse_payload(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s.chars().filter(|c| *c != '.').collect();
+    if !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+        return Err(format!("invalid payload `{s}`"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid payload `{s}`"))
+}
+
+pub fn parse_frame(s: &str) -> Result<AnyFrame, String> {
+    let (id, body) = s
+        .split_once('#')
+        .ok_or_else(|| format!("missing `#` in `{s}`"))?;
+    if id.is_empty() || id.len() > 8 {
+        return Err(format!("invalid identifier `{id}`"));
+    }
+    let can_id = u32::from_str_radix(id, 16).map_err(|_| format!("invalid identifier `{id}`"))?;
+    let msg_type = if id.len() > 3 {
+        MessageType::Extended
+    } else {
+        MessageType::Standard
+    };
+    if msg_type == MessageType::Standard && can_id > 0x7FF {
+        return Err(format!(
+            "identifier `{id}` exceeds 11 bits, write it with 8 digits"
+        ));
+    }
+
+    if let Some(fd) = body.strip_prefix('#') {
+        let mut chars = fd.chars();
+        let flags = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or_else(|| format!("missing flags after `##` in `{s}`"))?;
+        let data = parse_payload(chars.as_str())?;
+        if !FD_LENGTHS.contains(&data.len()) {
+            return Err(format!(
+                "{} bytes is not a CAN FD payload length",
+                data.len()
+            ));
+        }
+        let brs = flags & 0x1 != 0;
+        return CanFdFrame::new(can_id, msg_type, &data, true, brs)
+            .map(AnyFrame::Fd)
+            .map_err(|err| format!("{err:?}"));
+    }
+
+    if let Some(len) = body.strip_prefix(['R', 'r']) {
+        let dlc = if len.is_empty() {
+            0
+        } else {
+            len.parse::<u8>()
+                .map_err(|_| format!("invalid remote length `{len}`"))?
+        };
+        return CanFrame::new_remote(can_id, msg_type, dlc)
+            .map(AnyFrame::Can)
+            .map_err(|err| format!("{err:?}"));
+    }
+
+    let data = parse_payload(body)?;
+    CanFrame::new(can_id, msg_type, &data)
+        .map(AnyFrame::Can)
+        .map_err(|err| format!("{err:?}"))
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let channel = Channel::open(args.channel, &args.bitrate)?;
+    if !channel.is_fd() && args.frames.iter().any(AnyFrame::is_fd_frame) {
+        return Err("CAN FD frames need a data bit rate (--data-bitrate)".into());
+    }
+
+    let Some(interval) = args.interval else {
+        for frame in &args.frames {
+            channel.send(frame)?;
+        }
+        return Ok(());
+    };
+
+    let mut deadline = Instant::now();
+    let mut cycle = 0;
+    while args.count != Some(cycle) {
+        for frame in &args.frames {
+            channel.send(frame)?;
+        }
+        cycle += 1;
+
+        deadline += interval;
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else {
+            // Fell behind, e.g. after a full transmit queue, do not burst to catch up.
+            deadline = now;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cansend_notation() {
+        let frame = parse_frame("123#DE.AD.BE.EF").unwrap();
+        assert!(!frame.is_extended_frame() && !frame.is_fd_frame());
+        assert_eq!(
+            (frame.can_id(), frame.data()),
+            (0x123, &[0xDE, 0xAD, 0xBE, 0xEF][..])
+        );
+
+        let frame = parse_frame("18DAF110#0201").unwrap();
+        assert!(frame.is_extended_frame());
+        assert_eq!(frame.can_id(), 0x18DAF110);
+
+        let AnyFrame::Can(remote) = parse_frame("7DF#R3").unwrap() else {
+            panic!("remote frames are classic");
+        };
+        assert!(remote.is_remote_frame());
+        assert_eq!(remote.dlc(), 3);
+
+        let frame = parse_frame("123##1000102030405060708090A0B").unwrap();
+        assert!(frame.is_fd_frame() && frame.is_brs_frame());
+        assert_eq!(frame.data().len(), 12);
+
+        assert!(parse_frame("123##100010203040506070809").is_err());
+        assert!(parse_frame("123#000102030405060708").is_err());
+        assert!(parse_frame("800#00").is_err());
+        assert!(parse_frame("123").is_err());
+        assert!(parse_frame("123#ABC").is_err());
+    }
+}
@@ -0,0 +1,544 @@
+//! Vector binary logging format (`.blf`), enabled with the `blf` feature.
+//!
+//! A BLF file starts with a `LOGG` header holding the measurement start time, followed by
+//! `LOBJ` log containers whose zlib compressed (or plain) payload is a stream of objects.
+//! [parse] reads CAN, CAN FD and error objects (`CAN_MESSAGE`, `CAN_MESSAGE2`,
+//! `CAN_FD_MESSAGE`, `CAN_FD_MESSAGE_64` and `CAN_ERROR_EXT`) and skips every other object
+//! type. Timestamps are the start time of the file (UNIX epoch) plus the object offsets,
+//! channels keep their BLF numbers, which start at 1.
+//!
+//! [write] stores events as `CAN_MESSAGE`, `CAN_FD_MESSAGE` and `CAN_ERROR_EXT` objects with
+//! nanosecond offsets in compressed containers, the start time being the timestamp of the
+//! first event.
+
+use crate::logfile::{DateTime, LogFileError};
+use crate::mdf::{BusEvent, BusEventKind, error_type};
+use crate::socket::CanFdFrame;
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use std::io::{Read, Write};
+
+const FILE_SIGNATURE: &[u8; 4] = b"LOGG";
+const OBJECT_SIGNATURE: &[u8; 4] = b"LOBJ";
+const FILE_HEADER_SIZE: usize = 144;
+const OBJECT_HEADER_BASE_SIZE: usize = 16;
+const OBJECT_HEADER_V1_SIZE: usize = 16;
+const CONTAINER_HEADER_SIZE: usize = 16;
+/// Uncompressed payload of one log container.
+const CONTAINER_SIZE: usize = 128 * 1024;
+
+/* Object types */
+
+const CAN_MESSAGE: u32 = 1;
+const LOG_CONTAINER: u32 = 10;
+const CAN_ERROR_EXT: u32 = 73;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+
+const TIME_TEN_MICS: u32 = 1;
+const TIME_ONE_NANS: u32 = 2;
+
+/* Flags */
+
+const DIR_TX: u8 = 0x01;
+const REMOTE: u8 = 0x80;
+const EXTENDED_ID: u32 = 0x8000_0000;
+
+const FD_EDL: u8 = 0x01;
+const FD_BRS: u8 = 0x02;
+const FD_ESI: u8 = 0x04;
+
+const FD64_REMOTE: u32 = 0x0010;
+const FD64_EDL: u32 = 0x1000;
+const FD64_BRS: u32 = 0x2000;
+const FD64_ESI: u32 = 0x4000;
+
+fn corrupt(message: impl Into<String>) -> LogFileError {
+    LogFileError::Corrupt(message.into())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, LogFileError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| corrupt("truncated object"))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, LogFileError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| corrupt("truncated object"))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, LogFileError> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| corrupt("truncated object"))
+}
+
+fn bytes_at(data: &[u8], offset: usize, len: usize) -> Result<&[u8], LogFileError> {
+    data.get(offset..offset + len)
+        .ok_or_else(|| corrupt("truncated object"))
+}
+
+/* SYSTEMTIME */
+
+fn read_system_time(data: &[u8]) -> Result<Option<u64>, LogFileError> {
+    let field = |i: usize| u16_at(data, i * 2);
+    let (year, month, day) = (field(0)?, field(1)?, field(3)?);
+    if year == 0 || month == 0 || day == 0 {
+        return Ok(None);
+    }
+    let time = DateTime {
+        year: year as i64,
+        month: month as u32,
+        day: day as u32,
+        weekday: field(2)? as u32,
+        hour: field(4)? as u32,
+        minute: field(5)? as u32,
+        second: field(6)? as u32,
+        micros: field(7)? as u32 * 1_000,
+    };
+    Ok(time.to_micros())
+}
+
+fn write_system_time(out: &mut Vec<u8>, micros: u64) {
+    let time = DateTime::from_micros(micros);
+    for field in [
+        time.year as u16,
+        time.month as u16,
+        time.weekday as u16,
+        time.day as u16,
+        time.hour as u16,
+        time.minute as u16,
+        time.second as u16,
+        (time.micros / 1_000) as u16,
+    ] {
+        out.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+/* Reading */
+
+/// Reads all CAN events of a BLF file.
+pub fn parse<R: Read>(mut reader: R) -> Result<Vec<BusEvent>, LogFileError> {
+    let mut file = Vec::new();
+    reader.read_to_end(&mut file)?;
+
+    if file.get(..4) != Some(FILE_SIGNATURE.as_slice()) {
+        return Err(corrupt("missing LOGG signature"));
+    }
+    let header_size = u32_at(&file, 4)? as usize;
+    let start_us = read_system_time(bytes_at(&file, 40, 16)?)?.unwrap_or(0);
+
+    // Objects may span containers, so the payloads are joined before parsing
+    let mut stream = Vec::new();
+    let mut pos = header_size;
+    while pos + OBJECT_HEADER_BASE_SIZE <= file.len() {
+        if &file[pos..pos + 4] != OBJECT_SIGNATURE {
+            return Err(corrupt(format!("missing LOBJ signature at offset {pos}")));
+        }
+        let object_size = u32_at(&file, pos + 8)? as usize;
+        let object_type = u32_at(&file, pos + 12)?;
+        if object_size < OBJECT_HEADER_BASE_SIZE {
+            return Err(corrupt(format!("invalid object size at offset {pos}")));
+        }
+        let object = bytes_at(&file, pos, object_size)?;
+
+        if object_type == LOG_CONTAINER {
+            let method = u16_at(object, OBJECT_HEADER_BASE_SIZE)?;
+            let uncompressed_size = u32_at(object, OBJECT_HEADER_BASE_SIZE + 8)? as usize;
+            let payload = object
+                .get(OBJECT_HEADER_BASE_SIZE + CONTAINER_HEADER_SIZE..)
+                .ok_or_else(|| corrupt("truncated log container"))?;
+            match method {
+                NO_COMPRESSION => stream.extend_from_slice(payload),
+                ZLIB_DEFLATE => {
+                    let mut decompressed = Vec::with_capacity(uncompressed_size);
+                    ZlibDecoder::new(payload)
+                        .read_to_end(&mut decompressed)
+                        .map_err(|e| corrupt(format!("invalid zlib data: {e}")))?;
+                    stream.extend_from_slice(&decompressed);
+                }
+                other => return Err(corrupt(format!("unsupported compression {other}"))),
+            }
+        } else {
+            stream.extend_from_slice(object);
+        }
+        pos += object_size + object_size % 4;
+    }
+
+    parse_objects(&stream, start_us)
+}
+
+fn parse_objects(stream: &[u8], start_us: u64) -> Result<Vec<BusEvent>, LogFileError> {
+    let mut events = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        // Skip the padding between objects
+        let window = &stream[pos..stream.len().min(pos + OBJECT_HEADER_BASE_SIZE / 2 + 4)];
+        let Some(skip) = window.windows(4).position(|w| w == OBJECT_SIGNATURE) else {
+            if stream.len() - pos >= OBJECT_HEADER_BASE_SIZE {
+                return Err(corrupt("missing LOBJ signature in log container"));
+            }
+            break;
+        };
+        pos += skip;
+
+        let header_size = u16_at(stream, pos + 4)? as usize;
+        let object_size = u32_at(stream, pos + 8)? as usize;
+        let object_type = u32_at(stream, pos + 12)?;
+        if object_size < header_size || header_size < OBJECT_HEADER_BASE_SIZE + 16 {
+            return Err(corrupt(format!("invalid object header at offset {pos}")));
+        }
+        let object = bytes_at(stream, pos, object_size)?;
+
+        let flags = u32_at(object, OBJECT_HEADER_BASE_SIZE)?;
+        let offset = u64_at(object, OBJECT_HEADER_BASE_SIZE + 8)?;
+        let offset_us = match flags {
+            TIME_TEN_MICS => offset * 10,
+            TIME_ONE_NANS => offset / 1_000,
+            _ => offset / 1_000,
+        };
+        let payload = &object[header_size..];
+        if let Some(event) = parse_object(object_type, payload, start_us + offset_us)? {
+            events.push(event);
+        }
+        pos += object_size;
+    }
+
+    Ok(events)
+}
+
+fn new_event(timestamp_us: u64, channel: u16, kind: BusEventKind) -> BusEvent {
+    BusEvent {
+        timestamp_us,
+        bus_channel: channel as u8,
+        kind,
+        can_id: 0,
+        extended: false,
+        dlc: 0,
+        data: Vec::new(),
+        tx: false,
+        edl: false,
+        brs: false,
+        esi: false,
+        error_type: error_type::UNKNOWN,
+    }
+}
+
+fn set_id(event: &mut BusEvent, raw_id: u32) {
+    event.extended = raw_id & EXTENDED_ID != 0;
+    event.can_id = raw_id & !EXTENDED_ID;
+}
+
+fn parse_object(
+    object_type: u32,
+    payload: &[u8],
+    timestamp_us: u64,
+) -> Result<Option<BusEvent>, LogFileError> {
+    let event = match object_type {
+        CAN_MESSAGE | CAN_MESSAGE2 => {
+            let flags = payload.get(2).copied().unwrap_or_default();
+            let dlc = payload.get(3).copied().unwrap_or_default();
+            let kind = if flags & REMOTE != 0 {
+                BusEventKind::RemoteFrame
+            } else {
+                BusEventKind::DataFrame
+            };
+            let mut event = new_event(timestamp_us, u16_at(payload, 0)?, kind);
+            set_id(&mut event, u32_at(payload, 4)?);
+            event.tx = flags & DIR_TX != 0;
+            event.dlc = dlc;
+            if kind == BusEventKind::DataFrame {
+                event.data = bytes_at(payload, 8, dlc.min(8) as usize)?.to_vec();
+            }
+            event
+        }
+        CAN_FD_MESSAGE => {
+            let flags = payload.get(2).copied().unwrap_or_default();
+            let fd_flags = payload.get(13).copied().unwrap_or_default();
+            let length = payload.get(14).copied().unwrap_or_default().min(64);
+            let kind = if flags & REMOTE != 0 {
+                BusEventKind::RemoteFrame
+            } else {
+                BusEventKind::DataFrame
+            };
+            let mut event = new_event(timestamp_us, u16_at(payload, 0)?, kind);
+            set_id(&mut event, u32_at(payload, 4)?);
+            event.tx = flags & DIR_TX != 0;
+            event.dlc = payload.get(3).copied().unwrap_or_default();
+            event.edl = fd_flags & FD_EDL != 0;
+            event.brs = fd_flags & FD_BRS != 0;
+            event.esi = fd_flags & FD_ESI != 0;
+            if kind == BusEventKind::DataFrame {
+                event.data = bytes_at(payload, 20, length as usize)?.to_vec();
+            }
+            event
+        }
+        CAN_FD_MESSAGE_64 => {
+            let flags = u32_at(payload, 12)?;
+            let length = payload.get(2).copied().unwrap_or_default().min(64);
+            let kind = if flags & FD64_REMOTE != 0 {
+                BusEventKind::RemoteFrame
+            } else {
+                BusEventKind::DataFrame
+            };
+            let mut event = new_event(timestamp_us, payload[0] as u16, kind);
+            set_id(&mut event, u32_at(payload, 4)?);
+            event.tx = payload.get(34).copied().unwrap_or_default() != 0;
+            event.dlc = payload.get(1).copied().unwrap_or_default();
+            event.edl = flags & FD64_EDL != 0;
+            event.brs = flags & FD64_BRS != 0;
+            event.esi = flags & FD64_ESI != 0;
+            if kind == BusEventKind::DataFrame {
+                event.data = bytes_at(payload, 40, length as usize)?.to_vec();
+            }
+            event
+        }
+        CAN_ERROR_EXT => {
+            let mut event = new_event(timestamp_us, u16_at(payload, 0)?, BusEventKind::ErrorFrame);
+            let dlc = payload.get(10).copied().unwrap_or_default();
+            set_id(&mut event, u32_at(payload, 16)?);
+            event.dlc = dlc;
+            event.data = bytes_at(payload, 24, dlc.min(8) as usize)?.to_vec();
+            event
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/* Writing */
+
+fn raw_id(event: &BusEvent) -> u32 {
+    if event.extended {
+        event.can_id | EXTENDED_ID
+    } else {
+        event.can_id
+    }
+}
+
+fn object_payload(event: &BusEvent) -> (u32, Vec<u8>) {
+    let channel = (event.bus_channel as u16).to_le_bytes();
+    let mut data = [0u8; 64];
+    let length = event.data.len().min(64);
+    data[..length].copy_from_slice(&event.data[..length]);
+
+    let mut payload = Vec::with_capacity(84);
+    payload.extend_from_slice(&channel);
+    match event.kind {
+        BusEventKind::ErrorFrame => {
+            payload.extend_from_slice(&0u16.to_le_bytes());
+            payload.extend_from_slice(&0u32.to_le_bytes());
+            payload.extend_from_slice(&[0, 0, length.min(8) as u8, 0]);
+            payload.extend_from_slice(&0u32.to_le_bytes());
+            payload.extend_from_slice(&raw_id(event).to_le_bytes());
+            payload.extend_from_slice(&[0; 4]);
+            payload.extend_from_slice(&data[..8]);
+            (CAN_ERROR_EXT, payload)
+        }
+        _ => {
+            let mut flags = if event.tx { DIR_TX } else { 0 };
+            if event.kind == BusEventKind::RemoteFrame {
+                flags |= REMOTE;
+            }
+
+            if event.edl {
+                let mut fd_flags = FD_EDL;
+                if event.brs {
+                    fd_flags |= FD_BRS;
+                }
+                if event.esi {
+                    fd_flags |= FD_ESI;
+                }
+                payload.extend_from_slice(&[flags, CanFdFrame::calc_dlc(length)]);
+                payload.extend_from_slice(&raw_id(event).to_le_bytes());
+                payload.extend_from_slice(&0u32.to_le_bytes());
+                payload.extend_from_slice(&[0, fd_flags, length as u8, 0, 0, 0, 0, 0]);
+                payload.extend_from_slice(&data);
+                (CAN_FD_MESSAGE, payload)
+            } else {
+                payload.extend_from_slice(&[flags, event.dlc]);
+                payload.extend_from_slice(&raw_id(event).to_le_bytes());
+                payload.extend_from_slice(&data[..8]);
+                (CAN_MESSAGE, payload)
+            }
+        }
+    }
+}
+
+fn write_object_header(out: &mut Vec<u8>, header_size: usize, object_size: usize, kind: u32) {
+    out.extend_from_slice(OBJECT_SIGNATURE);
+    out.extend_from_slice(&(header_size as u16).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&(object_size as u32).to_le_bytes());
+    out.extend_from_slice(&kind.to_le_bytes());
+}
+
+/// Writes `events`, which should be sorted by timestamp, as a BLF file.
+pub fn write<W: Write>(events: &[BusEvent], mut writer: W) -> Result<(), LogFileError> {
+    // SYSTEMTIME has millisecond resolution, the remainder goes into the object offsets
+    let start_us = events.first().map_or(0, |e| e.timestamp_us / 1_000 * 1_000);
+    let stop_us = events.last().map_or(0, |e| e.timestamp_us);
+
+    let mut stream = Vec::new();
+    for event in events {
+        let (kind, payload) = object_payload(event);
+        let header_size = OBJECT_HEADER_BASE_SIZE + OBJECT_HEADER_V1_SIZE;
+        let object_size = header_size + payload.len();
+        let offset_ns = event.timestamp_us.saturating_sub(start_us) * 1_000;
+
+        write_object_header(&mut stream, header_size, object_size, kind);
+        stream.extend_from_slice(&TIME_ONE_NANS.to_le_bytes());
+        stream.extend_from_slice(&[0; 4]);
+        stream.extend_from_slice(&offset_ns.to_le_bytes());
+        stream.extend_from_slice(&payload);
+        stream.resize(stream.len() + object_size % 4, 0);
+    }
+
+    let mut body = Vec::new();
+    for chunk in stream.chunks(CONTAINER_SIZE) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(chunk)?;
+        let compressed = encoder.finish()?;
+
+        let object_size = OBJECT_HEADER_BASE_SIZE + CONTAINER_HEADER_SIZE + compressed.len();
+        write_object_header(
+            &mut body,
+            OBJECT_HEADER_BASE_SIZE,
+            object_size,
+            LOG_CONTAINER,
+        );
+        body.extend_from_slice(&ZLIB_DEFLATE.to_le_bytes());
+        body.extend_from_slice(&[0; 6]);
+        body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(&compressed);
+        body.resize(body.len() + object_size % 4, 0);
+    }
+
+    let containers = stream.len().div_ceil(CONTAINER_SIZE);
+    let uncompressed_size = FILE_HEADER_SIZE
+        + stream.len()
+        + containers * (OBJECT_HEADER_BASE_SIZE + CONTAINER_HEADER_SIZE);
+
+    let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+    header.extend_from_slice(FILE_SIGNATURE);
+    header.extend_from_slice(&(FILE_HEADER_SIZE as u32).to_le_bytes());
+    // Application id and version, then BL API version 2.6.8.1
+    header.extend_from_slice(&[0, 0, 0, 0, 2, 6, 8, 1]);
+    header.extend_from_slice(&((FILE_HEADER_SIZE + body.len()) as u64).to_le_bytes());
+    header.extend_from_slice(&(uncompressed_size as u64).to_le_bytes());
+    header.extend_from_slice(&(events.len() as u32).to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    write_system_time(&mut header, start_us);
+    write_system_time(&mut header, stop_us);
+    header.resize(FILE_HEADER_SIZE, 0);
+
+    writer.write_all(&header)?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp_us: u64, kind: BusEventKind, can_id: u32, data: &[u8]) -> BusEvent {
+        let mut event = new_event(timestamp_us, 1, kind);
+        event.can_id = can_id;
+        event.dlc = data.len() as u8;
+        event.data = data.to_vec();
+        event
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut events = vec![
+            event(
+                1_710_237_600_123_456,
+                BusEventKind::DataFrame,
+                0x123,
+                &[1, 2, 3],
+            ),
+            event(
+                1_710_237_600_223_457,
+                BusEventKind::RemoteFrame,
+                0x1ABCDEF,
+                &[],
+            ),
+            event(
+                1_710_237_601_000_000,
+                BusEventKind::DataFrame,
+                0x7FF,
+                &[0xAA; 12],
+            ),
+            event(
+                1_710_237_602_500_001,
+                BusEventKind::ErrorFrame,
+                0x4,
+                &[0, 8, 0, 0],
+            ),
+        ];
+        events[1].extended = true;
+        events[1].dlc = 4;
+        events[1].tx = true;
+        events[2].edl = true;
+        events[2].brs = true;
+        events[2].dlc = 9;
+        events[2].bus_channel = 2;
+
+        let mut file = Vec::new();
+        write(&events, &mut file).unwrap();
+        assert_eq!(&file[..4], b"LOGG");
+        assert_eq!(u64_at(&file, 16).unwrap() as usize, file.len());
+
+        assert_eq!(parse(file.as_slice()).unwrap(), events);
+    }
+
+    #[test]
+    fn uncompressed_containers() {
+        // Object with a 10 µs timestamp in a plain container, start time 2024-03-12 10:00:00
+        let mut object = Vec::new();
+        write_object_header(&mut object, 32, 48, CAN_MESSAGE);
+        object.extend_from_slice(&TIME_TEN_MICS.to_le_bytes());
+        object.extend_from_slice(&[0; 4]);
+        object.extend_from_slice(&150u64.to_le_bytes());
+        object.extend_from_slice(&[
+            3, 0, DIR_TX, 2, 0x00, 0x01, 0, 0, 0xDE, 0xAD, 0, 0, 0, 0, 0, 0,
+        ]);
+
+        let mut file = vec![0u8; FILE_HEADER_SIZE];
+        file[..4].copy_from_slice(FILE_SIGNATURE);
+        file[4..8].copy_from_slice(&(FILE_HEADER_SIZE as u32).to_le_bytes());
+        let mut start = Vec::new();
+        write_system_time(&mut start, 1_710_237_600_000_000);
+        file[40..56].copy_from_slice(&start);
+
+        write_object_header(&mut file, 16, 32 + object.len(), LOG_CONTAINER);
+        file.extend_from_slice(&NO_COMPRESSION.to_le_bytes());
+        file.extend_from_slice(&[0; 6]);
+        file.extend_from_slice(&(object.len() as u32).to_le_bytes());
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(&object);
+
+        let events = parse(file.as_slice()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].timestamp_us, 1_710_237_600_001_500);
+        assert_eq!(events[0].bus_channel, 3);
+        assert_eq!(events[0].can_id, 0x100);
+        assert!(events[0].tx);
+        assert_eq!(events[0].data, vec![0xDE, 0xAD]);
+
+        assert!(matches!(parse(&b"LOGX"[..]), Err(LogFileError::Corrupt(_))));
+    }
+}
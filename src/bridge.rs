@@ -0,0 +1,807 @@
+//! CAN over UDP and TCP.
+//!
+//! A [Bridge] tunnels the frames of a local socket to a remote peer and sends the frames
+//! of the peer on the socket. On the remote side a [NetLink] implements the socket traits
+//! itself, so protocol layers and tools work over the network as with a local adapter.
+//!
+//! The wire format is the [cannelloni](https://github.com/mguentner/cannelloni) protocol:
+//! UDP packets with a version 2 header and frames with SocketCAN identifier flags, or a
+//! TCP stream of the same frames after the `CANNELLONIv1` handshake. Either end can be a
+//! cannelloni instance on a SocketCAN host. [Framing::Simple] is a TCP alternative with a
+//! length prefix per frame. Cannelloni carries no timestamps, received frames are
+//! timestamped on arrival. Error, status and echo frames of the local socket are not
+//! tunneled.
+//!
+//! # Examples
+//!
+//! Lab side, forwarding a PCAN-USB channel to a developer machine:
+//!
+//! ```no_run
+//! # use peak_can::bridge::{Bridge, NetLink};
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::gateway::Port;
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use std::sync::atomic::AtomicBool;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let link = NetLink::udp("0.0.0.0:20000", "192.168.1.20:20000")?;
+//! let mut bridge = Bridge::new(Port::can(&socket), link);
+//! bridge.run(&AtomicBool::new(false))?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Developer side, using the link as a socket:
+//!
+//! ```no_run
+//! # use peak_can::bridge::NetLink;
+//! # use peak_can::socket::{CanFrame, MessageType, RecvCan, SendCan};
+//! let link = NetLink::udp("0.0.0.0:20000", "lab-rig:20000")?;
+//! link.send(CanFrame::new(0x7DF, MessageType::Standard, &[0x02, 0x01, 0x00]).unwrap())?;
+//! if let Ok((frame, timestamp)) = link.recv() {
+//!     println!("{:?} {:03X} {:02X?}", timestamp, frame.can_id(), frame.data());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::filter::AnyFrame;
+use crate::gateway::Port;
+use crate::socket::{
+    CanFdFrame, CanFrame, MessageType, RecvCan, RecvCanFd, SendCan, SendCanFd, Timestamp,
+};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const CANNELLONI_VERSION: u8 = 2;
+/// Greeting both ends of a cannelloni TCP connection send first.
+pub const CANNELLONI_TCP_HANDSHAKE: &[u8] = b"CANNELLONIv1";
+/// Largest UDP packet sent, fits an Ethernet frame.
+pub const MAX_UDP_PACKET: usize = 1472;
+
+const OP_DATA: u8 = 0;
+const PACKET_HEADER_LEN: usize = 5;
+
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+const CAN_SFF_MASK: u32 = 0x7FF;
+const CANFD_FRAME: u8 = 0x80;
+const CANFD_BRS: u8 = 0x01;
+const CANFD_ESI: u8 = 0x02;
+
+const SIMPLE_EXTENDED: u8 = 0x01;
+const SIMPLE_REMOTE: u8 = 0x02;
+const SIMPLE_FD: u8 = 0x04;
+const SIMPLE_BRS: u8 = 0x08;
+const SIMPLE_ERROR: u8 = 0x10;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const IDLE_SLEEP: Duration = Duration::from_micros(200);
+
+#[derive(Debug)]
+pub enum BridgeError {
+    Io(io::Error),
+    Can(CanError),
+    /// The peer sent data that is not a valid packet or frame.
+    Protocol(&'static str),
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BridgeError::Io(err) => write!(f, "network error: {err}"),
+            BridgeError::Can(err) => write!(f, "CAN error: {err}"),
+            BridgeError::Protocol(reason) => write!(f, "protocol error: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+impl From<io::Error> for BridgeError {
+    fn from(value: io::Error) -> Self {
+        BridgeError::Io(value)
+    }
+}
+
+impl From<CanError> for BridgeError {
+    fn from(value: CanError) -> Self {
+        BridgeError::Can(value)
+    }
+}
+
+/// Frame format of a TCP connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// Cannelloni frames after the `CANNELLONIv1` handshake.
+    #[default]
+    Cannelloni,
+    /// Frames prefixed with their length: `u16` length, flags, `u32` identifier and
+    /// payload, big endian. Remote frames carry their DLC as the only payload byte.
+    Simple,
+}
+
+/* Cannelloni codec */
+
+fn message_type(extended: bool) -> MessageType {
+    if extended {
+        MessageType::Extended
+    } else {
+        MessageType::Standard
+    }
+}
+
+fn remote_dlc(frame: &AnyFrame) -> u8 {
+    match frame {
+        AnyFrame::Can(frame) => frame.dlc(),
+        AnyFrame::Fd(frame) => frame.dlc(),
+    }
+}
+
+/// Appends a frame in cannelloni format.
+pub fn encode_frame(frame: &AnyFrame, out: &mut Vec<u8>) {
+    let mut id = frame.can_id();
+    if frame.is_extended_frame() {
+        id |= CAN_EFF_FLAG;
+    }
+    if frame.is_remote_frame() {
+        id |= CAN_RTR_FLAG;
+    }
+    if frame.is_error_frame() {
+        id |= CAN_ERR_FLAG;
+    }
+    out.extend_from_slice(&id.to_be_bytes());
+    match frame {
+        AnyFrame::Fd(fd) if fd.is_fd_frame() => {
+            let mut flags = 0;
+            if fd.is_brs_frame() {
+                flags |= CANFD_BRS;
+            }
+            if fd.is_esi_frame() {
+                flags |= CANFD_ESI;
+            }
+            out.push(frame.data().len() as u8 | CANFD_FRAME);
+            out.push(flags);
+        }
+        // Remote frames carry the requested length, data() still returns that many bytes
+        _ if frame.is_remote_frame() => {
+            out.push(remote_dlc(frame));
+            return;
+        }
+        _ => out.push(frame.data().len() as u8),
+    }
+    out.extend_from_slice(frame.data());
+}
+
+/// Decodes one cannelloni frame, returns the frame and the bytes it took or `None` if
+/// `buffer` holds only part of it.
+pub fn decode_frame(buffer: &[u8]) -> Result<Option<(AnyFrame, usize)>, BridgeError> {
+    let Some(header) = buffer.first_chunk::<5>() else {
+        return Ok(None);
+    };
+    let raw_id = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let fd = header[4] & CANFD_FRAME != 0;
+    let (len, flags, offset) = if fd {
+        match buffer.get(5) {
+            Some(flags) => ((header[4] & !CANFD_FRAME) as usize, *flags, 6),
+            None => return Ok(None),
+        }
+    } else {
+        (header[4] as usize, 0, 5)
+    };
+    let remote = raw_id & CAN_RTR_FLAG != 0;
+    if len > if fd { 64 } else { 8 } || (fd && remote) {
+        return Err(BridgeError::Protocol("invalid frame length"));
+    }
+    let end = offset + if remote { 0 } else { len };
+    let Some(data) = buffer.get(offset..end) else {
+        return Ok(None);
+    };
+
+    let extended = raw_id & CAN_EFF_FLAG != 0;
+    let id = raw_id & if extended { CAN_EFF_MASK } else { CAN_SFF_MASK };
+    let kind = message_type(extended);
+    let frame = if raw_id & CAN_ERR_FLAG != 0 {
+        CanFrame::new_error(raw_id & CAN_EFF_MASK, data).map(AnyFrame::Can)
+    } else if fd {
+        CanFdFrame::new(id, kind, data, true, flags & CANFD_BRS != 0).map(AnyFrame::Fd)
+    } else if remote {
+        CanFrame::new_remote(id, kind, len as u8).map(AnyFrame::Can)
+    } else {
+        CanFrame::new(id, kind, data).map(AnyFrame::Can)
+    }
+    .map_err(|_| BridgeError::Protocol("invalid frame"))?;
+    Ok(Some((frame, end)))
+}
+
+/// Builds a UDP data packet.
+pub fn encode_packet(sequence: u8, frames: &[AnyFrame]) -> Vec<u8> {
+    let mut packet = vec![CANNELLONI_VERSION, OP_DATA, sequence];
+    packet.extend_from_slice(&(frames.len() as u16).to_be_bytes());
+    frames
+        .iter()
+        .for_each(|frame| encode_frame(frame, &mut packet));
+    packet
+}
+
+/// Frames of a UDP packet with its sequence number, acknowledge packets hold none.
+pub fn decode_packet(packet: &[u8]) -> Result<(u8, Vec<AnyFrame>), BridgeError> {
+    let Some(header) = packet.first_chunk::<PACKET_HEADER_LEN>() else {
+        return Err(BridgeError::Protocol("truncated packet header"));
+    };
+    if header[0] != CANNELLONI_VERSION {
+        return Err(BridgeError::Protocol("unsupported cannelloni version"));
+    }
+    if header[1] != OP_DATA {
+        return Ok((header[2], Vec::new()));
+    }
+    let count = u16::from_be_bytes([header[3], header[4]]) as usize;
+    let mut frames = Vec::with_capacity(count);
+    let mut offset = PACKET_HEADER_LEN;
+    for _ in 0..count {
+        let (frame, len) =
+            decode_frame(&packet[offset..])?.ok_or(BridgeError::Protocol("truncated packet"))?;
+        frames.push(frame);
+        offset += len;
+    }
+    Ok((header[2], frames))
+}
+
+fn encode_simple(frame: &AnyFrame, out: &mut Vec<u8>) {
+    let mut flags = 0;
+    if frame.is_extended_frame() {
+        flags |= SIMPLE_EXTENDED;
+    }
+    if frame.is_remote_frame() {
+        flags |= SIMPLE_REMOTE;
+    }
+    if frame.is_fd_frame() {
+        flags |= SIMPLE_FD;
+    }
+    if frame.is_brs_frame() {
+        flags |= SIMPLE_BRS;
+    }
+    if frame.is_error_frame() {
+        flags |= SIMPLE_ERROR;
+    }
+    let data = if frame.is_remote_frame() {
+        vec![remote_dlc(frame)]
+    } else {
+        frame.data().to_vec()
+    };
+    out.extend_from_slice(&(5 + data.len() as u16).to_be_bytes());
+    out.push(flags);
+    out.extend_from_slice(&frame.can_id().to_be_bytes());
+    out.extend_from_slice(&data);
+}
+
+fn decode_simple(buffer: &[u8]) -> Result<Option<(AnyFrame, usize)>, BridgeError> {
+    let Some(prefix) = buffer.first_chunk::<2>() else {
+        return Ok(None);
+    };
+    let len = u16::from_be_bytes(*prefix) as usize;
+    if !(5..=5 + 64).contains(&len) {
+        return Err(BridgeError::Protocol("invalid record length"));
+    }
+    let Some(record) = buffer.get(2..2 + len) else {
+        return Ok(None);
+    };
+    let flags = record[0];
+    let id = u32::from_be_bytes([record[1], record[2], record[3], record[4]]);
+    let data = &record[5..];
+    let kind = message_type(flags & SIMPLE_EXTENDED != 0);
+    let frame = if flags & SIMPLE_ERROR != 0 {
+        CanFrame::new_error(id, data).map(AnyFrame::Can)
+    } else if flags & SIMPLE_REMOTE != 0 {
+        let dlc = *data
+            .first()
+            .ok_or(BridgeError::Protocol("remote frame without DLC"))?;
+        CanFrame::new_remote(id, kind, dlc).map(AnyFrame::Can)
+    } else if flags & SIMPLE_FD != 0 {
+        CanFdFrame::new(id, kind, data, true, flags & SIMPLE_BRS != 0).map(AnyFrame::Fd)
+    } else {
+        CanFrame::new(id, kind, data).map(AnyFrame::Can)
+    }
+    .map_err(|_| BridgeError::Protocol("invalid frame"))?;
+    Ok(Some((frame, 2 + len)))
+}
+
+/* Network link */
+
+enum Transport {
+    Udp {
+        socket: UdpSocket,
+        peer: SocketAddr,
+        sequence: u8,
+    },
+    Tcp {
+        stream: TcpStream,
+        framing: Framing,
+        buffer: Vec<u8>,
+    },
+}
+
+struct LinkState {
+    transport: Transport,
+    received: VecDeque<(AnyFrame, u64)>,
+}
+
+/// Connection to a remote peer, usable as a socket.
+///
+/// Received frames are timestamped in microseconds since the link was created. Classic
+/// receivers skip frames in CAN FD format, CAN FD receivers skip remote frames.
+pub struct NetLink {
+    state: Mutex<LinkState>,
+    epoch: Instant,
+}
+
+impl NetLink {
+    fn new(transport: Transport) -> NetLink {
+        NetLink {
+            state: Mutex::new(LinkState {
+                transport,
+                received: VecDeque::new(),
+            }),
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Cannelloni over UDP between the local address and the peer.
+    pub fn udp(
+        local: impl ToSocketAddrs,
+        peer: impl ToSocketAddrs,
+    ) -> Result<NetLink, BridgeError> {
+        let peer = peer
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no peer address"))?;
+        NetLink::from_udp(UdpSocket::bind(local)?, peer)
+    }
+
+    pub fn from_udp(socket: UdpSocket, peer: SocketAddr) -> Result<NetLink, BridgeError> {
+        socket.set_nonblocking(true)?;
+        Ok(NetLink::new(Transport::Udp {
+            socket,
+            peer,
+            sequence: 0,
+        }))
+    }
+
+    pub fn tcp_connect(addr: impl ToSocketAddrs, framing: Framing) -> Result<NetLink, BridgeError> {
+        NetLink::from_tcp(TcpStream::connect(addr)?, framing)
+    }
+
+    /// Waits for one connection on `listener`.
+    pub fn tcp_accept(listener: &TcpListener, framing: Framing) -> Result<NetLink, BridgeError> {
+        let (stream, _) = listener.accept()?;
+        NetLink::from_tcp(stream, framing)
+    }
+
+    /// Uses a connected stream, exchanging the cannelloni handshake first.
+    pub fn from_tcp(mut stream: TcpStream, framing: Framing) -> Result<NetLink, BridgeError> {
+        if framing == Framing::Cannelloni {
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+            stream.write_all(CANNELLONI_TCP_HANDSHAKE)?;
+            let mut greeting = [0; CANNELLONI_TCP_HANDSHAKE.len()];
+            stream.read_exact(&mut greeting)?;
+            if greeting != CANNELLONI_TCP_HANDSHAKE {
+                return Err(BridgeError::Protocol("unexpected handshake"));
+            }
+            stream.set_read_timeout(None)?;
+        }
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(NetLink::new(Transport::Tcp {
+            stream,
+            framing,
+            buffer: Vec::new(),
+        }))
+    }
+
+    /// Sends frames to the peer, on UDP as few packets as possible.
+    pub fn send_frames(&self, frames: &[AnyFrame]) -> Result<(), BridgeError> {
+        let mut state = self.state.lock().unwrap();
+        match &mut state.transport {
+            Transport::Udp {
+                socket,
+                peer,
+                sequence,
+            } => {
+                let mut start = 0;
+                while start < frames.len() {
+                    let mut size = PACKET_HEADER_LEN;
+                    let mut end = start;
+                    let mut encoded = Vec::new();
+                    while end < frames.len() {
+                        encoded.clear();
+                        encode_frame(&frames[end], &mut encoded);
+                        if end > start && size + encoded.len() > MAX_UDP_PACKET {
+                            break;
+                        }
+                        size += encoded.len();
+                        end += 1;
+                    }
+                    let packet = encode_packet(*sequence, &frames[start..end]);
+                    *sequence = sequence.wrapping_add(1);
+                    socket.send_to(&packet, *peer)?;
+                    start = end;
+                }
+                Ok(())
+            }
+            Transport::Tcp {
+                stream, framing, ..
+            } => {
+                let mut bytes = Vec::new();
+                for frame in frames {
+                    match framing {
+                        Framing::Cannelloni => encode_frame(frame, &mut bytes),
+                        Framing::Simple => encode_simple(frame, &mut bytes),
+                    }
+                }
+                write_all_nonblocking(stream, &bytes)
+            }
+        }
+    }
+
+    /// Next frame from the peer with its arrival timestamp, `None` if nothing arrived.
+    pub fn recv_any(&self) -> Result<Option<(AnyFrame, u64)>, BridgeError> {
+        let mut state = self.state.lock().unwrap();
+        if state.received.is_empty() {
+            let timestamp = self.epoch.elapsed().as_micros() as u64;
+            let LinkState {
+                transport,
+                received,
+            } = &mut *state;
+            match transport {
+                Transport::Udp { socket, peer, .. } => {
+                    let mut packet = [0; 65536];
+                    loop {
+                        match socket.recv_from(&mut packet) {
+                            Ok((len, from)) if from.ip() == peer.ip() => {
+                                let (_, frames) = decode_packet(&packet[..len])?;
+                                received.extend(frames.into_iter().map(|f| (f, timestamp)));
+                            }
+                            Ok(_) => {}
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                            Err(err) => return Err(err.into()),
+                        }
+                    }
+                }
+                Transport::Tcp {
+                    stream,
+                    framing,
+                    buffer,
+                } => {
+                    let mut chunk = [0; 4096];
+                    loop {
+                        match stream.read(&mut chunk) {
+                            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+                            Ok(len) => buffer.extend_from_slice(&chunk[..len]),
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                            Err(err) => return Err(err.into()),
+                        }
+                    }
+                    let mut offset = 0;
+                    loop {
+                        let decoded = match framing {
+                            Framing::Cannelloni => decode_frame(&buffer[offset..])?,
+                            Framing::Simple => decode_simple(&buffer[offset..])?,
+                        };
+                        let Some((frame, len)) = decoded else {
+                            break;
+                        };
+                        received.push_back((frame, timestamp));
+                        offset += len;
+                    }
+                    buffer.drain(..offset);
+                }
+            }
+        }
+        Ok(state.received.pop_front())
+    }
+}
+
+fn write_all_nonblocking(stream: &mut TcpStream, mut bytes: &[u8]) -> Result<(), BridgeError> {
+    while !bytes.is_empty() {
+        match stream.write(bytes) {
+            Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero).into()),
+            Ok(len) => bytes = &bytes[len..],
+            Err(err) if err.kind() == ErrorKind::WouldBlock => thread::sleep(IDLE_SLEEP),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Network failures have no driver error code, they surface as [CanError::Unknown].
+fn can_error(err: BridgeError) -> CanError {
+    match err {
+        BridgeError::Can(err) => err,
+        _ => CanError::Unknown,
+    }
+}
+
+impl RecvCan for NetLink {
+    fn recv(&self) -> Result<(CanFrame, Timestamp), CanError> {
+        loop {
+            let (frame, timestamp) = self
+                .recv_any()
+                .map_err(can_error)?
+                .ok_or(CanError::QrcvEmpty)?;
+            if let Some(frame) = frame.to_can() {
+                return Ok((frame, Timestamp::from_micros(timestamp)));
+            }
+        }
+    }
+
+    fn recv_frame(&self) -> Result<CanFrame, CanError> {
+        self.recv().map(|(frame, _)| frame)
+    }
+}
+
+impl RecvCanFd for NetLink {
+    fn recv_fd(&self) -> Result<(CanFdFrame, u64), CanError> {
+        loop {
+            let (frame, timestamp) = self
+                .recv_any()
+                .map_err(can_error)?
+                .ok_or(CanError::QrcvEmpty)?;
+            if let Some(frame) = frame.to_fd() {
+                return Ok((frame, timestamp));
+            }
+        }
+    }
+
+    fn recv_fd_frame(&self) -> Result<CanFdFrame, CanError> {
+        self.recv_fd().map(|(frame, _)| frame)
+    }
+}
+
+impl SendCan for NetLink {
+    fn send(&self, frame: CanFrame) -> Result<(), CanError> {
+        self.send_frames(&[AnyFrame::Can(frame)]).map_err(can_error)
+    }
+}
+
+impl SendCanFd for NetLink {
+    fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError> {
+        self.send_frames(&[AnyFrame::Fd(frame)]).map_err(can_error)
+    }
+}
+
+/* Bridge */
+
+/// Applies the bit rate switch setting of the port to frames in CAN FD format.
+fn with_brs(frame: CanFdFrame, brs: bool) -> CanFdFrame {
+    if !frame.is_fd_frame() || frame.is_brs_frame() == brs {
+        return frame;
+    }
+    let kind = message_type(frame.is_extended_frame());
+    CanFdFrame::new(frame.can_id(), kind, frame.data(), true, brs).unwrap_or(frame)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BridgeStats {
+    /// Frames read from the socket and sent to the peer.
+    pub to_network: u64,
+    /// Frames received from the peer and sent on the socket.
+    pub from_network: u64,
+    /// Frames from the peer the socket cannot send, such as CAN FD frames on a classic
+    /// socket.
+    pub rejected: u64,
+}
+
+/// Forwards frames between a local socket and a [NetLink] in both directions.
+pub struct Bridge<'a> {
+    port: Port<'a>,
+    link: NetLink,
+    stats: BridgeStats,
+}
+
+impl<'a> Bridge<'a> {
+    pub fn new(port: Port<'a>, link: NetLink) -> Bridge<'a> {
+        Bridge {
+            port,
+            link,
+            stats: BridgeStats::default(),
+        }
+    }
+
+    pub fn link(&self) -> &NetLink {
+        &self.link
+    }
+
+    pub fn stats(&self) -> BridgeStats {
+        self.stats
+    }
+
+    /// Forwards everything waiting on either side, returns the number of frames moved.
+    pub fn poll(&mut self) -> Result<usize, BridgeError> {
+        let mut outgoing = Vec::new();
+        loop {
+            match self.port.recv() {
+                Ok(frame) => {
+                    if !frame.is_error_frame() && !frame.is_status_frame() && !frame.is_echo_frame()
+                    {
+                        outgoing.push(frame);
+                    }
+                }
+                Err(CanError::QrcvEmpty) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if !outgoing.is_empty() {
+            self.link.send_frames(&outgoing)?;
+            self.stats.to_network += outgoing.len() as u64;
+        }
+
+        let mut incoming = 0;
+        while let Some((frame, _)) = self.link.recv_any()? {
+            incoming += 1;
+            let sent = match &self.port {
+                Port::Can { tx, .. } => frame.to_can().map(|frame| tx.send(frame)),
+                Port::Fd { tx, brs, .. } => {
+                    frame.to_fd().map(|frame| tx.send_fd(with_brs(frame, *brs)))
+                }
+            };
+            match sent {
+                Some(result) => {
+                    result?;
+                    self.stats.from_network += 1;
+                }
+                None => self.stats.rejected += 1,
+            }
+        }
+        Ok(outgoing.len() + incoming)
+    }
+
+    /// Polls until `stop` is set.
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), BridgeError> {
+        while !stop.load(Ordering::Relaxed) {
+            if self.poll()? == 0 {
+                thread::sleep(IDLE_SLEEP);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::mock_bus;
+
+    fn wait_for<T>(mut poll: impl FnMut() -> Option<T>) -> T {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            if let Some(value) = poll() {
+                return value;
+            }
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn bridge_cannelloni_codec() {
+        let frames = [
+            AnyFrame::Can(CanFrame::new(0x123, MessageType::Standard, &[1, 2, 3]).unwrap()),
+            AnyFrame::Can(CanFrame::new_remote(0x1ABC_DEF0, MessageType::Extended, 4).unwrap()),
+            AnyFrame::Fd(
+                CanFdFrame::new(0x456, MessageType::Standard, &[7; 12], true, true).unwrap(),
+            ),
+        ];
+        let packet = encode_packet(9, &frames);
+        assert_eq!(packet[..5], [2, 0, 9, 0, 3]);
+        assert_eq!(packet[5..13], [0x00, 0x00, 0x01, 0x23, 3, 1, 2, 3]);
+        // No payload follows the DLC of a remote frame
+        assert_eq!(frames[1].data().len(), 4);
+        assert_eq!(packet[13..18], [0xDA, 0xBC, 0xDE, 0xF0, 4]);
+        assert_eq!(
+            packet[18..24],
+            [0x00, 0x00, 0x04, 0x56, 0x80 | 12, CANFD_BRS]
+        );
+
+        let (sequence, decoded) = decode_packet(&packet).unwrap();
+        assert_eq!(sequence, 9);
+        assert_eq!(decoded.len(), 3);
+        assert!(decoded[1].is_remote_frame() && decoded[1].is_extended_frame());
+        assert_eq!(remote_dlc(&decoded[1]), 4);
+        assert!(decoded[2].is_fd_frame() && decoded[2].is_brs_frame());
+        assert_eq!(decoded[2].data(), [7; 12]);
+
+        assert!(decode_packet(&packet[..packet.len() - 1]).is_err());
+        assert!(decode_frame(&packet[5..10]).unwrap().is_none());
+
+        let mut simple = Vec::new();
+        frames
+            .iter()
+            .for_each(|frame| encode_simple(frame, &mut simple));
+        let (first, len) = decode_simple(&simple).unwrap().unwrap();
+        assert_eq!((first.can_id(), first.data()), (0x123, &[1, 2, 3][..]));
+        let (remote, _) = decode_simple(&simple[len..]).unwrap().unwrap();
+        assert_eq!(remote.can_id(), 0x1ABC_DEF0);
+    }
+
+    #[test]
+    fn bridge_udp_loopback() {
+        let lab = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (lab_addr, remote_addr) = (lab.local_addr().unwrap(), remote.local_addr().unwrap());
+        let remote = NetLink::from_udp(remote, lab_addr).unwrap();
+
+        let (adapter, bus) = mock_bus();
+        let mut bridge = Bridge::new(
+            Port::can(&adapter),
+            NetLink::from_udp(lab, remote_addr).unwrap(),
+        );
+
+        for id in 0..3 {
+            bus.send(CanFrame::new(0x100 + id, MessageType::Standard, &[id as u8]).unwrap())
+                .unwrap();
+        }
+        assert_eq!(bridge.poll().unwrap(), 3);
+        let ids: Vec<u32> = (0..3)
+            .map(|_| wait_for(|| remote.recv_frame().ok()).can_id())
+            .collect();
+        assert_eq!(ids, [0x100, 0x101, 0x102]);
+
+        remote
+            .send(CanFrame::new(0x7DF, MessageType::Standard, &[2, 1, 0]).unwrap())
+            .unwrap();
+        let frame = wait_for(|| {
+            bridge.poll().unwrap();
+            bus.recv_frame().ok()
+        });
+        assert_eq!(frame.can_id(), 0x7DF);
+        assert_eq!(bridge.stats().to_network, 3);
+        assert_eq!(bridge.stats().from_network, 1);
+    }
+
+    #[test]
+    fn bridge_tcp_loopback() {
+        for framing in [Framing::Cannelloni, Framing::Simple] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let client = thread::spawn(move || NetLink::tcp_connect(addr, framing).unwrap());
+            let server = NetLink::tcp_accept(&listener, framing).unwrap();
+            let client = client.join().unwrap();
+
+            let (adapter, bus) = mock_bus();
+            let mut bridge = Bridge::new(Port::fd(&adapter, true), server);
+
+            let fd = CanFdFrame::new(0x18DA_F110, MessageType::Extended, &[5; 20], true, true);
+            client.send_fd(fd.unwrap()).unwrap();
+            client
+                .send(CanFrame::new_remote(0x321, MessageType::Standard, 2).unwrap())
+                .unwrap();
+            let (frame, _) = wait_for(|| {
+                bridge.poll().unwrap();
+                bus.recv_fd().ok()
+            });
+            assert_eq!(frame.can_id(), 0x18DA_F110);
+            assert_eq!(frame.data(), [5; 20]);
+            // CAN FD sockets cannot send remote frames
+            wait_for(|| {
+                bridge.poll().unwrap();
+                (bridge.stats().rejected == 1).then_some(())
+            });
+
+            bus.send_fd(
+                CanFdFrame::new(0x42, MessageType::Standard, &[1; 64], true, false).unwrap(),
+            )
+            .unwrap();
+            bridge.poll().unwrap();
+            let (frame, _) = wait_for(|| client.recv_fd().ok());
+            assert_eq!((frame.can_id(), frame.len()), (0x42, 64));
+        }
+    }
+}
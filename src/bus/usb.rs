@@ -7,7 +7,9 @@ use crate::hw::{
 };
 use crate::info::{HasBitrateInfo, HasBitrateInfoFd, HasChannelFeatures, HasChannelVersion};
 use crate::peak_can;
-use crate::special::HasFiveVoltsPower;
+use crate::special::{
+    HasBitrateAdapting, HasFiveVoltsPower, HasListenOnly, HasSetBitrateAdapting, HasSetListenOnly,
+};
 
 ///
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -140,6 +142,12 @@ impl HasBitrateInfoFd for UsbBus {}
 
 impl HasFiveVoltsPower for UsbBus {}
 
+impl HasListenOnly for UsbBus {}
+impl HasSetListenOnly for UsbBus {}
+
+impl HasBitrateAdapting for UsbBus {}
+impl HasSetBitrateAdapting for UsbBus {}
+
 /* CONTROLLING DATA FLOW */
 
 impl HasReceiveStatus for UsbBus {}
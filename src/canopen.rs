@@ -0,0 +1,1253 @@
+//! CANopen (CiA 301) master utilities.
+//!
+//! A [CanOpenMaster] sends NMT commands and SYNC messages, reads and writes object
+//! dictionary entries of remote nodes with SDO transfers (expedited, segmented and block
+//! mode) and configures transmit and receive PDOs. Heartbeat, node guarding, EMCY, SYNC and
+//! PDO frames are reported by [poll](CanOpenMaster::poll) as [CanOpenEvent]s; heartbeat
+//! producers registered with [monitor_heartbeat](CanOpenMaster::monitor_heartbeat) are
+//! checked with [expired_heartbeats](CanOpenMaster::expired_heartbeats).
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::canopen::{CanOpenMaster, NmtCommand, PdoConfig, PdoMapping};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let master = CanOpenMaster::new(&socket);
+//!
+//! let device_type = master.sdo_upload(5, 0x1000, 0)?;
+//! println!("device type {device_type:02X?}");
+//!
+//! // Transmit the status word and actual position on SYNC
+//! let tpdo = PdoConfig {
+//!     cob_id: 0x185,
+//!     transmission_type: 1,
+//!     inhibit_time: None,
+//!     event_timer: None,
+//!     mapping: vec![PdoMapping::new(0x6041, 0, 16), PdoMapping::new(0x6064, 0, 32)],
+//! };
+//! master.configure_tpdo(5, 0, &tpdo)?;
+//! master.nmt(NmtCommand::Start, 5)?;
+//! master.sync()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::socket::{self, CanFrame, FrameConstructionError, MessageType, RecvCan, SendCan};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const COB_NMT: u32 = 0x000;
+pub const COB_SYNC: u32 = 0x080;
+pub const COB_EMCY: u32 = 0x080;
+pub const COB_TPDO1: u32 = 0x180;
+pub const COB_RPDO1: u32 = 0x200;
+pub const COB_SDO_TX: u32 = 0x580;
+pub const COB_SDO_RX: u32 = 0x600;
+pub const COB_HEARTBEAT: u32 = 0x700;
+
+/// Set in a PDO COB-ID to disable the PDO.
+pub const PDO_COB_ID_INVALID: u32 = 0x8000_0000;
+
+/// Client side abort codes.
+const ABORT_TIMEOUT: u32 = 0x0504_0000;
+const ABORT_INVALID_COMMAND: u32 = 0x0504_0001;
+const ABORT_INVALID_SEQUENCE: u32 = 0x0504_0003;
+const ABORT_CRC_ERROR: u32 = 0x0504_0004;
+
+/// Segments per block requested for block uploads.
+const BLOCK_SIZE: u8 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtCommand {
+    Start,
+    Stop,
+    EnterPreOperational,
+    ResetNode,
+    ResetCommunication,
+}
+
+impl From<NmtCommand> for u8 {
+    fn from(value: NmtCommand) -> Self {
+        match value {
+            NmtCommand::Start => 0x01,
+            NmtCommand::Stop => 0x02,
+            NmtCommand::EnterPreOperational => 0x80,
+            NmtCommand::ResetNode => 0x81,
+            NmtCommand::ResetCommunication => 0x82,
+        }
+    }
+}
+
+/// NMT state reported by heartbeat and node guarding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmtState {
+    BootUp,
+    Stopped,
+    Operational,
+    PreOperational,
+    Unknown(u8),
+}
+
+impl From<u8> for NmtState {
+    fn from(value: u8) -> Self {
+        match value & 0x7F {
+            0x00 => NmtState::BootUp,
+            0x04 => NmtState::Stopped,
+            0x05 => NmtState::Operational,
+            0x7F => NmtState::PreOperational,
+            other => NmtState::Unknown(other),
+        }
+    }
+}
+
+/// SDO abort code with its CiA 301 description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdoAbortCode(pub u32);
+
+impl SdoAbortCode {
+    pub fn description(&self) -> &'static str {
+        match self.0 {
+            0x0503_0000 => "toggle bit not alternated",
+            0x0504_0000 => "SDO protocol timed out",
+            0x0504_0001 => "client/server command specifier not valid or unknown",
+            0x0504_0002 => "invalid block size",
+            0x0504_0003 => "invalid sequence number",
+            0x0504_0004 => "CRC error",
+            0x0504_0005 => "out of memory",
+            0x0601_0000 => "unsupported access to an object",
+            0x0601_0001 => "attempt to read a write only object",
+            0x0601_0002 => "attempt to write a read only object",
+            0x0602_0000 => "object does not exist in the object dictionary",
+            0x0604_0041 => "object cannot be mapped to the PDO",
+            0x0604_0042 => "number and length of objects to be mapped exceed PDO length",
+            0x0604_0043 => "general parameter incompatibility",
+            0x0604_0047 => "general internal incompatibility in the device",
+            0x0606_0000 => "access failed due to a hardware error",
+            0x0607_0010 => "data type does not match, length of service parameter does not match",
+            0x0607_0012 => "data type does not match, length of service parameter too high",
+            0x0607_0013 => "data type does not match, length of service parameter too low",
+            0x0609_0011 => "sub-index does not exist",
+            0x0609_0030 => "invalid value for parameter",
+            0x0609_0031 => "value of parameter written too high",
+            0x0609_0032 => "value of parameter written too low",
+            0x0609_0036 => "maximum value is less than minimum value",
+            0x060A_0023 => "resource not available: SDO connection",
+            0x0800_0000 => "general error",
+            0x0800_0020 => "data cannot be transferred or stored to the application",
+            0x0800_0021 => "data cannot be transferred or stored because of local control",
+            0x0800_0022 => "data cannot be transferred or stored because of the device state",
+            0x0800_0023 => "object dictionary not present or dynamic generation failed",
+            0x0800_0024 => "no data available",
+            _ => "unknown abort code",
+        }
+    }
+}
+
+impl fmt::Display for SdoAbortCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08X} ({})", self.0, self.description())
+    }
+}
+
+#[derive(Debug)]
+pub enum CanOpenError {
+    Can(CanError),
+    Frame(FrameConstructionError),
+    /// The node did not answer in time.
+    Timeout,
+    /// The SDO transfer was aborted by the server or by this client.
+    SdoAbort {
+        node: u8,
+        index: u16,
+        subindex: u8,
+        code: SdoAbortCode,
+    },
+    /// Invalid node id or PDO number.
+    InvalidArgument(String),
+    /// The node violated the protocol.
+    Protocol(String),
+}
+
+impl fmt::Display for CanOpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CanOpenError::Can(e) => write!(f, "{e}"),
+            CanOpenError::Frame(e) => write!(f, "invalid frame: {e:?}"),
+            CanOpenError::Timeout => write!(f, "no response from node"),
+            CanOpenError::SdoAbort {
+                node,
+                index,
+                subindex,
+                code,
+            } => write!(
+                f,
+                "SDO {index:04X}sub{subindex} on node {node} aborted: {code}"
+            ),
+            CanOpenError::InvalidArgument(message) => write!(f, "invalid argument: {message}"),
+            CanOpenError::Protocol(message) => write!(f, "protocol error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for CanOpenError {}
+
+impl From<CanError> for CanOpenError {
+    fn from(value: CanError) -> Self {
+        CanOpenError::Can(value)
+    }
+}
+
+impl From<FrameConstructionError> for CanOpenError {
+    fn from(value: FrameConstructionError) -> Self {
+        CanOpenError::Frame(value)
+    }
+}
+
+/// An emergency message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Emergency {
+    pub node: u8,
+    /// Emergency error code, 0 for error reset.
+    pub error_code: u16,
+    /// Value of object 0x1001.
+    pub error_register: u8,
+    pub data: [u8; 5],
+}
+
+/// A frame received by [poll](CanOpenMaster::poll), classified by its COB-ID.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CanOpenEvent {
+    Heartbeat {
+        node: u8,
+        state: NmtState,
+    },
+    Emergency(Emergency),
+    Sync {
+        counter: Option<u8>,
+    },
+    Pdo {
+        cob_id: u32,
+        data: Vec<u8>,
+    },
+    /// Any other frame, for instance SDO traffic of other clients.
+    Other(CanFrame),
+}
+
+/// One entry of a PDO mapping table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdoMapping {
+    pub index: u16,
+    pub subindex: u8,
+    /// Length of the mapped object in bits.
+    pub bits: u8,
+}
+
+impl PdoMapping {
+    pub fn new(index: u16, subindex: u8, bits: u8) -> PdoMapping {
+        PdoMapping {
+            index,
+            subindex,
+            bits,
+        }
+    }
+}
+
+impl From<PdoMapping> for u32 {
+    fn from(value: PdoMapping) -> Self {
+        (value.index as u32) << 16 | (value.subindex as u32) << 8 | value.bits as u32
+    }
+}
+
+impl From<u32> for PdoMapping {
+    fn from(value: u32) -> Self {
+        PdoMapping {
+            index: (value >> 16) as u16,
+            subindex: (value >> 8) as u8,
+            bits: value as u8,
+        }
+    }
+}
+
+/// Extracts the mapped values, little endian, from a PDO payload.
+///
+/// Values beyond the end of `data` are omitted.
+pub fn decode_pdo(mapping: &[PdoMapping], data: &[u8]) -> Vec<u64> {
+    let mut values = Vec::with_capacity(mapping.len());
+    let mut bit = 0usize;
+    for entry in mapping {
+        let bits = entry.bits as usize;
+        if bit + bits > data.len() * 8 || bits > 64 {
+            break;
+        }
+        let mut value = 0u64;
+        for i in 0..bits {
+            let set = data[(bit + i) / 8] >> ((bit + i) % 8) & 1;
+            value |= (set as u64) << i;
+        }
+        values.push(value);
+        bit += bits;
+    }
+    values
+}
+
+/// Communication and mapping parameters of a PDO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PdoConfig {
+    pub cob_id: u32,
+    /// 0 acyclic synchronous, 1-240 every n-th SYNC, 254/255 event driven.
+    pub transmission_type: u8,
+    /// Inhibit time in multiples of 100 µs.
+    pub inhibit_time: Option<u16>,
+    /// Event timer in ms.
+    pub event_timer: Option<u16>,
+    pub mapping: Vec<PdoMapping>,
+}
+
+struct Heartbeat {
+    consumer_time: Duration,
+    last_seen: Option<Instant>,
+    state: Option<NmtState>,
+}
+
+/// CANopen master on a classic CAN socket.
+pub struct CanOpenMaster<'a> {
+    tx: &'a dyn SendCan,
+    rx: &'a dyn RecvCan,
+    sdo_timeout: Cell<Duration>,
+    heartbeats: RefCell<BTreeMap<u8, Heartbeat>>,
+    /// Expected toggle bit of the next node guarding response.
+    guard_toggle: RefCell<BTreeMap<u8, u8>>,
+    /// Frames received while waiting for an SDO or guarding response.
+    pending: RefCell<VecDeque<CanFrame>>,
+}
+
+impl<'a> CanOpenMaster<'a> {
+    pub fn new<S: SendCan + RecvCan>(socket: &'a S) -> CanOpenMaster<'a> {
+        CanOpenMaster {
+            tx: socket,
+            rx: socket,
+            sdo_timeout: Cell::new(Duration::from_millis(500)),
+            heartbeats: RefCell::new(BTreeMap::new()),
+            guard_toggle: RefCell::new(BTreeMap::new()),
+            pending: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Time to wait for each SDO response, 500 ms by default.
+    pub fn set_sdo_timeout(&self, timeout: Duration) {
+        self.sdo_timeout.set(timeout);
+    }
+
+    fn send_frame(&self, cob_id: u32, data: &[u8]) -> Result<(), CanOpenError> {
+        self.tx
+            .send(CanFrame::new(cob_id, MessageType::Standard, data)?)?;
+        Ok(())
+    }
+
+    fn check_node(node: u8) -> Result<(), CanOpenError> {
+        match node {
+            1..=127 => Ok(()),
+            _ => Err(CanOpenError::InvalidArgument(format!("node id {node}"))),
+        }
+    }
+
+    /* NMT, SYNC */
+
+    /// Sends an NMT command to `node`, or to all nodes with node 0.
+    pub fn nmt(&self, command: NmtCommand, node: u8) -> Result<(), CanOpenError> {
+        if node > 127 {
+            return Err(CanOpenError::InvalidArgument(format!("node id {node}")));
+        }
+        self.send_frame(COB_NMT, &[u8::from(command), node])
+    }
+
+    pub fn sync(&self) -> Result<(), CanOpenError> {
+        self.send_frame(COB_SYNC, &[])
+    }
+
+    /// Sends a SYNC with counter (1-240).
+    pub fn sync_with_counter(&self, counter: u8) -> Result<(), CanOpenError> {
+        self.send_frame(COB_SYNC, &[counter])
+    }
+
+    /* Heartbeat, node guarding */
+
+    /// Expects a heartbeat from `node` at least every `consumer_time`.
+    pub fn monitor_heartbeat(&self, node: u8, consumer_time: Duration) {
+        self.heartbeats.borrow_mut().insert(
+            node,
+            Heartbeat {
+                consumer_time,
+                last_seen: None,
+                state: None,
+            },
+        );
+    }
+
+    /// Last NMT state reported by a monitored node.
+    pub fn node_state(&self, node: u8) -> Option<NmtState> {
+        self.heartbeats.borrow().get(&node).and_then(|h| h.state)
+    }
+
+    /// Monitored nodes whose heartbeat is overdue.
+    ///
+    /// A node that has not sent any heartbeat yet is overdue once its consumer time has
+    /// passed since monitoring started.
+    pub fn expired_heartbeats(&self) -> Vec<u8> {
+        let now = Instant::now();
+        self.heartbeats
+            .borrow_mut()
+            .iter_mut()
+            .filter_map(|(node, heartbeat)| {
+                let last = *heartbeat.last_seen.get_or_insert(now);
+                (now - last > heartbeat.consumer_time).then_some(*node)
+            })
+            .collect()
+    }
+
+    /// Guards `node`: sends a remote request and returns the reported state.
+    ///
+    /// The toggle bit of consecutive responses must alternate, starting with 0.
+    pub fn guard(&self, node: u8) -> Result<NmtState, CanOpenError> {
+        Self::check_node(node)?;
+        let cob_id = COB_HEARTBEAT + node as u32;
+        self.tx
+            .send(CanFrame::new_remote(cob_id, MessageType::Standard, 1)?)?;
+
+        let deadline = Instant::now() + self.sdo_timeout.get();
+        let response = self.wait_frame(deadline, |f| {
+            f.can_id() == cob_id && !f.is_remote_frame() && f.data().len() == 1
+        })?;
+
+        let value = response.data()[0];
+        let mut toggles = self.guard_toggle.borrow_mut();
+        let expected = toggles.entry(node).or_insert(0);
+        if value & 0x80 != *expected {
+            *expected = 0;
+            return Err(CanOpenError::Protocol(format!(
+                "node {node} guarding toggle bit not alternated"
+            )));
+        }
+        *expected ^= 0x80;
+        Ok(NmtState::from(value))
+    }
+
+    /* Events */
+
+    /// Receives and classifies the next frame, `Ok(None)` if none arrives in `timeout`.
+    pub fn poll(&self, timeout: Duration) -> Result<Option<CanOpenEvent>, CanOpenError> {
+        let frame = match self.pending.borrow_mut().pop_front() {
+            Some(frame) => frame,
+            None => match socket::poll_until(Instant::now() + timeout, || self.rx.recv_frame())? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(self.classify(frame)))
+    }
+
+    fn classify(&self, frame: CanFrame) -> CanOpenEvent {
+        if frame.is_extended_frame() || frame.is_remote_frame() {
+            return CanOpenEvent::Other(frame);
+        }
+        let cob_id = frame.can_id();
+        let function = cob_id & 0x780;
+        let node = (cob_id & 0x7F) as u8;
+        let data = frame.data();
+
+        match function {
+            COB_SYNC if node == 0 => CanOpenEvent::Sync {
+                counter: data.first().copied(),
+            },
+            COB_EMCY if data.len() == 8 => {
+                let mut extra = [0; 5];
+                extra.copy_from_slice(&data[3..8]);
+                CanOpenEvent::Emergency(Emergency {
+                    node,
+                    error_code: u16::from_le_bytes([data[0], data[1]]),
+                    error_register: data[2],
+                    data: extra,
+                })
+            }
+            COB_HEARTBEAT if node != 0 && data.len() == 1 => {
+                let state = NmtState::from(data[0]);
+                if let Some(heartbeat) = self.heartbeats.borrow_mut().get_mut(&node) {
+                    heartbeat.last_seen = Some(Instant::now());
+                    heartbeat.state = Some(state);
+                }
+                CanOpenEvent::Heartbeat { node, state }
+            }
+            0x180..=0x500 if node != 0 => CanOpenEvent::Pdo {
+                cob_id,
+                data: data.to_vec(),
+            },
+            _ => CanOpenEvent::Other(frame),
+        }
+    }
+
+    /// Waits for a frame accepted by `matches`, queueing all others for [poll].
+    fn wait_frame(
+        &self,
+        deadline: Instant,
+        matches: impl Fn(&CanFrame) -> bool,
+    ) -> Result<CanFrame, CanOpenError> {
+        loop {
+            let Some(frame) = socket::poll_until(deadline, || self.rx.recv_frame())? else {
+                return Err(CanOpenError::Timeout);
+            };
+            if !frame.is_extended_frame() && matches(&frame) {
+                return Ok(frame);
+            }
+            self.pending.borrow_mut().push_back(frame);
+        }
+    }
+
+    /* SDO */
+
+    fn sdo_request(&self, node: u8, request: [u8; 8]) -> Result<(), CanOpenError> {
+        self.send_frame(COB_SDO_RX + node as u32, &request)
+    }
+
+    /// Waits for the next SDO response of `node`, converting server aborts into errors.
+    fn sdo_response(&self, node: u8, index: u16, subindex: u8) -> Result<[u8; 8], CanOpenError> {
+        let deadline = Instant::now() + self.sdo_timeout.get();
+        let cob_id = COB_SDO_TX + node as u32;
+        let frame = match self.wait_frame(deadline, |f| f.can_id() == cob_id && f.data().len() == 8)
+        {
+            Err(CanOpenError::Timeout) => {
+                self.sdo_abort(node, index, subindex, ABORT_TIMEOUT)?;
+                return Err(CanOpenError::Timeout);
+            }
+            other => other?,
+        };
+
+        let mut response = [0; 8];
+        response.copy_from_slice(frame.data());
+        if response[0] == 0x80 {
+            return Err(CanOpenError::SdoAbort {
+                node,
+                index,
+                subindex,
+                code: SdoAbortCode(u32::from_le_bytes([
+                    response[4],
+                    response[5],
+                    response[6],
+                    response[7],
+                ])),
+            });
+        }
+        Ok(response)
+    }
+
+    fn sdo_abort(&self, node: u8, index: u16, subindex: u8, code: u32) -> Result<(), CanOpenError> {
+        let index = index.to_le_bytes();
+        let code = code.to_le_bytes();
+        self.sdo_request(
+            node,
+            [
+                0x80, index[0], index[1], subindex, code[0], code[1], code[2], code[3],
+            ],
+        )
+    }
+
+    /// Aborts the transfer with `code` and returns the matching error.
+    fn client_abort(&self, node: u8, index: u16, subindex: u8, code: u32) -> CanOpenError {
+        match self.sdo_abort(node, index, subindex, code) {
+            Ok(()) => CanOpenError::SdoAbort {
+                node,
+                index,
+                subindex,
+                code: SdoAbortCode(code),
+            },
+            Err(err) => err,
+        }
+    }
+
+    fn check_multiplexer(index: u16, subindex: u8, response: &[u8; 8]) -> Result<(), CanOpenError> {
+        if u16::from_le_bytes([response[1], response[2]]) != index || response[3] != subindex {
+            return Err(CanOpenError::Protocol(String::from(
+                "SDO response for another object",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Reads an object with an expedited or segmented transfer.
+    pub fn sdo_upload(&self, node: u8, index: u16, subindex: u8) -> Result<Vec<u8>, CanOpenError> {
+        Self::check_node(node)?;
+        let idx = index.to_le_bytes();
+        self.sdo_request(node, [0x40, idx[0], idx[1], subindex, 0, 0, 0, 0])?;
+
+        let response = self.sdo_response(node, index, subindex)?;
+        if response[0] >> 5 != 2 {
+            return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+        }
+        Self::check_multiplexer(index, subindex, &response)?;
+
+        let expedited = response[0] & 0x02 != 0;
+        let size_indicated = response[0] & 0x01 != 0;
+        if expedited {
+            let len = if size_indicated {
+                4 - ((response[0] >> 2) & 0x03) as usize
+            } else {
+                4
+            };
+            return Ok(response[4..4 + len].to_vec());
+        }
+
+        let size = size_indicated.then(|| {
+            u32::from_le_bytes([response[4], response[5], response[6], response[7]]) as usize
+        });
+        let mut data = Vec::with_capacity(size.unwrap_or(0));
+        let mut toggle = 0u8;
+        loop {
+            self.sdo_request(node, [0x60 | toggle, 0, 0, 0, 0, 0, 0, 0])?;
+            let segment = self.sdo_response(node, index, subindex)?;
+            if segment[0] >> 5 != 0 {
+                return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+            }
+            if segment[0] & 0x10 != toggle {
+                return Err(self.client_abort(node, index, subindex, 0x0503_0000));
+            }
+
+            let unused = ((segment[0] >> 1) & 0x07) as usize;
+            data.extend_from_slice(&segment[1..8 - unused]);
+            if segment[0] & 0x01 != 0 {
+                break;
+            }
+            toggle ^= 0x10;
+        }
+
+        if size.is_some_and(|size| size != data.len()) {
+            return Err(CanOpenError::Protocol(String::from(
+                "uploaded size does not match indicated size",
+            )));
+        }
+        Ok(data)
+    }
+
+    /// Writes an object, expedited up to 4 bytes and segmented beyond.
+    pub fn sdo_download(
+        &self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        data: &[u8],
+    ) -> Result<(), CanOpenError> {
+        Self::check_node(node)?;
+        let idx = index.to_le_bytes();
+
+        if data.len() <= 4 {
+            let mut request = [
+                0x23 | ((4 - data.len() as u8) << 2),
+                idx[0],
+                idx[1],
+                subindex,
+                0,
+                0,
+                0,
+                0,
+            ];
+            request[4..4 + data.len()].copy_from_slice(data);
+            self.sdo_request(node, request)?;
+            let response = self.sdo_response(node, index, subindex)?;
+            if response[0] != 0x60 {
+                return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+            }
+            return Self::check_multiplexer(index, subindex, &response);
+        }
+
+        let size = (data.len() as u32).to_le_bytes();
+        self.sdo_request(
+            node,
+            [
+                0x21, idx[0], idx[1], subindex, size[0], size[1], size[2], size[3],
+            ],
+        )?;
+        let response = self.sdo_response(node, index, subindex)?;
+        if response[0] != 0x60 {
+            return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+        }
+        Self::check_multiplexer(index, subindex, &response)?;
+
+        let mut toggle = 0u8;
+        let chunks = data.chunks(7);
+        let count = chunks.len();
+        for (i, chunk) in chunks.enumerate() {
+            let last = i + 1 == count;
+            let mut request = [0; 8];
+            request[0] = toggle | ((7 - chunk.len() as u8) << 1) | last as u8;
+            request[1..1 + chunk.len()].copy_from_slice(chunk);
+            self.sdo_request(node, request)?;
+
+            let response = self.sdo_response(node, index, subindex)?;
+            if response[0] != 0x20 | toggle {
+                return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+            }
+            toggle ^= 0x10;
+        }
+        Ok(())
+    }
+
+    /// Reads an object with an SDO block upload, verifying the CRC if the server supports it.
+    pub fn sdo_block_upload(
+        &self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+    ) -> Result<Vec<u8>, CanOpenError> {
+        Self::check_node(node)?;
+        let idx = index.to_le_bytes();
+        self.sdo_request(node, [0xA4, idx[0], idx[1], subindex, BLOCK_SIZE, 0, 0, 0])?;
+
+        let response = self.sdo_response(node, index, subindex)?;
+        if response[0] & 0xE1 != 0xC0 {
+            return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+        }
+        Self::check_multiplexer(index, subindex, &response)?;
+        let crc_supported = response[0] & 0x04 != 0;
+        let size = (response[0] & 0x02 != 0).then(|| {
+            u32::from_le_bytes([response[4], response[5], response[6], response[7]]) as usize
+        });
+
+        self.sdo_request(node, [0xA3, 0, 0, 0, 0, 0, 0, 0])?;
+
+        let mut data = Vec::with_capacity(size.unwrap_or(0));
+        loop {
+            // Receive one block
+            let mut sequence = 0u8;
+            let mut complete = false;
+            loop {
+                let segment = self.sdo_response(node, index, subindex)?;
+                if segment[0] & 0x7F == sequence + 1 {
+                    sequence += 1;
+                    data.extend_from_slice(&segment[1..]);
+                    complete = segment[0] & 0x80 != 0;
+                }
+                if complete || sequence == BLOCK_SIZE {
+                    break;
+                }
+            }
+            self.sdo_request(node, [0xA2, sequence, BLOCK_SIZE, 0, 0, 0, 0, 0])?;
+            if complete {
+                break;
+            }
+        }
+
+        let end = self.sdo_response(node, index, subindex)?;
+        if end[0] & 0xE3 != 0xC1 {
+            return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+        }
+        let unused = ((end[0] >> 2) & 0x07) as usize;
+        data.truncate(data.len() - unused);
+
+        if crc_supported && crc16(&data) != u16::from_le_bytes([end[1], end[2]]) {
+            return Err(self.client_abort(node, index, subindex, ABORT_CRC_ERROR));
+        }
+        if size.is_some_and(|size| size != data.len()) {
+            return Err(CanOpenError::Protocol(String::from(
+                "uploaded size does not match indicated size",
+            )));
+        }
+        self.sdo_request(node, [0xA1, 0, 0, 0, 0, 0, 0, 0])?;
+        Ok(data)
+    }
+
+    /// Writes an object with an SDO block download.
+    pub fn sdo_block_download(
+        &self,
+        node: u8,
+        index: u16,
+        subindex: u8,
+        data: &[u8],
+    ) -> Result<(), CanOpenError> {
+        Self::check_node(node)?;
+        let idx = index.to_le_bytes();
+        let size = (data.len() as u32).to_le_bytes();
+        self.sdo_request(
+            node,
+            [
+                0xC6, idx[0], idx[1], subindex, size[0], size[1], size[2], size[3],
+            ],
+        )?;
+
+        let response = self.sdo_response(node, index, subindex)?;
+        if response[0] & 0xE3 != 0xA0 {
+            return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+        }
+        Self::check_multiplexer(index, subindex, &response)?;
+        let crc_supported = response[0] & 0x04 != 0;
+        let mut block_size = response[4];
+
+        let segments: Vec<&[u8]> = data.chunks(7).collect();
+        let mut next = 0usize;
+        while next < segments.len() {
+            if block_size == 0 || block_size > 127 {
+                return Err(self.client_abort(node, index, subindex, 0x0504_0002));
+            }
+            let count = (block_size as usize).min(segments.len() - next);
+            for i in 0..count {
+                let chunk = segments[next + i];
+                let mut request = [0; 8];
+                request[0] = (i + 1) as u8;
+                if next + i + 1 == segments.len() {
+                    request[0] |= 0x80;
+                }
+                request[1..1 + chunk.len()].copy_from_slice(chunk);
+                self.sdo_request(node, request)?;
+            }
+
+            let ack = self.sdo_response(node, index, subindex)?;
+            if ack[0] & 0xE3 != 0xA2 {
+                return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+            }
+            let acknowledged = ack[1] as usize;
+            if acknowledged > count {
+                return Err(self.client_abort(node, index, subindex, ABORT_INVALID_SEQUENCE));
+            }
+            // Segments after the acknowledged one are sent again in the next block
+            next += acknowledged;
+            block_size = ack[2];
+        }
+
+        let unused = (7 - data.len() % 7) % 7;
+        let crc = if crc_supported { crc16(data) } else { 0 }.to_le_bytes();
+        self.sdo_request(
+            node,
+            [0xC1 | (unused as u8) << 2, crc[0], crc[1], 0, 0, 0, 0, 0],
+        )?;
+        let end = self.sdo_response(node, index, subindex)?;
+        if end[0] & 0xE3 != 0xA1 {
+            return Err(self.client_abort(node, index, subindex, ABORT_INVALID_COMMAND));
+        }
+        Ok(())
+    }
+
+    pub fn sdo_read_u32(&self, node: u8, index: u16, subindex: u8) -> Result<u32, CanOpenError> {
+        let data = self.sdo_upload(node, index, subindex)?;
+        let mut bytes = [0; 4];
+        let len = data.len().min(4);
+        bytes[..len].copy_from_slice(&data[..len]);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /* PDO */
+
+    /// Configures transmit PDO `pdo` (0 for TPDO1) of `node`.
+    pub fn configure_tpdo(
+        &self,
+        node: u8,
+        pdo: u16,
+        config: &PdoConfig,
+    ) -> Result<(), CanOpenError> {
+        self.configure_pdo(node, 0x1800, 0x1A00, pdo, config)
+    }
+
+    /// Configures receive PDO `pdo` (0 for RPDO1) of `node`.
+    pub fn configure_rpdo(
+        &self,
+        node: u8,
+        pdo: u16,
+        config: &PdoConfig,
+    ) -> Result<(), CanOpenError> {
+        self.configure_pdo(node, 0x1400, 0x1600, pdo, config)
+    }
+
+    /// Disables a PDO, writes communication and mapping parameters and enables it again.
+    fn configure_pdo(
+        &self,
+        node: u8,
+        communication: u16,
+        mapping: u16,
+        pdo: u16,
+        config: &PdoConfig,
+    ) -> Result<(), CanOpenError> {
+        if pdo >= 512 {
+            return Err(CanOpenError::InvalidArgument(format!("PDO number {pdo}")));
+        }
+        if config.mapping.len() > 64
+            || config
+                .mapping
+                .iter()
+                .map(|m| m.bits as usize)
+                .sum::<usize>()
+                > 64
+        {
+            return Err(CanOpenError::InvalidArgument(String::from(
+                "PDO mapping exceeds 8 bytes",
+            )));
+        }
+        let communication = communication + pdo;
+        let mapping_index = mapping + pdo;
+        let cob_id = config.cob_id & !PDO_COB_ID_INVALID;
+
+        self.sdo_download(
+            node,
+            communication,
+            1,
+            &(cob_id | PDO_COB_ID_INVALID).to_le_bytes(),
+        )?;
+        self.sdo_download(node, communication, 2, &[config.transmission_type])?;
+        if let Some(inhibit_time) = config.inhibit_time {
+            self.sdo_download(node, communication, 3, &inhibit_time.to_le_bytes())?;
+        }
+        if let Some(event_timer) = config.event_timer {
+            self.sdo_download(node, communication, 5, &event_timer.to_le_bytes())?;
+        }
+
+        self.sdo_download(node, mapping_index, 0, &[0])?;
+        for (i, entry) in config.mapping.iter().enumerate() {
+            self.sdo_download(
+                node,
+                mapping_index,
+                i as u8 + 1,
+                &u32::from(*entry).to_le_bytes(),
+            )?;
+        }
+        self.sdo_download(node, mapping_index, 0, &[config.mapping.len() as u8])?;
+
+        self.sdo_download(node, communication, 1, &cob_id.to_le_bytes())
+    }
+
+    /// Reads the mapping table of transmit PDO `pdo` of `node`.
+    pub fn read_tpdo_mapping(&self, node: u8, pdo: u16) -> Result<Vec<PdoMapping>, CanOpenError> {
+        let index = 0x1A00 + pdo;
+        let count = self.sdo_read_u32(node, index, 0)? as u8;
+        (1..=count)
+            .map(|sub| self.sdo_read_u32(node, index, sub).map(PdoMapping::from))
+            .collect()
+    }
+
+    /// Sends a PDO payload.
+    pub fn send_pdo(&self, cob_id: u32, data: &[u8]) -> Result<(), CanOpenError> {
+        self.send_frame(cob_id, data)
+    }
+}
+
+/// CRC-16-CCITT (polynomial 0x1021, initial value 0) of SDO block transfers.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::{MockEnd, mock_bus};
+
+    use std::collections::HashMap;
+    use std::thread;
+
+    const NODE: u8 = 5;
+
+    /// Minimal SDO server with expedited, segmented and block transfers.
+    struct Server<'a> {
+        end: &'a MockEnd,
+        objects: HashMap<(u16, u8), Vec<u8>>,
+    }
+
+    impl Server<'_> {
+        fn recv(&self) -> Option<[u8; 8]> {
+            let frame = socket::poll_until(Instant::now() + Duration::from_millis(300), || {
+                self.end.recv_frame()
+            })
+            .unwrap()?;
+            assert_eq!(frame.can_id(), COB_SDO_RX + NODE as u32);
+            Some(frame.data().try_into().unwrap())
+        }
+
+        fn send(&self, data: [u8; 8]) {
+            self.end
+                .send(
+                    CanFrame::new(COB_SDO_TX + NODE as u32, MessageType::Standard, &data).unwrap(),
+                )
+                .unwrap();
+        }
+
+        fn run(mut self) -> HashMap<(u16, u8), Vec<u8>> {
+            while let Some(request) = self.recv() {
+                let key = (u16::from_le_bytes([request[1], request[2]]), request[3]);
+                let mux = [request[1], request[2], request[3]];
+                match request[0] >> 5 {
+                    // Initiate download
+                    1 => {
+                        if request[0] & 0x02 != 0 {
+                            let len = 4 - ((request[0] >> 2) & 0x03) as usize;
+                            self.objects.insert(key, request[4..4 + len].to_vec());
+                            self.send([0x60, mux[0], mux[1], mux[2], 0, 0, 0, 0]);
+                            continue;
+                        }
+                        self.send([0x60, mux[0], mux[1], mux[2], 0, 0, 0, 0]);
+                        let mut data = Vec::new();
+                        loop {
+                            let segment = self.recv().unwrap();
+                            let unused = ((segment[0] >> 1) & 0x07) as usize;
+                            data.extend_from_slice(&segment[1..8 - unused]);
+                            self.send([0x20 | (segment[0] & 0x10), 0, 0, 0, 0, 0, 0, 0]);
+                            if segment[0] & 0x01 != 0 {
+                                break;
+                            }
+                        }
+                        self.objects.insert(key, data);
+                    }
+                    // Initiate upload
+                    2 => {
+                        let Some(data) = self.objects.get(&key).cloned() else {
+                            self.send([0x80, mux[0], mux[1], mux[2], 0x00, 0x00, 0x02, 0x06]);
+                            continue;
+                        };
+                        if data.len() <= 4 {
+                            let mut response = [
+                                0x43 | ((4 - data.len() as u8) << 2),
+                                mux[0],
+                                mux[1],
+                                mux[2],
+                                0,
+                                0,
+                                0,
+                                0,
+                            ];
+                            response[4..4 + data.len()].copy_from_slice(&data);
+                            self.send(response);
+                            continue;
+                        }
+                        let size = (data.len() as u32).to_le_bytes();
+                        self.send([
+                            0x41, mux[0], mux[1], mux[2], size[0], size[1], size[2], size[3],
+                        ]);
+                        for (i, chunk) in data.chunks(7).enumerate() {
+                            let request = self.recv().unwrap();
+                            assert_eq!(request[0], 0x60 | ((i as u8 & 1) << 4));
+                            let last = (i + 1) * 7 >= data.len();
+                            let mut segment = [0; 8];
+                            segment[0] =
+                                (request[0] & 0x10) | ((7 - chunk.len() as u8) << 1) | last as u8;
+                            segment[1..1 + chunk.len()].copy_from_slice(chunk);
+                            self.send(segment);
+                        }
+                    }
+                    // Block upload
+                    5 => {
+                        let data = self.objects[&key].clone();
+                        let size = (data.len() as u32).to_le_bytes();
+                        self.send([
+                            0xC6, mux[0], mux[1], mux[2], size[0], size[1], size[2], size[3],
+                        ]);
+                        assert_eq!(self.recv().unwrap()[0], 0xA3);
+
+                        let segments: Vec<&[u8]> = data.chunks(7).collect();
+                        for (i, chunk) in segments.iter().enumerate() {
+                            let mut segment = [0; 8];
+                            segment[0] = (i % 127 + 1) as u8
+                                | if i + 1 == segments.len() { 0x80 } else { 0 };
+                            segment[1..1 + chunk.len()].copy_from_slice(chunk);
+                            self.send(segment);
+                            if i + 1 == segments.len() || (i + 1) % 127 == 0 {
+                                assert_eq!(self.recv().unwrap()[0], 0xA2);
+                            }
+                        }
+                        let unused = ((7 - data.len() % 7) % 7) as u8;
+                        let crc = crc16(&data).to_le_bytes();
+                        self.send([0xC1 | unused << 2, crc[0], crc[1], 0, 0, 0, 0, 0]);
+                        assert_eq!(self.recv().unwrap()[0], 0xA1);
+                    }
+                    // Block download, acknowledging blocks of 4 segments
+                    6 => {
+                        let size =
+                            u32::from_le_bytes([request[4], request[5], request[6], request[7]])
+                                as usize;
+                        self.send([0xA4, mux[0], mux[1], mux[2], 4, 0, 0, 0]);
+                        let mut data = Vec::new();
+                        loop {
+                            let segment = self.recv().unwrap();
+                            if segment[0] >> 5 == 6 {
+                                data.truncate(size);
+                                assert_eq!(
+                                    crc16(&data),
+                                    u16::from_le_bytes([segment[1], segment[2]])
+                                );
+                                self.send([0xA1, 0, 0, 0, 0, 0, 0, 0]);
+                                break;
+                            }
+                            data.extend_from_slice(&segment[1..]);
+                            let sequence = segment[0] & 0x7F;
+                            if sequence == 4 || segment[0] & 0x80 != 0 {
+                                self.send([0xA2, sequence, 4, 0, 0, 0, 0, 0]);
+                            }
+                        }
+                        self.objects.insert(key, data);
+                    }
+                    _ => panic!("unexpected request {request:02X?}"),
+                }
+            }
+            self.objects
+        }
+    }
+
+    fn with_server(test: impl FnOnce(&CanOpenMaster)) -> HashMap<(u16, u8), Vec<u8>> {
+        let (a, b) = mock_bus();
+        let mut objects = HashMap::new();
+        objects.insert((0x1000, 0), vec![0x92, 0x01, 0x02, 0x00]);
+        objects.insert((0x1008, 0), b"PEAK test drive controller".to_vec());
+        objects.insert((0x1F50, 1), (0..2000).map(|i| (i % 251) as u8).collect());
+
+        thread::scope(|scope| {
+            let server = scope.spawn(|| Server { end: &b, objects }.run());
+            test(&CanOpenMaster::new(&a));
+            server.join().unwrap()
+        })
+    }
+
+    #[test]
+    fn canopen_sdo_expedited_and_segmented() {
+        let objects = with_server(|master| {
+            assert_eq!(master.sdo_read_u32(NODE, 0x1000, 0).unwrap(), 0x0002_0192);
+            assert_eq!(
+                master.sdo_upload(NODE, 0x1008, 0).unwrap(),
+                b"PEAK test drive controller"
+            );
+
+            master.sdo_download(NODE, 0x6040, 0, &[0x0F, 0x00]).unwrap();
+            master
+                .sdo_download(NODE, 0x2000, 1, b"calibration table")
+                .unwrap();
+
+            match master.sdo_upload(NODE, 0x2001, 0) {
+                Err(CanOpenError::SdoAbort { code, .. }) => {
+                    assert_eq!(code, SdoAbortCode(0x0602_0000));
+                    assert_eq!(
+                        code.description(),
+                        "object does not exist in the object dictionary"
+                    );
+                }
+                other => panic!("unexpected result {other:?}"),
+            }
+        });
+        assert_eq!(objects[&(0x6040, 0)], vec![0x0F, 0x00]);
+        assert_eq!(objects[&(0x2000, 1)], b"calibration table");
+    }
+
+    #[test]
+    fn canopen_sdo_block_transfers() {
+        let image: Vec<u8> = (0..100).map(|i| (i * 3) as u8).collect();
+        let objects = with_server(|master| {
+            let data = master.sdo_block_upload(NODE, 0x1F50, 1).unwrap();
+            assert_eq!(data.len(), 2000);
+            assert_eq!(data, (0..2000).map(|i| (i % 251) as u8).collect::<Vec<_>>());
+
+            master.sdo_block_download(NODE, 0x1F50, 2, &image).unwrap();
+        });
+        assert_eq!(objects[&(0x1F50, 2)], image);
+    }
+
+    #[test]
+    fn canopen_pdo_configuration() {
+        let config = PdoConfig {
+            cob_id: 0x185,
+            transmission_type: 1,
+            inhibit_time: None,
+            event_timer: Some(100),
+            mapping: vec![
+                PdoMapping::new(0x6041, 0, 16),
+                PdoMapping::new(0x6064, 0, 32),
+            ],
+        };
+        let objects = with_server(|master| {
+            master.configure_tpdo(NODE, 0, &config).unwrap();
+            assert_eq!(master.read_tpdo_mapping(NODE, 0).unwrap(), config.mapping);
+        });
+        assert_eq!(objects[&(0x1800, 1)], 0x185u32.to_le_bytes());
+        assert_eq!(objects[&(0x1800, 5)], 100u16.to_le_bytes());
+        assert_eq!(objects[&(0x1A00, 1)], 0x6041_0010u32.to_le_bytes());
+
+        let values = decode_pdo(&config.mapping, &[0x37, 0x02, 0x10, 0x27, 0x00, 0x00]);
+        assert_eq!(values, vec![0x0237, 10_000]);
+    }
+
+    #[test]
+    fn canopen_events_and_heartbeat() {
+        let (a, b) = mock_bus();
+        let master = CanOpenMaster::new(&a);
+        master.monitor_heartbeat(NODE, Duration::from_millis(30));
+        master.monitor_heartbeat(6, Duration::from_millis(30));
+
+        master.nmt(NmtCommand::Start, 0).unwrap();
+        master.sync_with_counter(3).unwrap();
+        assert_eq!(b.recv_frame().unwrap().data(), &[0x01, 0x00]);
+        assert_eq!(b.recv_frame().unwrap().data(), &[3]);
+
+        let send = |id: u32, data: &[u8]| {
+            b.send(CanFrame::new(id, MessageType::Standard, data).unwrap())
+                .unwrap()
+        };
+        assert!(master.expired_heartbeats().is_empty());
+        thread::sleep(Duration::from_millis(40));
+        send(0x705, &[0x05]);
+        send(0x085, &[0x10, 0x32, 0x04, 1, 2, 3, 4, 5]);
+        send(0x185, &[0xAA]);
+
+        assert_eq!(
+            master.poll(Duration::from_millis(10)).unwrap(),
+            Some(CanOpenEvent::Heartbeat {
+                node: NODE,
+                state: NmtState::Operational
+            })
+        );
+        assert_eq!(master.node_state(NODE), Some(NmtState::Operational));
+        assert_eq!(master.expired_heartbeats(), vec![6]);
+
+        assert_eq!(
+            master.poll(Duration::from_millis(10)).unwrap(),
+            Some(CanOpenEvent::Emergency(Emergency {
+                node: NODE,
+                error_code: 0x3210,
+                error_register: 0x04,
+                data: [1, 2, 3, 4, 5],
+            }))
+        );
+        assert!(matches!(
+            master.poll(Duration::from_millis(10)).unwrap(),
+            Some(CanOpenEvent::Pdo { cob_id: 0x185, .. })
+        ));
+        assert_eq!(master.poll(Duration::from_millis(1)).unwrap(), None);
+    }
+
+    #[test]
+    fn canopen_node_guarding() {
+        let (a, b) = mock_bus();
+        let master = CanOpenMaster::new(&a);
+        let respond = |value: u8| {
+            b.send(CanFrame::new(0x705, MessageType::Standard, &[value]).unwrap())
+                .unwrap()
+        };
+
+        respond(0x7F);
+        assert_eq!(master.guard(NODE).unwrap(), NmtState::PreOperational);
+        respond(0x85);
+        assert_eq!(master.guard(NODE).unwrap(), NmtState::Operational);
+        respond(0x85);
+        assert!(matches!(master.guard(NODE), Err(CanOpenError::Protocol(_))));
+        assert!(b.recv_frame().unwrap().is_remote_frame());
+    }
+
+    #[test]
+    fn canopen_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+}
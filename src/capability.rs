@@ -0,0 +1,392 @@
+//! Capability reports combining what a device family supports in this crate with what the
+//! hardware reports at runtime.
+//!
+//! The `Has*` marker traits decide at compile time which operations a socket or bus type
+//! offers, while [ChannelFeatures] tells at runtime whether the hardware behind a channel
+//! supports CAN FD, an interframe delay and digital I/O. The two can disagree: every USB
+//! socket implements [SendCanFd](crate::socket::SendCanFd), yet not every USB adapter is
+//! FD capable. [Capabilities] holds both views, its accessors answer whether an operation
+//! can actually be used.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::capability::ChannelCapabilities;
+//! let capabilities = UsbBus::USB1.capabilities()?;
+//! if !capabilities.fd() {
+//!     println!("{} is not CAN FD capable", capabilities.hardware_name);
+//! }
+//! # Ok::<(), peak_can::error::CanError>(())
+//! ```
+
+use crate::bus::{DngBus, IsaBus, LanBus, PccBus, PciBus, UsbBus};
+use crate::channel::Channel;
+use crate::error::CanError;
+use crate::hw::{DeviceId, HardwareName};
+use crate::info::{ChannelFeatures, FirmwareVersion};
+use crate::peak_can;
+use crate::socket::dng::DngCanSocket;
+use crate::socket::isa::IsaCanSocket;
+use crate::socket::lan::LanCanSocket;
+use crate::socket::pcc::PccCanSocket;
+use crate::socket::pci::PciCanSocket;
+use crate::socket::usb::UsbCanSocket;
+
+use std::fmt;
+
+/* Device families */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceType {
+    Isa,
+    Dng,
+    Pci,
+    Usb,
+    Pcc,
+    Lan,
+}
+
+impl DeviceType {
+    /// Family of a channel handle.
+    pub fn of_channel(channel: u16) -> Option<DeviceType> {
+        if UsbBus::try_from(channel).is_ok() {
+            Some(DeviceType::Usb)
+        } else if PciBus::try_from(channel).is_ok() {
+            Some(DeviceType::Pci)
+        } else if LanBus::try_from(channel).is_ok() {
+            Some(DeviceType::Lan)
+        } else if IsaBus::try_from(channel).is_ok() {
+            Some(DeviceType::Isa)
+        } else if DngBus::try_from(channel).is_ok() {
+            Some(DeviceType::Dng)
+        } else if PccBus::try_from(channel).is_ok() {
+            Some(DeviceType::Pcc)
+        } else {
+            None
+        }
+    }
+
+    /// Operations the crate offers for the family.
+    pub const fn family(&self) -> FamilySupport {
+        const NONE: FamilySupport = FamilySupport {
+            fd: false,
+            listen_only: false,
+            bus_off_autoreset: false,
+            bitrate_adapting: false,
+            interframe_delay: false,
+            five_volts: false,
+            echo_frames: false,
+            digital_io: false,
+            device_id: false,
+            identifying: false,
+            ip_address: false,
+        };
+        match self {
+            DeviceType::Usb => FamilySupport {
+                fd: true,
+                listen_only: true,
+                bus_off_autoreset: true,
+                bitrate_adapting: true,
+                interframe_delay: true,
+                five_volts: true,
+                echo_frames: true,
+                digital_io: true,
+                device_id: true,
+                identifying: true,
+                ip_address: false,
+            },
+            DeviceType::Lan => FamilySupport {
+                echo_frames: true,
+                device_id: true,
+                ip_address: true,
+                ..NONE
+            },
+            DeviceType::Pci => FamilySupport {
+                echo_frames: true,
+                device_id: true,
+                ..NONE
+            },
+            DeviceType::Pcc => FamilySupport {
+                five_volts: true,
+                ..NONE
+            },
+            DeviceType::Isa | DeviceType::Dng => NONE,
+        }
+    }
+}
+
+/// Device type as reported in [ChannelInformation](crate::hw::ChannelInformation).
+impl TryFrom<u32> for DeviceType {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            peak_can::PEAK_ISA => Ok(DeviceType::Isa),
+            peak_can::PEAK_DNG => Ok(DeviceType::Dng),
+            peak_can::PEAK_PCI => Ok(DeviceType::Pci),
+            peak_can::PEAK_USB => Ok(DeviceType::Usb),
+            peak_can::PEAK_PCC => Ok(DeviceType::Pcc),
+            peak_can::PEAK_LAN => Ok(DeviceType::Lan),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DeviceType::Isa => "ISA",
+            DeviceType::Dng => "Dongle",
+            DeviceType::Pci => "PCI",
+            DeviceType::Usb => "USB",
+            DeviceType::Pcc => "PC Card",
+            DeviceType::Lan => "LAN",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Operations a family's sockets implement, mirroring their `Has*` marker traits.
+///
+/// The table in [DeviceType::family] is checked against the traits at compile time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FamilySupport {
+    pub fd: bool,
+    pub listen_only: bool,
+    pub bus_off_autoreset: bool,
+    /// Set on the bus before the channel is opened, so offered by the bus type rather than
+    /// the socket.
+    pub bitrate_adapting: bool,
+    pub interframe_delay: bool,
+    pub five_volts: bool,
+    pub echo_frames: bool,
+    pub digital_io: bool,
+    pub device_id: bool,
+    pub identifying: bool,
+    pub ip_address: bool,
+}
+
+/* Reports */
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub channel: u16,
+    pub device_type: DeviceType,
+    pub hardware_name: String,
+    /// Only available on initialized channels.
+    pub firmware_version: Option<String>,
+    pub device_id: Option<u32>,
+    pub family: FamilySupport,
+    /// Runtime [ChannelFeatures] of the hardware.
+    pub fd_capable: bool,
+    pub delay_capable: bool,
+    pub io_capable: bool,
+}
+
+impl Capabilities {
+    /// CAN FD frames can be sent and received.
+    pub fn fd(&self) -> bool {
+        self.family.fd && self.fd_capable
+    }
+
+    pub fn interframe_delay(&self) -> bool {
+        self.family.interframe_delay && self.delay_capable
+    }
+
+    pub fn digital_io(&self) -> bool {
+        self.family.digital_io && self.io_capable
+    }
+
+    /// Features on which family support and hardware disagree, named as the fields of
+    /// [FamilySupport].
+    pub fn mismatches(&self) -> Vec<&'static str> {
+        [
+            ("fd", self.family.fd, self.fd_capable),
+            (
+                "interframe_delay",
+                self.family.interframe_delay,
+                self.delay_capable,
+            ),
+            ("digital_io", self.family.digital_io, self.io_capable),
+        ]
+        .into_iter()
+        .filter(|(_, family, hardware)| family != hardware)
+        .map(|(name, _, _)| name)
+        .collect()
+    }
+}
+
+pub trait ChannelCapabilities {
+    fn capabilities(&self) -> Result<Capabilities, CanError>;
+}
+
+fn report<C: Channel + ChannelFeatures + HardwareName>(
+    channel: &C,
+    device_type: DeviceType,
+    firmware_version: Option<String>,
+    device_id: Option<u32>,
+) -> Result<Capabilities, CanError> {
+    Ok(Capabilities {
+        channel: channel.channel(),
+        device_type,
+        hardware_name: channel.hardware_name()?,
+        firmware_version,
+        device_id,
+        family: device_type.family(),
+        fd_capable: channel.is_fd_capable()?,
+        delay_capable: channel.is_delay_capable()?,
+        io_capable: channel.is_io_capable()?,
+    })
+}
+
+macro_rules! impl_socket_capabilities {
+    ($($socket:ty: $device_type:expr, $device_id:expr;)*) => {$(
+        impl ChannelCapabilities for $socket {
+            fn capabilities(&self) -> Result<Capabilities, CanError> {
+                let device_id: fn(&$socket) -> Option<u32> = $device_id;
+                report(self, $device_type, self.firmware_version().ok(), device_id(self))
+            }
+        }
+    )*};
+}
+
+impl_socket_capabilities! {
+    UsbCanSocket: DeviceType::Usb, |socket| socket.device_id().ok();
+    LanCanSocket: DeviceType::Lan, |socket| socket.device_id().ok();
+    PciCanSocket: DeviceType::Pci, |socket| socket.device_id().ok();
+    DngCanSocket: DeviceType::Dng, |_| None;
+    IsaCanSocket: DeviceType::Isa, |_| None;
+    PccCanSocket: DeviceType::Pcc, |_| None;
+}
+
+macro_rules! impl_bus_capabilities {
+    ($($bus:ty: $device_type:expr, $device_id:expr;)*) => {$(
+        /// Reported before the channel is opened, without firmware version.
+        impl ChannelCapabilities for $bus {
+            fn capabilities(&self) -> Result<Capabilities, CanError> {
+                let device_id: fn(&$bus) -> Option<u32> = $device_id;
+                report(self, $device_type, None, device_id(self))
+            }
+        }
+    )*};
+}
+
+impl_bus_capabilities! {
+    UsbBus: DeviceType::Usb, |bus| bus.device_id().ok();
+    LanBus: DeviceType::Lan, |bus| bus.device_id().ok();
+    PciBus: DeviceType::Pci, |bus| bus.device_id().ok();
+    DngBus: DeviceType::Dng, |_| None;
+    IsaBus: DeviceType::Isa, |_| None;
+    PccBus: DeviceType::Pcc, |_| None;
+}
+
+/* Family table checks */
+
+/// Whether `$type` implements `$trait`, decided at compile time: the inherent constant
+/// only exists when the bound holds and then takes precedence over the trait constant.
+macro_rules! implements {
+    ($type:ty: $trait:path) => {{
+        struct Probe<T: ?Sized>(std::marker::PhantomData<T>);
+        #[allow(dead_code)]
+        impl<T: ?Sized + $trait> Probe<T> {
+            const IMPLEMENTS: bool = true;
+        }
+        #[allow(dead_code)]
+        trait NotImplemented {
+            const IMPLEMENTS: bool = false;
+        }
+        impl<T: ?Sized> NotImplemented for T {}
+        <Probe<$type>>::IMPLEMENTS
+    }};
+}
+
+/// Fails to compile when a row of [DeviceType::family] disagrees with the type it mirrors.
+macro_rules! assert_row {
+    ($row:expr, $type:ty: $trait:path) => {
+        assert!(
+            $row == implements!($type: $trait),
+            concat!(
+                stringify!($row),
+                " disagrees with ",
+                stringify!($type),
+                ": ",
+                stringify!($trait)
+            )
+        );
+    };
+}
+
+macro_rules! assert_family_support {
+    ($($device_type:expr => $bus:ty, $socket:ty;)*) => {$(
+        const _: () = {
+            let family = $device_type.family();
+            assert_row!(family.fd, $socket: crate::socket::SendCanFd);
+            assert_row!(family.listen_only, $socket: crate::special::SetListenOnly);
+            assert_row!(family.bus_off_autoreset, $socket: crate::special::SetBusOffAutoreset);
+            assert_row!(family.bitrate_adapting, $bus: crate::special::SetBitrateAdapting);
+            assert_row!(family.interframe_delay, $socket: crate::special::SetInterframeDelay);
+            assert_row!(family.five_volts, $socket: crate::special::SetFiveVoltsPower);
+            assert_row!(family.echo_frames, $socket: crate::df::SetAllowEchoFrames);
+            assert_row!(family.digital_io, $socket: crate::io::DigitalValue);
+            assert_row!(family.device_id, $socket: crate::hw::DeviceId);
+            assert_row!(family.identifying, $socket: crate::hw::ChannelIdentifying);
+            assert_row!(family.ip_address, $socket: crate::hw::IpAddress);
+        };
+    )*};
+}
+
+assert_family_support! {
+    DeviceType::Usb => UsbBus, UsbCanSocket;
+    DeviceType::Lan => LanBus, LanCanSocket;
+    DeviceType::Pci => PciBus, PciCanSocket;
+    DeviceType::Pcc => PccBus, PccCanSocket;
+    DeviceType::Isa => IsaBus, IsaCanSocket;
+    DeviceType::Dng => DngBus, DngCanSocket;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn family_and_hardware() {
+        assert_eq!(
+            DeviceType::of_channel(peak_can::PEAK_USBBUS1 as u16),
+            Some(DeviceType::Usb)
+        );
+        assert_eq!(
+            DeviceType::of_channel(peak_can::PEAK_LANBUS9 as u16),
+            Some(DeviceType::Lan)
+        );
+        assert_eq!(DeviceType::of_channel(0), None);
+        assert_eq!(
+            DeviceType::try_from(peak_can::PEAK_PCI),
+            Ok(DeviceType::Pci)
+        );
+
+        let mut capabilities = Capabilities {
+            channel: peak_can::PEAK_USBBUS1 as u16,
+            device_type: DeviceType::Usb,
+            hardware_name: String::from("PCAN-USB"),
+            firmware_version: None,
+            device_id: Some(0),
+            family: DeviceType::Usb.family(),
+            fd_capable: false,
+            delay_capable: true,
+            io_capable: true,
+        };
+        assert!(!capabilities.fd() && capabilities.interframe_delay());
+        assert_eq!(capabilities.mismatches(), ["fd"]);
+
+        capabilities.device_type = DeviceType::Pci;
+        capabilities.family = DeviceType::Pci.family();
+        capabilities.fd_capable = true;
+        assert!(!capabilities.fd() && !capabilities.digital_io());
+        assert!(capabilities.family.echo_frames && !capabilities.family.listen_only);
+        assert_eq!(
+            capabilities.mismatches(),
+            ["fd", "interframe_delay", "digital_io"]
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Channel names, bit rate options and the socket of whichever family a channel belongs to,
+//! shared by the `pcan` and `pcan-log` command line tools.
+//!
+//! Built with the `cli` feature and hidden from the documentation, it is not part of the
+//! library API.
+
+use crate::autobaud;
+use crate::bus::{DngBus, IsaBus, LanBus, PccBus, PciBus, UsbBus};
+use crate::capability::DeviceType;
+use crate::device::{DeviceError, FindByDeviceId};
+use crate::df::{SetAllowErrorFrames, SetAllowStatusFrames};
+use crate::error::CanError;
+use crate::filter::AnyFrame;
+use crate::info::{DataBusSpeed, FirmwareVersion, NominalBusSpeed};
+use crate::replay::ReplayTarget;
+use crate::socket::dng::DngCanSocket;
+use crate::socket::isa::IsaCanSocket;
+use crate::socket::lan::LanCanSocket;
+use crate::socket::pcc::PccCanSocket;
+use crate::socket::pci::PciCanSocket;
+use crate::socket::usb::UsbCanSocket;
+use crate::socket::{Baudrate, RecvCan, RecvCanFd, SendCan, SendCanFd};
+use crate::status::{BusStatus, ErrorState};
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Highest channel handle in use, LAN channels end at 0x810.
+const MAX_HANDLE: u16 = 0x0FFF;
+
+/* Channel names */
+
+/// Channel named as PCAN-View does (`USB1`, `PCI2`, `LAN1`), by its handle (`0x51`) or by
+/// the device ID of its adapter (`USB@7`).
+///
+/// Device IDs are looked up by [Bus::resolve] when the channel is used, parsing a name does
+/// not load the driver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bus {
+    Usb(UsbBus),
+    Pci(PciBus),
+    Lan(LanBus),
+    Isa(IsaBus),
+    Dng(DngBus),
+    Pcc(PccBus),
+    /// Adapter of a USB, PCI or LAN family with a device ID, not looked up yet.
+    DeviceId(DeviceType, u32),
+}
+
+impl Bus {
+    pub fn from_handle(handle: u16) -> Option<Bus> {
+        UsbBus::try_from(handle)
+            .map(Bus::Usb)
+            .or_else(|_| PciBus::try_from(handle).map(Bus::Pci))
+            .or_else(|_| LanBus::try_from(handle).map(Bus::Lan))
+            .or_else(|_| IsaBus::try_from(handle).map(Bus::Isa))
+            .or_else(|_| DngBus::try_from(handle).map(Bus::Dng))
+            .or_else(|_| PccBus::try_from(handle).map(Bus::Pcc))
+            .ok()
+    }
+
+    /// Finds the channel of the adapter with a device ID.
+    pub fn resolve(self) -> Result<ResolvedBus, DeviceError> {
+        Ok(match self {
+            Bus::Usb(bus) => ResolvedBus::Usb(bus),
+            Bus::Pci(bus) => ResolvedBus::Pci(bus),
+            Bus::Lan(bus) => ResolvedBus::Lan(bus),
+            Bus::Isa(bus) => ResolvedBus::Isa(bus),
+            Bus::Dng(bus) => ResolvedBus::Dng(bus),
+            Bus::Pcc(bus) => ResolvedBus::Pcc(bus),
+            Bus::DeviceId(DeviceType::Usb, device_id) => {
+                ResolvedBus::Usb(UsbBus::by_device_id(device_id)?)
+            }
+            Bus::DeviceId(DeviceType::Pci, device_id) => {
+                ResolvedBus::Pci(PciBus::by_device_id(device_id)?)
+            }
+            Bus::DeviceId(DeviceType::Lan, device_id) => {
+                ResolvedBus::Lan(LanBus::by_device_id(device_id)?)
+            }
+            Bus::DeviceId(device_type, device_id) => {
+                return Err(DeviceError::NotFound {
+                    device_type,
+                    device_id,
+                });
+            }
+        })
+    }
+}
+
+/// Channel of a [Bus] with its device ID looked up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResolvedBus {
+    Usb(UsbBus),
+    Pci(PciBus),
+    Lan(LanBus),
+    Isa(IsaBus),
+    Dng(DngBus),
+    Pcc(PccBus),
+}
+
+impl ResolvedBus {
+    pub fn handle(&self) -> u16 {
+        match *self {
+            ResolvedBus::Usb(bus) => bus.into(),
+            ResolvedBus::Pci(bus) => bus.into(),
+            ResolvedBus::Lan(bus) => bus.into(),
+            ResolvedBus::Isa(bus) => bus.into(),
+            ResolvedBus::Dng(bus) => bus.into(),
+            ResolvedBus::Pcc(bus) => bus.into(),
+        }
+    }
+}
+
+impl From<ResolvedBus> for Bus {
+    fn from(value: ResolvedBus) -> Self {
+        match value {
+            ResolvedBus::Usb(bus) => Bus::Usb(bus),
+            ResolvedBus::Pci(bus) => Bus::Pci(bus),
+            ResolvedBus::Lan(bus) => Bus::Lan(bus),
+            ResolvedBus::Isa(bus) => Bus::Isa(bus),
+            ResolvedBus::Dng(bus) => Bus::Dng(bus),
+            ResolvedBus::Pcc(bus) => Bus::Pcc(bus),
+        }
+    }
+}
+
+impl fmt::Display for ResolvedBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Bus::from(*self).fmt(f)
+    }
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        if let Some((family, device_id)) = name.split_once('@') {
+            let device_id = parse_device_id(device_id)?;
+            let device_type = match family.to_ascii_lowercase().as_str() {
+                "usb" => DeviceType::Usb,
+                "pci" => DeviceType::Pci,
+                "lan" => DeviceType::Lan,
+                _ => {
+                    return Err(format!(
+                        "unknown channel `{s}`, device IDs are stored by USB, PCI and LAN adapters"
+                    ));
+                }
+            };
+            return Ok(Bus::DeviceId(device_type, device_id));
+        }
+        let handle = match name.strip_prefix("0x").or_else(|| name.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => {
+                let name = name.strip_prefix("PCAN_").unwrap_or(name);
+                let name = name.strip_prefix("pcan_").unwrap_or(name);
+                (1..=MAX_HANDLE).find(|handle| {
+                    Bus::from_handle(*handle)
+                        .is_some_and(|bus| bus.to_string().eq_ignore_ascii_case(name))
+                })
+            }
+        };
+        handle.and_then(Bus::from_handle).ok_or_else(|| {
+            format!(
+                "unknown channel `{s}`, expected a name such as USB1, a handle such as 0x51 or a device ID such as USB@7"
+            )
+        })
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bus::Usb(bus) => write!(f, "{bus:?}"),
+            Bus::Pci(bus) => write!(f, "{bus:?}"),
+            Bus::Lan(bus) => write!(f, "{bus:?}"),
+            Bus::Isa(bus) => write!(f, "{bus:?}"),
+            Bus::Dng(bus) => write!(f, "{bus:?}"),
+            Bus::Pcc(bus) => write!(f, "{bus:?}"),
+            Bus::DeviceId(device_type, device_id) => write!(f, "{device_type}@{device_id}"),
+        }
+    }
+}
+
+/// Device ID in decimal or, with `0x`, in hex.
+pub fn parse_device_id(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("invalid device ID `{s}`"))
+}
+
+/* Bit rates */
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct BitrateArgs {
+    /// Nominal bit rate, e.g. 500K or 1M
+    #[arg(short, long, default_value = "500K", value_parser = parse_baudrate)]
+    pub bitrate: Baudrate,
+    /// CAN FD data bit rate, e.g. 2M; opens the channel in CAN FD mode (USB only)
+    #[arg(short, long, value_parser = parse_bits_per_second)]
+    pub data_bitrate: Option<u32>,
+}
+
+pub fn parse_baudrate(s: &str) -> Result<Baudrate, String> {
+    s.parse()
+        .map_err(|_| format!("unknown bit rate `{s}`, expected one of 1M, 800K, 500K, 250K, 125K, 100K, 95K, 83K, 50K, 47K, 33K, 20K, 10K, 5K"))
+}
+
+/// Bits per second with an optional `K` or `M` suffix.
+pub fn parse_bits_per_second(s: &str) -> Result<u32, String> {
+    let s = s.trim();
+    let (digits, factor) = match s.chars().last() {
+        Some('k' | 'K') => (&s[..s.len() - 1], 1_000.0),
+        Some('m' | 'M') => (&s[..s.len() - 1], 1_000_000.0),
+        _ => (s, 1.0),
+    };
+    digits
+        .parse::<f64>()
+        .ok()
+        .map(|value| value * factor)
+        .filter(|bps| *bps >= 1.0 && *bps <= u32::MAX as f64)
+        .map(|bps| bps.round() as u32)
+        .ok_or_else(|| format!("invalid bit rate `{s}`"))
+}
+
+/* Sockets */
+
+/// Socket of the family a [Channel] belongs to.
+enum Socket {
+    Usb(UsbCanSocket),
+    Pci(PciCanSocket),
+    Lan(LanCanSocket),
+    Isa(IsaCanSocket),
+    Dng(DngCanSocket),
+    Pcc(PccCanSocket),
+}
+
+/// Evaluates `$body` with `$socket` bound to the family specific socket.
+macro_rules! with_socket {
+    ($target:expr, $socket:ident => $body:expr) => {
+        match $target {
+            Socket::Usb($socket) => $body,
+            Socket::Pci($socket) => $body,
+            Socket::Lan($socket) => $body,
+            Socket::Isa($socket) => $body,
+            Socket::Dng($socket) => $body,
+            Socket::Pcc($socket) => $body,
+        }
+    };
+}
+
+/// Initialized channel.
+pub struct Channel {
+    pub bus: ResolvedBus,
+    socket: Socket,
+    fd: bool,
+}
+
+impl Channel {
+    /// Initializes the channel, looking up a device ID first.
+    pub fn open(bus: Bus, bitrate: &BitrateArgs) -> Result<Channel, Box<dyn Error>> {
+        let bus = bus.resolve()?;
+        if let Some(data_bps) = bitrate.data_bitrate {
+            let ResolvedBus::Usb(usb) = bus else {
+                return Err(format!("{bus}: CAN FD is only supported on USB channels").into());
+            };
+            let nominal_bps = bitrate.bitrate.bits_per_second();
+            let timing = autobaud::fd_bit_timing(nominal_bps, data_bps).ok_or_else(|| {
+                format!(
+                    "no CAN FD bit timing for {nominal_bps} bit/s nominal and {data_bps} bit/s data"
+                )
+            })?;
+            let socket = UsbCanSocket::open_fd_with_timing(usb, &timing)?;
+            return Ok(Channel {
+                bus,
+                socket: Socket::Usb(socket),
+                fd: true,
+            });
+        }
+
+        let baud = bitrate.bitrate;
+        let socket = match bus {
+            ResolvedBus::Usb(bus) => Socket::Usb(UsbCanSocket::open(bus, baud)?),
+            ResolvedBus::Pci(bus) => Socket::Pci(PciCanSocket::open(bus, baud)?),
+            ResolvedBus::Lan(bus) => Socket::Lan(LanCanSocket::open(bus, baud)?),
+            ResolvedBus::Isa(bus) => Socket::Isa(IsaCanSocket::open(bus, baud)?),
+            ResolvedBus::Dng(bus) => Socket::Dng(DngCanSocket::open(bus, baud)?),
+            ResolvedBus::Pcc(bus) => Socket::Pcc(PccCanSocket::open(bus, baud)?),
+        };
+        Ok(Channel {
+            bus,
+            socket,
+            fd: false,
+        })
+    }
+
+    pub fn is_fd(&self) -> bool {
+        self.fd
+    }
+
+    /// Next received frame with its timestamp in microseconds.
+    pub fn recv(&self) -> Result<(AnyFrame, u64), CanError> {
+        if let (Socket::Usb(socket), true) = (&self.socket, self.fd) {
+            return socket
+                .recv_fd()
+                .map(|(frame, timestamp)| (AnyFrame::Fd(frame), timestamp));
+        }
+        with_socket!(&self.socket, socket => socket
+            .recv()
+            .map(|(frame, timestamp)| (AnyFrame::Can(frame), timestamp.as_micros())))
+    }
+
+    /// Sends classic frames in CAN FD format on channels opened for CAN FD, which cannot send
+    /// remote frames.
+    pub fn send(&self, frame: &AnyFrame) -> Result<(), CanError> {
+        match &self.socket {
+            Socket::Usb(socket) if self.fd => {
+                socket.send_fd(frame.to_fd().ok_or(CanError::IllData)?)
+            }
+            socket => {
+                let frame = frame.to_can().ok_or(CanError::IllOperation)?;
+                with_socket!(socket, socket => socket.send(frame))
+            }
+        }
+    }
+
+    /// Delivers error and status frames along with the bus traffic.
+    pub fn allow_error_frames(&self, value: bool) -> Result<(), CanError> {
+        with_socket!(&self.socket, socket => {
+            socket.allow_error_frames(value)?;
+            socket.allow_status_frames(value)
+        })
+    }
+
+    pub fn bus_status(&self) -> Result<ErrorState, CanError> {
+        with_socket!(&self.socket, socket => socket.bus_status())
+    }
+
+    pub fn firmware_version(&self) -> Result<String, CanError> {
+        with_socket!(&self.socket, socket => socket.firmware_version())
+    }
+
+    pub fn nominal_bus_speed(&self) -> Result<u32, CanError> {
+        with_socket!(&self.socket, socket => socket.nominal_bus_speed())
+    }
+
+    pub fn data_bus_speed(&self) -> Result<u32, CanError> {
+        with_socket!(&self.socket, socket => socket.data_bus_speed())
+    }
+
+    /// The socket as replay target, in CAN FD format on channels opened for CAN FD.
+    pub fn replay_target(&self) -> ReplayTarget<'_> {
+        match &self.socket {
+            Socket::Usb(socket) if self.fd => ReplayTarget::Fd(socket),
+            socket => with_socket!(socket, socket => ReplayTarget::Can(socket)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_names() {
+        assert_eq!("USB1".parse(), Ok(Bus::Usb(UsbBus::USB1)));
+        assert_eq!("pcan_usb16".parse(), Ok(Bus::Usb(UsbBus::USB16)));
+        assert_eq!("lan2".parse(), Ok(Bus::Lan(LanBus::LAN2)));
+        assert_eq!("0x51".parse(), Ok(Bus::Usb(UsbBus::USB1)));
+        assert!("USB17".parse::<Bus>().is_err());
+        assert_eq!("usb@7".parse(), Ok(Bus::DeviceId(DeviceType::Usb, 7)));
+        assert_eq!("LAN@0x10".parse(), Ok(Bus::DeviceId(DeviceType::Lan, 16)));
+        assert_eq!(Bus::DeviceId(DeviceType::Pci, 3).to_string(), "PCI@3");
+        assert_eq!(Bus::Usb(UsbBus::USB1).resolve().unwrap().handle(), 0x51);
+        assert!("ISA@1".parse::<Bus>().is_err());
+        assert_eq!(Bus::Pci(PciBus::PCI3).to_string(), "PCI3");
+    }
+
+    #[test]
+    fn bit_rates() {
+        assert_eq!(parse_bits_per_second("2M"), Ok(2_000_000));
+        assert_eq!(parse_bits_per_second("833.333k"), Ok(833_333));
+        assert_eq!(parse_bits_per_second("500000"), Ok(500_000));
+        assert!(parse_bits_per_second("fast").is_err());
+        assert_eq!(parse_baudrate("125k"), Ok(Baudrate::Baud125K));
+    }
+}
@@ -0,0 +1,800 @@
+//! Declarative channel configuration.
+//!
+//! A [ChannelConfig] collects the settings otherwise made through [special](crate::special),
+//! [df](crate::df) and [trace](crate::trace) calls, plus the bit rate the channel is opened
+//! with. Settings left at `None` are not touched. [ChannelConfig::apply] writes all
+//! settings or none of them: when one fails, the settings written before it are restored
+//! and the error names the failing setting. [ChannelConfig::snapshot] reads the current
+//! configuration back from a live channel.
+//!
+//! With the `toml` feature, configurations load from and save to TOML:
+//!
+//! ```toml
+//! listen_only = true
+//! error_frames = true
+//! acceptance_11bit = { code = 0x700, mask = 0x0FF }
+//!
+//! [bitrate]
+//! baudrate = "500K"
+//!
+//! [trace]
+//! location = "/var/log/can"
+//! mode = "segmented"
+//! enabled = true
+//! ```
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::config::{Bitrate, ChannelConfig};
+//! # use peak_can::socket::Baudrate;
+//! let config = ChannelConfig {
+//!     bitrate: Some(Bitrate::Baudrate(Baudrate::Baud500K)),
+//!     listen_only: Some(true),
+//!     error_frames: Some(true),
+//!     ..Default::default()
+//! };
+//! let socket = config.open_usb(UsbBus::USB1)?;
+//! println!("{:?}", ChannelConfig::snapshot(&socket)?);
+//! # Ok::<(), peak_can::config::ConfigError>(())
+//! ```
+
+use crate::autobaud::{baudrate_of, fd_bit_timing};
+use crate::bus::UsbBus;
+use crate::df::{
+    AcceptanceFilter11Bit, AcceptanceFilter29Bit, AllowEchoFrames, AllowErrorFrames,
+    AllowRTRFrames, AllowStatusFrames, SetAcceptanceFilter11Bit, SetAcceptanceFilter29Bit,
+    SetAllowEchoFrames, SetAllowErrorFrames, SetAllowRTRFrames, SetAllowStatusFrames,
+};
+use crate::error::CanError;
+use crate::info::{DataBusSpeed, NominalBusSpeed};
+use crate::socket::dng::DngCanSocket;
+use crate::socket::isa::IsaCanSocket;
+use crate::socket::lan::LanCanSocket;
+use crate::socket::pcc::PccCanSocket;
+use crate::socket::pci::PciCanSocket;
+use crate::socket::usb::UsbCanSocket;
+use crate::socket::{Baudrate, CanBitTiming, CanFdBitTiming};
+use crate::special::{
+    BusOffAutoreset, InterframeDelay, ListenOnly, SetBusOffAutoreset, SetInterframeDelay,
+    SetListenOnly,
+};
+use crate::trace::{
+    SetTraceConfigure, SetTraceLocation, SetTraceSize, SetTraceStatus, TraceConfigure, TraceFile,
+    TraceLocation, TraceSize, TraceStatus,
+};
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/* Configuration */
+
+/// Bit rate a channel is opened with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Bitrate {
+    Baudrate(Baudrate),
+    Timing(CanBitTiming),
+    Fd(CanFdBitTiming),
+}
+
+impl Bitrate {
+    /// Arbitration phase bit rate in bits per second.
+    pub fn nominal_bits_per_second(&self) -> u32 {
+        match self {
+            Bitrate::Baudrate(baudrate) => baudrate.bits_per_second(),
+            Bitrate::Timing(timing) => timing.bits_per_second(),
+            Bitrate::Fd(timing) => timing.nominal_bits_per_second(),
+        }
+    }
+
+    /// Data phase bit rate of CAN FD channels.
+    pub fn data_bits_per_second(&self) -> Option<u32> {
+        match self {
+            Bitrate::Fd(timing) => Some(timing.data_bits_per_second()),
+            _ => None,
+        }
+    }
+}
+
+/// Acceptance code and mask as the controller applies them, mask bits set are ignored
+/// when matching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AcceptanceFilter {
+    pub code: u32,
+    pub mask: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct TraceSettings {
+    pub location: Option<PathBuf>,
+    pub size_mb: Option<u8>,
+    pub mode: Option<TraceFile>,
+    /// Applied after the other trace settings.
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct ChannelConfig {
+    /// Used when opening, checked against the bus speed of an open channel.
+    pub bitrate: Option<Bitrate>,
+    pub listen_only: Option<bool>,
+    pub bus_off_autoreset: Option<bool>,
+    /// Delay between transmitted frames in microseconds.
+    pub interframe_delay: Option<u32>,
+    pub status_frames: Option<bool>,
+    pub rtr_frames: Option<bool>,
+    pub error_frames: Option<bool>,
+    pub echo_frames: Option<bool>,
+    pub acceptance_11bit: Option<AcceptanceFilter>,
+    pub acceptance_29bit: Option<AcceptanceFilter>,
+    pub trace: TraceSettings,
+}
+
+/// A single setting of a [ChannelConfig], displayed as its TOML key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Setting {
+    Bitrate,
+    ListenOnly,
+    BusOffAutoreset,
+    InterframeDelay,
+    StatusFrames,
+    RtrFrames,
+    ErrorFrames,
+    EchoFrames,
+    Acceptance11Bit,
+    Acceptance29Bit,
+    TraceLocation,
+    TraceSize,
+    TraceMode,
+    Tracing,
+}
+
+impl Setting {
+    /// All settings in the order they are applied.
+    pub const ALL: [Setting; 14] = [
+        Setting::Bitrate,
+        Setting::ListenOnly,
+        Setting::BusOffAutoreset,
+        Setting::InterframeDelay,
+        Setting::StatusFrames,
+        Setting::RtrFrames,
+        Setting::ErrorFrames,
+        Setting::EchoFrames,
+        Setting::Acceptance11Bit,
+        Setting::Acceptance29Bit,
+        Setting::TraceLocation,
+        Setting::TraceSize,
+        Setting::TraceMode,
+        Setting::Tracing,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Setting::Bitrate => "bitrate",
+            Setting::ListenOnly => "listen_only",
+            Setting::BusOffAutoreset => "bus_off_autoreset",
+            Setting::InterframeDelay => "interframe_delay",
+            Setting::StatusFrames => "status_frames",
+            Setting::RtrFrames => "rtr_frames",
+            Setting::ErrorFrames => "error_frames",
+            Setting::EchoFrames => "echo_frames",
+            Setting::Acceptance11Bit => "acceptance_11bit",
+            Setting::Acceptance29Bit => "acceptance_29bit",
+            Setting::TraceLocation => "trace.location",
+            Setting::TraceSize => "trace.size_mb",
+            Setting::TraceMode => "trace.mode",
+            Setting::Tracing => "trace.enabled",
+        }
+    }
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/* Errors */
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Setting a value failed, settings written before it were restored.
+    Setting(Setting, CanError),
+    /// Setting a value failed and restoring the listed settings failed too, the channel
+    /// is left partially configured.
+    Rollback {
+        setting: Setting,
+        error: CanError,
+        failed: Vec<(Setting, CanError)>,
+    },
+    /// Opening a channel requires a bit rate.
+    MissingBitrate,
+    Io(io::Error),
+    /// The configuration file is not valid TOML or contains unknown settings.
+    Parse(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Setting(setting, err) => write!(f, "cannot set `{setting}`: {err}"),
+            ConfigError::Rollback {
+                setting,
+                error,
+                failed,
+            } => {
+                write!(f, "cannot set `{setting}`: {error}, restoring failed for")?;
+                for (setting, err) in failed {
+                    write!(f, " `{setting}` ({err})")?;
+                }
+                Ok(())
+            }
+            ConfigError::MissingBitrate => write!(f, "no bitrate configured"),
+            ConfigError::Io(err) => write!(f, "cannot read configuration: {err}"),
+            ConfigError::Parse(reason) => write!(f, "invalid configuration: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(value: io::Error) -> Self {
+        ConfigError::Io(value)
+    }
+}
+
+/* Configurable channels */
+
+/// Channels a [ChannelConfig] applies to. Settings a channel family does not support
+/// fail with [CanError::IllParamType].
+pub trait Configurable {
+    /// Reads `setting` from the channel into `config`.
+    fn read_setting(&self, setting: Setting, config: &mut ChannelConfig) -> Result<(), CanError>;
+    /// Writes `setting` from `config` to the channel, nothing is written if it is `None`.
+    fn write_setting(&self, setting: Setting, config: &ChannelConfig) -> Result<(), CanError>;
+}
+
+/// Bus speeds within 1 % count as equal, the driver reports the rate it reached.
+fn same_speed(live: u32, configured: u32) -> bool {
+    live.abs_diff(configured) * 100 <= configured
+}
+
+mod common {
+    use super::*;
+
+    pub(super) fn read<S>(
+        socket: &S,
+        setting: Setting,
+        config: &mut ChannelConfig,
+    ) -> Option<Result<(), CanError>>
+    where
+        S: NominalBusSpeed + DataBusSpeed + AllowStatusFrames + AllowRTRFrames,
+        S: AllowErrorFrames + AcceptanceFilter11Bit + AcceptanceFilter29Bit,
+        S: TraceLocation + TraceSize + TraceConfigure + TraceStatus,
+    {
+        let result = match setting {
+            Setting::Bitrate => read_bitrate(socket).map(|bitrate| {
+                config.bitrate = bitrate;
+            }),
+            Setting::StatusFrames => socket.allows_status_frames().map(|value| {
+                config.status_frames = Some(value);
+            }),
+            Setting::RtrFrames => socket.allows_rtr_frames().map(|value| {
+                config.rtr_frames = Some(value);
+            }),
+            Setting::ErrorFrames => socket.allows_error_frames().map(|value| {
+                config.error_frames = Some(value);
+            }),
+            Setting::Acceptance11Bit => socket.acceptance_filter_11bit().map(|(mask, code)| {
+                config.acceptance_11bit = Some(AcceptanceFilter { code, mask });
+            }),
+            Setting::Acceptance29Bit => socket.acceptance_filter_29bit().map(|(mask, code)| {
+                config.acceptance_29bit = Some(AcceptanceFilter { code, mask });
+            }),
+            Setting::TraceLocation => socket.trace_location().map(|path| {
+                config.trace.location = Some(path);
+            }),
+            Setting::TraceSize => socket.trace_size().map(|size| {
+                config.trace.size_mb = Some(size);
+            }),
+            Setting::TraceMode => socket.trace_configuration().map(|mode| {
+                config.trace.mode = Some(mode);
+            }),
+            Setting::Tracing => socket.is_tracing().map(|enabled| {
+                config.trace.enabled = Some(enabled);
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// A data phase faster than the nominal phase means the channel runs CAN FD.
+    fn read_bitrate<S>(socket: &S) -> Result<Option<Bitrate>, CanError>
+    where
+        S: NominalBusSpeed + DataBusSpeed,
+    {
+        let nominal = socket.nominal_bus_speed()?;
+        Ok(match socket.data_bus_speed() {
+            Ok(data) if data > nominal => fd_bit_timing(nominal, data).map(Bitrate::Fd),
+            _ => baudrate_of(nominal).map(Bitrate::Baudrate),
+        })
+    }
+
+    pub(super) fn write<S>(
+        socket: &S,
+        setting: Setting,
+        config: &ChannelConfig,
+    ) -> Option<Result<(), CanError>>
+    where
+        S: NominalBusSpeed + DataBusSpeed + SetAllowStatusFrames + SetAllowRTRFrames,
+        S: SetAllowErrorFrames + SetAcceptanceFilter11Bit + SetAcceptanceFilter29Bit,
+        S: SetTraceLocation + SetTraceSize + SetTraceConfigure + SetTraceStatus,
+    {
+        let result = match setting {
+            Setting::Bitrate => config.bitrate.map_or(Ok(()), |bitrate| {
+                if !same_speed(
+                    socket.nominal_bus_speed()?,
+                    bitrate.nominal_bits_per_second(),
+                ) {
+                    return Err(CanError::IllParamVal);
+                }
+                match bitrate.data_bits_per_second() {
+                    Some(data) if !same_speed(socket.data_bus_speed()?, data) => {
+                        Err(CanError::IllParamVal)
+                    }
+                    _ => Ok(()),
+                }
+            }),
+            Setting::StatusFrames => config
+                .status_frames
+                .map_or(Ok(()), |value| socket.allow_status_frames(value)),
+            Setting::RtrFrames => config
+                .rtr_frames
+                .map_or(Ok(()), |value| socket.allow_rtr_frames(value)),
+            Setting::ErrorFrames => config
+                .error_frames
+                .map_or(Ok(()), |value| socket.allow_error_frames(value)),
+            Setting::Acceptance11Bit => config.acceptance_11bit.map_or(Ok(()), |filter| {
+                socket.set_acceptance_mask_11bit(filter.code, filter.mask)
+            }),
+            Setting::Acceptance29Bit => config.acceptance_29bit.map_or(Ok(()), |filter| {
+                socket.set_acceptance_mask_29bit(filter.code, filter.mask)
+            }),
+            Setting::TraceLocation => config
+                .trace
+                .location
+                .as_ref()
+                .map_or(Ok(()), |path| socket.set_trace_location(path)),
+            Setting::TraceSize => config
+                .trace
+                .size_mb
+                .map_or(Ok(()), |size| socket.set_trace_size(size)),
+            Setting::TraceMode => config
+                .trace
+                .mode
+                .map_or(Ok(()), |mode| socket.configure_trace(mode)),
+            Setting::Tracing => config
+                .trace
+                .enabled
+                .map_or(Ok(()), |enabled| socket.set_tracing(enabled)),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+mod special {
+    use super::*;
+
+    pub(super) fn read<S>(
+        socket: &S,
+        setting: Setting,
+        config: &mut ChannelConfig,
+    ) -> Option<Result<(), CanError>>
+    where
+        S: ListenOnly + BusOffAutoreset + InterframeDelay,
+    {
+        let result = match setting {
+            Setting::ListenOnly => socket.listen_only().map(|value| {
+                config.listen_only = Some(value);
+            }),
+            Setting::BusOffAutoreset => socket.bus_off_autoreset().map(|value| {
+                config.bus_off_autoreset = Some(value);
+            }),
+            Setting::InterframeDelay => socket.interframe_delay().map(|value| {
+                config.interframe_delay = Some(value);
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    pub(super) fn write<S>(
+        socket: &S,
+        setting: Setting,
+        config: &ChannelConfig,
+    ) -> Option<Result<(), CanError>>
+    where
+        S: SetListenOnly + SetBusOffAutoreset + SetInterframeDelay,
+    {
+        let result = match setting {
+            Setting::ListenOnly => config
+                .listen_only
+                .map_or(Ok(()), |value| socket.set_listen_only(value)),
+            Setting::BusOffAutoreset => config
+                .bus_off_autoreset
+                .map_or(Ok(()), |value| socket.set_bus_off_autoreset(value)),
+            Setting::InterframeDelay => config
+                .interframe_delay
+                .map_or(Ok(()), |value| socket.set_interframe_delay(value)),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+mod echo {
+    use super::*;
+
+    pub(super) fn read<S: AllowEchoFrames>(
+        socket: &S,
+        setting: Setting,
+        config: &mut ChannelConfig,
+    ) -> Option<Result<(), CanError>> {
+        (setting == Setting::EchoFrames).then(|| {
+            socket.allows_echo_frames().map(|value| {
+                config.echo_frames = Some(value);
+            })
+        })
+    }
+
+    pub(super) fn write<S: SetAllowEchoFrames>(
+        socket: &S,
+        setting: Setting,
+        config: &ChannelConfig,
+    ) -> Option<Result<(), CanError>> {
+        (setting == Setting::EchoFrames).then(|| {
+            config
+                .echo_frames
+                .map_or(Ok(()), |value| socket.allow_echo_frames(value))
+        })
+    }
+}
+
+macro_rules! impl_configurable {
+    ($($socket:ty: $($group:ident),+;)*) => {$(
+        impl Configurable for $socket {
+            fn read_setting(
+                &self,
+                setting: Setting,
+                config: &mut ChannelConfig,
+            ) -> Result<(), CanError> {
+                $(if let Some(result) = $group::read(self, setting, config) {
+                    return result;
+                })+
+                Err(CanError::IllParamType)
+            }
+
+            fn write_setting(&self, setting: Setting, config: &ChannelConfig) -> Result<(), CanError> {
+                $(if let Some(result) = $group::write(self, setting, config) {
+                    return result;
+                })+
+                Err(CanError::IllParamType)
+            }
+        }
+    )*};
+}
+
+impl_configurable! {
+    UsbCanSocket: common, special, echo;
+    LanCanSocket: common, echo;
+    PciCanSocket: common, echo;
+    DngCanSocket: common;
+    IsaCanSocket: common;
+    PccCanSocket: common;
+}
+
+/* Applying */
+
+impl ChannelConfig {
+    /// Settings that are not `None`, in the order they are applied.
+    pub fn settings(&self) -> Vec<Setting> {
+        Setting::ALL
+            .into_iter()
+            .filter(|setting| self.is_set(*setting))
+            .collect()
+    }
+
+    fn is_set(&self, setting: Setting) -> bool {
+        match setting {
+            Setting::Bitrate => self.bitrate.is_some(),
+            Setting::ListenOnly => self.listen_only.is_some(),
+            Setting::BusOffAutoreset => self.bus_off_autoreset.is_some(),
+            Setting::InterframeDelay => self.interframe_delay.is_some(),
+            Setting::StatusFrames => self.status_frames.is_some(),
+            Setting::RtrFrames => self.rtr_frames.is_some(),
+            Setting::ErrorFrames => self.error_frames.is_some(),
+            Setting::EchoFrames => self.echo_frames.is_some(),
+            Setting::Acceptance11Bit => self.acceptance_11bit.is_some(),
+            Setting::Acceptance29Bit => self.acceptance_29bit.is_some(),
+            Setting::TraceLocation => self.trace.location.is_some(),
+            Setting::TraceSize => self.trace.size_mb.is_some(),
+            Setting::TraceMode => self.trace.mode.is_some(),
+            Setting::Tracing => self.trace.enabled.is_some(),
+        }
+    }
+
+    /// Applies all settings or none. The current values are read first, a failing write
+    /// restores the settings written before it. The bit rate of an open channel cannot
+    /// change, it is only checked.
+    pub fn apply<S: Configurable>(&self, socket: &S) -> Result<(), ConfigError> {
+        let settings = self.settings();
+
+        let mut previous = ChannelConfig::default();
+        for &setting in &settings {
+            if setting != Setting::Bitrate {
+                socket
+                    .read_setting(setting, &mut previous)
+                    .map_err(|error| ConfigError::Setting(setting, error))?;
+            }
+        }
+
+        for (i, &setting) in settings.iter().enumerate() {
+            if let Err(error) = socket.write_setting(setting, self) {
+                let failed: Vec<_> = settings[..i]
+                    .iter()
+                    .rev()
+                    .filter_map(|&written| {
+                        let result = socket.write_setting(written, &previous);
+                        result.err().map(|err| (written, err))
+                    })
+                    .collect();
+                return Err(if failed.is_empty() {
+                    ConfigError::Setting(setting, error)
+                } else {
+                    ConfigError::Rollback {
+                        setting,
+                        error,
+                        failed,
+                    }
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the configuration of a live channel. Settings the channel does not support
+    /// are left at `None`, as is a bit rate that is no standard [Baudrate] or CAN FD
+    /// timing.
+    pub fn snapshot<S: Configurable>(socket: &S) -> Result<ChannelConfig, ConfigError> {
+        let mut config = ChannelConfig::default();
+        for setting in Setting::ALL {
+            match socket.read_setting(setting, &mut config) {
+                Ok(()) | Err(CanError::IllParamType | CanError::IllOperation) => {}
+                Err(error) => return Err(ConfigError::Setting(setting, error)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Opens a channel at the configured bit rate and applies the other settings.
+    pub fn open_with<S, F>(&self, open: F) -> Result<S, ConfigError>
+    where
+        S: Configurable,
+        F: FnOnce(Bitrate) -> Result<S, CanError>,
+    {
+        let bitrate = self.bitrate.ok_or(ConfigError::MissingBitrate)?;
+        let socket =
+            open(bitrate).map_err(|error| ConfigError::Setting(Setting::Bitrate, error))?;
+        self.apply(&socket)?;
+        Ok(socket)
+    }
+
+    /// Opens a USB channel with the configured baud rate, bit timing or CAN FD timing.
+    pub fn open_usb(&self, bus: UsbBus) -> Result<UsbCanSocket, ConfigError> {
+        self.open_with(|bitrate| match bitrate {
+            Bitrate::Baudrate(baudrate) => UsbCanSocket::open(bus, baudrate),
+            Bitrate::Timing(timing) => UsbCanSocket::open_with_timing(bus, &timing),
+            Bitrate::Fd(timing) => UsbCanSocket::open_fd_with_timing(bus, &timing),
+        })
+    }
+}
+
+/* TOML */
+
+#[cfg(feature = "toml")]
+impl ChannelConfig {
+    pub fn from_toml(text: &str) -> Result<ChannelConfig, ConfigError> {
+        toml::from_str(text).map_err(|err| ConfigError::Parse(err.to_string()))
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<ChannelConfig, ConfigError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        toml::to_string(self).map_err(|err| ConfigError::Parse(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    /// Channel keeping its settings in memory, writing `broken` fails.
+    struct Memory {
+        state: RefCell<ChannelConfig>,
+        broken: Option<Setting>,
+        writes: RefCell<Vec<Setting>>,
+    }
+
+    impl Configurable for Memory {
+        fn read_setting(
+            &self,
+            setting: Setting,
+            config: &mut ChannelConfig,
+        ) -> Result<(), CanError> {
+            let state = self.state.borrow();
+            match setting {
+                Setting::ListenOnly => config.listen_only = state.listen_only,
+                Setting::ErrorFrames => config.error_frames = state.error_frames,
+                Setting::Acceptance11Bit => config.acceptance_11bit = state.acceptance_11bit,
+                Setting::TraceMode => config.trace.mode = state.trace.mode,
+                _ => return Err(CanError::IllParamType),
+            }
+            Ok(())
+        }
+
+        fn write_setting(&self, setting: Setting, config: &ChannelConfig) -> Result<(), CanError> {
+            if self.broken == Some(setting) {
+                return Err(CanError::IllParamVal);
+            }
+            self.writes.borrow_mut().push(setting);
+            let mut state = self.state.borrow_mut();
+            match setting {
+                Setting::ListenOnly => state.listen_only = config.listen_only,
+                Setting::ErrorFrames => state.error_frames = config.error_frames,
+                Setting::Acceptance11Bit => state.acceptance_11bit = config.acceptance_11bit,
+                Setting::TraceMode => state.trace.mode = config.trace.mode,
+                _ => return Err(CanError::IllParamType),
+            }
+            Ok(())
+        }
+    }
+
+    fn memory(broken: Option<Setting>) -> Memory {
+        Memory {
+            state: RefCell::new(ChannelConfig {
+                listen_only: Some(false),
+                error_frames: Some(false),
+                acceptance_11bit: Some(AcceptanceFilter {
+                    code: 0,
+                    mask: 0x7FF,
+                }),
+                trace: TraceSettings {
+                    mode: Some(TraceFile::Single),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            broken,
+            writes: RefCell::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn apply_is_atomic() {
+        let config = ChannelConfig {
+            listen_only: Some(true),
+            error_frames: Some(true),
+            acceptance_11bit: Some(AcceptanceFilter {
+                code: 0x700,
+                mask: 0xFF,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.settings(),
+            [
+                Setting::ListenOnly,
+                Setting::ErrorFrames,
+                Setting::Acceptance11Bit
+            ]
+        );
+
+        let channel = memory(None);
+        config.apply(&channel).unwrap();
+        let snapshot = ChannelConfig::snapshot(&channel).unwrap();
+        assert_eq!(snapshot.listen_only, Some(true));
+        assert_eq!(snapshot.acceptance_11bit, config.acceptance_11bit);
+        assert_eq!(snapshot.trace.mode, Some(TraceFile::Single));
+        assert_eq!(snapshot.bitrate, None);
+
+        let channel = memory(Some(Setting::Acceptance11Bit));
+        let before = channel.state.borrow().clone();
+        match config.apply(&channel) {
+            Err(ConfigError::Setting(Setting::Acceptance11Bit, CanError::IllParamVal)) => {}
+            other => panic!("unexpected {other:?}"),
+        }
+        assert_eq!(*channel.state.borrow(), before);
+        assert_eq!(
+            *channel.writes.borrow(),
+            [
+                Setting::ListenOnly,
+                Setting::ErrorFrames,
+                Setting::ErrorFrames,
+                Setting::ListenOnly
+            ]
+        );
+
+        let error = ConfigError::Setting(Setting::TraceMode, CanError::IllParamType);
+        assert_eq!(
+            error.to_string(),
+            "cannot set `trace.mode`: illegal parameter type"
+        );
+        assert!(matches!(
+            ChannelConfig::default().open_with(|_| Ok(memory(None))),
+            Err(ConfigError::MissingBitrate)
+        ));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn toml_profiles() {
+        let config = ChannelConfig::from_toml(
+            r#"
+            listen_only = true
+            acceptance_29bit = { code = 0x18DA0000, mask = 0xFFFF }
+
+            [bitrate.fd]
+            nom_prescaler = 1
+            nom_sjw = 16
+            nom_tseg1 = 127
+            nom_tseg2 = 32
+            data_prescaler = 1
+            data_sjw = 4
+            data_tseg1 = 29
+            data_tseg2 = 10
+
+            [trace]
+            location = "/tmp/trace"
+            mode = "segmented"
+            enabled = true
+            "#,
+        )
+        .unwrap();
+        let Some(Bitrate::Fd(timing)) = config.bitrate else {
+            panic!("expected CAN FD timing");
+        };
+        assert_eq!(timing.nominal_bits_per_second(), 500_000);
+        assert_eq!(timing.data_bits_per_second(), 2_000_000);
+        assert_eq!(config.trace.mode, Some(TraceFile::Segmented));
+        assert_eq!(config.settings().last(), Some(&Setting::Tracing));
+        assert_eq!(
+            ChannelConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+
+        let config = ChannelConfig::from_toml("[bitrate]\nbaudrate = \"250K\"").unwrap();
+        assert_eq!(config.bitrate, Some(Bitrate::Baudrate(Baudrate::Baud250K)));
+        assert!(ChannelConfig::from_toml("listen_onyl = true").is_err());
+        assert!(
+            ChannelConfig::from_toml(
+                "[bitrate.timing]\nprescaler = 0\nsjw = 1\ntseg1 = 13\ntseg2 = 2"
+            )
+            .is_err()
+        );
+    }
+}
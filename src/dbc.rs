@@ -0,0 +1,1163 @@
+//! DBC database parsing and signal decoding.
+//!
+//! [Database::parse] reads the message and signal definitions of a Vector DBC file: byte
+//! order (Intel / Motorola), signedness, IEEE float signals (`SIG_VALTYPE_`), scaling and
+//! offset, value descriptions (`VAL_` and `VAL_TABLE_`), simple multiplexing and extended
+//! identifiers. Statements that do not affect decoding (attributes, environment variables,
+//! signal groups, ...) are skipped.
+//!
+//! A [Message] decodes the payload of a received [CanFrame] or [CanFdFrame] into physical
+//! [SignalValue]s and encodes physical values into a frame ready for
+//! [SendCan::send](crate::socket::SendCan::send).
+//!
+//! # Examples
+//!
+//! ```
+//! # use peak_can::dbc::Database;
+//! # use peak_can::socket::{CanFrame, MessageType};
+//! let db = Database::parse(r#"
+//! BO_ 256 EngineData: 8 ECU
+//!  SG_ EngineSpeed : 0|16@1+ (0.25,0) [0|16383.75] "rpm" Dashboard
+//!  SG_ CoolantTemp : 16|8@1+ (1,-40) [-40|215] "degC" Dashboard
+//! "#)?;
+//!
+//! let message = db.message_by_name("EngineData").unwrap();
+//! let frame = message.encode_frame(&[("EngineSpeed", 2500.0), ("CoolantTemp", 90.0)])?;
+//!
+//! let (_, values) = db.decode_frame(&frame).unwrap();
+//! assert_eq!(values[0].physical, 2500.0);
+//! assert_eq!(values[1].physical, 90.0);
+//! # Ok::<(), peak_can::dbc::DbcError>(())
+//! ```
+
+use crate::socket::{CanFdFrame, CanFrame, FrameConstructionError, MessageType};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Bit 31 of a DBC message identifier marks a 29-bit identifier.
+const DBC_EXTENDED_FLAG: u32 = 0x80_00_00_00;
+
+/// Errors raised while parsing a database or encoding signals.
+#[derive(Debug)]
+pub enum DbcError {
+    /// Underlying I/O failure.
+    Io(io::Error),
+    /// The database text is malformed.
+    Parse { line: usize, message: String },
+    /// No signal with this name in the message.
+    UnknownSignal(String),
+    /// A physical value lies outside the signal range or does not fit its raw width.
+    ValueOutOfRange { signal: String, value: f64 },
+    /// The encoded payload does not fit into the requested frame.
+    Frame(FrameConstructionError),
+}
+
+impl fmt::Display for DbcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbcError::Io(e) => write!(f, "{e}"),
+            DbcError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            DbcError::UnknownSignal(name) => write!(f, "unknown signal {name}"),
+            DbcError::ValueOutOfRange { signal, value } => {
+                write!(f, "value {value} out of range for signal {signal}")
+            }
+            DbcError::Frame(e) => write!(f, "invalid frame: {e:?}"),
+        }
+    }
+}
+
+impl std::error::Error for DbcError {}
+
+impl From<io::Error> for DbcError {
+    fn from(value: io::Error) -> Self {
+        DbcError::Io(value)
+    }
+}
+
+impl From<FrameConstructionError> for DbcError {
+    fn from(value: FrameConstructionError) -> Self {
+        DbcError::Frame(value)
+    }
+}
+
+/// Bit numbering of a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Intel, `@1` in DBC files. The start bit is the least significant bit.
+    LittleEndian,
+    /// Motorola, `@0` in DBC files. The start bit is the most significant bit.
+    BigEndian,
+}
+
+/// Interpretation of the raw bits of a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Unsigned,
+    Signed,
+    /// IEEE 754 single precision, 32 bit signals only.
+    Float32,
+    /// IEEE 754 double precision, 64 bit signals only.
+    Float64,
+}
+
+/// Multiplexing role of a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplex {
+    /// Always present.
+    None,
+    /// Selects which multiplexed signals are present (`M`).
+    Multiplexor,
+    /// Present when the multiplexor holds this value (`m<value>`).
+    Multiplexed(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub start_bit: u32,
+    pub length: u32,
+    pub byte_order: ByteOrder,
+    pub value_type: ValueType,
+    pub factor: f64,
+    pub offset: f64,
+    pub minimum: f64,
+    pub maximum: f64,
+    pub unit: String,
+    pub receivers: Vec<String>,
+    pub multiplex: Multiplex,
+    /// Raw value descriptions from `VAL_` statements.
+    pub value_descriptions: BTreeMap<i64, String>,
+    pub comment: Option<String>,
+}
+
+impl Signal {
+    /// Bit positions (byte * 8 + bit) covered by the signal, most significant first.
+    /// `None` if a start bit and length from a malformed database run past `u32`.
+    fn bit_positions(&self) -> Option<Vec<u32>> {
+        match self.byte_order {
+            ByteOrder::LittleEndian => (0..self.length)
+                .rev()
+                .map(|i| self.start_bit.checked_add(i))
+                .collect(),
+            ByteOrder::BigEndian => {
+                let mut bits = Vec::with_capacity(self.length as usize);
+                let mut bit = self.start_bit;
+                for _ in 0..self.length {
+                    bits.push(bit);
+                    // Motorola signals continue at the MSB of the next byte
+                    if bit.is_multiple_of(8) {
+                        bit = bit.checked_add(15)?;
+                    } else {
+                        bit -= 1;
+                    }
+                }
+                Some(bits)
+            }
+        }
+    }
+
+    /// Bit positions of the signal if `len` payload bytes hold all of them.
+    fn bits_within(&self, len: usize) -> Option<Vec<u32>> {
+        self.bit_positions()
+            .filter(|bits| bits.iter().all(|bit| (*bit as usize / 8) < len))
+    }
+
+    /// Extracts the unsigned raw bits of the signal, `None` if `data` is too short.
+    pub fn extract_raw(&self, data: &[u8]) -> Option<u64> {
+        let mut raw = 0u64;
+        for bit in self.bits_within(data.len())? {
+            let value = (data[bit as usize / 8] >> (bit % 8)) & 1;
+            raw = raw << 1 | value as u64;
+        }
+        Some(raw)
+    }
+
+    /// Writes the raw bits of the signal into `data`, fails with
+    /// [TooMuchData](FrameConstructionError::TooMuchData) if `data` is too short.
+    pub fn insert_raw(&self, data: &mut [u8], raw: u64) -> Result<(), DbcError> {
+        let bits = self
+            .bits_within(data.len())
+            .ok_or(DbcError::Frame(FrameConstructionError::TooMuchData))?;
+        let count = bits.len();
+        for (i, bit) in bits.into_iter().enumerate() {
+            let value = (raw >> (count - 1 - i)) & 1;
+            let byte = &mut data[bit as usize / 8];
+            *byte = (*byte & !(1 << (bit % 8))) | ((value as u8) << (bit % 8));
+        }
+        Ok(())
+    }
+
+    /// Raw value as a number: sign extended for signed signals, bit pattern for floats.
+    fn raw_number(&self, raw: u64) -> i64 {
+        match self.value_type {
+            ValueType::Signed if self.length < 64 && raw & (1 << (self.length - 1)) != 0 => {
+                (raw | !((1u64 << self.length) - 1)) as i64
+            }
+            _ => raw as i64,
+        }
+    }
+
+    /// Converts raw bits into the physical value.
+    pub fn to_physical(&self, raw: u64) -> f64 {
+        let value = match self.value_type {
+            ValueType::Float32 => f32::from_bits(raw as u32) as f64,
+            ValueType::Float64 => f64::from_bits(raw),
+            _ => self.raw_number(raw) as f64,
+        };
+        value * self.factor + self.offset
+    }
+
+    /// Converts a physical value into raw bits, checking range and raw width.
+    pub fn to_raw(&self, physical: f64) -> Result<u64, DbcError> {
+        let out_of_range = || DbcError::ValueOutOfRange {
+            signal: self.name.clone(),
+            value: physical,
+        };
+
+        // A 0|0 range means the range is not specified
+        if (self.minimum != 0.0 || self.maximum != 0.0)
+            && (physical < self.minimum - f64::EPSILON || physical > self.maximum + f64::EPSILON)
+        {
+            return Err(out_of_range());
+        }
+
+        let scaled = (physical - self.offset) / self.factor;
+        match self.value_type {
+            ValueType::Float32 => Ok((scaled as f32).to_bits() as u64),
+            ValueType::Float64 => Ok(scaled.to_bits()),
+            ValueType::Unsigned => {
+                let raw = scaled.round();
+                let max = if self.length >= 64 {
+                    u64::MAX as f64
+                } else {
+                    ((1u64 << self.length) - 1) as f64
+                };
+                if raw < 0.0 || raw > max {
+                    return Err(out_of_range());
+                }
+                Ok(raw as u64)
+            }
+            ValueType::Signed => {
+                let raw = scaled.round();
+                let half = if self.length >= 64 {
+                    i64::MAX as f64
+                } else {
+                    (1u64 << (self.length - 1)) as f64
+                };
+                if raw < -half || raw > half - 1.0 {
+                    return Err(out_of_range());
+                }
+                let mask = if self.length >= 64 {
+                    u64::MAX
+                } else {
+                    (1u64 << self.length) - 1
+                };
+                Ok((raw as i64) as u64 & mask)
+            }
+        }
+    }
+}
+
+/// One decoded signal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignalValue {
+    pub name: String,
+    /// Raw value, sign extended for signed signals and the bit pattern for float signals.
+    pub raw: i64,
+    /// Scaled value: `raw * factor + offset`.
+    pub physical: f64,
+    pub unit: String,
+    /// Value description of the raw value, if the database defines one.
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// Identifier without the DBC extended flag.
+    pub id: u32,
+    pub extended: bool,
+    pub name: String,
+    /// Payload size in bytes.
+    pub size: u8,
+    pub transmitter: String,
+    pub signals: Vec<Signal>,
+    pub comment: Option<String>,
+}
+
+impl Message {
+    pub fn signal(&self, name: &str) -> Option<&Signal> {
+        self.signals.iter().find(|s| s.name == name)
+    }
+
+    fn multiplexor(&self) -> Option<&Signal> {
+        self.signals
+            .iter()
+            .find(|s| s.multiplex == Multiplex::Multiplexor)
+    }
+
+    fn msg_type(&self) -> MessageType {
+        if self.extended {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        }
+    }
+
+    /// Decodes every signal present in `data`.
+    ///
+    /// Multiplexed signals are only returned when the multiplexor selects them, signals
+    /// reaching beyond the end of `data` are skipped.
+    pub fn decode(&self, data: &[u8]) -> Vec<SignalValue> {
+        let selector = self.multiplexor().and_then(|m| m.extract_raw(data));
+
+        self.signals
+            .iter()
+            .filter(|signal| match signal.multiplex {
+                Multiplex::Multiplexed(value) => selector == Some(value),
+                _ => true,
+            })
+            .filter_map(|signal| {
+                let raw = signal.extract_raw(data)?;
+                let number = signal.raw_number(raw);
+                Some(SignalValue {
+                    name: signal.name.clone(),
+                    raw: number,
+                    physical: signal.to_physical(raw),
+                    unit: signal.unit.clone(),
+                    description: signal.value_descriptions.get(&number).cloned(),
+                })
+            })
+            .collect()
+    }
+
+    /// Encodes physical signal values into a payload of [size](Message::size) bytes.
+    ///
+    /// Signals that are not given are encoded as raw zero. Multiplexed signals may only be
+    /// given when the multiplexor value selects them.
+    pub fn encode(&self, values: &[(&str, f64)]) -> Result<Vec<u8>, DbcError> {
+        for (name, _) in values {
+            if self.signal(name).is_none() {
+                return Err(DbcError::UnknownSignal(String::from(*name)));
+            }
+        }
+
+        let value_of = |name: &str| values.iter().find(|(n, _)| *n == name).map(|(_, v)| *v);
+        let mut data = vec![0u8; self.size as usize];
+
+        let mut selector = None;
+        if let Some(multiplexor) = self.multiplexor() {
+            let raw = match value_of(&multiplexor.name) {
+                Some(value) => multiplexor.to_raw(value)?,
+                None => 0,
+            };
+            selector = Some(raw);
+        }
+
+        for signal in &self.signals {
+            if signal.bits_within(data.len()).is_none() {
+                return Err(DbcError::Frame(FrameConstructionError::TooMuchData));
+            }
+            let value = value_of(&signal.name);
+            if let Multiplex::Multiplexed(expected) = signal.multiplex
+                && selector != Some(expected)
+            {
+                if let Some(value) = value {
+                    return Err(DbcError::ValueOutOfRange {
+                        signal: signal.name.clone(),
+                        value,
+                    });
+                }
+                continue;
+            }
+            if let Some(value) = value {
+                signal.insert_raw(&mut data, signal.to_raw(value)?)?;
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Encodes physical values into a classic frame.
+    pub fn encode_frame(&self, values: &[(&str, f64)]) -> Result<CanFrame, DbcError> {
+        let data = self.encode(values)?;
+        Ok(CanFrame::new(self.id, self.msg_type(), &data)?)
+    }
+
+    /// Encodes physical values into a CAN FD frame.
+    pub fn encode_fd_frame(
+        &self,
+        values: &[(&str, f64)],
+        brs: bool,
+    ) -> Result<CanFdFrame, DbcError> {
+        let data = self.encode(values)?;
+        Ok(CanFdFrame::new(self.id, self.msg_type(), &data, true, brs)?)
+    }
+}
+
+/// Messages, signals and value tables of a DBC file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Database {
+    pub version: String,
+    pub nodes: Vec<String>,
+    pub messages: Vec<Message>,
+    /// Named value tables from `VAL_TABLE_` statements.
+    pub value_tables: HashMap<String, BTreeMap<i64, String>>,
+}
+
+impl Database {
+    /// Reads and parses the DBC file at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Database, DbcError> {
+        let bytes = fs::read(path)?;
+        // DBC files are frequently written in Windows-1252; keep what is valid UTF-8
+        Database::parse(&String::from_utf8_lossy(&bytes))
+    }
+
+    /// Parses the text of a DBC file.
+    pub fn parse(text: &str) -> Result<Database, DbcError> {
+        Parser::new(text).parse()
+    }
+
+    pub fn message_by_id(&self, id: u32, extended: bool) -> Option<&Message> {
+        self.messages
+            .iter()
+            .find(|m| m.id == id && m.extended == extended)
+    }
+
+    pub fn message_by_name(&self, name: &str) -> Option<&Message> {
+        self.messages.iter().find(|m| m.name == name)
+    }
+
+    /// Decodes a received classic frame, `None` if the database does not define it.
+    pub fn decode_frame(&self, frame: &CanFrame) -> Option<(&Message, Vec<SignalValue>)> {
+        let message = self.message_by_id(frame.can_id(), frame.is_extended_frame())?;
+        Some((message, message.decode(frame.data())))
+    }
+
+    /// Decodes a received CAN FD frame, `None` if the database does not define it.
+    pub fn decode_fd_frame(&self, frame: &CanFdFrame) -> Option<(&Message, Vec<SignalValue>)> {
+        let message = self.message_by_id(frame.can_id(), frame.is_extended_frame())?;
+        Some((message, message.decode(frame.data())))
+    }
+}
+
+/* Parser */
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Str(String),
+    Punct(char),
+}
+
+struct Lexed {
+    token: Token,
+    line: usize,
+    /// First token of its line.
+    line_start: bool,
+}
+
+fn lex(text: &str) -> Result<Vec<Lexed>, DbcError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut line_start = true;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+
+        if c == '\n' {
+            line += 1;
+            line_start = true;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        // Line comments are not part of the DBC grammar, but some tools emit them
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+
+        let token = if c == '"' {
+            let mut value = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    i += 1;
+                }
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                value.push(chars[i]);
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(DbcError::Parse {
+                    line: start_line,
+                    message: String::from("unterminated string"),
+                });
+            }
+            i += 1;
+            Token::Str(value)
+        } else if c.is_ascii_digit()
+            || ((c == '-' || c == '+')
+                && chars
+                    .get(i + 1)
+                    .is_some_and(|n| n.is_ascii_digit() || *n == '.'))
+        {
+            let mut value = String::from(c);
+            i += 1;
+            while i < chars.len() {
+                let n = chars[i];
+                let exponent_sign =
+                    (n == '-' || n == '+') && matches!(value.chars().last(), Some('e') | Some('E'));
+                if n.is_ascii_digit() || n == '.' || n == 'e' || n == 'E' || exponent_sign {
+                    value.push(n);
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            Token::Number(value)
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut value = String::new();
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                value.push(chars[i]);
+                i += 1;
+            }
+            Token::Ident(value)
+        } else {
+            i += 1;
+            Token::Punct(c)
+        };
+
+        tokens.push(Lexed {
+            token,
+            line: start_line,
+            line_start,
+        });
+        line_start = false;
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<Lexed>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Parser<'a> {
+        Parser {
+            text,
+            tokens: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map(|t| t.line)
+            .unwrap_or(1)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, DbcError> {
+        Err(DbcError::Parse {
+            line: self.line(),
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|t| t.token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn at_statement_start(&self) -> bool {
+        self.tokens
+            .get(self.pos)
+            .is_some_and(|t| t.line_start && matches!(t.token, Token::Ident(_)))
+    }
+
+    fn at_keyword(&self, keywords: &[&str]) -> bool {
+        self.at_statement_start()
+            && matches!(self.peek(), Some(Token::Ident(k)) if keywords.contains(&k.as_str()))
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), DbcError> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            other => self.error(format!("expected '{c}', found {other:?}")),
+        }
+    }
+
+    fn skip_punct(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, DbcError> {
+        match self.next() {
+            Some(Token::Ident(s)) => Ok(s),
+            other => self.error(format!("expected identifier, found {other:?}")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DbcError> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(s),
+            other => self.error(format!("expected string, found {other:?}")),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, DbcError> {
+        match self.next() {
+            Some(Token::Number(s)) => match s.parse() {
+                Ok(value) => Ok(value),
+                Err(_) => self.error(format!("invalid number {s}")),
+            },
+            other => self.error(format!("expected number, found {other:?}")),
+        }
+    }
+
+    /// Skips to the first token of the next statement.
+    fn skip_statement(&mut self) {
+        self.pos += 1;
+        while self.pos < self.tokens.len() && !self.at_statement_start() {
+            self.pos += 1;
+        }
+    }
+
+    fn parse(mut self) -> Result<Database, DbcError> {
+        self.tokens = lex(self.text)?;
+        let mut db = Database::default();
+
+        while let Some(token) = self.peek().cloned() {
+            let Token::Ident(keyword) = token else {
+                return self.error(format!("unexpected {token:?}"));
+            };
+
+            match keyword.as_str() {
+                "VERSION" => {
+                    self.pos += 1;
+                    db.version = self.string()?;
+                }
+                "NS_" => {
+                    // The new symbol list is indented and may contain keywords
+                    self.pos += 1;
+                    while self.pos < self.tokens.len() && !self.at_keyword(&["BS_", "BU_", "BO_"]) {
+                        self.pos += 1;
+                    }
+                }
+                "BU_" => {
+                    self.pos += 1;
+                    self.expect_punct(':')?;
+                    while matches!(self.peek(), Some(Token::Ident(_))) && !self.at_statement_start()
+                    {
+                        db.nodes.push(self.ident()?);
+                    }
+                }
+                "BO_" => {
+                    self.pos += 1;
+                    let message = self.message()?;
+                    db.messages.push(message);
+                }
+                "SG_" => {
+                    self.pos += 1;
+                    let signal = self.signal()?;
+                    match db.messages.last_mut() {
+                        Some(message) => message.signals.push(signal),
+                        None => return self.error("signal outside of a message"),
+                    }
+                }
+                "CM_" => {
+                    self.pos += 1;
+                    self.comment(&mut db)?;
+                }
+                "VAL_TABLE_" => {
+                    self.pos += 1;
+                    let name = self.ident()?;
+                    let table = self.value_descriptions()?;
+                    db.value_tables.insert(name, table);
+                }
+                "VAL_" => {
+                    self.pos += 1;
+                    self.signal_values(&mut db)?;
+                }
+                "SIG_VALTYPE_" => {
+                    self.pos += 1;
+                    self.signal_value_type(&mut db)?;
+                }
+                _ => self.skip_statement(),
+            }
+        }
+
+        Ok(db)
+    }
+
+    fn message(&mut self) -> Result<Message, DbcError> {
+        let raw_id: u32 = self.number()?;
+        let name = self.ident()?;
+        self.expect_punct(':')?;
+        let size: u8 = self.number()?;
+        let transmitter = self.ident()?;
+
+        Ok(Message {
+            id: raw_id & !DBC_EXTENDED_FLAG,
+            extended: raw_id & DBC_EXTENDED_FLAG != 0,
+            name,
+            size,
+            transmitter,
+            signals: Vec::new(),
+            comment: None,
+        })
+    }
+
+    fn signal(&mut self) -> Result<Signal, DbcError> {
+        let name = self.ident()?;
+
+        let mut multiplex = Multiplex::None;
+        if let Some(Token::Ident(indicator)) = self.peek().cloned() {
+            self.pos += 1;
+            if indicator == "M" {
+                multiplex = Multiplex::Multiplexor;
+            } else if let Some(value) = indicator.strip_prefix('m') {
+                // Extended multiplexing ("m3M") is decoded as plain multiplexing
+                let value = value.trim_end_matches('M');
+                match value.parse() {
+                    Ok(value) => multiplex = Multiplex::Multiplexed(value),
+                    Err(_) => {
+                        return self.error(format!("invalid multiplex indicator {indicator}"));
+                    }
+                }
+            } else {
+                return self.error(format!("invalid multiplex indicator {indicator}"));
+            }
+        }
+
+        self.expect_punct(':')?;
+        let start_bit: u32 = self.number()?;
+        self.expect_punct('|')?;
+        let length: u32 = self.number()?;
+        self.expect_punct('@')?;
+        let byte_order = match self.next() {
+            Some(Token::Number(n)) if n == "0" => ByteOrder::BigEndian,
+            Some(Token::Number(n)) if n == "1" => ByteOrder::LittleEndian,
+            other => return self.error(format!("invalid byte order {other:?}")),
+        };
+        let value_type = match self.next() {
+            Some(Token::Punct('+')) => ValueType::Unsigned,
+            Some(Token::Punct('-')) => ValueType::Signed,
+            other => return self.error(format!("invalid value type {other:?}")),
+        };
+
+        if length == 0 || length > 64 {
+            return self.error(format!("invalid signal length {length}"));
+        }
+
+        self.expect_punct('(')?;
+        let factor: f64 = self.number()?;
+        self.expect_punct(',')?;
+        let offset: f64 = self.number()?;
+        self.expect_punct(')')?;
+        self.expect_punct('[')?;
+        let minimum: f64 = self.number()?;
+        self.expect_punct('|')?;
+        let maximum: f64 = self.number()?;
+        self.expect_punct(']')?;
+        let unit = self.string()?;
+
+        let mut receivers = Vec::new();
+        while let Some(Token::Ident(_)) = self.peek() {
+            if self.at_statement_start() {
+                break;
+            }
+            receivers.push(self.ident()?);
+            self.skip_punct(',');
+        }
+
+        Ok(Signal {
+            name,
+            start_bit,
+            length,
+            byte_order,
+            value_type,
+            factor,
+            offset,
+            minimum,
+            maximum,
+            unit,
+            receivers,
+            multiplex,
+            value_descriptions: BTreeMap::new(),
+            comment: None,
+        })
+    }
+
+    fn comment(&mut self, db: &mut Database) -> Result<(), DbcError> {
+        match self.peek().cloned() {
+            Some(Token::Ident(kind)) if kind == "BO_" => {
+                self.pos += 1;
+                let id: u32 = self.number()?;
+                let text = self.string()?;
+                if let Some(message) = find_message(db, id) {
+                    message.comment = Some(text);
+                }
+            }
+            Some(Token::Ident(kind)) if kind == "SG_" => {
+                self.pos += 1;
+                let id: u32 = self.number()?;
+                let name = self.ident()?;
+                let text = self.string()?;
+                if let Some(signal) =
+                    find_message(db, id).and_then(|m| m.signals.iter_mut().find(|s| s.name == name))
+                {
+                    signal.comment = Some(text);
+                }
+            }
+            _ => {
+                // Network, node and environment variable comments
+                while !matches!(self.peek(), Some(Token::Punct(';')) | None) {
+                    self.pos += 1;
+                }
+            }
+        }
+        self.expect_punct(';')
+    }
+
+    fn value_descriptions(&mut self) -> Result<BTreeMap<i64, String>, DbcError> {
+        let mut table = BTreeMap::new();
+        while !self.skip_punct(';') {
+            let value: f64 = self.number()?;
+            let text = self.string()?;
+            table.insert(value as i64, text);
+        }
+        Ok(table)
+    }
+
+    fn signal_values(&mut self, db: &mut Database) -> Result<(), DbcError> {
+        // Value descriptions of environment variables have no message identifier
+        if !matches!(self.peek(), Some(Token::Number(_))) {
+            self.skip_statement();
+            return Ok(());
+        }
+        let id: u32 = self.number()?;
+        let name = self.ident()?;
+
+        let table = if let Some(Token::Ident(table)) = self.peek().cloned() {
+            self.pos += 1;
+            self.expect_punct(';')?;
+            db.value_tables.get(&table).cloned().unwrap_or_default()
+        } else {
+            self.value_descriptions()?
+        };
+
+        if let Some(signal) =
+            find_message(db, id).and_then(|m| m.signals.iter_mut().find(|s| s.name == name))
+        {
+            signal.value_descriptions = table;
+        }
+        Ok(())
+    }
+
+    fn signal_value_type(&mut self, db: &mut Database) -> Result<(), DbcError> {
+        let id: u32 = self.number()?;
+        let name = self.ident()?;
+        self.skip_punct(':');
+        let kind: u8 = self.number()?;
+        self.expect_punct(';')?;
+
+        let value_type = match kind {
+            1 => ValueType::Float32,
+            2 => ValueType::Float64,
+            _ => return Ok(()),
+        };
+        if let Some(signal) =
+            find_message(db, id).and_then(|m| m.signals.iter_mut().find(|s| s.name == name))
+        {
+            signal.value_type = value_type;
+        }
+        Ok(())
+    }
+}
+
+fn find_message(db: &mut Database, raw_id: u32) -> Option<&mut Message> {
+    let id = raw_id & !DBC_EXTENDED_FLAG;
+    let extended = raw_id & DBC_EXTENDED_FLAG != 0;
+    db.messages
+        .iter_mut()
+        .find(|m| m.id == id && m.extended == extended)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBC: &str = r#"VERSION "1.0"
+
+NS_ :
+    NS_DESC_
+    CM_
+    BA_DEF_
+
+BS_:
+
+BU_: ECU Dashboard Tester
+
+VAL_TABLE_ GearTable 0 "Park" 1 "Reverse" 2 "Neutral" 3 "Drive" ;
+
+BO_ 256 EngineData: 8 ECU
+ SG_ EngineSpeed : 0|16@1+ (0.25,0) [0|16383.75] "rpm" Dashboard,Tester
+ SG_ CoolantTemp : 16|8@1+ (1,-40) [-40|215] "degC" Dashboard
+ SG_ Torque : 24|12@1- (0.5,0) [-1024|1023.5] "Nm" Dashboard
+ SG_ Gear : 36|4@1+ (1,0) [0|15] "" Dashboard
+
+BO_ 2566844672 BrakeStatus: 8 ECU
+ SG_ Pressure : 7|16@0+ (0.1,0) [0|6553.5] "bar" Dashboard
+ SG_ Temperature : 23|10@0- (1,0) [-512|511] "degC" Dashboard
+
+BO_ 512 Diagnostics: 8 Tester
+ SG_ Mode M : 0|8@1+ (1,0) [0|255] "" ECU
+ SG_ Voltage m1 : 8|16@1+ (0.001,0) [0|65.535] "V" ECU
+ SG_ Current m2 : 8|16@1- (0.01,0) [-327.68|327.67] "A" ECU
+
+BO_ 768 Physics: 8 ECU
+ SG_ Ratio : 0|32@1- (1,0) [0|0] "" Dashboard
+
+BA_DEF_ SG_ "GenSigStartValue" INT 0 65535;
+BA_ "GenSigStartValue" SG_ 256 EngineSpeed 0;
+
+CM_ "Network comment";
+CM_ BO_ 256 "Engine status
+spanning two lines";
+CM_ SG_ 256 EngineSpeed "Crankshaft speed";
+
+VAL_ 256 Gear GearTable ;
+VAL_ 512 Mode 1 "Voltage" 2 "Current" ;
+SIG_VALTYPE_ 768 Ratio : 1;
+"#;
+
+    #[test]
+    fn dbc_parse_structure() {
+        let db = Database::parse(DBC).unwrap();
+
+        assert_eq!(db.version, "1.0");
+        assert_eq!(db.nodes, vec!["ECU", "Dashboard", "Tester"]);
+        assert_eq!(db.messages.len(), 4);
+
+        let engine = db.message_by_id(256, false).unwrap();
+        assert_eq!(engine.signals.len(), 4);
+        assert_eq!(
+            engine.comment.as_deref(),
+            Some("Engine status\nspanning two lines")
+        );
+        assert_eq!(
+            engine.signal("EngineSpeed").unwrap().receivers,
+            vec!["Dashboard", "Tester"]
+        );
+        assert_eq!(
+            engine.signal("EngineSpeed").unwrap().comment.as_deref(),
+            Some("Crankshaft speed")
+        );
+        assert_eq!(
+            engine
+                .signal("Gear")
+                .unwrap()
+                .value_descriptions
+                .get(&3)
+                .map(String::as_str),
+            Some("Drive")
+        );
+
+        let brake = db.message_by_name("BrakeStatus").unwrap();
+        assert!(brake.extended);
+        assert_eq!(brake.id, 0x18FE_F100);
+        assert_eq!(brake.signals[0].byte_order, ByteOrder::BigEndian);
+
+        let physics = db.message_by_name("Physics").unwrap();
+        assert_eq!(physics.signals[0].value_type, ValueType::Float32);
+    }
+
+    #[test]
+    fn dbc_decode_intel() {
+        let db = Database::parse(DBC).unwrap();
+        // EngineSpeed 0x2710 * 0.25 = 2500, CoolantTemp 130 - 40 = 90, Torque -100 * 0.5, Gear 3
+        let frame = CanFrame::new(
+            256,
+            MessageType::Standard,
+            &[0x10, 0x27, 0x82, 0x9C, 0x3F, 0, 0, 0],
+        )
+        .unwrap();
+
+        let (message, values) = db.decode_frame(&frame).unwrap();
+        assert_eq!(message.name, "EngineData");
+        assert_eq!(values[0].physical, 2500.0);
+        assert_eq!(values[0].unit, "rpm");
+        assert_eq!(values[1].physical, 90.0);
+        assert_eq!(values[2].raw, -100);
+        assert_eq!(values[2].physical, -50.0);
+        assert_eq!(values[3].raw, 3);
+        assert_eq!(values[3].description.as_deref(), Some("Drive"));
+    }
+
+    #[test]
+    fn dbc_decode_motorola() {
+        let db = Database::parse(DBC).unwrap();
+        // Pressure 0x1234 big endian in bytes 0..2, Temperature -3 in 10 bits from bit 23
+        let temperature = (-3i16 as u16) & 0x3FF;
+        let data = [
+            0x12,
+            0x34,
+            (temperature >> 2) as u8,
+            ((temperature & 0x03) << 6) as u8,
+            0,
+            0,
+            0,
+            0,
+        ];
+        let frame = CanFrame::new(0x18FE_F100, MessageType::Extended, &data).unwrap();
+
+        let (_, values) = db.decode_frame(&frame).unwrap();
+        assert!((values[0].physical - 466.0).abs() < 1e-9);
+        assert_eq!(values[1].raw, -3);
+    }
+
+    #[test]
+    fn dbc_encode_round_trip() {
+        let db = Database::parse(DBC).unwrap();
+
+        let engine = db.message_by_name("EngineData").unwrap();
+        let frame = engine
+            .encode_frame(&[
+                ("EngineSpeed", 812.5),
+                ("CoolantTemp", -12.0),
+                ("Torque", -300.5),
+                ("Gear", 2.0),
+            ])
+            .unwrap();
+        let values = engine.decode(frame.data());
+        assert_eq!(
+            values.iter().map(|v| v.physical).collect::<Vec<_>>(),
+            vec![812.5, -12.0, -300.5, 2.0]
+        );
+
+        let brake = db.message_by_name("BrakeStatus").unwrap();
+        let frame = brake
+            .encode_frame(&[("Pressure", 123.4), ("Temperature", -200.0)])
+            .unwrap();
+        assert!(frame.is_extended_frame());
+        let values = brake.decode(frame.data());
+        assert!((values[0].physical - 123.4).abs() < 1e-9);
+        assert_eq!(values[1].physical, -200.0);
+
+        let physics = db.message_by_name("Physics").unwrap();
+        let frame = physics.encode_fd_frame(&[("Ratio", 1.5)], true).unwrap();
+        assert!(frame.is_fd_frame());
+        assert_eq!(physics.decode(frame.data())[0].physical, 1.5);
+    }
+
+    #[test]
+    fn dbc_multiplexing() {
+        let db = Database::parse(DBC).unwrap();
+        let diagnostics = db.message_by_name("Diagnostics").unwrap();
+
+        let data = diagnostics
+            .encode(&[("Mode", 2.0), ("Current", -1.5)])
+            .unwrap();
+        let values = diagnostics.decode(&data);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].description.as_deref(), Some("Current"));
+        assert_eq!(values[1].name, "Current");
+        assert!((values[1].physical + 1.5).abs() < 1e-9);
+
+        // Voltage is not selected by mode 2
+        assert!(
+            diagnostics
+                .encode(&[("Mode", 2.0), ("Voltage", 1.0)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn dbc_encode_errors() {
+        let db = Database::parse(DBC).unwrap();
+        let engine = db.message_by_name("EngineData").unwrap();
+
+        assert!(matches!(
+            engine.encode(&[("Missing", 1.0)]),
+            Err(DbcError::UnknownSignal(_))
+        ));
+        assert!(matches!(
+            engine.encode(&[("CoolantTemp", 300.0)]),
+            Err(DbcError::ValueOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn dbc_malformed_signal_layouts() {
+        let text = "VERSION \"\"\n\nBO_ 100 Broken: 8 ECU\n \
+                    SG_ Intel : 4294967290|8@1+ (1,0) [0|0] \"\" ECU\n \
+                    SG_ Motorola : 4294967288|2@0+ (1,0) [0|0] \"\" ECU\n";
+        let db = Database::parse(text).unwrap();
+        let broken = db.message_by_name("Broken").unwrap();
+        assert!(broken.decode(&[0; 8]).is_empty());
+        for signal in &broken.signals {
+            assert_eq!(signal.extract_raw(&[0; 8]), None);
+            assert!(signal.insert_raw(&mut [0; 8], 1).is_err());
+        }
+        assert!(matches!(
+            broken.encode(&[]),
+            Err(DbcError::Frame(FrameConstructionError::TooMuchData))
+        ));
+
+        // A payload shorter than the signal
+        let engine = Database::parse(DBC).unwrap();
+        let speed = engine
+            .message_by_name("EngineData")
+            .unwrap()
+            .signal("EngineSpeed")
+            .unwrap();
+        assert!(matches!(
+            speed.insert_raw(&mut [0; 1], 0x1234),
+            Err(DbcError::Frame(FrameConstructionError::TooMuchData))
+        ));
+        let mut data = [0; 2];
+        speed.insert_raw(&mut data, 0x1234).unwrap();
+        assert_eq!(data, [0x34, 0x12]);
+    }
+
+    #[test]
+    fn dbc_parse_error_line() {
+        let text =
+            "VERSION \"\"\n\nBO_ 100 Broken: 8 ECU\n SG_ Sig : 0|8@2+ (1,0) [0|0] \"\" ECU\n";
+        match Database::parse(text) {
+            Err(DbcError::Parse { line, .. }) => assert_eq!(line, 4),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! Device IDs for setups with several identical adapters.
+//!
+//! Channel handles follow the order in which the driver finds the hardware, so `USB1` may
+//! be another adapter after a reboot or after replugging. USB, PCI and LAN adapters store a
+//! device ID that survives power cycles instead. [adapters] lists the attached channels
+//! with their IDs, [identify] blinks a USB adapter so it can be found in the rack,
+//! [AssignDeviceId] stores a new ID and [FindByDeviceId] turns an ID back into the channel
+//! to open.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::device::{self, AssignDeviceId, FindByDeviceId};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use std::time::Duration;
+//! device::identify(UsbBus::USB3, Duration::from_secs(5))?;
+//! UsbBus::USB3.assign_device_id(7)?;
+//!
+//! // Whichever handle the adapter gets after the next reboot
+//! let socket = UsbCanSocket::open(UsbBus::by_device_id(7)?, Baudrate::Baud500K)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::bus::{LanBus, PciBus, UsbBus};
+use crate::capability::DeviceType;
+use crate::error::{CanError, CanOkError};
+use crate::hw::{self, ChannelConditionStatus, ChannelIdentifying, DeviceId, SetDeviceId};
+use crate::peak_can;
+use crate::peak_lib;
+use crate::socket::Baudrate;
+use crate::socket::lan::LanCanSocket;
+use crate::socket::pci::PciCanSocket;
+use crate::socket::usb::UsbCanSocket;
+use crate::special::{ListenOnly, SetListenOnly};
+
+use std::ffi::CString;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+/// Bit rate channels are briefly initialized with to store a device ID.
+const ASSIGN_BAUDRATE: Baudrate = Baudrate::Baud500K;
+
+/* Attached adapters */
+
+/// An attached channel as reported by [hw::attached_channels].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adapter {
+    pub channel: u16,
+    /// `None` for device types unknown to this crate.
+    pub device_type: Option<DeviceType>,
+    pub device_name: String,
+    pub controller_number: u8,
+    pub device_id: u32,
+    pub condition: Option<ChannelConditionStatus>,
+    pub fd_capable: bool,
+}
+
+impl Adapter {
+    /// The family stores a device ID: USB, PCI and LAN.
+    pub fn has_device_id(&self) -> bool {
+        self.device_type
+            .is_some_and(|device_type| device_type.family().device_id)
+    }
+}
+
+impl From<&hw::ChannelInformation> for Adapter {
+    fn from(value: &hw::ChannelInformation) -> Self {
+        let info = &value.channel_information;
+        Adapter {
+            channel: info.channel_handle,
+            device_type: DeviceType::try_from(u32::from(info.device_type)).ok(),
+            device_name: value.device_name(),
+            controller_number: info.controller_number,
+            device_id: info.device_id,
+            condition: ChannelConditionStatus::try_from(info.channel_condition).ok(),
+            fd_capable: value.is_fd_capable(),
+        }
+    }
+}
+
+pub fn adapters() -> Result<Vec<Adapter>, CanError> {
+    Ok(hw::attached_channels()?.iter().map(Adapter::from).collect())
+}
+
+/// Device IDs shared by more than one adapter of the same family, as `(family, ID,
+/// channels)`. Adapters leave the factory with the same ID, so these are the ones still
+/// to be assigned.
+pub fn duplicates(adapters: &[Adapter]) -> Vec<(DeviceType, u32, Vec<u16>)> {
+    let mut duplicates: Vec<(DeviceType, u32, Vec<u16>)> = Vec::new();
+    for adapter in adapters.iter().filter(|adapter| adapter.has_device_id()) {
+        let Some(device_type) = adapter.device_type else {
+            continue;
+        };
+        let others = adapters.iter().filter(|other| {
+            other.channel != adapter.channel
+                && other.device_type == Some(device_type)
+                && other.device_id == adapter.device_id
+        });
+        if others.count() == 0 {
+            continue;
+        }
+        match duplicates
+            .iter_mut()
+            .find(|(t, id, _)| *t == device_type && *id == adapter.device_id)
+        {
+            Some((_, _, channels)) => channels.push(adapter.channel),
+            None => duplicates.push((device_type, adapter.device_id, vec![adapter.channel])),
+        }
+    }
+    duplicates
+}
+
+/// Blinks the LED of a USB adapter for `duration`.
+pub fn identify(bus: UsbBus, duration: Duration) -> Result<(), CanError> {
+    bus.set_channel_identifying(true)?;
+    thread::sleep(duration);
+    bus.set_channel_identifying(false)
+}
+
+/* Lookup */
+
+/// Family name as `CAN_LookUpChannel` expects it.
+fn lookup_name(device_type: DeviceType) -> &'static str {
+    match device_type {
+        DeviceType::Isa => "pcan_isa",
+        DeviceType::Dng => "pcan_dng",
+        DeviceType::Pci => "pcan_pci",
+        DeviceType::Usb => "pcan_usb",
+        DeviceType::Pcc => "pcan_pcc",
+        DeviceType::Lan => "pcan_lan",
+    }
+}
+
+fn lookup_parameters(device_type: DeviceType, device_id: u32) -> String {
+    format!(
+        "devicetype={}, deviceid={device_id}",
+        lookup_name(device_type)
+    )
+}
+
+/// Handle of the first attached channel of `device_type` whose adapter has `device_id`.
+///
+/// Uses `CAN_LookUpChannel` and falls back to searching [adapters] with PCAN-Basic
+/// libraries that predate it.
+pub fn find_channel(device_type: DeviceType, device_id: u32) -> Result<Option<u16>, CanError> {
+    let lib = peak_lib()?;
+    if lib.CAN_LookUpChannel.is_err() {
+        return Ok(adapters()?
+            .into_iter()
+            .find(|adapter| {
+                adapter.device_type == Some(device_type) && adapter.device_id == device_id
+            })
+            .map(|adapter| adapter.channel));
+    }
+
+    let mut parameters = CString::new(lookup_parameters(device_type, device_id))
+        .map_err(|_| CanError::Unknown)?
+        .into_bytes_with_nul();
+    let mut channel = peak_can::PEAK_NONEBUS as u16;
+    let code = unsafe { lib.CAN_LookUpChannel(parameters.as_mut_ptr().cast(), &mut channel) };
+
+    match CanOkError::try_from(code) {
+        Ok(CanOkError::Ok) => Ok((channel != peak_can::PEAK_NONEBUS as u16).then_some(channel)),
+        Ok(CanOkError::Err(err)) => Err(err),
+        Err(_) => Err(CanError::Unknown),
+    }
+}
+
+pub trait FindByDeviceId: Sized {
+    const DEVICE_TYPE: DeviceType;
+
+    fn find_by_device_id(device_id: u32) -> Result<Option<Self>, CanError>;
+
+    /// Like [find_by_device_id](FindByDeviceId::find_by_device_id), with a missing adapter
+    /// as [DeviceError::NotFound].
+    fn by_device_id(device_id: u32) -> Result<Self, DeviceError> {
+        Self::find_by_device_id(device_id)?.ok_or(DeviceError::NotFound {
+            device_type: Self::DEVICE_TYPE,
+            device_id,
+        })
+    }
+}
+
+/* Assignment */
+
+pub trait AssignDeviceId {
+    /// Stores `device_id` in the adapter by briefly initializing the channel, USB channels
+    /// in listen-only mode, restoring their previous setting if it could be read. Refuses IDs
+    /// another attached adapter of the family has.
+    fn assign_device_id(&self, device_id: u32) -> Result<(), DeviceError>;
+}
+
+/// Fails if an adapter of the family other than `channel` already has `device_id`.
+fn check_unused(
+    adapters: &[Adapter],
+    device_type: DeviceType,
+    channel: u16,
+    device_id: u32,
+) -> Result<(), DeviceError> {
+    match adapters.iter().find(|adapter| {
+        adapter.channel != channel
+            && adapter.device_type == Some(device_type)
+            && adapter.device_id == device_id
+    }) {
+        Some(adapter) => Err(DeviceError::InUse {
+            device_id,
+            channel: adapter.channel,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn assign<S: SetDeviceId<Item = u32> + DeviceId>(
+    socket: Result<S, CanError>,
+    device_id: u32,
+) -> Result<(), DeviceError> {
+    let socket = socket?;
+    socket.set_device_id(device_id)?;
+    match socket.device_id()? {
+        stored if stored == device_id => Ok(()),
+        stored => Err(DeviceError::NotStored { device_id, stored }),
+    }
+}
+
+macro_rules! impl_device_id {
+    ($($bus:ty: $device_type:expr;)*) => {$(
+        impl FindByDeviceId for $bus {
+            const DEVICE_TYPE: DeviceType = $device_type;
+
+            fn find_by_device_id(device_id: u32) -> Result<Option<Self>, CanError> {
+                let channel = find_channel(Self::DEVICE_TYPE, device_id)?;
+                Ok(channel.and_then(|channel| <$bus>::try_from(channel).ok()))
+            }
+        }
+    )*};
+}
+
+impl_device_id! {
+    UsbBus: DeviceType::Usb;
+    PciBus: DeviceType::Pci;
+    LanBus: DeviceType::Lan;
+}
+
+impl AssignDeviceId for UsbBus {
+    fn assign_device_id(&self, device_id: u32) -> Result<(), DeviceError> {
+        check_unused(&adapters()?, DeviceType::Usb, u16::from(*self), device_id)?;
+        // Only a setting that could be read is restored
+        let listen_only = self.listen_only().ok();
+        self.set_listen_only(true)?;
+        let result = assign(UsbCanSocket::open(*self, ASSIGN_BAUDRATE), device_id);
+        let restored = listen_only.map_or(Ok(()), |value| self.set_listen_only(value));
+        result?;
+        restored.map_err(DeviceError::ListenOnlyNotRestored)
+    }
+}
+
+impl AssignDeviceId for PciBus {
+    fn assign_device_id(&self, device_id: u32) -> Result<(), DeviceError> {
+        check_unused(&adapters()?, DeviceType::Pci, u16::from(*self), device_id)?;
+        assign(PciCanSocket::open(*self, ASSIGN_BAUDRATE), device_id)
+    }
+}
+
+impl AssignDeviceId for LanBus {
+    fn assign_device_id(&self, device_id: u32) -> Result<(), DeviceError> {
+        check_unused(&adapters()?, DeviceType::Lan, u16::from(*self), device_id)?;
+        assign(LanCanSocket::open(*self, ASSIGN_BAUDRATE), device_id)
+    }
+}
+
+/* Errors */
+
+#[derive(Debug, Clone)]
+pub enum DeviceError {
+    Can(CanError),
+    /// No attached adapter of the family has the device ID.
+    NotFound {
+        device_type: DeviceType,
+        device_id: u32,
+    },
+    /// The adapter on `channel` already has the device ID.
+    InUse {
+        device_id: u32,
+        channel: u16,
+    },
+    /// The adapter reports another ID after the assignment, e.g. because it stores fewer
+    /// bits.
+    NotStored {
+        device_id: u32,
+        stored: u32,
+    },
+    /// The device ID was stored, but the previous listen-only setting of the USB channel
+    /// could not be restored.
+    ListenOnlyNotRestored(CanError),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::Can(err) => write!(f, "{err}"),
+            DeviceError::NotFound {
+                device_type,
+                device_id,
+            } => write!(f, "no {device_type} adapter with device ID {device_id}"),
+            DeviceError::InUse { device_id, channel } => write!(
+                f,
+                "device ID {device_id} is already used by channel {channel:#X}"
+            ),
+            DeviceError::NotStored { device_id, stored } => write!(
+                f,
+                "adapter reports device ID {stored} after assigning {device_id}"
+            ),
+            DeviceError::ListenOnlyNotRestored(err) => write!(
+                f,
+                "device ID stored, but restoring listen-only mode failed: {err}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+impl From<CanError> for DeviceError {
+    fn from(value: CanError) -> Self {
+        DeviceError::Can(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(channel: UsbBus, device_id: u32) -> Adapter {
+        Adapter {
+            channel: channel.into(),
+            device_type: Some(DeviceType::Usb),
+            device_name: String::from("PCAN-USB"),
+            controller_number: 0,
+            device_id,
+            condition: Some(ChannelConditionStatus::Available),
+            fd_capable: false,
+        }
+    }
+
+    #[test]
+    fn adapter_from_channel_information() {
+        let mut information = hw::ChannelInformation::new();
+        let info = &mut information.channel_information;
+        info.channel_handle = u16::from(UsbBus::USB2);
+        info.device_type = peak_can::PEAK_USB as u8;
+        info.device_id = 7;
+        info.channel_condition = peak_can::PEAK_CHANNEL_OCCUPIED;
+        info.device_features = peak_can::FEATURE_FD_CAPABLE;
+        for (c, b) in info.device_name.iter_mut().zip(b"PCAN-USB FD") {
+            *c = *b as _;
+        }
+
+        let adapter = Adapter::from(&information);
+        assert_eq!(adapter.channel, u16::from(UsbBus::USB2));
+        assert_eq!(adapter.device_type, Some(DeviceType::Usb));
+        assert_eq!(adapter.device_name, "PCAN-USB FD");
+        assert_eq!(adapter.device_id, 7);
+        assert_eq!(adapter.condition, Some(ChannelConditionStatus::Occupied));
+        assert!(adapter.fd_capable && adapter.has_device_id());
+    }
+
+    #[test]
+    fn lookup_parameters_name_family_and_id() {
+        assert_eq!(
+            lookup_parameters(DeviceType::Usb, 7),
+            "devicetype=pcan_usb, deviceid=7"
+        );
+        assert_eq!(
+            lookup_parameters(DeviceType::Lan, 0x1234),
+            "devicetype=pcan_lan, deviceid=4660"
+        );
+    }
+
+    #[test]
+    fn assigned_ids_stay_unique() {
+        let adapters = [
+            adapter(UsbBus::USB1, 0),
+            adapter(UsbBus::USB2, 0),
+            adapter(UsbBus::USB3, 0),
+            adapter(UsbBus::USB4, 5),
+        ];
+        assert_eq!(
+            duplicates(&adapters),
+            vec![(
+                DeviceType::Usb,
+                0,
+                vec![
+                    UsbBus::USB1.into(),
+                    UsbBus::USB2.into(),
+                    UsbBus::USB3.into()
+                ]
+            )]
+        );
+
+        assert!(check_unused(&adapters, DeviceType::Usb, UsbBus::USB1.into(), 7).is_ok());
+        // Reassigning an adapter its own ID is fine, other families do not count
+        assert!(check_unused(&adapters, DeviceType::Usb, UsbBus::USB4.into(), 5).is_ok());
+        assert!(check_unused(&adapters, DeviceType::Pci, PciBus::PCI1.into(), 5).is_ok());
+        match check_unused(&adapters, DeviceType::Usb, UsbBus::USB1.into(), 5) {
+            Err(err @ DeviceError::InUse { .. }) => {
+                assert_eq!(
+                    err.to_string(),
+                    "device ID 5 is already used by channel 0x54"
+                )
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+}
@@ -478,6 +478,8 @@ pub(crate) trait HasSetAcceptanceFilter11Bit {}
 
 pub trait SetAcceptanceFilter11Bit {
     fn set_acceptance_filter_11bit(&self, ids: &[u32]) -> Result<(), CanError>;
+    /// Sets the acceptance code and mask directly, mask bits set are ignored when matching.
+    fn set_acceptance_mask_11bit(&self, code: u32, mask: u32) -> Result<(), CanError>;
 }
 
 impl<T: HasSetAcceptanceFilter11Bit + Channel> SetAcceptanceFilter11Bit for T {
@@ -486,10 +488,14 @@ impl<T: HasSetAcceptanceFilter11Bit + Channel> SetAcceptanceFilter11Bit for T {
             .iter()
             .map(|x| *x & 0x7_FFu32)
             .fold(0xFF_FF_FF_FFu32, |x, y| x & y);
-        let acceptance_code_data = acceptance_code.to_le_bytes();
 
         let acceptance_mask = ids.iter().map(|x| *x & 0x7_FFu32).fold(0u32, |x, y| x ^ y);
-        let acceptance_mask_data = acceptance_mask.to_le_bytes();
+        self.set_acceptance_mask_11bit(acceptance_code, acceptance_mask)
+    }
+
+    fn set_acceptance_mask_11bit(&self, code: u32, mask: u32) -> Result<(), CanError> {
+        let acceptance_code_data = code.to_le_bytes();
+        let acceptance_mask_data = mask.to_le_bytes();
 
         let mut data = [
             acceptance_mask_data[0],
@@ -554,6 +560,8 @@ pub(crate) trait HasSetAcceptanceFilter29Bit {}
 
 pub trait SetAcceptanceFilter29Bit {
     fn set_acceptance_filter_29bit(&self, ids: &[u32]) -> Result<(), CanError>;
+    /// Sets the acceptance code and mask directly, mask bits set are ignored when matching.
+    fn set_acceptance_mask_29bit(&self, code: u32, mask: u32) -> Result<(), CanError>;
 }
 
 impl<T: HasSetAcceptanceFilter29Bit + Channel> SetAcceptanceFilter29Bit for T {
@@ -562,13 +570,17 @@ impl<T: HasSetAcceptanceFilter29Bit + Channel> SetAcceptanceFilter29Bit for T {
             .iter()
             .map(|x| *x & 0x1F_FF_FF_FFu32)
             .fold(0xFF_FF_FF_FFu32, |x, y| x & y);
-        let acceptance_code_data = acceptance_code.to_le_bytes();
 
         let acceptance_mask = ids
             .iter()
             .map(|x| *x & 0x1F_FF_FF_FFu32)
             .fold(0u32, |x, y| x ^ y);
-        let acceptance_mask_data = acceptance_mask.to_le_bytes();
+        self.set_acceptance_mask_29bit(acceptance_code, acceptance_mask)
+    }
+
+    fn set_acceptance_mask_29bit(&self, code: u32, mask: u32) -> Result<(), CanError> {
+        let acceptance_code_data = code.to_le_bytes();
+        let acceptance_mask_data = mask.to_le_bytes();
 
         let mut data = [
             acceptance_mask_data[0],
@@ -0,0 +1,264 @@
+//! [embedded-can](https://docs.rs/embedded-can) support, enabled with the `embedded-can`
+//! feature.
+//!
+//! [CanFrame] and [CanFdFrame] implement [Frame], the socket types implement the blocking
+//! and non-blocking [Can](blocking::Can) traits for classic frames, so protocol stacks
+//! written against embedded-can run unchanged on PEAK hardware. [CanError] implements
+//! [Error] for them.
+//!
+//! Frames of other implementations, such as the frames of the `socketcan` crate, convert
+//! through [from_frame] and [into_frame].
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use embedded_can::blocking::Can;
+//! # use embedded_can::{Frame, StandardId};
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::socket::{Baudrate, CanFrame};
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! fn echo<C: Can>(can: &mut C) -> Result<(), C::Error> {
+//!     let frame = can.receive()?;
+//!     can.transmit(&frame)
+//! }
+//!
+//! let mut socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let id = StandardId::new(0x123).unwrap();
+//! socket.transmit(&<CanFrame as Frame>::new(id, &[1, 2, 3]).unwrap())?;
+//! echo(&mut socket)?;
+//! # Ok::<(), peak_can::error::CanError>(())
+//! ```
+
+use crate::error::CanError;
+use crate::socket::dng::DngCanSocket;
+use crate::socket::isa::IsaCanSocket;
+use crate::socket::lan::LanCanSocket;
+use crate::socket::pcc::PccCanSocket;
+use crate::socket::pci::PciCanSocket;
+use crate::socket::usb::UsbCanSocket;
+use crate::socket::{CanFdFrame, CanFrame, MessageType, RecvCan, SendCan};
+
+use embedded_can::{Error, ErrorKind, ExtendedId, Frame, Id, StandardId, blocking};
+
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_micros(200);
+
+fn split_id(id: Id) -> (u32, MessageType) {
+    match id {
+        Id::Standard(id) => (id.as_raw() as u32, MessageType::Standard),
+        Id::Extended(id) => (id.as_raw(), MessageType::Extended),
+    }
+}
+
+fn make_id(id: u32, extended: bool) -> Id {
+    if extended {
+        ExtendedId::new(id).map(Id::Extended)
+    } else {
+        StandardId::new(id as u16).map(Id::Standard)
+    }
+    .expect("frame identifiers are masked to their width")
+}
+
+/* Frames */
+
+impl Frame for CanFrame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        let (id, kind) = split_id(id.into());
+        CanFrame::new(id, kind, data).ok()
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        let (id, kind) = split_id(id.into());
+        CanFrame::new_remote(id, kind, u8::try_from(dlc).ok()?).ok()
+    }
+
+    fn is_extended(&self) -> bool {
+        self.is_extended_frame()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        CanFrame::is_remote_frame(self)
+    }
+
+    fn id(&self) -> Id {
+        make_id(self.can_id(), self.is_extended_frame())
+    }
+
+    fn dlc(&self) -> usize {
+        CanFrame::dlc(self) as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        CanFrame::data(self)
+    }
+}
+
+/// Payloads over 8 bytes are sent in CAN FD format with bit rate switch. CAN FD frames
+/// have no remote variant.
+impl Frame for CanFdFrame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        let (id, kind) = split_id(id.into());
+        let fd = data.len() > 8;
+        CanFdFrame::new(id, kind, data, fd, fd).ok()
+    }
+
+    fn new_remote(_id: impl Into<Id>, _dlc: usize) -> Option<Self> {
+        None
+    }
+
+    fn is_extended(&self) -> bool {
+        self.is_extended_frame()
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        false
+    }
+
+    fn id(&self) -> Id {
+        make_id(self.can_id(), self.is_extended_frame())
+    }
+
+    fn dlc(&self) -> usize {
+        CanFdFrame::dlc(self) as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        CanFdFrame::data(self)
+    }
+}
+
+/// Converts a frame of another embedded-can implementation, such as
+/// `socketcan::CanFrame`.
+pub fn from_frame<F: Frame>(frame: &F) -> Option<CanFrame> {
+    if frame.is_remote_frame() {
+        <CanFrame as Frame>::new_remote(frame.id(), frame.dlc())
+    } else {
+        <CanFrame as Frame>::new(frame.id(), frame.data())
+    }
+}
+
+/// Converts a frame into another embedded-can implementation.
+pub fn into_frame<F: Frame>(frame: &CanFrame) -> Option<F> {
+    if frame.is_remote_frame() {
+        F::new_remote(Frame::id(frame), Frame::dlc(frame))
+    } else {
+        F::new(Frame::id(frame), frame.data())
+    }
+}
+
+/* Errors */
+
+impl Error for CanError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            CanError::Overrun | CanError::QOverrun => ErrorKind::Overrun,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/* Sockets */
+
+fn transmit_blocking<S: SendCan>(socket: &S, frame: &CanFrame) -> Result<(), CanError> {
+    loop {
+        match socket.send(*frame) {
+            Err(CanError::XmtFull | CanError::QxmtFull) => thread::sleep(POLL_INTERVAL),
+            result => return result,
+        }
+    }
+}
+
+fn receive_blocking<S: RecvCan>(socket: &S) -> Result<CanFrame, CanError> {
+    loop {
+        match socket.recv_frame() {
+            Err(CanError::QrcvEmpty) => thread::sleep(POLL_INTERVAL),
+            result => return result,
+        }
+    }
+}
+
+fn would_block(err: CanError) -> nb::Error<CanError> {
+    match err {
+        CanError::XmtFull | CanError::QxmtFull | CanError::QrcvEmpty => nb::Error::WouldBlock,
+        err => nb::Error::Other(err),
+    }
+}
+
+macro_rules! impl_embedded_can {
+    ($($socket:ty),*) => {$(
+        /// Waits while the transmit queue is full or no frame was received.
+        impl blocking::Can for $socket {
+            type Frame = CanFrame;
+            type Error = CanError;
+
+            fn transmit(&mut self, frame: &CanFrame) -> Result<(), CanError> {
+                transmit_blocking(self, frame)
+            }
+
+            fn receive(&mut self) -> Result<CanFrame, CanError> {
+                receive_blocking(self)
+            }
+        }
+
+        /// Frames are queued by the driver, a pending frame is never replaced.
+        impl embedded_can::nb::Can for $socket {
+            type Frame = CanFrame;
+            type Error = CanError;
+
+            fn transmit(&mut self, frame: &CanFrame) -> nb::Result<Option<CanFrame>, CanError> {
+                self.send(*frame).map(|()| None).map_err(would_block)
+            }
+
+            fn receive(&mut self) -> nb::Result<CanFrame, CanError> {
+                self.recv_frame().map_err(would_block)
+            }
+        }
+    )*};
+}
+
+impl_embedded_can!(
+    UsbCanSocket,
+    DngCanSocket,
+    IsaCanSocket,
+    LanCanSocket,
+    PccCanSocket,
+    PciCanSocket
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_frames() {
+        let id = StandardId::new(0x123).unwrap();
+        let frame = <CanFrame as Frame>::new(id, &[1, 2, 3]).unwrap();
+        assert_eq!(Frame::id(&frame), Id::Standard(id));
+        assert_eq!(Frame::dlc(&frame), 3);
+        assert!(Frame::is_data_frame(&frame) && Frame::is_standard(&frame));
+        assert!(<CanFrame as Frame>::new(id, &[0; 9]).is_none());
+
+        let id = ExtendedId::new(0x18DA_F110).unwrap();
+        let remote = <CanFrame as Frame>::new_remote(id, 8).unwrap();
+        assert!(Frame::is_remote_frame(&remote) && Frame::is_extended(&remote));
+        assert_eq!(Frame::dlc(&remote), 8);
+
+        let fd = <CanFdFrame as Frame>::new(id, &[0xAA; 32]).unwrap();
+        assert!(fd.is_fd_frame() && fd.is_brs_frame());
+        assert_eq!(Frame::data(&fd).len(), 32);
+        assert!(<CanFdFrame as Frame>::new_remote(id, 0).is_none());
+
+        let copy: CanFrame = into_frame(&remote).unwrap();
+        let back = from_frame(&copy).unwrap();
+        assert_eq!(Frame::id(&back), Id::Extended(id));
+        assert!(back.is_remote_frame());
+
+        assert_eq!(CanError::QOverrun.kind(), ErrorKind::Overrun);
+        assert!(matches!(
+            would_block(CanError::QrcvEmpty),
+            nb::Error::WouldBlock
+        ));
+    }
+}
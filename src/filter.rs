@@ -0,0 +1,541 @@
+//! Software frame filtering and routing.
+//!
+//! The acceptance filters of [df](crate::df) hold one code/mask pair per identifier width.
+//! A [Rule] combines any number of conditions on identifiers, frame kind, CAN FD flags,
+//! echo and payload bytes. [Filtered] applies a rule to a socket, [Router] hands the frames
+//! of one socket to several consumers, each with its own rule and channel, so several
+//! components of a process can share one socket.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::filter::{Router, Rule};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//!
+//! let mut router = Router::new();
+//! let diagnostics = router.route(Rule::IdRange(0x7E0, 0x7EF));
+//! let engine = router.route(Rule::Id(0x100).or(Rule::mask(0x200, 0x7F0)).and(!Rule::Echo));
+//! let errors = router.route(Rule::Kind(peak_can::filter::FrameKind::Error));
+//!
+//! std::thread::spawn(move || {
+//!     for (frame, timestamp) in diagnostics {
+//!         println!("{timestamp} {:03X} {:02X?}", frame.can_id(), frame.data());
+//!     }
+//! });
+//!
+//! loop {
+//!     router.pump(&socket)?;
+//!     std::thread::sleep(std::time::Duration::from_millis(1));
+//! }
+//! # Ok::<(), peak_can::error::CanError>(())
+//! ```
+
+use crate::error::CanError;
+use crate::socket::{
+    CanFdFrame, CanFrame, MessageType, RecvCan, RecvCanFd, SendCan, SendCanFd, Timestamp,
+};
+
+use std::ops::Not;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Received classic or CAN FD frame.
+#[derive(Debug, Clone, Copy)]
+pub enum AnyFrame {
+    Can(CanFrame),
+    Fd(CanFdFrame),
+}
+
+impl AnyFrame {
+    pub fn can_id(&self) -> u32 {
+        match self {
+            AnyFrame::Can(frame) => frame.can_id(),
+            AnyFrame::Fd(frame) => frame.can_id(),
+        }
+    }
+
+    pub fn is_extended_frame(&self) -> bool {
+        match self {
+            AnyFrame::Can(frame) => frame.is_extended_frame(),
+            AnyFrame::Fd(frame) => frame.is_extended_frame(),
+        }
+    }
+
+    pub fn is_remote_frame(&self) -> bool {
+        match self {
+            AnyFrame::Can(frame) => frame.is_remote_frame(),
+            AnyFrame::Fd(_) => false,
+        }
+    }
+
+    pub fn is_error_frame(&self) -> bool {
+        match self {
+            AnyFrame::Can(frame) => frame.is_error_frame(),
+            AnyFrame::Fd(frame) => frame.is_error_frame(),
+        }
+    }
+
+    pub fn is_status_frame(&self) -> bool {
+        match self {
+            AnyFrame::Can(frame) => frame.is_status_frame(),
+            AnyFrame::Fd(frame) => frame.is_status_frame(),
+        }
+    }
+
+    pub fn is_echo_frame(&self) -> bool {
+        match self {
+            AnyFrame::Can(frame) => frame.is_echo_frame(),
+            AnyFrame::Fd(frame) => frame.is_echo_frame(),
+        }
+    }
+
+    /// `true` for frames in CAN FD format, frames received on a CAN FD socket may be
+    /// classic frames.
+    pub fn is_fd_frame(&self) -> bool {
+        match self {
+            AnyFrame::Can(_) => false,
+            AnyFrame::Fd(frame) => frame.is_fd_frame(),
+        }
+    }
+
+    pub fn is_brs_frame(&self) -> bool {
+        match self {
+            AnyFrame::Can(_) => false,
+            AnyFrame::Fd(frame) => frame.is_brs_frame(),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            AnyFrame::Can(frame) => frame.data(),
+            AnyFrame::Fd(frame) => frame.data(),
+        }
+    }
+
+    pub fn kind(&self) -> FrameKind {
+        if self.is_error_frame() {
+            FrameKind::Error
+        } else if self.is_status_frame() {
+            FrameKind::Status
+        } else if self.is_remote_frame() {
+            FrameKind::Remote
+        } else {
+            FrameKind::Data
+        }
+    }
+
+    /// The frame for a classic CAN socket, `None` for frames in CAN FD format.
+    pub fn to_can(&self) -> Option<CanFrame> {
+        match self {
+            AnyFrame::Can(frame) => Some(*frame),
+            AnyFrame::Fd(frame) if frame.is_fd_frame() => None,
+            AnyFrame::Fd(frame) => {
+                CanFrame::new(frame.can_id(), self.message_type(), frame.data()).ok()
+            }
+        }
+    }
+
+    /// The frame for a CAN FD socket, classic frames keep the classic format. `None` for
+    /// remote frames, which CAN FD sockets cannot send.
+    pub fn to_fd(&self) -> Option<CanFdFrame> {
+        match self {
+            AnyFrame::Fd(frame) => Some(*frame),
+            AnyFrame::Can(frame) if frame.is_remote_frame() => None,
+            AnyFrame::Can(frame) => CanFdFrame::new(
+                frame.can_id(),
+                self.message_type(),
+                frame.data(),
+                false,
+                false,
+            )
+            .ok(),
+        }
+    }
+
+    fn message_type(&self) -> MessageType {
+        if self.is_extended_frame() {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        }
+    }
+}
+
+impl From<CanFrame> for AnyFrame {
+    fn from(frame: CanFrame) -> Self {
+        AnyFrame::Can(frame)
+    }
+}
+
+impl From<CanFdFrame> for AnyFrame {
+    fn from(frame: CanFdFrame) -> Self {
+        AnyFrame::Fd(frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameKind {
+    Data,
+    Remote,
+    Error,
+    Status,
+}
+
+/* Rules */
+
+/// Condition on a received frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// Matches every frame.
+    Always,
+    Id(u32),
+    Ids(Vec<u32>),
+    /// Inclusive identifier range.
+    IdRange(u32, u32),
+    /// `id & mask == code & mask`.
+    Mask {
+        code: u32,
+        mask: u32,
+    },
+    /// 29 bit identifier.
+    Extended,
+    Kind(FrameKind),
+    /// CAN FD format.
+    Fd,
+    /// Bit rate switch.
+    Brs,
+    /// Echo of a frame sent by this channel.
+    Echo,
+    /// `data[index] & mask == value & mask`, never matches shorter frames.
+    Byte {
+        index: usize,
+        value: u8,
+        mask: u8,
+    },
+    /// Payload of at least this many bytes.
+    MinLength(usize),
+    Not(Box<Rule>),
+    All(Vec<Rule>),
+    Any(Vec<Rule>),
+}
+
+impl Rule {
+    pub fn mask(code: u32, mask: u32) -> Rule {
+        Rule::Mask { code, mask }
+    }
+
+    /// Payload bytes starting at `offset` equal to `bytes`.
+    pub fn payload(offset: usize, bytes: &[u8]) -> Rule {
+        Rule::All(
+            bytes
+                .iter()
+                .enumerate()
+                .map(|(i, value)| Rule::Byte {
+                    index: offset + i,
+                    value: *value,
+                    mask: 0xFF,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn and(self, other: Rule) -> Rule {
+        match self {
+            Rule::All(mut rules) => {
+                rules.push(other);
+                Rule::All(rules)
+            }
+            rule => Rule::All(vec![rule, other]),
+        }
+    }
+
+    pub fn or(self, other: Rule) -> Rule {
+        match self {
+            Rule::Any(mut rules) => {
+                rules.push(other);
+                Rule::Any(rules)
+            }
+            rule => Rule::Any(vec![rule, other]),
+        }
+    }
+
+    pub fn matches(&self, frame: &AnyFrame) -> bool {
+        match self {
+            Rule::Always => true,
+            Rule::Id(id) => frame.can_id() == *id,
+            Rule::Ids(ids) => ids.contains(&frame.can_id()),
+            Rule::IdRange(first, last) => (*first..=*last).contains(&frame.can_id()),
+            Rule::Mask { code, mask } => frame.can_id() & mask == code & mask,
+            Rule::Extended => frame.is_extended_frame(),
+            Rule::Kind(kind) => frame.kind() == *kind,
+            Rule::Fd => frame.is_fd_frame(),
+            Rule::Brs => frame.is_brs_frame(),
+            Rule::Echo => frame.is_echo_frame(),
+            Rule::Byte { index, value, mask } => frame
+                .data()
+                .get(*index)
+                .is_some_and(|byte| byte & mask == value & mask),
+            Rule::MinLength(len) => frame.data().len() >= *len,
+            Rule::Not(rule) => !rule.matches(frame),
+            Rule::All(rules) => rules.iter().all(|rule| rule.matches(frame)),
+            Rule::Any(rules) => rules.iter().any(|rule| rule.matches(frame)),
+        }
+    }
+}
+
+impl Not for Rule {
+    type Output = Rule;
+
+    fn not(self) -> Rule {
+        match self {
+            Rule::Not(rule) => *rule,
+            rule => Rule::Not(Box::new(rule)),
+        }
+    }
+}
+
+/* Filtered socket */
+
+/// Socket that only receives frames matching a rule. Other frames are read and dropped,
+/// sending is passed through.
+pub struct Filtered<S> {
+    socket: S,
+    rule: Rule,
+}
+
+impl<S> Filtered<S> {
+    pub fn new(socket: S, rule: Rule) -> Filtered<S> {
+        Filtered { socket, rule }
+    }
+
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    pub fn set_rule(&mut self, rule: Rule) {
+        self.rule = rule;
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+}
+
+impl<S: RecvCan> RecvCan for Filtered<S> {
+    fn recv(&self) -> Result<(CanFrame, Timestamp), CanError> {
+        loop {
+            let (frame, timestamp) = self.socket.recv()?;
+            if self.rule.matches(&AnyFrame::Can(frame)) {
+                return Ok((frame, timestamp));
+            }
+        }
+    }
+
+    fn recv_frame(&self) -> Result<CanFrame, CanError> {
+        self.recv().map(|(frame, _)| frame)
+    }
+}
+
+impl<S: RecvCanFd> RecvCanFd for Filtered<S> {
+    fn recv_fd(&self) -> Result<(CanFdFrame, u64), CanError> {
+        loop {
+            let (frame, timestamp) = self.socket.recv_fd()?;
+            if self.rule.matches(&AnyFrame::Fd(frame)) {
+                return Ok((frame, timestamp));
+            }
+        }
+    }
+
+    fn recv_fd_frame(&self) -> Result<CanFdFrame, CanError> {
+        self.recv_fd().map(|(frame, _)| frame)
+    }
+}
+
+impl<S: SendCan> SendCan for Filtered<S> {
+    fn send(&self, frame: CanFrame) -> Result<(), CanError> {
+        self.socket.send(frame)
+    }
+}
+
+impl<S: SendCanFd> SendCanFd for Filtered<S> {
+    fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError> {
+        self.socket.send_fd(frame)
+    }
+}
+
+/* Router */
+
+struct Route {
+    rule: Rule,
+    sender: Sender<(AnyFrame, u64)>,
+}
+
+/// Distributes frames to the consumers whose rule matches, with timestamps in
+/// microseconds. A frame matching several rules is delivered to each of them, routes
+/// whose receiver was dropped are removed.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    dispatched: u64,
+    unmatched: u64,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Adds a consumer receiving every frame matching `rule`.
+    pub fn route(&mut self, rule: Rule) -> Receiver<(AnyFrame, u64)> {
+        let (sender, receiver) = mpsc::channel();
+        self.routes.push(Route { rule, sender });
+        receiver
+    }
+
+    pub fn routes(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Frames delivered to at least one consumer.
+    pub fn dispatched(&self) -> u64 {
+        self.dispatched
+    }
+
+    /// Frames no rule matched.
+    pub fn unmatched(&self) -> u64 {
+        self.unmatched
+    }
+
+    /// Delivers one frame, returns the number of consumers that received it.
+    pub fn dispatch(&mut self, frame: AnyFrame, timestamp: u64) -> usize {
+        let mut delivered = 0;
+        self.routes.retain(|route| {
+            if !route.rule.matches(&frame) {
+                return true;
+            }
+            let connected = route.sender.send((frame, timestamp)).is_ok();
+            if connected {
+                delivered += 1;
+            }
+            connected
+        });
+        if delivered > 0 {
+            self.dispatched += 1;
+        } else {
+            self.unmatched += 1;
+        }
+        delivered
+    }
+
+    /// Dispatches every frame waiting on a classic socket, returns the number of frames read.
+    pub fn pump<S: RecvCan + ?Sized>(&mut self, socket: &S) -> Result<usize, CanError> {
+        let mut count = 0;
+        loop {
+            match socket.recv() {
+                Ok((frame, timestamp)) => {
+                    self.dispatch(AnyFrame::Can(frame), timestamp.as_micros());
+                    count += 1;
+                }
+                Err(CanError::QrcvEmpty) => return Ok(count),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Dispatches every frame waiting on a CAN FD socket, returns the number of frames read.
+    pub fn pump_fd<S: RecvCanFd + ?Sized>(&mut self, socket: &S) -> Result<usize, CanError> {
+        let mut count = 0;
+        loop {
+            match socket.recv_fd() {
+                Ok((frame, timestamp)) => {
+                    self.dispatch(AnyFrame::Fd(frame), timestamp);
+                    count += 1;
+                }
+                Err(CanError::QrcvEmpty) => return Ok(count),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::mock_bus;
+
+    fn frame(id: u32, data: &[u8]) -> AnyFrame {
+        AnyFrame::Can(CanFrame::new(id, MessageType::Standard, data).unwrap())
+    }
+
+    #[test]
+    fn filter_rules() {
+        let fd = CanFdFrame::new(0x1234, MessageType::Extended, &[0; 12], true, true).unwrap();
+        let fd = AnyFrame::Fd(fd);
+        let remote = CanFrame::new_remote(0x7E0, MessageType::Standard, 8).unwrap();
+        let remote = AnyFrame::Can(remote);
+
+        assert!(Rule::Ids(vec![0x100, 0x200]).matches(&frame(0x200, &[])));
+        assert!(Rule::IdRange(0x7E0, 0x7EF).matches(&remote));
+        assert!(!Rule::IdRange(0x7E8, 0x7EF).matches(&remote));
+        assert!(Rule::mask(0x200, 0x7F0).matches(&frame(0x20A, &[])));
+        assert!(!Rule::mask(0x200, 0x7F0).matches(&frame(0x21A, &[])));
+        assert!(Rule::Kind(FrameKind::Remote).matches(&remote));
+        assert!(Rule::Fd.and(Rule::Brs).and(Rule::Extended).matches(&fd));
+        assert!(!Rule::Fd.matches(&remote));
+
+        let payload = Rule::payload(1, &[0x22, 0xF1]);
+        assert!(payload.matches(&frame(0x7E8, &[0x62, 0x22, 0xF1, 0x90])));
+        assert!(!payload.matches(&frame(0x7E8, &[0x62, 0x22])));
+        let nibble = Rule::Byte {
+            index: 0,
+            value: 0x10,
+            mask: 0xF0,
+        };
+        assert!(nibble.matches(&frame(0x7E8, &[0x1A])));
+        assert!(Rule::MinLength(12).matches(&fd));
+
+        let rule = Rule::Id(1).or(Rule::Id(2)).and(!Rule::Echo);
+        assert!(rule.matches(&frame(2, &[])));
+        assert!(!rule.matches(&frame(3, &[])));
+        assert_eq!(!!Rule::Echo, Rule::Echo);
+        assert!(Rule::All(vec![]).matches(&fd) && !Rule::Any(vec![]).matches(&fd));
+    }
+
+    #[test]
+    fn filter_socket_and_router() {
+        let (node, bus) = mock_bus();
+        for id in [0x100, 0x7E8, 0x200, 0x7E9, 0x300] {
+            bus.send(CanFrame::new(id, MessageType::Standard, &[id as u8]).unwrap())
+                .unwrap();
+        }
+
+        let filtered = Filtered::new(node, Rule::IdRange(0x7E8, 0x7EF));
+        assert_eq!(filtered.recv_frame().unwrap().can_id(), 0x7E8);
+
+        let mut router = Router::new();
+        let diagnostics = router.route(Rule::IdRange(0x700, 0x7FF));
+        let everything = router.route(Rule::Always);
+        let none = router.route(Rule::Id(0x555));
+        drop(none);
+        assert_eq!(router.pump(filtered.get_ref()).unwrap(), 3);
+
+        let ids: Vec<u32> = diagnostics.try_iter().map(|(f, _)| f.can_id()).collect();
+        assert_eq!(ids, [0x7E9]);
+        assert_eq!(everything.try_iter().count(), 3);
+        assert_eq!(router.dispatched(), 3);
+
+        // Routes of dropped receivers are removed on their next match
+        assert_eq!(router.dispatch(frame(0x555, &[]), 0), 1);
+        assert_eq!(router.routes(), 2);
+        drop(everything);
+        assert_eq!(router.dispatch(frame(0x555, &[]), 0), 0);
+        assert_eq!(router.unmatched(), 1);
+        assert_eq!(router.routes(), 1);
+    }
+}
@@ -0,0 +1,597 @@
+//! Frame forwarding between channels.
+//!
+//! A [Gateway] connects any number of [Port]s, classic CAN or CAN FD, and forwards frames
+//! along [Route]s. Each route goes in one direction and has its own filter, identifier
+//! translation, payload callback and rate limits. Frames are converted between the formats
+//! of the two ports: classic frames may be sent as CAN FD frames, CAN FD frames of more
+//! than 8 bytes are split into classic frames or rejected, see [Oversize]. Remote frames
+//! cannot be sent on CAN FD ports and are rejected.
+//!
+//! Error, status and echo frames are never forwarded. Every route counts its frames and
+//! measures the forwarding latency from reading a frame to the completed send.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::filter::Rule;
+//! # use peak_can::gateway::{Gateway, Oversize, Port, Route};
+//! # use peak_can::socket::{Baudrate, CanFdBitTiming};
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use std::time::Duration;
+//! let powertrain = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let timing = CanFdBitTiming::new(1, 32, 127, 32, 1, 10, 29, 10)?;
+//! let body = UsbCanSocket::open_fd_with_timing(UsbBus::USB2, &timing)?;
+//!
+//! let mut gateway = Gateway::new();
+//! let pt = gateway.add_port(Port::can(&powertrain));
+//! let bd = gateway.add_port(Port::fd(&body, true));
+//!
+//! gateway.add_route(
+//!     Route::new(pt, bd)
+//!         .filter(Rule::IdRange(0x100, 0x1FF))
+//!         .translate(0x120, 0x520)
+//!         .to_fd(true)
+//!         .rate_limit(100, Duration::from_secs(1)),
+//! );
+//! gateway.add_route(
+//!     Route::new(bd, pt)
+//!         .oversize(Oversize::Split)
+//!         .modify(|message| {
+//!             message.data.iter_mut().for_each(|byte| *byte ^= 0xFF);
+//!             true
+//!         }),
+//! );
+//!
+//! loop {
+//!     gateway.poll()?;
+//!     std::thread::sleep(Duration::from_micros(500));
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::filter::{AnyFrame, Rule};
+use crate::socket::{CanFdFrame, CanFrame, MessageType, RecvCan, RecvCanFd, SendCan, SendCanFd};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const IDLE_SLEEP: Duration = Duration::from_micros(200);
+
+/// Socket a gateway reads from and writes to.
+#[derive(Clone, Copy)]
+pub enum Port<'a> {
+    Can {
+        tx: &'a dyn SendCan,
+        rx: &'a dyn RecvCan,
+    },
+    Fd {
+        tx: &'a dyn SendCanFd,
+        rx: &'a dyn RecvCanFd,
+        /// Bit rate switch for frames sent in CAN FD format.
+        brs: bool,
+    },
+}
+
+impl<'a> Port<'a> {
+    pub fn can<S: SendCan + RecvCan>(socket: &'a S) -> Port<'a> {
+        Port::Can {
+            tx: socket,
+            rx: socket,
+        }
+    }
+
+    pub fn fd<S: SendCanFd + RecvCanFd>(socket: &'a S, brs: bool) -> Port<'a> {
+        Port::Fd {
+            tx: socket,
+            rx: socket,
+            brs,
+        }
+    }
+
+    pub(crate) fn recv(&self) -> Result<AnyFrame, CanError> {
+        match self {
+            Port::Can { rx, .. } => rx.recv_frame().map(AnyFrame::Can),
+            Port::Fd { rx, .. } => rx.recv_fd_frame().map(AnyFrame::Fd),
+        }
+    }
+}
+
+/// Frame on its way through a route, handed to the payload callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub id: u32,
+    pub extended: bool,
+    pub remote: bool,
+    /// Requested length of a remote frame.
+    pub dlc: u8,
+    pub data: Vec<u8>,
+    /// Received in CAN FD format.
+    pub fd: bool,
+    pub brs: bool,
+}
+
+impl Message {
+    fn from_frame(frame: &AnyFrame) -> Message {
+        let dlc = match frame {
+            AnyFrame::Can(frame) => frame.dlc(),
+            AnyFrame::Fd(frame) => frame.dlc(),
+        };
+        Message {
+            id: frame.can_id(),
+            extended: frame.is_extended_frame(),
+            remote: frame.is_remote_frame(),
+            dlc,
+            data: frame.data().to_vec(),
+            fd: frame.is_fd_frame(),
+            brs: frame.is_brs_frame(),
+        }
+    }
+
+    fn message_type(&self) -> MessageType {
+        if self.extended {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        }
+    }
+}
+
+/// Handling of payloads over 8 bytes forwarded to a classic CAN port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oversize {
+    /// The frame is dropped and counted as rejected.
+    #[default]
+    Reject,
+    /// The payload is sent in consecutive classic frames of up to 8 bytes with the same
+    /// identifier.
+    Split,
+}
+
+/* Routes */
+
+type Modify<'a> = Box<dyn FnMut(&mut Message) -> bool + 'a>;
+
+/// One forwarding direction between two ports.
+pub struct Route<'a> {
+    from: usize,
+    to: usize,
+    filter: Rule,
+    ids: HashMap<u32, u32>,
+    modify: Option<Modify<'a>>,
+    oversize: Oversize,
+    to_fd: bool,
+    bucket: Option<TokenBucket>,
+    min_interval: Option<(Duration, HashMap<u32, Instant>)>,
+    stats: RouteStats,
+}
+
+impl<'a> Route<'a> {
+    /// Forwards every data and remote frame from port `from` to port `to`.
+    pub fn new(from: usize, to: usize) -> Route<'a> {
+        Route {
+            from,
+            to,
+            filter: Rule::Always,
+            ids: HashMap::new(),
+            modify: None,
+            oversize: Oversize::Reject,
+            to_fd: false,
+            bucket: None,
+            min_interval: None,
+            stats: RouteStats::default(),
+        }
+    }
+
+    /// Only forwards frames matching `rule`, evaluated on the received frame.
+    pub fn filter(mut self, rule: Rule) -> Self {
+        self.filter = rule;
+        self
+    }
+
+    /// Sends frames received with identifier `from` with identifier `to`.
+    pub fn translate(mut self, from: u32, to: u32) -> Self {
+        self.ids.insert(from, to);
+        self
+    }
+
+    /// Called after the identifier translation, may change the message or return `false`
+    /// to drop it.
+    pub fn modify(mut self, modify: impl FnMut(&mut Message) -> bool + 'a) -> Self {
+        self.modify = Some(Box::new(modify));
+        self
+    }
+
+    pub fn oversize(mut self, oversize: Oversize) -> Self {
+        self.oversize = oversize;
+        self
+    }
+
+    /// Sends classic frames in CAN FD format on a CAN FD port. Frames over 8 bytes are
+    /// always sent in CAN FD format.
+    pub fn to_fd(mut self, to_fd: bool) -> Self {
+        self.to_fd = to_fd;
+        self
+    }
+
+    /// Forwards at most `frames` frames per `period`, bursts included.
+    pub fn rate_limit(mut self, frames: u32, period: Duration) -> Self {
+        self.bucket = Some(TokenBucket::new(frames, period));
+        self
+    }
+
+    /// Minimum time between two forwarded frames of the same identifier.
+    pub fn min_interval(mut self, interval: Duration) -> Self {
+        self.min_interval = Some((interval, HashMap::new()));
+        self
+    }
+
+    pub fn stats(&self) -> &RouteStats {
+        &self.stats
+    }
+
+    /// Applies filter, rate limits, translation and callback, `None` drops the frame.
+    fn prepare(&mut self, frame: &AnyFrame, now: Instant) -> Option<Message> {
+        if !self.filter.matches(frame) {
+            self.stats.filtered += 1;
+            return None;
+        }
+        let too_soon = self.min_interval.as_ref().is_some_and(|(interval, last)| {
+            last.get(&frame.can_id())
+                .is_some_and(|sent| now.duration_since(*sent) < *interval)
+        });
+        if too_soon || self.bucket.as_mut().is_some_and(|bucket| !bucket.take(now)) {
+            self.stats.rate_limited += 1;
+            return None;
+        }
+        if let Some((_, last)) = &mut self.min_interval {
+            last.insert(frame.can_id(), now);
+        }
+        let mut message = Message::from_frame(frame);
+        if let Some(id) = self.ids.get(&message.id) {
+            message.id = *id;
+        }
+        if let Some(modify) = &mut self.modify
+            && !modify(&mut message)
+        {
+            self.stats.filtered += 1;
+            return None;
+        }
+        Some(message)
+    }
+
+    /// Frames to send on the target port, an error if the message cannot be sent there.
+    fn convert(&self, message: &Message, port: &Port) -> Result<Vec<AnyFrame>, ()> {
+        let kind = message.message_type();
+        match port {
+            Port::Can { .. } if message.remote => {
+                CanFrame::new_remote(message.id, kind, message.dlc)
+                    .map(|frame| vec![AnyFrame::Can(frame)])
+                    .map_err(|_| ())
+            }
+            Port::Can { .. } if message.data.len() > 8 && self.oversize == Oversize::Reject => {
+                Err(())
+            }
+            Port::Can { .. } if message.data.is_empty() => CanFrame::new(message.id, kind, &[])
+                .map(|frame| vec![AnyFrame::Can(frame)])
+                .map_err(|_| ()),
+            Port::Can { .. } => message
+                .data
+                .chunks(8)
+                .map(|chunk| CanFrame::new(message.id, kind, chunk).map(AnyFrame::Can))
+                .collect::<Result<_, _>>()
+                .map_err(|_| ()),
+            Port::Fd { .. } if message.remote => Err(()),
+            Port::Fd { brs, .. } => {
+                let fd = message.fd || self.to_fd || message.data.len() > 8;
+                CanFdFrame::new(message.id, kind, &message.data, fd, fd && *brs)
+                    .map(|frame| vec![AnyFrame::Fd(frame)])
+                    .map_err(|_| ())
+            }
+        }
+    }
+}
+
+/// Token bucket refilled continuously to `capacity` tokens per `period`.
+struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    updated: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(frames: u32, period: Duration) -> TokenBucket {
+        TokenBucket {
+            capacity: frames as f64,
+            per_second: frames as f64 / period.as_secs_f64().max(f64::EPSILON),
+            tokens: frames as f64,
+            updated: None,
+        }
+    }
+
+    fn take(&mut self, now: Instant) -> bool {
+        if let Some(updated) = self.updated {
+            let refill = now.duration_since(updated).as_secs_f64() * self.per_second;
+            self.tokens = (self.tokens + refill).min(self.capacity);
+        }
+        self.updated = Some(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Counters and forwarding latency of a route.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteStats {
+    /// Frames read from the source port and handed to the target port.
+    pub forwarded: u64,
+    /// Frames sent on the target port, more than forwarded when payloads are split.
+    pub sent: u64,
+    /// Frames dropped by the filter or the payload callback.
+    pub filtered: u64,
+    /// Frames that cannot be sent on the target port.
+    pub rejected: u64,
+    pub rate_limited: u64,
+    pub send_errors: u64,
+    pub latency: LatencyStats,
+}
+
+/// Time from reading a frame to the completed send.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyStats {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl LatencyStats {
+    fn record(&mut self, latency: Duration) {
+        if self.count == 0 || latency < self.min {
+            self.min = latency;
+        }
+        self.max = self.max.max(latency);
+        self.total += latency;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0)
+            .then(|| Duration::from_nanos((self.total.as_nanos() / u128::from(self.count)) as u64))
+    }
+}
+
+/* Gateway */
+
+/// Forwards frames between ports, see the [module documentation](self).
+#[derive(Default)]
+pub struct Gateway<'a> {
+    ports: Vec<Port<'a>>,
+    routes: Vec<Route<'a>>,
+}
+
+impl<'a> Gateway<'a> {
+    pub fn new() -> Gateway<'a> {
+        Gateway::default()
+    }
+
+    /// Adds a port, returns its index for the routes.
+    pub fn add_port(&mut self, port: Port<'a>) -> usize {
+        self.ports.push(port);
+        self.ports.len() - 1
+    }
+
+    /// Adds a route, returns its index for [Gateway::stats].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the route refers to a port that was not added.
+    pub fn add_route(&mut self, route: Route<'a>) -> usize {
+        assert!(
+            route.from < self.ports.len() && route.to < self.ports.len(),
+            "route refers to an unknown port"
+        );
+        self.routes.push(route);
+        self.routes.len() - 1
+    }
+
+    pub fn stats(&self, route: usize) -> Option<&RouteStats> {
+        self.routes.get(route).map(Route::stats)
+    }
+
+    /// Forwards one frame received on port `from`.
+    pub fn forward(&mut self, from: usize, frame: &AnyFrame) {
+        self.forward_at(from, frame, Instant::now());
+    }
+
+    fn forward_at(&mut self, from: usize, frame: &AnyFrame, received: Instant) {
+        if frame.is_error_frame() || frame.is_status_frame() || frame.is_echo_frame() {
+            return;
+        }
+        for route in self.routes.iter_mut().filter(|route| route.from == from) {
+            let Some(message) = route.prepare(frame, received) else {
+                continue;
+            };
+            let port = self.ports[route.to];
+            let Ok(frames) = route.convert(&message, &port) else {
+                route.stats.rejected += 1;
+                continue;
+            };
+            route.stats.forwarded += 1;
+            let mut failed = false;
+            for frame in frames {
+                let result = match (&port, frame) {
+                    (Port::Can { tx, .. }, AnyFrame::Can(frame)) => tx.send(frame),
+                    (Port::Fd { tx, .. }, AnyFrame::Fd(frame)) => tx.send_fd(frame),
+                    _ => unreachable!("frames are converted for their port"),
+                };
+                match result {
+                    Ok(()) => route.stats.sent += 1,
+                    Err(_) => failed = true,
+                }
+            }
+            if failed {
+                route.stats.send_errors += 1;
+            } else {
+                route.stats.latency.record(received.elapsed());
+            }
+        }
+    }
+
+    /// Forwards every frame waiting on any port, returns the number of frames read.
+    pub fn poll(&mut self) -> Result<usize, CanError> {
+        let mut count = 0;
+        for from in 0..self.ports.len() {
+            loop {
+                match self.ports[from].recv() {
+                    Ok(frame) => {
+                        self.forward_at(from, &frame, Instant::now());
+                        count += 1;
+                    }
+                    Err(CanError::QrcvEmpty) => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Polls until `stop` is set.
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), CanError> {
+        while !stop.load(Ordering::Relaxed) {
+            if self.poll()? == 0 {
+                thread::sleep(IDLE_SLEEP);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::mock_bus;
+
+    fn drain(socket: &dyn RecvCan) -> Vec<CanFrame> {
+        std::iter::from_fn(|| socket.recv_frame().ok()).collect()
+    }
+
+    #[test]
+    fn gateway_translates_and_converts() {
+        let (classic, classic_bus) = mock_bus();
+        let (fd, fd_bus) = mock_bus();
+        let mut gateway = Gateway::new();
+        let a = gateway.add_port(Port::can(&classic));
+        let b = gateway.add_port(Port::fd(&fd, true));
+        let ab = gateway.add_route(
+            Route::new(a, b)
+                .filter(!Rule::Id(0x300))
+                .translate(0x100, 0x500)
+                .to_fd(true),
+        );
+        let ba = gateway.add_route(Route::new(b, a).oversize(Oversize::Split).modify(|m| {
+            m.data.reverse();
+            m.id != 0x666
+        }));
+
+        classic_bus
+            .send(CanFrame::new(0x100, MessageType::Standard, &[1, 2]).unwrap())
+            .unwrap();
+        classic_bus
+            .send(CanFrame::new(0x300, MessageType::Standard, &[3]).unwrap())
+            .unwrap();
+        classic_bus
+            .send(CanFrame::new_remote(0x200, MessageType::Standard, 4).unwrap())
+            .unwrap();
+        let long: Vec<u8> = (0..12).collect();
+        fd_bus
+            .send_fd(
+                CanFdFrame::new(0x18DA_F110, MessageType::Extended, &long, true, true).unwrap(),
+            )
+            .unwrap();
+        fd_bus
+            .send_fd(CanFdFrame::new(0x666, MessageType::Standard, &[0], true, false).unwrap())
+            .unwrap();
+        assert_eq!(gateway.poll().unwrap(), 5);
+
+        let (frame, _) = fd_bus.recv_fd().unwrap();
+        assert_eq!(frame.can_id(), 0x500);
+        assert!(frame.is_fd_frame() && frame.is_brs_frame());
+        assert_eq!(frame.data(), [1, 2]);
+        assert!(fd_bus.recv_fd().is_err());
+
+        let stats = gateway.stats(ab).unwrap();
+        assert_eq!((stats.forwarded, stats.filtered, stats.rejected), (1, 1, 1));
+        assert_eq!(stats.latency.count, 1);
+
+        let frames = drain(&classic_bus);
+        assert_eq!(frames.len(), 2);
+        assert!(
+            frames
+                .iter()
+                .all(|f| f.can_id() == 0x18DA_F110 && f.is_extended_frame())
+        );
+        assert_eq!(frames[0].data(), [11, 10, 9, 8, 7, 6, 5, 4]);
+        assert_eq!(frames[1].data(), [3, 2, 1, 0]);
+        let stats = gateway.stats(ba).unwrap();
+        assert_eq!((stats.forwarded, stats.sent, stats.filtered), (1, 2, 1));
+    }
+
+    #[test]
+    fn gateway_limits_rate_and_rejects_oversize() {
+        let (left, left_bus) = mock_bus();
+        let (right, right_bus) = mock_bus();
+        let mut gateway = Gateway::new();
+        let l = gateway.add_port(Port::fd(&left, false));
+        let r = gateway.add_port(Port::can(&right));
+        let route = gateway.add_route(
+            Route::new(l, r)
+                .rate_limit(3, Duration::from_secs(3600))
+                .min_interval(Duration::from_secs(3600)),
+        );
+
+        for id in [1, 1, 2, 3, 4, 5] {
+            left_bus
+                .send_fd(CanFdFrame::new(id, MessageType::Standard, &[0; 8], false, false).unwrap())
+                .unwrap();
+        }
+        gateway.poll().unwrap();
+        let ids: Vec<u32> = drain(&right_bus).iter().map(CanFrame::can_id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(gateway.stats(route).unwrap().rate_limited, 3);
+
+        let mut gateway = Gateway::new();
+        let l = gateway.add_port(Port::fd(&left, false));
+        let r = gateway.add_port(Port::can(&right));
+        let route = gateway.add_route(Route::new(l, r));
+        let long = CanFdFrame::new(7, MessageType::Standard, &[0; 16], true, false).unwrap();
+        gateway.forward(l, &AnyFrame::Fd(long));
+        assert_eq!(gateway.stats(route).unwrap().rejected, 1);
+        assert!(right_bus.recv_frame().is_err());
+    }
+
+    #[test]
+    fn gateway_latency_mean() {
+        let mut latency = LatencyStats::default();
+        assert_eq!(latency.mean(), None);
+        latency.record(Duration::from_micros(10));
+        latency.record(Duration::from_micros(15));
+        assert_eq!(latency.mean(), Some(Duration::from_nanos(12_500)));
+
+        // Beyond u32::MAX samples the count no longer fits the divisor of Duration
+        let latency = LatencyStats {
+            count: 1 << 32,
+            total: Duration::from_secs(1 << 32),
+            ..LatencyStats::default()
+        };
+        assert_eq!(latency.mean(), Some(Duration::from_secs(1)));
+    }
+}
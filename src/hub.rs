@@ -0,0 +1,498 @@
+//! One socket shared by several consumers.
+//!
+//! A channel can be initialized only once per process. [Hub] takes ownership of a socket,
+//! runs the receive loop on its own thread and copies every received frame into the
+//! bounded queue of each [Subscription] whose [Rule] matches. A full queue either drops
+//! the new frame, drops its oldest frame or detaches the subscriber, see [Overflow];
+//! dropped frames are counted per subscriber. [Transmitter] handles send through the same
+//! socket from any thread. Subscribers attach with [Hub::subscribe] and detach by dropping
+//! their subscription while the hub keeps running.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::filter::Rule;
+//! # use peak_can::hub::{Hub, Overflow};
+//! # use peak_can::socket::{Baudrate, CanFrame, MessageType, SendCan};
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let hub = Hub::start(socket);
+//!
+//! let logger = hub.subscribe(10_000);
+//! let decoder = hub.subscribe_with(Rule::IdRange(0x100, 0x1FF), 256, Overflow::DropOldest);
+//! std::thread::spawn(move || {
+//!     for (frame, timestamp) in logger.iter() {
+//!         println!("{timestamp} {:03X} {:02X?}", frame.can_id(), frame.data());
+//!     }
+//! });
+//!
+//! let tx = hub.transmitter();
+//! tx.send(CanFrame::new(0x123, MessageType::Standard, &[1, 2, 3]).unwrap())?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::filter::{AnyFrame, Rule};
+use crate::socket::{CanFdFrame, CanFrame, RecvCan, RecvCanFd, SendCan, SendCanFd};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const IDLE_SLEEP: Duration = Duration::from_micros(200);
+
+/// What happens to a frame for a subscriber whose queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// The new frame is dropped.
+    #[default]
+    DropNewest,
+    /// The oldest queued frame is dropped to make room.
+    DropOldest,
+    /// The subscriber is detached, its queue drains and then reports the end.
+    Detach,
+}
+
+/* Socket access */
+
+/// Socket owned by the hub, classic or CAN FD.
+trait HubSocket: Send + Sync {
+    fn recv_any(&self) -> Result<(AnyFrame, u64), CanError>;
+    fn send_can(&self, frame: CanFrame) -> Result<(), CanError>;
+    fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError>;
+}
+
+struct Classic<S>(S);
+
+impl<S: RecvCan + SendCan + Send + Sync> HubSocket for Classic<S> {
+    fn recv_any(&self) -> Result<(AnyFrame, u64), CanError> {
+        let (frame, timestamp) = self.0.recv()?;
+        Ok((AnyFrame::Can(frame), timestamp.as_micros()))
+    }
+
+    fn send_can(&self, frame: CanFrame) -> Result<(), CanError> {
+        self.0.send(frame)
+    }
+
+    /// Classic sockets only take frames in classic format.
+    fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError> {
+        let frame = AnyFrame::Fd(frame).to_can().ok_or(CanError::IllOperation)?;
+        self.0.send(frame)
+    }
+}
+
+struct Fd<S>(S);
+
+impl<S: RecvCanFd + SendCanFd + Send + Sync> HubSocket for Fd<S> {
+    fn recv_any(&self) -> Result<(AnyFrame, u64), CanError> {
+        let (frame, timestamp) = self.0.recv_fd()?;
+        Ok((AnyFrame::Fd(frame), timestamp))
+    }
+
+    /// CAN FD sockets cannot send remote frames.
+    fn send_can(&self, frame: CanFrame) -> Result<(), CanError> {
+        let frame = AnyFrame::Can(frame).to_fd().ok_or(CanError::IllOperation)?;
+        self.0.send_fd(frame)
+    }
+
+    fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError> {
+        self.0.send_fd(frame)
+    }
+}
+
+/* Subscriber queues */
+
+struct Queue {
+    rule: Rule,
+    capacity: usize,
+    overflow: Overflow,
+    frames: Mutex<VecDeque<(AnyFrame, u64)>>,
+    ready: Condvar,
+    /// Set when the subscription is dropped, detached on overflow or the hub stops.
+    closed: AtomicBool,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Queue {
+    /// Queues a frame, returns `false` once the queue no longer takes frames.
+    fn push(&self, frame: AnyFrame, timestamp: u64) -> bool {
+        if self.closed.load(Ordering::Acquire) {
+            return false;
+        }
+        if !self.rule.matches(&frame) {
+            return true;
+        }
+        let mut frames = self.frames.lock().unwrap();
+        if frames.len() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            match self.overflow {
+                Overflow::DropNewest => return true,
+                Overflow::DropOldest => {
+                    frames.pop_front();
+                }
+                Overflow::Detach => {
+                    self.closed.store(true, Ordering::Release);
+                    self.ready.notify_all();
+                    return false;
+                }
+            }
+        }
+        frames.push_back((frame, timestamp));
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+        self.ready.notify_one();
+        true
+    }
+
+    fn close(&self) {
+        // Taking the lock orders the flag with waiting receivers
+        let _frames = self.frames.lock().unwrap();
+        self.closed.store(true, Ordering::Release);
+        self.ready.notify_all();
+    }
+}
+
+/// Frames of one subscriber, with timestamps in microseconds.
+///
+/// Dropping the subscription detaches it from the hub.
+pub struct Subscription {
+    queue: Arc<Queue>,
+}
+
+impl Subscription {
+    /// Next frame without waiting.
+    pub fn try_recv(&self) -> Option<(AnyFrame, u64)> {
+        self.queue.frames.lock().unwrap().pop_front()
+    }
+
+    /// Waits for the next frame, `None` once the queue is drained and the subscription
+    /// was detached or the hub stopped.
+    pub fn recv(&self) -> Option<(AnyFrame, u64)> {
+        let mut frames = self.queue.frames.lock().unwrap();
+        loop {
+            if let Some(frame) = frames.pop_front() {
+                return Some(frame);
+            }
+            if self.queue.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            frames = self.queue.ready.wait(frames).unwrap();
+        }
+    }
+
+    /// Waits up to `timeout` for the next frame.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<(AnyFrame, u64)> {
+        let deadline = Instant::now() + timeout;
+        let mut frames = self.queue.frames.lock().unwrap();
+        loop {
+            if let Some(frame) = frames.pop_front() {
+                return Some(frame);
+            }
+            let now = Instant::now();
+            if self.queue.closed.load(Ordering::Acquire) || now >= deadline {
+                return None;
+            }
+            frames = self
+                .queue
+                .ready
+                .wait_timeout(frames, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Blocking iterator over the frames, ends like [Subscription::recv].
+    pub fn iter(&self) -> impl Iterator<Item = (AnyFrame, u64)> + '_ {
+        std::iter::from_fn(|| self.recv())
+    }
+
+    /// Frames waiting in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.frames.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frames lost to a full queue.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Frames queued for this subscriber so far.
+    pub fn delivered(&self) -> u64 {
+        self.queue.delivered.load(Ordering::Relaxed)
+    }
+
+    /// `false` after the subscriber was detached on overflow or the hub stopped.
+    pub fn is_attached(&self) -> bool {
+        !self.queue.closed.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+/* Transmit path */
+
+/// Sends through the socket of a hub, cloneable and usable from any thread.
+#[derive(Clone)]
+pub struct Transmitter {
+    socket: Arc<dyn HubSocket>,
+}
+
+impl SendCan for Transmitter {
+    /// On CAN FD sockets the frame is sent in classic format, remote frames are rejected.
+    fn send(&self, frame: CanFrame) -> Result<(), CanError> {
+        self.socket.send_can(frame)
+    }
+}
+
+impl SendCanFd for Transmitter {
+    /// On classic sockets only frames in classic format can be sent.
+    fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError> {
+        self.socket.send_fd(frame)
+    }
+}
+
+/* Hub */
+
+#[derive(Default)]
+struct Shared {
+    subscribers: Mutex<Vec<Arc<Queue>>>,
+    stop: AtomicBool,
+    received: AtomicU64,
+    receive_errors: AtomicU64,
+    last_error: Mutex<Option<CanError>>,
+}
+
+/// Owns a socket and distributes its frames, see the [module documentation](self).
+///
+/// Dropping the hub stops the receive loop and closes the socket once all transmitters
+/// are gone.
+pub struct Hub {
+    shared: Arc<Shared>,
+    socket: Arc<dyn HubSocket>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Hub {
+    /// Starts the receive loop on a classic CAN socket.
+    pub fn start<S>(socket: S) -> Hub
+    where
+        S: RecvCan + SendCan + Send + Sync + 'static,
+    {
+        Hub::spawn(Arc::new(Classic(socket)))
+    }
+
+    /// Starts the receive loop on a CAN FD socket.
+    pub fn start_fd<S>(socket: S) -> Hub
+    where
+        S: RecvCanFd + SendCanFd + Send + Sync + 'static,
+    {
+        Hub::spawn(Arc::new(Fd(socket)))
+    }
+
+    fn spawn(socket: Arc<dyn HubSocket>) -> Hub {
+        let shared = Arc::new(Shared::default());
+        let thread = thread::Builder::new()
+            .name("peak-can-hub".into())
+            .spawn({
+                let shared = shared.clone();
+                let socket = socket.clone();
+                move || receive_loop(&*socket, &shared)
+            })
+            .expect("failed to spawn the hub thread");
+        Hub {
+            shared,
+            socket,
+            thread: Some(thread),
+        }
+    }
+
+    /// Attaches a subscriber receiving every frame, dropping new frames when full.
+    pub fn subscribe(&self, capacity: usize) -> Subscription {
+        self.subscribe_with(Rule::Always, capacity, Overflow::DropNewest)
+    }
+
+    /// Attaches a subscriber receiving the frames matching `rule`.
+    pub fn subscribe_with(&self, rule: Rule, capacity: usize, overflow: Overflow) -> Subscription {
+        let queue = Arc::new(Queue {
+            rule,
+            capacity: capacity.max(1),
+            overflow,
+            frames: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+            closed: AtomicBool::new(self.shared.stop.load(Ordering::Acquire)),
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        self.shared.subscribers.lock().unwrap().push(queue.clone());
+        Subscription { queue }
+    }
+
+    pub fn transmitter(&self) -> Transmitter {
+        Transmitter {
+            socket: self.socket.clone(),
+        }
+    }
+
+    /// Attached subscribers.
+    pub fn subscribers(&self) -> usize {
+        let mut subscribers = self.shared.subscribers.lock().unwrap();
+        subscribers.retain(|queue| !queue.closed.load(Ordering::Acquire));
+        subscribers.len()
+    }
+
+    /// Frames read from the socket.
+    pub fn received(&self) -> u64 {
+        self.shared.received.load(Ordering::Acquire)
+    }
+
+    /// Failed reads other than an empty queue, the last one is kept.
+    pub fn receive_errors(&self) -> (u64, Option<CanError>) {
+        (
+            self.shared.receive_errors.load(Ordering::Relaxed),
+            self.shared.last_error.lock().unwrap().clone(),
+        )
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Stops the receive loop and detaches all subscribers, which still drain their
+    /// queues.
+    pub fn stop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        for queue in self.shared.subscribers.lock().unwrap().drain(..) {
+            queue.close();
+        }
+    }
+}
+
+impl Drop for Hub {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn receive_loop(socket: &dyn HubSocket, shared: &Shared) {
+    while !shared.stop.load(Ordering::Acquire) {
+        match socket.recv_any() {
+            Ok((frame, timestamp)) => {
+                shared
+                    .subscribers
+                    .lock()
+                    .unwrap()
+                    .retain(|queue| queue.push(frame, timestamp));
+                shared.received.fetch_add(1, Ordering::Release);
+            }
+            Err(CanError::QrcvEmpty) => thread::sleep(IDLE_SLEEP),
+            Err(err) => {
+                shared.receive_errors.fetch_add(1, Ordering::Relaxed);
+                *shared.last_error.lock().unwrap() = Some(err);
+                thread::sleep(IDLE_SLEEP);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::MessageType;
+    use crate::socket::mock::mock_bus;
+
+    fn frame(id: u32) -> CanFrame {
+        CanFrame::new(id, MessageType::Standard, &[id as u8]).unwrap()
+    }
+
+    fn wait_until(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn hub_fans_out_and_transmits() {
+        let (node, bus) = mock_bus();
+        let mut hub = Hub::start(node);
+        let all = hub.subscribe(16);
+        let high = hub.subscribe_with(Rule::IdRange(0x200, 0x2FF), 16, Overflow::DropNewest);
+        assert_eq!(hub.subscribers(), 2);
+
+        for id in [0x100, 0x200, 0x201] {
+            bus.send(frame(id)).unwrap();
+        }
+        let ids: Vec<u32> = (0..3)
+            .map(|_| all.recv_timeout(Duration::from_secs(2)).unwrap().0.can_id())
+            .collect();
+        assert_eq!(ids, [0x100, 0x200, 0x201]);
+        assert_eq!(high.recv().unwrap().0.can_id(), 0x200);
+        assert_eq!(high.recv().unwrap().0.can_id(), 0x201);
+
+        // Detaching at runtime
+        drop(high);
+        assert_eq!(hub.subscribers(), 1);
+        bus.send(frame(0x202)).unwrap();
+        assert_eq!(all.recv().unwrap().0.can_id(), 0x202);
+        assert_eq!(hub.received(), 4);
+
+        let tx = hub.transmitter();
+        thread::spawn(move || tx.send(frame(0x7FF)).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(bus.recv_frame().unwrap().can_id(), 0x7FF);
+        let fd = CanFdFrame::new(0x10, MessageType::Standard, &[0; 12], true, false).unwrap();
+        assert!(hub.transmitter().send_fd(fd).is_err());
+
+        hub.stop();
+        assert!(!hub.is_running() && !all.is_attached());
+        assert!(all.recv().is_none());
+    }
+
+    #[test]
+    fn hub_overflow_policies() {
+        let (node, bus) = mock_bus();
+        let hub = Hub::start(node);
+        let newest = hub.subscribe_with(Rule::Always, 2, Overflow::DropNewest);
+        let oldest = hub.subscribe_with(Rule::Always, 2, Overflow::DropOldest);
+        let detach = hub.subscribe_with(Rule::Always, 2, Overflow::Detach);
+
+        for id in 1..=4 {
+            bus.send(frame(id)).unwrap();
+        }
+        wait_until(|| hub.received() == 4);
+
+        let ids = |sub: &Subscription| -> Vec<u32> {
+            std::iter::from_fn(|| sub.try_recv())
+                .map(|(frame, _)| frame.can_id())
+                .collect()
+        };
+        assert_eq!(newest.dropped(), 2);
+        assert_eq!(ids(&newest), [1, 2]);
+        assert_eq!(oldest.dropped(), 2);
+        assert_eq!(ids(&oldest), [3, 4]);
+
+        assert!(!detach.is_attached());
+        assert_eq!(detach.dropped(), 1);
+        assert_eq!(ids(&detach), [1, 2]);
+        assert!(detach.recv().is_none());
+        assert_eq!(hub.subscribers(), 2);
+    }
+}
@@ -11,7 +11,7 @@ use std::mem::size_of;
 use std::net::Ipv4Addr;
 use std::os::raw::c_char;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChannelConditionStatus {
     Unavailable,
     Available,
@@ -12,6 +12,8 @@ use crate::peak_can;
 use std::ffi::c_void;
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum IOConfig {
     In,
     InOut,
@@ -0,0 +1,757 @@
+//! ISO-TP (ISO 15765-2) transport protocol.
+//!
+//! An [IsoTpChannel] transfers payloads of up to 4 GB over a pair of CAN identifiers,
+//! segmenting them into single, first and consecutive frames and honouring the flow
+//! control (block size and STmin) requested by the receiver. Classic CAN and CAN FD links
+//! are supported, the latter with up to 64 byte frames and the escape sequences for long
+//! single frames and first frames. Normal, extended and mixed addressing, frame padding
+//! and the N_As, N_Bs and N_Cr timeouts are set in the [IsoTpConfig].
+//!
+//! Frames that do not belong to the configured receive identifier are read from the
+//! socket and dropped while a transfer is in progress.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::isotp::{IsoTpChannel, IsoTpConfig, IsoTpLink};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use std::time::Duration;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//!
+//! let mut config = IsoTpConfig::new(0x7E0, 0x7E8);
+//! config.padding = Some(0xCC);
+//! let channel = IsoTpChannel::new(IsoTpLink::can(&socket), config)?;
+//!
+//! channel.send(&[0x22, 0xF1, 0x90])?;
+//! if let Some(response) = channel.recv(Duration::from_secs(1))? {
+//!     println!("{response:02X?}");
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::socket::{
+    self, CanFdFrame, CanFrame, FrameConstructionError, MessageType, RecvCan, RecvCanFd, SendCan,
+    SendCanFd,
+};
+
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PCI_SINGLE_FRAME: u8 = 0x0;
+const PCI_FIRST_FRAME: u8 = 0x1;
+const PCI_CONSECUTIVE_FRAME: u8 = 0x2;
+const PCI_FLOW_CONTROL: u8 = 0x3;
+
+const FLOW_STATUS_CONTINUE: u8 = 0x0;
+const FLOW_STATUS_WAIT: u8 = 0x1;
+const FLOW_STATUS_OVERFLOW: u8 = 0x2;
+
+/// Largest first frame length encodable without the escape sequence.
+const MAX_SHORT_FF_DL: usize = 0xFFF;
+/// Padding used for CAN FD frames whose length is not a valid data length code.
+const DEFAULT_FD_PADDING: u8 = 0xCC;
+/// Upper bound for the receive buffer reserved up front.
+const MAX_PREALLOCATION: usize = 1 << 20;
+
+/// The timer that expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoTpTimeout {
+    /// A frame could not be handed to the transmit queue (N_As / N_Ar).
+    As,
+    /// No flow control frame from the receiver (N_Bs).
+    Bs,
+    /// No consecutive frame from the sender (N_Cr).
+    Cr,
+}
+
+#[derive(Debug)]
+pub enum IsoTpError {
+    Can(CanError),
+    Frame(FrameConstructionError),
+    Timeout(IsoTpTimeout),
+    /// The receiver rejected the first frame with a flow control overflow.
+    Overflow,
+    /// The receiver sent more wait frames than allowed.
+    WaitLimit,
+    /// The payload exceeds 4 GB or the configured receive limit.
+    PayloadTooLarge(usize),
+    /// The peer violated the protocol.
+    Protocol(String),
+    InvalidConfig(String),
+}
+
+impl fmt::Display for IsoTpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsoTpError::Can(e) => write!(f, "{e}"),
+            IsoTpError::Frame(e) => write!(f, "invalid frame: {e:?}"),
+            IsoTpError::Timeout(timer) => write!(f, "N_{timer:?} timeout"),
+            IsoTpError::Overflow => write!(f, "receiver buffer overflow"),
+            IsoTpError::WaitLimit => write!(f, "too many flow control wait frames"),
+            IsoTpError::PayloadTooLarge(len) => write!(f, "payload of {len} bytes too large"),
+            IsoTpError::Protocol(message) => write!(f, "protocol error: {message}"),
+            IsoTpError::InvalidConfig(message) => write!(f, "invalid configuration: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for IsoTpError {}
+
+impl From<CanError> for IsoTpError {
+    fn from(value: CanError) -> Self {
+        IsoTpError::Can(value)
+    }
+}
+
+impl From<FrameConstructionError> for IsoTpError {
+    fn from(value: FrameConstructionError) -> Self {
+        IsoTpError::Frame(value)
+    }
+}
+
+/// Network layer addressing format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addressing {
+    /// The CAN identifiers alone address the peers.
+    Normal,
+    /// The first data byte carries the target address. Received frames must carry
+    /// `source_address`.
+    Extended {
+        target_address: u8,
+        source_address: u8,
+    },
+    /// The first data byte carries the address extension in both directions.
+    Mixed { address_extension: u8 },
+}
+
+impl Addressing {
+    fn tx_prefix(&self) -> Option<u8> {
+        match *self {
+            Addressing::Normal => None,
+            Addressing::Extended { target_address, .. } => Some(target_address),
+            Addressing::Mixed { address_extension } => Some(address_extension),
+        }
+    }
+
+    fn rx_prefix(&self) -> Option<u8> {
+        match *self {
+            Addressing::Normal => None,
+            Addressing::Extended { source_address, .. } => Some(source_address),
+            Addressing::Mixed { address_extension } => Some(address_extension),
+        }
+    }
+
+    fn overhead(&self) -> usize {
+        self.tx_prefix().map_or(0, |_| 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsoTpConfig {
+    /// Identifier of transmitted frames.
+    pub tx_id: u32,
+    /// Identifier of received frames.
+    pub rx_id: u32,
+    pub msg_type: MessageType,
+    pub addressing: Addressing,
+    /// Block size announced to the sender, 0 for no further flow control.
+    pub block_size: u8,
+    /// Separation time announced to the sender.
+    pub st_min: Duration,
+    /// Fill byte for unused frame bytes, `None` to send frames of minimal length.
+    pub padding: Option<u8>,
+    /// Transmit data length: 8 on classic links, 8 to 64 on CAN FD links.
+    pub tx_dl: usize,
+    /// Bit rate switch for CAN FD frames.
+    pub brs: bool,
+    /// Time allowed for handing a frame to the transmit queue (N_As and N_Ar).
+    pub n_as: Duration,
+    /// Time allowed for the receiver to answer with a flow control frame.
+    pub n_bs: Duration,
+    /// Time allowed between two consecutive frames.
+    pub n_cr: Duration,
+    /// Number of flow control wait frames accepted in a row (N_WFTmax).
+    pub max_wait_frames: u32,
+    /// Largest payload accepted, longer first frames are answered with an overflow.
+    pub max_rx_len: usize,
+}
+
+impl IsoTpConfig {
+    /// Configuration with normal addressing and the ISO 15765-2 default timeouts of 1 s.
+    ///
+    /// The message type is extended when either identifier does not fit in 11 bits.
+    pub fn new(tx_id: u32, rx_id: u32) -> IsoTpConfig {
+        let msg_type = if tx_id > socket::STANDARD_MASK || rx_id > socket::STANDARD_MASK {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        };
+
+        IsoTpConfig {
+            tx_id,
+            rx_id,
+            msg_type,
+            addressing: Addressing::Normal,
+            block_size: 0,
+            st_min: Duration::ZERO,
+            padding: None,
+            tx_dl: 8,
+            brs: false,
+            n_as: Duration::from_millis(1000),
+            n_bs: Duration::from_millis(1000),
+            n_cr: Duration::from_millis(1000),
+            max_wait_frames: 10,
+            max_rx_len: u32::MAX as usize,
+        }
+    }
+}
+
+/// Encodes a separation time into the STmin byte of a flow control frame.
+pub fn encode_st_min(st_min: Duration) -> u8 {
+    let micros = st_min.as_micros();
+    if micros > 0 && micros < 1_000 {
+        0xF0 + micros.div_ceil(100) as u8
+    } else {
+        micros.div_ceil(1_000).min(0x7F) as u8
+    }
+}
+
+/// Decodes the STmin byte of a flow control frame. Reserved values map to 127 ms.
+pub fn decode_st_min(value: u8) -> Duration {
+    match value {
+        0x00..=0x7F => Duration::from_millis(value as u64),
+        0xF1..=0xF9 => Duration::from_micros((value - 0xF0) as u64 * 100),
+        _ => Duration::from_millis(0x7F),
+    }
+}
+
+/// The socket an [IsoTpChannel] transfers frames on.
+#[derive(Clone, Copy)]
+pub enum IsoTpLink<'a> {
+    Can {
+        tx: &'a dyn SendCan,
+        rx: &'a dyn RecvCan,
+    },
+    Fd {
+        tx: &'a dyn SendCanFd,
+        rx: &'a dyn RecvCanFd,
+    },
+}
+
+impl<'a> IsoTpLink<'a> {
+    /// Classic CAN link.
+    pub fn can<S: SendCan + RecvCan>(socket: &'a S) -> IsoTpLink<'a> {
+        IsoTpLink::Can {
+            tx: socket,
+            rx: socket,
+        }
+    }
+
+    /// CAN FD link, every frame is sent as an FD frame.
+    pub fn fd<S: SendCanFd + RecvCanFd>(socket: &'a S) -> IsoTpLink<'a> {
+        IsoTpLink::Fd {
+            tx: socket,
+            rx: socket,
+        }
+    }
+
+    fn max_frame_len(&self) -> usize {
+        match self {
+            IsoTpLink::Can { .. } => 8,
+            IsoTpLink::Fd { .. } => 64,
+        }
+    }
+}
+
+/// One ISO-TP connection between a pair of identifiers.
+pub struct IsoTpChannel<'a> {
+    link: IsoTpLink<'a>,
+    config: IsoTpConfig,
+}
+
+impl<'a> IsoTpChannel<'a> {
+    pub fn new(link: IsoTpLink<'a>, config: IsoTpConfig) -> Result<IsoTpChannel<'a>, IsoTpError> {
+        let valid_dl = matches!(config.tx_dl, 8 | 12 | 16 | 20 | 24 | 32 | 48 | 64);
+        if !valid_dl || config.tx_dl > link.max_frame_len() {
+            return Err(IsoTpError::InvalidConfig(format!(
+                "transmit data length {} not supported on this link",
+                config.tx_dl
+            )));
+        }
+        let max_id = match config.msg_type {
+            MessageType::Standard => socket::STANDARD_MASK,
+            MessageType::Extended => socket::EXTENDED_MASK,
+        };
+        if config.tx_id > max_id || config.rx_id > max_id {
+            return Err(IsoTpError::InvalidConfig(String::from(
+                "identifier does not match message type",
+            )));
+        }
+
+        Ok(IsoTpChannel { link, config })
+    }
+
+    pub fn config(&self) -> &IsoTpConfig {
+        &self.config
+    }
+
+    /// Sends `data` and blocks until the last frame has been queued.
+    pub fn send(&self, data: &[u8]) -> Result<(), IsoTpError> {
+        if data.len() > u32::MAX as usize {
+            return Err(IsoTpError::PayloadTooLarge(data.len()));
+        }
+
+        let overhead = self.config.addressing.overhead();
+        let tx_dl = self.config.tx_dl;
+        let max_short_sf = 7 - overhead;
+        let max_sf = if tx_dl == 8 {
+            max_short_sf
+        } else {
+            tx_dl - 2 - overhead
+        };
+
+        if data.len() <= max_sf {
+            let mut pdu = if data.len() <= max_short_sf {
+                vec![PCI_SINGLE_FRAME << 4 | data.len() as u8]
+            } else {
+                vec![PCI_SINGLE_FRAME << 4, data.len() as u8]
+            };
+            pdu.extend_from_slice(data);
+            return self.send_pdu(&pdu);
+        }
+
+        let mut pdu = if data.len() <= MAX_SHORT_FF_DL {
+            vec![
+                PCI_FIRST_FRAME << 4 | (data.len() >> 8) as u8,
+                data.len() as u8,
+            ]
+        } else {
+            let mut pdu = vec![PCI_FIRST_FRAME << 4, 0];
+            pdu.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            pdu
+        };
+        let mut pos = tx_dl - overhead - pdu.len();
+        pdu.extend_from_slice(&data[..pos]);
+        self.send_pdu(&pdu)?;
+
+        let cf_len = tx_dl - overhead - 1;
+        let mut sequence = 1u8;
+        while pos < data.len() {
+            let (block_size, st_min) = self.wait_flow_control()?;
+
+            let mut sent_in_block = 0;
+            while pos < data.len() && (block_size == 0 || sent_in_block < block_size) {
+                if sent_in_block > 0 && !st_min.is_zero() {
+                    thread::sleep(st_min);
+                }
+                let end = (pos + cf_len).min(data.len());
+                let mut pdu = vec![PCI_CONSECUTIVE_FRAME << 4 | sequence];
+                pdu.extend_from_slice(&data[pos..end]);
+                self.send_pdu(&pdu)?;
+
+                pos = end;
+                sequence = (sequence + 1) & 0x0F;
+                sent_in_block += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receives one payload.
+    ///
+    /// Returns `Ok(None)` when no single frame or first frame arrives within `timeout`.
+    pub fn recv(&self, timeout: Duration) -> Result<Option<Vec<u8>>, IsoTpError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let Some(pdu) = socket::poll_until(deadline, || self.recv_pdu())? else {
+                return Ok(None);
+            };
+            let Some(pdu) = pdu else {
+                continue;
+            };
+
+            match pdu[0] >> 4 {
+                PCI_SINGLE_FRAME => return self.single_frame(&pdu).map(Some),
+                PCI_FIRST_FRAME => return self.segmented(&pdu).map(Some),
+                // Stray consecutive or flow control frames
+                _ => continue,
+            }
+        }
+    }
+
+    fn single_frame(&self, pdu: &[u8]) -> Result<Vec<u8>, IsoTpError> {
+        let (len, start) = match pdu[0] & 0x0F {
+            0 if pdu.len() > 2 => (pdu[1] as usize, 2),
+            len => (len as usize, 1),
+        };
+        if len == 0 || start + len > pdu.len() {
+            return Err(IsoTpError::Protocol(format!(
+                "invalid single frame length {len}"
+            )));
+        }
+        Ok(pdu[start..start + len].to_vec())
+    }
+
+    fn segmented(&self, pdu: &[u8]) -> Result<Vec<u8>, IsoTpError> {
+        if pdu.len() < 2 {
+            return Err(IsoTpError::Protocol(String::from("truncated first frame")));
+        }
+        let (len, start) = match ((pdu[0] & 0x0F) as usize) << 8 | pdu[1] as usize {
+            0 if pdu.len() >= 6 => (
+                u32::from_be_bytes([pdu[2], pdu[3], pdu[4], pdu[5]]) as usize,
+                6,
+            ),
+            0 => return Err(IsoTpError::Protocol(String::from("truncated first frame"))),
+            len => (len, 2),
+        };
+
+        if len > self.config.max_rx_len {
+            self.send_flow_control(FLOW_STATUS_OVERFLOW)?;
+            return Err(IsoTpError::PayloadTooLarge(len));
+        }
+
+        let mut data = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+        data.extend_from_slice(&pdu[start..pdu.len().min(start + len)]);
+        self.send_flow_control(FLOW_STATUS_CONTINUE)?;
+
+        let mut sequence = 1u8;
+        let mut received_in_block = 0u8;
+        while data.len() < len {
+            let deadline = Instant::now() + self.config.n_cr;
+            let pdu = loop {
+                match socket::poll_until(deadline, || self.recv_pdu())? {
+                    None => return Err(IsoTpError::Timeout(IsoTpTimeout::Cr)),
+                    Some(Some(pdu)) => break pdu,
+                    Some(None) => continue,
+                }
+            };
+
+            match pdu[0] >> 4 {
+                PCI_CONSECUTIVE_FRAME => {}
+                PCI_FLOW_CONTROL => continue,
+                _ => {
+                    return Err(IsoTpError::Protocol(String::from(
+                        "transfer interrupted by a new message",
+                    )));
+                }
+            }
+            if pdu[0] & 0x0F != sequence {
+                return Err(IsoTpError::Protocol(format!(
+                    "expected sequence number {sequence}, got {}",
+                    pdu[0] & 0x0F
+                )));
+            }
+
+            let take = (len - data.len()).min(pdu.len() - 1);
+            data.extend_from_slice(&pdu[1..1 + take]);
+            sequence = (sequence + 1) & 0x0F;
+
+            received_in_block = received_in_block.wrapping_add(1);
+            if self.config.block_size != 0
+                && received_in_block == self.config.block_size
+                && data.len() < len
+            {
+                received_in_block = 0;
+                self.send_flow_control(FLOW_STATUS_CONTINUE)?;
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Waits for a clear-to-send flow control frame and returns block size and STmin.
+    fn wait_flow_control(&self) -> Result<(u8, Duration), IsoTpError> {
+        let mut waits = 0;
+        loop {
+            let deadline = Instant::now() + self.config.n_bs;
+            let pdu = loop {
+                match socket::poll_until(deadline, || self.recv_pdu())? {
+                    None => return Err(IsoTpError::Timeout(IsoTpTimeout::Bs)),
+                    Some(Some(pdu)) if pdu[0] >> 4 == PCI_FLOW_CONTROL => break pdu,
+                    Some(_) => continue,
+                }
+            };
+
+            match pdu[0] & 0x0F {
+                FLOW_STATUS_CONTINUE if pdu.len() >= 3 => {
+                    return Ok((pdu[1], decode_st_min(pdu[2])));
+                }
+                FLOW_STATUS_WAIT => {
+                    waits += 1;
+                    if waits > self.config.max_wait_frames {
+                        return Err(IsoTpError::WaitLimit);
+                    }
+                }
+                FLOW_STATUS_OVERFLOW => return Err(IsoTpError::Overflow),
+                status => {
+                    return Err(IsoTpError::Protocol(format!(
+                        "invalid flow status {status}"
+                    )));
+                }
+            }
+        }
+    }
+
+    fn send_flow_control(&self, status: u8) -> Result<(), IsoTpError> {
+        self.send_pdu(&[
+            PCI_FLOW_CONTROL << 4 | status,
+            self.config.block_size,
+            encode_st_min(self.config.st_min),
+        ])
+    }
+
+    /// Adds the address byte and padding to a protocol data unit and queues it.
+    fn send_pdu(&self, pdu: &[u8]) -> Result<(), IsoTpError> {
+        let mut data = Vec::with_capacity(self.config.tx_dl);
+        if let Some(prefix) = self.config.addressing.tx_prefix() {
+            data.push(prefix);
+        }
+        data.extend_from_slice(pdu);
+
+        if data.len() > 8 {
+            let len = CanFdFrame::dlc_to_len(CanFdFrame::calc_dlc(data.len()));
+            data.resize(len, self.config.padding.unwrap_or(DEFAULT_FD_PADDING));
+        } else if let Some(padding) = self.config.padding {
+            data.resize(8, padding);
+        }
+
+        let deadline = Instant::now() + self.config.n_as;
+        loop {
+            let result = match self.link {
+                IsoTpLink::Can { tx, .. } => tx.send(CanFrame::new(
+                    self.config.tx_id,
+                    self.config.msg_type,
+                    &data,
+                )?),
+                IsoTpLink::Fd { tx, .. } => tx.send_fd(CanFdFrame::new(
+                    self.config.tx_id,
+                    self.config.msg_type,
+                    &data,
+                    true,
+                    self.config.brs,
+                )?),
+            };
+
+            match result {
+                Err(CanError::QxmtFull) if Instant::now() < deadline => thread::yield_now(),
+                Err(CanError::QxmtFull) => return Err(IsoTpError::Timeout(IsoTpTimeout::As)),
+                other => return Ok(other?),
+            }
+        }
+    }
+
+    /// Reads one frame; `Ok(None)` if it is not addressed to this channel.
+    fn recv_pdu(&self) -> Result<Option<Vec<u8>>, CanError> {
+        let (can_id, extended, data) = match self.link {
+            IsoTpLink::Can { rx, .. } => {
+                let frame = rx.recv_frame()?;
+                if frame.is_remote_frame() {
+                    return Ok(None);
+                }
+                (
+                    frame.can_id(),
+                    frame.is_extended_frame(),
+                    frame.data().to_vec(),
+                )
+            }
+            IsoTpLink::Fd { rx, .. } => {
+                let frame = rx.recv_fd_frame()?;
+                (
+                    frame.can_id(),
+                    frame.is_extended_frame(),
+                    frame.data().to_vec(),
+                )
+            }
+        };
+
+        if can_id != self.config.rx_id
+            || extended != (self.config.msg_type == MessageType::Extended)
+        {
+            return Ok(None);
+        }
+
+        let data = match self.config.addressing.rx_prefix() {
+            None => data,
+            Some(prefix) if data.first() == Some(&prefix) => data[1..].to_vec(),
+            Some(_) => return Ok(None),
+        };
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::mock_bus;
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    fn transfer(tester: IsoTpConfig, ecu: IsoTpConfig, fd: bool, data: &[u8]) -> Vec<u8> {
+        let (a, b) = mock_bus();
+        thread::scope(|scope| {
+            let sender = scope.spawn(|| {
+                let link = if fd {
+                    IsoTpLink::fd(&a)
+                } else {
+                    IsoTpLink::can(&a)
+                };
+                IsoTpChannel::new(link, tester).unwrap().send(data)
+            });
+
+            let link = if fd {
+                IsoTpLink::fd(&b)
+            } else {
+                IsoTpLink::can(&b)
+            };
+            let received = IsoTpChannel::new(link, ecu)
+                .unwrap()
+                .recv(Duration::from_secs(2))
+                .unwrap()
+                .unwrap();
+            sender.join().unwrap().unwrap();
+            received
+        })
+    }
+
+    #[test]
+    fn isotp_single_frame_padding() {
+        let (a, b) = mock_bus();
+        let mut config = IsoTpConfig::new(0x7E0, 0x7E8);
+        config.padding = Some(0xAA);
+        IsoTpChannel::new(IsoTpLink::can(&a), config)
+            .unwrap()
+            .send(&[0x10, 0x03])
+            .unwrap();
+
+        let frame = b.recv_frame().unwrap();
+        assert_eq!(frame.can_id(), 0x7E0);
+        assert_eq!(
+            frame.data(),
+            &[0x02, 0x10, 0x03, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA]
+        );
+
+        let unpadded =
+            IsoTpChannel::new(IsoTpLink::can(&a), IsoTpConfig::new(0x7E0, 0x7E8)).unwrap();
+        unpadded.send(&[0x3E, 0x00]).unwrap();
+        assert_eq!(b.recv_frame().unwrap().data(), &[0x02, 0x3E, 0x00]);
+    }
+
+    #[test]
+    fn isotp_segmented_with_block_size() {
+        let data = payload(300);
+        let tester = IsoTpConfig::new(0x7E0, 0x7E8);
+        let mut ecu = IsoTpConfig::new(0x7E8, 0x7E0);
+        ecu.block_size = 4;
+        ecu.st_min = Duration::from_micros(200);
+
+        assert_eq!(transfer(tester, ecu, false, &data), data);
+    }
+
+    #[test]
+    fn isotp_extended_and_mixed_addressing() {
+        let data = payload(40);
+        let mut tester = IsoTpConfig::new(0x600, 0x601);
+        tester.addressing = Addressing::Extended {
+            target_address: 0x12,
+            source_address: 0xF1,
+        };
+        let mut ecu = IsoTpConfig::new(0x601, 0x600);
+        ecu.addressing = Addressing::Extended {
+            target_address: 0xF1,
+            source_address: 0x12,
+        };
+        assert_eq!(transfer(tester, ecu, false, &data), data);
+
+        let mut tester = IsoTpConfig::new(0x18DA_12F1, 0x18DA_F112);
+        tester.addressing = Addressing::Mixed {
+            address_extension: 0x55,
+        };
+        let mut ecu = IsoTpConfig::new(0x18DA_F112, 0x18DA_12F1);
+        ecu.addressing = Addressing::Mixed {
+            address_extension: 0x55,
+        };
+        assert_eq!(transfer(tester, ecu, false, &data), data);
+    }
+
+    #[test]
+    fn isotp_fd_escape_sequences() {
+        let mut tester = IsoTpConfig::new(0x7E0, 0x7E8);
+        tester.tx_dl = 64;
+        tester.brs = true;
+        let ecu = IsoTpConfig::new(0x7E8, 0x7E0);
+
+        // Single frame with escaped length
+        let (a, b) = mock_bus();
+        let data = payload(40);
+        IsoTpChannel::new(IsoTpLink::fd(&a), tester.clone())
+            .unwrap()
+            .send(&data)
+            .unwrap();
+        let frame = b.recv_fd_frame().unwrap();
+        assert_eq!(&frame.data()[..2], &[0x00, 40]);
+        assert_eq!(frame.len(), 48);
+        let received = IsoTpChannel::new(IsoTpLink::fd(&a), ecu.clone()).unwrap();
+        b.send_fd(frame).unwrap();
+        assert_eq!(
+            received.recv(Duration::from_millis(10)).unwrap(),
+            Some(data)
+        );
+
+        // First frame with escaped length beyond 4095 bytes
+        let data = payload(10_000);
+        assert_eq!(transfer(tester, ecu, true, &data), data);
+    }
+
+    #[test]
+    fn isotp_flow_control_timeout() {
+        let (a, _b) = mock_bus();
+        let mut config = IsoTpConfig::new(0x7E0, 0x7E8);
+        config.n_bs = Duration::from_millis(20);
+        let channel = IsoTpChannel::new(IsoTpLink::can(&a), config).unwrap();
+
+        assert!(matches!(
+            channel.send(&payload(20)),
+            Err(IsoTpError::Timeout(IsoTpTimeout::Bs))
+        ));
+        assert_eq!(channel.recv(Duration::from_millis(5)).unwrap(), None);
+    }
+
+    #[test]
+    fn isotp_receive_overflow() {
+        let (a, b) = mock_bus();
+        let mut config = IsoTpConfig::new(0x7E8, 0x7E0);
+        config.max_rx_len = 100;
+        let channel = IsoTpChannel::new(IsoTpLink::can(&a), config).unwrap();
+
+        b.send(
+            CanFrame::new(0x7E0, MessageType::Standard, &[0x10, 200, 0, 1, 2, 3, 4, 5]).unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            channel.recv(Duration::from_millis(10)),
+            Err(IsoTpError::PayloadTooLarge(200))
+        ));
+        assert_eq!(b.recv_frame().unwrap().data(), &[0x32, 0, 0]);
+    }
+
+    #[test]
+    fn isotp_st_min_encoding() {
+        assert_eq!(encode_st_min(Duration::ZERO), 0x00);
+        assert_eq!(encode_st_min(Duration::from_millis(20)), 20);
+        assert_eq!(encode_st_min(Duration::from_micros(300)), 0xF3);
+        assert_eq!(encode_st_min(Duration::from_secs(1)), 0x7F);
+        assert_eq!(decode_st_min(0xF5), Duration::from_micros(500));
+        assert_eq!(decode_st_min(0x80), Duration::from_millis(127));
+    }
+}
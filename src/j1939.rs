@@ -0,0 +1,988 @@
+//! SAE J1939 network layer.
+//!
+//! [J1939Id] converts between 29-bit CAN identifiers and priority, parameter group number,
+//! source and destination address. A [J1939Node] claims an address for its [Name] on the
+//! network (J1939-81), defends it against conflicting claims, transfers messages longer
+//! than 8 bytes with the BAM and RTS/CTS transport protocols (J1939-21) and sends request
+//! and acknowledgement messages.
+//!
+//! The node is driven from the calling thread: address claims, requests for the address
+//! claimed and transport sessions addressed to the node are handled inside
+//! [recv](J1939Node::recv) and [send](J1939Node::send).
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::j1939::{J1939Node, Name, GLOBAL_ADDRESS};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use std::time::Duration;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud250K)?;
+//! let name = Name {
+//!     identity_number: 0x1234,
+//!     manufacturer_code: 0x7FF,
+//!     function: 0x81,
+//!     arbitrary_address_capable: true,
+//!     ..Name::default()
+//! };
+//!
+//! let node = J1939Node::new(&socket, name, 0x80);
+//! let address = node.claim_address()?;
+//! println!("claimed address 0x{address:02X}");
+//!
+//! // Request the software identification of every node
+//! node.request(0xFEDA, GLOBAL_ADDRESS)?;
+//! while let Some(message) = node.recv(Duration::from_millis(500))? {
+//!     println!("{:?}: {:02X?}", message.id, message.data);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::socket::{self, CanFrame, FrameConstructionError, MessageType, RecvCan, SendCan};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const GLOBAL_ADDRESS: u8 = 0xFF;
+pub const NULL_ADDRESS: u8 = 0xFE;
+
+pub const PGN_REQUEST: u32 = 0xEA00;
+pub const PGN_ADDRESS_CLAIMED: u32 = 0xEE00;
+pub const PGN_ACKNOWLEDGEMENT: u32 = 0xE800;
+pub const PGN_TP_CM: u32 = 0xEC00;
+pub const PGN_TP_DT: u32 = 0xEB00;
+
+/// Default priority of control messages.
+pub const DEFAULT_PRIORITY: u8 = 6;
+
+/// Largest message transferred with the transport protocol.
+pub const MAX_TP_LEN: usize = 1785;
+
+const TP_CM_RTS: u8 = 16;
+const TP_CM_CTS: u8 = 17;
+const TP_CM_END_OF_MSG_ACK: u8 = 19;
+const TP_CM_BAM: u8 = 32;
+const TP_CM_ABORT: u8 = 255;
+
+/// Abort reason: timeout.
+const ABORT_TIMEOUT: u8 = 3;
+
+/// Time to wait for competing claims after an address claim.
+const CLAIM_TIMEOUT: Duration = Duration::from_millis(250);
+/// Gap between BAM data packets, J1939-21 allows 50 to 200 ms.
+const BAM_PACKET_INTERVAL: Duration = Duration::from_millis(50);
+const T1: Duration = Duration::from_millis(750);
+const T2: Duration = Duration::from_millis(1250);
+const T3: Duration = Duration::from_millis(1250);
+const T4: Duration = Duration::from_millis(1050);
+
+/// First address of the range used for arbitrary address selection.
+const ARBITRARY_ADDRESS_START: u8 = 128;
+/// Last address of the range used for arbitrary address selection.
+const ARBITRARY_ADDRESS_END: u8 = 247;
+
+#[derive(Debug)]
+pub enum J1939Error {
+    Can(CanError),
+    Frame(FrameConstructionError),
+    /// No free address could be claimed.
+    AddressClaimFailed,
+    /// The node has no claimed address.
+    NoAddress,
+    /// The message exceeds the transport protocol limit.
+    TooLong(usize),
+    /// The peer did not answer within the transport protocol timeouts.
+    Timeout,
+    /// The peer aborted the transport session with this reason.
+    Aborted(u8),
+}
+
+impl fmt::Display for J1939Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            J1939Error::Can(e) => write!(f, "{e}"),
+            J1939Error::Frame(e) => write!(f, "invalid frame: {e:?}"),
+            J1939Error::AddressClaimFailed => write!(f, "cannot claim an address"),
+            J1939Error::NoAddress => write!(f, "no address claimed"),
+            J1939Error::TooLong(len) => write!(f, "message of {len} bytes too long"),
+            J1939Error::Timeout => write!(f, "transport protocol timeout"),
+            J1939Error::Aborted(reason) => write!(f, "transfer aborted, reason {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for J1939Error {}
+
+impl From<CanError> for J1939Error {
+    fn from(value: CanError) -> Self {
+        J1939Error::Can(value)
+    }
+}
+
+impl From<FrameConstructionError> for J1939Error {
+    fn from(value: FrameConstructionError) -> Self {
+        J1939Error::Frame(value)
+    }
+}
+
+/// Fields of a J1939 CAN identifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct J1939Id {
+    pub priority: u8,
+    /// Parameter group number. The PDU specific byte is zero for PDU1 groups.
+    pub pgn: u32,
+    pub source_address: u8,
+    /// Destination of PDU1 groups, [GLOBAL_ADDRESS] for PDU2 groups.
+    pub destination_address: u8,
+}
+
+impl J1939Id {
+    pub fn new(priority: u8, pgn: u32, source_address: u8, destination_address: u8) -> J1939Id {
+        let mut id = J1939Id {
+            priority: priority & 0x07,
+            pgn: pgn & 0x3FFFF,
+            source_address,
+            destination_address,
+        };
+        if id.is_pdu1() {
+            id.pgn &= 0x3FF00;
+        } else {
+            id.destination_address = GLOBAL_ADDRESS;
+        }
+        id
+    }
+
+    pub fn from_can_id(can_id: u32) -> J1939Id {
+        let pgn = (can_id >> 8) & 0x3FFFF;
+        let id = J1939Id {
+            priority: ((can_id >> 26) & 0x07) as u8,
+            pgn,
+            source_address: can_id as u8,
+            destination_address: GLOBAL_ADDRESS,
+        };
+        if id.is_pdu1() {
+            J1939Id {
+                pgn: pgn & 0x3FF00,
+                destination_address: pgn as u8,
+                ..id
+            }
+        } else {
+            id
+        }
+    }
+
+    /// PDU1 groups (PDU format below 240) are addressed to a destination.
+    pub fn is_pdu1(&self) -> bool {
+        (self.pgn >> 8) & 0xFF < 240
+    }
+
+    /// The 29-bit CAN identifier.
+    pub fn can_id(&self) -> u32 {
+        let specific = if self.is_pdu1() {
+            self.destination_address as u32
+        } else {
+            self.pgn & 0xFF
+        };
+        (self.priority as u32 & 0x07) << 26
+            | (self.pgn & 0x3FF00) << 8
+            | specific << 8
+            | self.source_address as u32
+    }
+}
+
+/// The 64-bit NAME identifying a controller application.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Name {
+    /// 21 bits.
+    pub identity_number: u32,
+    /// 11 bits.
+    pub manufacturer_code: u16,
+    /// 3 bits.
+    pub ecu_instance: u8,
+    /// 5 bits.
+    pub function_instance: u8,
+    pub function: u8,
+    /// 7 bits.
+    pub vehicle_system: u8,
+    /// 4 bits.
+    pub vehicle_system_instance: u8,
+    /// 3 bits.
+    pub industry_group: u8,
+    pub arbitrary_address_capable: bool,
+}
+
+impl From<Name> for u64 {
+    fn from(value: Name) -> Self {
+        (value.identity_number as u64 & 0x1F_FFFF)
+            | (value.manufacturer_code as u64 & 0x7FF) << 21
+            | (value.ecu_instance as u64 & 0x07) << 32
+            | (value.function_instance as u64 & 0x1F) << 35
+            | (value.function as u64) << 40
+            | (value.vehicle_system as u64 & 0x7F) << 49
+            | (value.vehicle_system_instance as u64 & 0x0F) << 56
+            | (value.industry_group as u64 & 0x07) << 60
+            | (value.arbitrary_address_capable as u64) << 63
+    }
+}
+
+impl From<u64> for Name {
+    fn from(value: u64) -> Self {
+        Name {
+            identity_number: (value & 0x1F_FFFF) as u32,
+            manufacturer_code: ((value >> 21) & 0x7FF) as u16,
+            ecu_instance: ((value >> 32) & 0x07) as u8,
+            function_instance: ((value >> 35) & 0x1F) as u8,
+            function: (value >> 40) as u8,
+            vehicle_system: ((value >> 49) & 0x7F) as u8,
+            vehicle_system_instance: ((value >> 56) & 0x0F) as u8,
+            industry_group: ((value >> 60) & 0x07) as u8,
+            arbitrary_address_capable: value >> 63 != 0,
+        }
+    }
+}
+
+/// Control byte of the acknowledgement PGN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AckControl {
+    Ack,
+    Nack,
+    AccessDenied,
+    CannotRespond,
+}
+
+impl From<AckControl> for u8 {
+    fn from(value: AckControl) -> Self {
+        match value {
+            AckControl::Ack => 0,
+            AckControl::Nack => 1,
+            AckControl::AccessDenied => 2,
+            AckControl::CannotRespond => 3,
+        }
+    }
+}
+
+/// Decoded acknowledgement message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Acknowledgement {
+    pub control: u8,
+    pub group_function: u8,
+    /// Address of the node the acknowledgement answers.
+    pub address: u8,
+    pub pgn: u32,
+}
+
+/// A complete message, reassembled if it was sent with a transport protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct J1939Message {
+    pub id: J1939Id,
+    pub data: Vec<u8>,
+}
+
+impl J1939Message {
+    /// The requested PGN if this is a request message.
+    pub fn as_request(&self) -> Option<u32> {
+        match self.data[..] {
+            [a, b, c, ..] if self.id.pgn == PGN_REQUEST => Some(pgn_from_bytes(&[a, b, c])),
+            _ => None,
+        }
+    }
+
+    pub fn as_acknowledgement(&self) -> Option<Acknowledgement> {
+        match self.data[..] {
+            [control, group_function, _, _, address, a, b, c]
+                if self.id.pgn == PGN_ACKNOWLEDGEMENT =>
+            {
+                Some(Acknowledgement {
+                    control,
+                    group_function,
+                    address,
+                    pgn: pgn_from_bytes(&[a, b, c]),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn pgn_to_bytes(pgn: u32) -> [u8; 3] {
+    [pgn as u8, (pgn >> 8) as u8, (pgn >> 16) as u8]
+}
+
+fn pgn_from_bytes(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16
+}
+
+/// A controller application on a J1939 network.
+pub struct J1939Node<'a> {
+    tx: &'a dyn SendCan,
+    rx: &'a dyn RecvCan,
+    name: Name,
+    preferred_address: u8,
+    address: Cell<u8>,
+    /// Addresses claimed by other nodes with their NAME.
+    claimed: RefCell<BTreeMap<u8, u64>>,
+    /// Messages received while a transport session was in progress.
+    pending: RefCell<VecDeque<J1939Message>>,
+}
+
+impl<'a> J1939Node<'a> {
+    pub fn new<S: SendCan + RecvCan>(
+        socket: &'a S,
+        name: Name,
+        preferred_address: u8,
+    ) -> J1939Node<'a> {
+        J1939Node {
+            tx: socket,
+            rx: socket,
+            name,
+            preferred_address,
+            address: Cell::new(NULL_ADDRESS),
+            claimed: RefCell::new(BTreeMap::new()),
+            pending: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    pub fn name(&self) -> Name {
+        self.name
+    }
+
+    /// The claimed address, `None` before a successful claim or after losing it.
+    pub fn address(&self) -> Option<u8> {
+        match self.address.get() {
+            NULL_ADDRESS => None,
+            address => Some(address),
+        }
+    }
+
+    /// Addresses claimed by other nodes seen so far.
+    pub fn network(&self) -> BTreeMap<u8, Name> {
+        self.claimed
+            .borrow()
+            .iter()
+            .map(|(a, n)| (*a, Name::from(*n)))
+            .collect()
+    }
+
+    /// Claims the preferred address and waits for competing claims.
+    ///
+    /// An arbitrary address capable node moves to a free address in 128..=247 when it
+    /// loses the preferred address, otherwise a cannot claim message is sent.
+    pub fn claim_address(&self) -> Result<u8, J1939Error> {
+        self.address.set(self.preferred_address);
+        self.send_address_claim()?;
+
+        let mut deadline = Instant::now() + CLAIM_TIMEOUT;
+        loop {
+            let before = self.address.get();
+            let Some(frame) = socket::poll_until(deadline, || self.rx.recv_frame())? else {
+                break;
+            };
+            if let Some(message) = self.handle_frame(&frame)? {
+                self.pending.borrow_mut().push_back(message);
+            }
+            match self.address.get() {
+                NULL_ADDRESS => return Err(J1939Error::AddressClaimFailed),
+                // Moved to another address, wait for competing claims again
+                address if address != before => deadline = Instant::now() + CLAIM_TIMEOUT,
+                _ => {}
+            }
+        }
+
+        Ok(self.address.get())
+    }
+
+    fn send_address_claim(&self) -> Result<(), J1939Error> {
+        let id = J1939Id::new(
+            DEFAULT_PRIORITY,
+            PGN_ADDRESS_CLAIMED,
+            self.address.get(),
+            GLOBAL_ADDRESS,
+        );
+        self.send_frame(id, &u64::from(self.name).to_le_bytes())
+    }
+
+    /// Handles a competing claim for our address.
+    fn resolve_conflict(&self, other: u64) -> Result<(), J1939Error> {
+        let ours = u64::from(self.name);
+        if ours < other {
+            // Our NAME has the higher priority
+            return self.send_address_claim();
+        }
+
+        let free = if self.name.arbitrary_address_capable {
+            let claimed = self.claimed.borrow();
+            (ARBITRARY_ADDRESS_START..=ARBITRARY_ADDRESS_END)
+                .find(|a| !claimed.contains_key(a) && *a != self.address.get())
+        } else {
+            None
+        };
+        match free {
+            Some(address) => self.address.set(address),
+            None => self.address.set(NULL_ADDRESS),
+        }
+        // A cannot claim message is an address claim from the null address
+        self.send_address_claim()
+    }
+
+    fn send_frame(&self, id: J1939Id, data: &[u8]) -> Result<(), J1939Error> {
+        let frame = CanFrame::new(id.can_id(), MessageType::Extended, data)?;
+        self.tx.send(frame)?;
+        Ok(())
+    }
+
+    fn source(&self) -> Result<u8, J1939Error> {
+        self.address().ok_or(J1939Error::NoAddress)
+    }
+
+    /// Sends a parameter group, with a transport protocol if it exceeds 8 bytes.
+    ///
+    /// Messages to [GLOBAL_ADDRESS] use BAM, messages to a node use RTS/CTS.
+    pub fn send(
+        &self,
+        priority: u8,
+        pgn: u32,
+        destination: u8,
+        data: &[u8],
+    ) -> Result<(), J1939Error> {
+        let source = self.source()?;
+        if data.len() <= 8 {
+            return self.send_frame(J1939Id::new(priority, pgn, source, destination), data);
+        }
+        if data.len() > MAX_TP_LEN {
+            return Err(J1939Error::TooLong(data.len()));
+        }
+
+        let packets = data.len().div_ceil(7) as u8;
+        let size = (data.len() as u16).to_le_bytes();
+        let pgn = pgn_to_bytes(pgn);
+        let cm = J1939Id::new(DEFAULT_PRIORITY + 1, PGN_TP_CM, source, destination);
+
+        if destination == GLOBAL_ADDRESS {
+            self.send_frame(
+                cm,
+                &[
+                    TP_CM_BAM, size[0], size[1], packets, 0xFF, pgn[0], pgn[1], pgn[2],
+                ],
+            )?;
+            for sequence in 1..=packets {
+                thread::sleep(BAM_PACKET_INTERVAL);
+                self.send_packet(destination, data, sequence)?;
+            }
+            return Ok(());
+        }
+
+        self.send_frame(
+            cm,
+            &[
+                TP_CM_RTS, size[0], size[1], packets, 0xFF, pgn[0], pgn[1], pgn[2],
+            ],
+        )?;
+        let mut timeout = T3;
+        loop {
+            let Some(control) = self.wait_control(destination, timeout)? else {
+                self.abort(destination, ABORT_TIMEOUT, pgn)?;
+                return Err(J1939Error::Timeout);
+            };
+            match control[0] {
+                TP_CM_CTS if control[1] == 0 => timeout = T4,
+                TP_CM_CTS => {
+                    let first = control[2].max(1);
+                    let last = first.saturating_add(control[1] - 1).min(packets);
+                    for sequence in first..=last {
+                        self.send_packet(destination, data, sequence)?;
+                    }
+                    timeout = T3;
+                }
+                TP_CM_END_OF_MSG_ACK => return Ok(()),
+                TP_CM_ABORT => return Err(J1939Error::Aborted(control[1])),
+                _ => {}
+            }
+        }
+    }
+
+    fn send_packet(&self, destination: u8, data: &[u8], sequence: u8) -> Result<(), J1939Error> {
+        let start = (sequence as usize - 1) * 7;
+        let mut packet = [0xFF; 8];
+        packet[0] = sequence;
+        let chunk = &data[start..(start + 7).min(data.len())];
+        packet[1..1 + chunk.len()].copy_from_slice(chunk);
+        let id = J1939Id::new(DEFAULT_PRIORITY + 1, PGN_TP_DT, self.source()?, destination);
+        self.send_frame(id, &packet)
+    }
+
+    fn abort(&self, destination: u8, reason: u8, pgn: [u8; 3]) -> Result<(), J1939Error> {
+        let id = J1939Id::new(DEFAULT_PRIORITY + 1, PGN_TP_CM, self.source()?, destination);
+        self.send_frame(
+            id,
+            &[
+                TP_CM_ABORT,
+                reason,
+                0xFF,
+                0xFF,
+                0xFF,
+                pgn[0],
+                pgn[1],
+                pgn[2],
+            ],
+        )
+    }
+
+    /// Waits for a connection management frame from `peer` to this node.
+    fn wait_control(&self, peer: u8, timeout: Duration) -> Result<Option<[u8; 8]>, J1939Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let Some(frame) = socket::poll_until(deadline, || self.rx.recv_frame())? else {
+                return Ok(None);
+            };
+            let id = J1939Id::from_can_id(frame.can_id());
+            if frame.is_extended_frame()
+                && id.pgn == PGN_TP_CM
+                && id.source_address == peer
+                && id.destination_address == self.address.get()
+                && frame.data().len() == 8
+                && frame.data()[0] != TP_CM_RTS
+                && frame.data()[0] != TP_CM_BAM
+            {
+                let mut control = [0; 8];
+                control.copy_from_slice(frame.data());
+                return Ok(Some(control));
+            }
+            if let Some(message) = self.handle_frame(&frame)? {
+                self.pending.borrow_mut().push_back(message);
+            }
+        }
+    }
+
+    /// Requests a parameter group from `destination` or from all nodes.
+    pub fn request(&self, pgn: u32, destination: u8) -> Result<(), J1939Error> {
+        let id = J1939Id::new(DEFAULT_PRIORITY, PGN_REQUEST, self.source()?, destination);
+        self.send_frame(id, &pgn_to_bytes(pgn))
+    }
+
+    /// Answers a message from `address` with a global acknowledgement.
+    pub fn acknowledge(
+        &self,
+        control: AckControl,
+        group_function: u8,
+        pgn: u32,
+        address: u8,
+    ) -> Result<(), J1939Error> {
+        let id = J1939Id::new(
+            DEFAULT_PRIORITY,
+            PGN_ACKNOWLEDGEMENT,
+            self.source()?,
+            GLOBAL_ADDRESS,
+        );
+        let pgn = pgn_to_bytes(pgn);
+        self.send_frame(
+            id,
+            &[
+                u8::from(control),
+                group_function,
+                0xFF,
+                0xFF,
+                address,
+                pgn[0],
+                pgn[1],
+                pgn[2],
+            ],
+        )
+    }
+
+    /// Receives the next message for this node or for all nodes.
+    ///
+    /// Network management and transport protocol frames are handled internally; returns
+    /// `Ok(None)` if no message arrives within `timeout`.
+    pub fn recv(&self, timeout: Duration) -> Result<Option<J1939Message>, J1939Error> {
+        if let Some(message) = self.pending.borrow_mut().pop_front() {
+            return Ok(Some(message));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let Some(frame) = socket::poll_until(deadline, || self.rx.recv_frame())? else {
+                return Ok(None);
+            };
+            if let Some(message) = self.handle_frame(&frame)? {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Processes a received frame, returning it as a message if it is for the application.
+    fn handle_frame(&self, frame: &CanFrame) -> Result<Option<J1939Message>, J1939Error> {
+        if !frame.is_extended_frame() || frame.is_remote_frame() {
+            return Ok(None);
+        }
+        let id = J1939Id::from_can_id(frame.can_id());
+        let data = frame.data();
+        let address = self.address.get();
+
+        if id.is_pdu1()
+            && id.destination_address != GLOBAL_ADDRESS
+            && id.destination_address != address
+        {
+            return Ok(None);
+        }
+
+        match id.pgn {
+            PGN_ADDRESS_CLAIMED if data.len() == 8 => {
+                let other = u64::from_le_bytes(data.try_into().unwrap());
+                if id.source_address < NULL_ADDRESS {
+                    self.claimed.borrow_mut().insert(id.source_address, other);
+                }
+                if id.source_address == address
+                    && address != NULL_ADDRESS
+                    && other != u64::from(self.name)
+                {
+                    self.resolve_conflict(other)?;
+                }
+                Ok(Some(J1939Message {
+                    id,
+                    data: data.to_vec(),
+                }))
+            }
+            PGN_REQUEST if data.len() >= 3 && pgn_from_bytes(data) == PGN_ADDRESS_CLAIMED => {
+                if address != NULL_ADDRESS {
+                    self.send_address_claim()?;
+                }
+                Ok(None)
+            }
+            PGN_TP_CM if data.len() == 8 => match data[0] {
+                TP_CM_BAM => self.receive_bam(id, data),
+                TP_CM_RTS if id.destination_address == address => self.receive_rts(id, data),
+                _ => Ok(None),
+            },
+            PGN_TP_DT => Ok(None),
+            _ => Ok(Some(J1939Message {
+                id,
+                data: data.to_vec(),
+            })),
+        }
+    }
+
+    fn receive_bam(&self, id: J1939Id, control: &[u8]) -> Result<Option<J1939Message>, J1939Error> {
+        let size = u16::from_le_bytes([control[1], control[2]]) as usize;
+        let packets = control[3];
+        let pgn = pgn_from_bytes(&control[5..8]);
+
+        let mut data = Vec::with_capacity(size);
+        for expected in 1..=packets {
+            let Some(packet) = self.wait_packet(id.source_address, GLOBAL_ADDRESS, T1)? else {
+                return Ok(None);
+            };
+            if packet[0] != expected {
+                return Ok(None);
+            }
+            data.extend_from_slice(&packet[1..]);
+        }
+
+        data.truncate(size);
+        Ok(Some(J1939Message {
+            id: J1939Id::new(id.priority, pgn, id.source_address, GLOBAL_ADDRESS),
+            data,
+        }))
+    }
+
+    fn receive_rts(&self, id: J1939Id, control: &[u8]) -> Result<Option<J1939Message>, J1939Error> {
+        let size = u16::from_le_bytes([control[1], control[2]]) as usize;
+        let packets = control[3];
+        let per_cts = control[4].max(1);
+        let pgn_bytes = [control[5], control[6], control[7]];
+        let peer = id.source_address;
+        let cm = J1939Id::new(DEFAULT_PRIORITY + 1, PGN_TP_CM, self.source()?, peer);
+
+        let mut data = Vec::with_capacity(size);
+        // Counted in u16, a transfer of 255 packets would overflow the sequence number
+        let mut next = 1u16;
+        while next <= u16::from(packets) {
+            let count = u16::from(per_cts).min(u16::from(packets) - next + 1) as u8;
+            self.send_frame(
+                cm,
+                &[
+                    TP_CM_CTS,
+                    count,
+                    next as u8,
+                    0xFF,
+                    0xFF,
+                    pgn_bytes[0],
+                    pgn_bytes[1],
+                    pgn_bytes[2],
+                ],
+            )?;
+
+            for _ in 0..count {
+                let Some(packet) = self.wait_packet(peer, self.address.get(), T2)? else {
+                    self.abort(peer, ABORT_TIMEOUT, pgn_bytes)?;
+                    return Ok(None);
+                };
+                if u16::from(packet[0]) != next {
+                    return Ok(None);
+                }
+                data.extend_from_slice(&packet[1..]);
+                next += 1;
+            }
+        }
+
+        data.truncate(size);
+        let size = (size as u16).to_le_bytes();
+        self.send_frame(
+            cm,
+            &[
+                TP_CM_END_OF_MSG_ACK,
+                size[0],
+                size[1],
+                packets,
+                0xFF,
+                pgn_bytes[0],
+                pgn_bytes[1],
+                pgn_bytes[2],
+            ],
+        )?;
+
+        Ok(Some(J1939Message {
+            id: J1939Id::new(
+                id.priority,
+                pgn_from_bytes(&pgn_bytes),
+                peer,
+                id.destination_address,
+            ),
+            data,
+        }))
+    }
+
+    /// Waits for the next transport data packet of a session.
+    fn wait_packet(
+        &self,
+        peer: u8,
+        destination: u8,
+        timeout: Duration,
+    ) -> Result<Option<[u8; 8]>, J1939Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let Some(frame) = socket::poll_until(deadline, || self.rx.recv_frame())? else {
+                return Ok(None);
+            };
+            let id = J1939Id::from_can_id(frame.can_id());
+            if frame.is_extended_frame()
+                && id.pgn == PGN_TP_DT
+                && id.source_address == peer
+                && id.destination_address == destination
+                && frame.data().len() == 8
+            {
+                let mut packet = [0; 8];
+                packet.copy_from_slice(frame.data());
+                return Ok(Some(packet));
+            }
+            // Nested transport sessions are not supported, other messages are queued
+            if id.pgn != PGN_TP_CM
+                && let Some(message) = self.handle_frame(&frame)?
+            {
+                self.pending.borrow_mut().push_back(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::mock_bus;
+
+    fn name(identity_number: u32, arbitrary_address_capable: bool) -> Name {
+        Name {
+            identity_number,
+            manufacturer_code: 0x123,
+            function: 0x81,
+            industry_group: 2,
+            arbitrary_address_capable,
+            ..Name::default()
+        }
+    }
+
+    #[test]
+    fn j1939_id_encoding() {
+        // EEC1 from the engine, PDU2
+        let id = J1939Id::from_can_id(0x0CF0_0400);
+        assert_eq!(id.priority, 3);
+        assert_eq!(id.pgn, 0xF004);
+        assert_eq!(id.source_address, 0x00);
+        assert_eq!(id.destination_address, GLOBAL_ADDRESS);
+        assert_eq!(id.can_id(), 0x0CF0_0400);
+
+        // Request from 0xF9 to 0x00, PDU1
+        let id = J1939Id::from_can_id(0x18EA_00F9);
+        assert_eq!(id.pgn, PGN_REQUEST);
+        assert_eq!(id.destination_address, 0x00);
+        assert_eq!(
+            J1939Id::new(6, PGN_REQUEST, 0xF9, 0x00).can_id(),
+            0x18EA_00F9
+        );
+    }
+
+    #[test]
+    fn j1939_name_round_trip() {
+        let name = Name {
+            identity_number: 0x1F_FFFF,
+            manufacturer_code: 0x7FF,
+            ecu_instance: 5,
+            function_instance: 0x1F,
+            function: 0xAB,
+            vehicle_system: 0x7F,
+            vehicle_system_instance: 0x0F,
+            industry_group: 7,
+            arbitrary_address_capable: true,
+        };
+        assert_eq!(Name::from(u64::from(name)), name);
+        assert_eq!(u64::from(name) >> 63, 1);
+    }
+
+    #[test]
+    fn j1939_address_claim_conflict() {
+        let (a, b) = mock_bus();
+        // The competitor has the lower NAME and therefore wins address 0x80
+        let competitor = u64::from(name(1, false));
+        b.send(
+            CanFrame::new(
+                J1939Id::new(6, PGN_ADDRESS_CLAIMED, 0x80, GLOBAL_ADDRESS).can_id(),
+                MessageType::Extended,
+                &competitor.to_le_bytes(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let node = J1939Node::new(&a, name(2, true), 0x80);
+        assert_eq!(node.claim_address().unwrap(), 0x81);
+        assert_eq!(node.network().get(&0x80), Some(&name(1, false)));
+
+        let rigid = J1939Node::new(&a, name(3, false), 0x80);
+        b.send(
+            CanFrame::new(
+                J1939Id::new(6, PGN_ADDRESS_CLAIMED, 0x80, GLOBAL_ADDRESS).can_id(),
+                MessageType::Extended,
+                &competitor.to_le_bytes(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert!(matches!(
+            rigid.claim_address(),
+            Err(J1939Error::AddressClaimFailed)
+        ));
+
+        // Claims: preferred address, moved address, preferred again and cannot claim
+        let mut claims = Vec::new();
+        while let Ok(frame) = b.recv_frame() {
+            claims.push(J1939Id::from_can_id(frame.can_id()).source_address);
+        }
+        assert_eq!(claims, vec![0x80, 0x81, 0x80, NULL_ADDRESS]);
+    }
+
+    #[test]
+    fn j1939_transport_protocols() {
+        let (a, b) = mock_bus();
+        let payload: Vec<u8> = (0..100).collect();
+
+        thread::scope(|scope| {
+            let receiver = scope.spawn(|| {
+                let node = J1939Node::new(&b, name(20, false), 0x20);
+                node.claim_address().unwrap();
+                let mut messages = Vec::new();
+                while messages.len() < 3 {
+                    if let Some(message) = node.recv(Duration::from_secs(3)).unwrap()
+                        && message.id.pgn != PGN_ADDRESS_CLAIMED
+                    {
+                        messages.push(message);
+                    }
+                }
+                messages
+            });
+
+            let node = J1939Node::new(&a, name(10, false), 0x10);
+            node.claim_address().unwrap();
+            node.send(3, 0xFEDA, GLOBAL_ADDRESS, &payload[..20])
+                .unwrap();
+            node.send(6, 0xEF00, 0x20, &payload).unwrap();
+            node.request(0xFEEC, 0x20).unwrap();
+
+            let messages = receiver.join().unwrap();
+            assert_eq!(messages[0].id.pgn, 0xFEDA);
+            assert_eq!(messages[0].data, payload[..20]);
+            assert_eq!(messages[1].id.pgn, 0xEF00);
+            assert_eq!(messages[1].id.source_address, 0x10);
+            assert_eq!(messages[1].data, payload);
+            assert_eq!(messages[2].as_request(), Some(0xFEEC));
+        });
+    }
+
+    #[test]
+    fn j1939_maximum_transfer_size() {
+        let (a, b) = mock_bus();
+        let payload: Vec<u8> = (0..MAX_TP_LEN).map(|i| i as u8).collect();
+        let size = (MAX_TP_LEN as u16).to_le_bytes();
+
+        // BAM, sent without the packet interval
+        let sender = J1939Node::new(&a, name(10, false), 0x10);
+        sender.address.set(0x10);
+        let cm = J1939Id::new(DEFAULT_PRIORITY + 1, PGN_TP_CM, 0x10, GLOBAL_ADDRESS);
+        sender
+            .send_frame(
+                cm,
+                &[TP_CM_BAM, size[0], size[1], 255, 0xFF, 0xDA, 0xFE, 0x00],
+            )
+            .unwrap();
+        for sequence in 1..=255 {
+            sender
+                .send_packet(GLOBAL_ADDRESS, &payload, sequence)
+                .unwrap();
+        }
+        let receiver = J1939Node::new(&b, name(20, false), 0x20);
+        receiver.address.set(0x20);
+        let message = receiver.recv(Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!(message.id.pgn, 0xFEDA);
+        assert_eq!(message.data, payload);
+
+        // RTS/CTS
+        thread::scope(|scope| {
+            let receiver = scope.spawn(|| {
+                let node = J1939Node::new(&b, name(20, false), 0x20);
+                node.address.set(0x20);
+                node.recv(Duration::from_secs(3)).unwrap()
+            });
+            sender.send(6, 0xEF00, 0x20, &payload).unwrap();
+
+            let message = receiver.join().unwrap().unwrap();
+            assert_eq!(message.id.pgn, 0xEF00);
+            assert_eq!(message.data, payload);
+        });
+    }
+
+    #[test]
+    fn j1939_acknowledgement() {
+        let (a, b) = mock_bus();
+        let node = J1939Node::new(&a, name(10, false), 0x10);
+        node.address.set(0x10);
+        node.acknowledge(AckControl::Nack, 0, 0xEF00, 0x20).unwrap();
+
+        let frame = b.recv_frame().unwrap();
+        let message = J1939Message {
+            id: J1939Id::from_can_id(frame.can_id()),
+            data: frame.data().to_vec(),
+        };
+        assert_eq!(
+            message.as_acknowledgement(),
+            Some(Acknowledgement {
+                control: 1,
+                group_function: 0,
+                address: 0x20,
+                pgn: 0xEF00
+            })
+        );
+    }
+}
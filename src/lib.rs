@@ -2,17 +2,44 @@
 //!
 
 #[warn(dead_code)]
+pub mod autobaud;
+#[cfg(feature = "blf")]
+pub mod blf;
+pub mod bridge;
 pub mod bus;
+pub mod canopen;
+pub mod capability;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli;
+pub mod config;
 mod channel;
+pub mod dbc;
+pub mod device;
 pub mod df;
+#[cfg(feature = "embedded-can")]
+pub mod embedded;
 pub mod error;
+pub mod filter;
+pub mod gateway;
+pub mod hub;
 pub mod hw;
 pub mod info;
 pub mod io;
+pub mod isotp;
+pub mod j1939;
 pub mod log;
+pub mod logfile;
+pub mod mdf;
+pub mod obd;
+pub mod replay;
 pub mod socket;
 pub mod special;
+pub mod stats;
+pub mod status;
 pub mod trace;
+pub mod uds;
+pub mod xcp;
 
 use peak_can_sys as peak_can;
 
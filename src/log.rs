@@ -201,6 +201,8 @@ pub fn set_default_trace_size<T: SetTraceSize>(value: &T) -> Result<(), CanError
 /* TRACE CONFIGURE traits */
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum TraceFile {
     Single,
     Segmented,
@@ -0,0 +1,1381 @@
+//! Readers and writers for common CAN trace file formats.
+//!
+//! Supported formats are PEAK `.trc` (versions 1.0 to 2.1), Vector `.asc`, the SocketCAN
+//! `candump -l` log format, CSV and, with the `blf` feature, Vector `.blf`. Every reader
+//! produces the [BusEvent]s also used by the [mdf](crate::mdf) module, with timestamps in
+//! microseconds:
+//!
+//! - `.trc` timestamps are the message offsets relative to the start of the trace,
+//! - `.asc` timestamps are relative to the start of the measurement,
+//! - candump, CSV and `.blf` timestamps are absolute (UNIX epoch).
+//!
+//! Channels are numbered from 1. For candump logs the interfaces are numbered in their
+//! order of appearance.
+//!
+//! [write] stores events in any of these formats. `.trc` and `.asc` files are written
+//! relative to the first event, whose timestamp becomes the start time in their header.
+//! Events of several files are combined in timestamp order with [merge].
+
+use crate::mdf::{BusEvent, BusEventKind, error_type};
+use crate::socket::CanFdFrame;
+
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Errors raised while reading or writing a trace file.
+#[derive(Debug)]
+pub enum LogFileError {
+    /// Underlying I/O failure.
+    Io(io::Error),
+    /// A line of the file could not be parsed.
+    Parse { line: usize, message: String },
+    /// The file format could not be determined.
+    UnknownFormat,
+    /// A binary file is malformed.
+    Corrupt(String),
+}
+
+impl fmt::Display for LogFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFileError::Io(e) => write!(f, "{e}"),
+            LogFileError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            LogFileError::UnknownFormat => write!(f, "unknown log file format"),
+            LogFileError::Corrupt(message) => write!(f, "corrupt log file: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for LogFileError {}
+
+impl From<io::Error> for LogFileError {
+    fn from(value: io::Error) -> Self {
+        LogFileError::Io(value)
+    }
+}
+
+/// Trace file formats understood by [read_file], [parse] and [write].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// PEAK-System PCAN trace (`.trc`).
+    Trc,
+    /// Vector ASCII log (`.asc`).
+    Asc,
+    /// SocketCAN `candump -l` log (`.log`).
+    Candump,
+    /// Comma separated values (`.csv`) with a header line, see [CSV_COLUMNS].
+    Csv,
+    /// Vector binary log (`.blf`).
+    #[cfg(feature = "blf")]
+    Blf,
+}
+
+impl LogFormat {
+    /// Guesses the format from the file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<LogFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "trc" => Some(LogFormat::Trc),
+            "asc" => Some(LogFormat::Asc),
+            "log" | "candump" => Some(LogFormat::Candump),
+            "csv" => Some(LogFormat::Csv),
+            #[cfg(feature = "blf")]
+            "blf" => Some(LogFormat::Blf),
+            _ => None,
+        }
+    }
+}
+
+/// Reads all events of the trace file at `path`, picking the format from its extension.
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<BusEvent>, LogFileError> {
+    let format = LogFormat::from_path(&path).ok_or(LogFileError::UnknownFormat)?;
+    let file = File::open(path)?;
+    parse(format, BufReader::new(file))
+}
+
+/// Reads all events from `reader` in the given `format`.
+pub fn parse<R: BufRead>(format: LogFormat, reader: R) -> Result<Vec<BusEvent>, LogFileError> {
+    match format {
+        LogFormat::Trc => parse_trc(reader),
+        LogFormat::Asc => parse_asc(reader),
+        LogFormat::Candump => parse_candump(reader),
+        LogFormat::Csv => parse_csv(reader),
+        #[cfg(feature = "blf")]
+        LogFormat::Blf => crate::blf::parse(reader),
+    }
+}
+
+/// Writes `events` to the file at `path`, picking the format from its extension.
+pub fn write_file<P: AsRef<Path>>(path: P, events: &[BusEvent]) -> Result<(), LogFileError> {
+    let format = LogFormat::from_path(&path).ok_or(LogFileError::UnknownFormat)?;
+    let file = File::create(path)?;
+    write(format, events, BufWriter::new(file))
+}
+
+/// Writes `events`, which should be sorted by timestamp, to `writer` in the given `format`.
+pub fn write<W: Write>(
+    format: LogFormat,
+    events: &[BusEvent],
+    writer: W,
+) -> Result<(), LogFileError> {
+    match format {
+        LogFormat::Trc => write_trc(events, writer),
+        LogFormat::Asc => write_asc(events, writer),
+        LogFormat::Candump => write_candump(events, writer),
+        LogFormat::Csv => write_csv(events, writer),
+        #[cfg(feature = "blf")]
+        LogFormat::Blf => crate::blf::write(events, writer),
+    }
+}
+
+/// Combines the events of several logs into one list sorted by timestamp. Events with equal
+/// timestamps keep the order of `logs`.
+pub fn merge<I>(logs: I) -> Vec<BusEvent>
+where
+    I: IntoIterator<Item = Vec<BusEvent>>,
+{
+    let mut events: Vec<BusEvent> = logs.into_iter().flatten().collect();
+    events.sort_by_key(|event| event.timestamp_us);
+    events
+}
+
+fn new_event(timestamp_us: u64, bus_channel: u8, kind: BusEventKind) -> BusEvent {
+    BusEvent {
+        timestamp_us,
+        bus_channel,
+        kind,
+        can_id: 0,
+        extended: false,
+        dlc: 0,
+        data: Vec::new(),
+        tx: false,
+        edl: false,
+        brs: false,
+        esi: false,
+        error_type: error_type::UNKNOWN,
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> LogFileError {
+    LogFileError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+fn parse_hex_u32(line: usize, token: &str) -> Result<u32, LogFileError> {
+    u32::from_str_radix(token, 16)
+        .map_err(|_| parse_error(line, format!("invalid hex value '{token}'")))
+}
+
+fn parse_hex_bytes<'a>(
+    line: usize,
+    tokens: impl Iterator<Item = &'a str>,
+    count: usize,
+) -> Result<Vec<u8>, LogFileError> {
+    let data = tokens
+        .take(count)
+        .map(|t| {
+            u8::from_str_radix(t, 16)
+                .map_err(|_| parse_error(line, format!("invalid data byte '{t}'")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if data.len() != count {
+        return Err(parse_error(line, "missing data bytes"));
+    }
+    Ok(data)
+}
+
+/// Converts a decimal number of `unit`s (e.g. "1059.900" milliseconds) into microseconds.
+fn parse_decimal_micros(
+    line: usize,
+    token: &str,
+    micros_per_unit: u64,
+) -> Result<u64, LogFileError> {
+    let (integer, fraction) = token.split_once('.').unwrap_or((token, ""));
+    let integer: u64 = integer
+        .parse()
+        .map_err(|_| parse_error(line, format!("invalid timestamp '{token}'")))?;
+
+    let invalid = || parse_error(line, format!("invalid timestamp '{token}'"));
+    let mut micros = integer.checked_mul(micros_per_unit).ok_or_else(invalid)?;
+    let mut scale = micros_per_unit;
+    for digit in fraction.chars() {
+        scale /= 10;
+        if scale == 0 {
+            break;
+        }
+        let digit = digit.to_digit(10).ok_or_else(invalid)?;
+        micros = micros
+            .checked_add(digit as u64 * scale)
+            .ok_or_else(invalid)?;
+    }
+    Ok(micros)
+}
+
+/// Formats microseconds as a decimal number of seconds with six digits.
+fn format_seconds(micros: u64) -> String {
+    format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000)
+}
+
+fn hex_bytes(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len() * 3);
+    for byte in data {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        let _ = write!(text, "{byte:02X}");
+    }
+    text
+}
+
+/// DLC matching the payload of a data frame event.
+fn data_dlc(event: &BusEvent) -> u8 {
+    if event.edl {
+        CanFdFrame::calc_dlc(event.data.len())
+    } else if event.dlc.min(8) as usize == event.data.len() {
+        event.dlc
+    } else {
+        event.data.len() as u8
+    }
+}
+
+/// Calendar date and time (UTC) of a timestamp in microseconds since the UNIX epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub year: i64,
+    /// 1 to 12.
+    pub month: u32,
+    /// 1 to 31.
+    pub day: u32,
+    /// 0 for Sunday to 6 for Saturday.
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub micros: u32,
+}
+
+impl DateTime {
+    const MICROS_PER_DAY: u64 = 86_400_000_000;
+
+    pub fn from_micros(micros: u64) -> DateTime {
+        let days = (micros / Self::MICROS_PER_DAY) as i64;
+        let time = micros % Self::MICROS_PER_DAY;
+
+        // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday
+            weekday: ((days + 4) % 7) as u32,
+            hour: (time / 3_600_000_000) as u32,
+            minute: (time / 60_000_000 % 60) as u32,
+            second: (time / 1_000_000 % 60) as u32,
+            micros: (time % 1_000_000) as u32,
+        }
+    }
+
+    /// Microseconds since the UNIX epoch, `None` for dates before 1970.
+    #[cfg(any(feature = "blf", test))]
+    pub fn to_micros(self) -> Option<u64> {
+        let month = self.month as i64;
+        let year = self.year - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year - era * 400;
+        let doy = (153 * ((month + 9) % 12) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+
+        let seconds = self.hour as u64 * 3_600 + self.minute as u64 * 60 + self.second as u64;
+        Some(days * Self::MICROS_PER_DAY + seconds * 1_000_000 + self.micros as u64)
+    }
+}
+
+/* PEAK trc */
+
+fn parse_trc<R: BufRead>(reader: R) -> Result<Vec<BusEvent>, LogFileError> {
+    let mut version = (1u32, 1u32);
+    let mut columns: Option<Vec<char>> = None;
+    let mut events = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line?;
+        let line = line.trim();
+
+        if let Some(header) = line.strip_prefix(';') {
+            if let Some(value) = header.trim().strip_prefix("$FILEVERSION=") {
+                let (major, minor) = value.trim().split_once('.').unwrap_or((value.trim(), "0"));
+                version = (
+                    major
+                        .parse()
+                        .map_err(|_| parse_error(line_no, "invalid file version"))?,
+                    minor
+                        .parse()
+                        .map_err(|_| parse_error(line_no, "invalid file version"))?,
+                );
+            } else if let Some(value) = header.trim().strip_prefix("$COLUMNS=") {
+                columns = Some(
+                    value
+                        .split(',')
+                        .filter_map(|c| c.trim().chars().next())
+                        .collect(),
+                );
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let event = if version.0 >= 2 {
+            let columns = columns.clone().unwrap_or_else(|| {
+                if version.1 >= 1 {
+                    vec!['N', 'O', 'T', 'B', 'I', 'd', 'R', 'L', 'D']
+                } else {
+                    vec!['N', 'O', 'T', 'I', 'd', 'l', 'D']
+                }
+            });
+            parse_trc_v2_line(line_no, line, &columns)?
+        } else {
+            parse_trc_v1_line(line_no, line, version.1)?
+        };
+
+        if let Some(event) = event {
+            events.push(event);
+        }
+    }
+
+    Ok(events)
+}
+
+fn parse_trc_v1_line(
+    line_no: usize,
+    line: &str,
+    minor: u32,
+) -> Result<Option<BusEvent>, LogFileError> {
+    let mut tokens = line.split_whitespace();
+    let _number = tokens.next();
+    let offset = tokens
+        .next()
+        .ok_or_else(|| parse_error(line_no, "missing time offset"))?;
+    let timestamp_us = parse_decimal_micros(line_no, offset, 1_000)?;
+
+    let mut bus = 1u8;
+    if minor >= 2 {
+        let token = tokens
+            .next()
+            .ok_or_else(|| parse_error(line_no, "missing bus"))?;
+        bus = token
+            .parse()
+            .map_err(|_| parse_error(line_no, format!("invalid bus '{token}'")))?;
+    }
+
+    let mut tx = false;
+    if minor >= 1 {
+        match tokens.next() {
+            Some("Rx") => {}
+            Some("Tx") => tx = true,
+            // Warning and error lines carry no frame
+            Some(_) => return Ok(None),
+            None => return Err(parse_error(line_no, "missing message type")),
+        }
+    }
+
+    let id = tokens
+        .next()
+        .ok_or_else(|| parse_error(line_no, "missing identifier"))?;
+    if minor >= 3 {
+        tokens.next();
+    }
+    let dlc = tokens
+        .next()
+        .ok_or_else(|| parse_error(line_no, "missing data length"))?;
+    let dlc: u8 = dlc
+        .parse()
+        .map_err(|_| parse_error(line_no, format!("invalid data length '{dlc}'")))?;
+
+    let mut tokens = tokens.peekable();
+    let remote = tokens.peek() == Some(&"RTR");
+
+    let mut event = new_event(
+        timestamp_us,
+        bus,
+        if remote {
+            BusEventKind::RemoteFrame
+        } else {
+            BusEventKind::DataFrame
+        },
+    );
+    event.can_id = parse_hex_u32(line_no, id)?;
+    event.extended = id.len() > 4;
+    event.dlc = dlc;
+    event.tx = tx;
+    if !remote {
+        event.data = parse_hex_bytes(line_no, tokens, dlc.min(8) as usize)?;
+    }
+    Ok(Some(event))
+}
+
+fn parse_trc_v2_line(
+    line_no: usize,
+    line: &str,
+    columns: &[char],
+) -> Result<Option<BusEvent>, LogFileError> {
+    let mut tokens = line.split_whitespace();
+    let mut event = new_event(0, 1, BusEventKind::DataFrame);
+    let mut length: Option<usize> = None;
+
+    for column in columns {
+        if *column == 'D' {
+            break;
+        }
+        let token = tokens
+            .next()
+            .ok_or_else(|| parse_error(line_no, format!("missing column '{column}'")))?;
+
+        match column {
+            'O' => event.timestamp_us = parse_decimal_micros(line_no, token, 1_000)?,
+            'T' => match token {
+                "DT" => {}
+                "FD" => event.edl = true,
+                "FB" => {
+                    event.edl = true;
+                    event.brs = true;
+                }
+                "FE" => {
+                    event.edl = true;
+                    event.esi = true;
+                }
+                "BI" => {
+                    event.edl = true;
+                    event.brs = true;
+                    event.esi = true;
+                }
+                "RR" => event.kind = BusEventKind::RemoteFrame,
+                "ER" => event.kind = BusEventKind::ErrorFrame,
+                // Status, error counter and event lines carry no frame
+                _ => return Ok(None),
+            },
+            'B' => {
+                event.bus_channel = token
+                    .parse()
+                    .map_err(|_| parse_error(line_no, format!("invalid bus '{token}'")))?
+            }
+            'I' if token != "-" => {
+                event.can_id = parse_hex_u32(line_no, token)?;
+                event.extended = token.len() > 4;
+            }
+            'd' => event.tx = token == "Tx",
+            'l' => {
+                let len: usize = token
+                    .parse()
+                    .map_err(|_| parse_error(line_no, format!("invalid data length '{token}'")))?;
+                event.dlc = CanFdFrame::calc_dlc(len);
+                length = Some(len);
+            }
+            'L' => {
+                let dlc: u8 = token
+                    .parse()
+                    .map_err(|_| parse_error(line_no, format!("invalid DLC '{token}'")))?;
+                event.dlc = dlc;
+                length = Some(if event.edl {
+                    CanFdFrame::dlc_to_len(dlc)
+                } else {
+                    dlc.min(8) as usize
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // Remote frames of version 2.0 report the requested length, not a payload
+    if event.kind == BusEventKind::RemoteFrame {
+        if let Some(len) = length {
+            event.dlc = len.min(8) as u8;
+        }
+        return Ok(Some(event));
+    }
+
+    let length = length.ok_or_else(|| parse_error(line_no, "missing data length column"))?;
+    event.data = parse_hex_bytes(line_no, tokens, length)?;
+    Ok(Some(event))
+}
+
+/* Vector asc */
+
+fn parse_asc<R: BufRead>(reader: R) -> Result<Vec<BusEvent>, LogFileError> {
+    let mut radix = 16;
+    let mut relative = false;
+    let mut last_timestamp = 0u64;
+    let mut events = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            [] => continue,
+            ["base", base, "timestamps", mode, ..] => {
+                radix = if *base == "dec" { 10 } else { 16 };
+                relative = *mode == "relative";
+                continue;
+            }
+            [first, ..] if first.parse::<f64>().is_err() => continue,
+            _ => {}
+        }
+
+        let mut timestamp_us = parse_decimal_micros(line_no, tokens[0], 1_000_000)?;
+        if relative {
+            timestamp_us += last_timestamp;
+        }
+        last_timestamp = timestamp_us;
+
+        let event = if tokens.get(1) == Some(&"CANFD") {
+            parse_asc_fd(line_no, timestamp_us, &tokens[2..], radix)?
+        } else {
+            parse_asc_classic(line_no, timestamp_us, &tokens[1..], radix)?
+        };
+
+        if let Some(event) = event {
+            events.push(event);
+        }
+    }
+
+    Ok(events)
+}
+
+fn parse_asc_id(line_no: usize, token: &str, radix: u32) -> Result<(u32, bool), LogFileError> {
+    let (digits, extended) = match token.strip_suffix(['x', 'X']) {
+        Some(digits) => (digits, true),
+        None => (token, false),
+    };
+    let id = u32::from_str_radix(digits, radix)
+        .map_err(|_| parse_error(line_no, format!("invalid identifier '{token}'")))?;
+    Ok((id, extended))
+}
+
+fn parse_asc_classic(
+    line_no: usize,
+    timestamp_us: u64,
+    tokens: &[&str],
+    radix: u32,
+) -> Result<Option<BusEvent>, LogFileError> {
+    let Some(channel) = tokens.first().and_then(|t| t.parse::<u8>().ok()) else {
+        // Statistics and other event lines
+        return Ok(None);
+    };
+
+    if tokens.get(1) == Some(&"ErrorFrame") {
+        return Ok(Some(new_event(
+            timestamp_us,
+            channel,
+            BusEventKind::ErrorFrame,
+        )));
+    }
+
+    let (Some(id), Some(direction), Some(kind)) = (tokens.get(1), tokens.get(2), tokens.get(3))
+    else {
+        return Ok(None);
+    };
+    if *direction != "Rx" && *direction != "Tx" {
+        return Ok(None);
+    }
+
+    let (can_id, extended) = parse_asc_id(line_no, id, radix)?;
+    let mut event = new_event(timestamp_us, channel, BusEventKind::DataFrame);
+    event.can_id = can_id;
+    event.extended = extended;
+    event.tx = *direction == "Tx";
+
+    match *kind {
+        "d" => {
+            let dlc = tokens
+                .get(4)
+                .ok_or_else(|| parse_error(line_no, "missing DLC"))?;
+            let dlc = u8::from_str_radix(dlc, 16)
+                .map_err(|_| parse_error(line_no, format!("invalid DLC '{dlc}'")))?;
+            event.dlc = dlc;
+            event.data =
+                parse_hex_bytes(line_no, tokens[5..].iter().copied(), dlc.min(8) as usize)?;
+        }
+        "r" => {
+            event.kind = BusEventKind::RemoteFrame;
+            event.dlc = tokens
+                .get(4)
+                .and_then(|t| u8::from_str_radix(t, 16).ok())
+                .unwrap_or_default();
+        }
+        _ => return Ok(None),
+    }
+
+    Ok(Some(event))
+}
+
+fn parse_asc_fd(
+    line_no: usize,
+    timestamp_us: u64,
+    tokens: &[&str],
+    radix: u32,
+) -> Result<Option<BusEvent>, LogFileError> {
+    let channel: u8 = tokens
+        .first()
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| parse_error(line_no, "missing channel"))?;
+
+    if tokens.get(1) == Some(&"ErrorFrame") {
+        return Ok(Some(new_event(
+            timestamp_us,
+            channel,
+            BusEventKind::ErrorFrame,
+        )));
+    }
+
+    let direction = tokens
+        .get(1)
+        .ok_or_else(|| parse_error(line_no, "missing direction"))?;
+    let id = tokens
+        .get(2)
+        .ok_or_else(|| parse_error(line_no, "missing identifier"))?;
+    let (can_id, extended) = parse_asc_id(line_no, id, radix)?;
+
+    // An optional symbolic message name may precede the BRS and ESI flags
+    let is_flag = |t: Option<&&str>| matches!(t, Some(&"0") | Some(&"1"));
+    let mut i = 3;
+    if !(is_flag(tokens.get(i)) && is_flag(tokens.get(i + 1))) {
+        i += 1;
+    }
+
+    let brs = tokens
+        .get(i)
+        .ok_or_else(|| parse_error(line_no, "missing BRS"))?;
+    let esi = tokens
+        .get(i + 1)
+        .ok_or_else(|| parse_error(line_no, "missing ESI"))?;
+    let dlc = tokens
+        .get(i + 2)
+        .ok_or_else(|| parse_error(line_no, "missing DLC"))?;
+    let length = tokens
+        .get(i + 3)
+        .ok_or_else(|| parse_error(line_no, "missing data length"))?;
+
+    let dlc = u8::from_str_radix(dlc, 16)
+        .map_err(|_| parse_error(line_no, format!("invalid DLC '{dlc}'")))?;
+    let length: usize = length
+        .parse()
+        .map_err(|_| parse_error(line_no, format!("invalid data length '{length}'")))?;
+
+    let mut event = new_event(timestamp_us, channel, BusEventKind::DataFrame);
+    event.can_id = can_id;
+    event.extended = extended;
+    event.tx = *direction == "Tx";
+    event.edl = true;
+    event.brs = *brs == "1";
+    event.esi = *esi == "1";
+    event.dlc = dlc;
+    event.data = parse_hex_bytes(line_no, tokens[i + 4..].iter().copied(), length)?;
+    Ok(Some(event))
+}
+
+/* candump -l */
+
+const CAN_ERR_FLAG: u32 = 0x20_00_00_00;
+
+fn parse_candump<R: BufRead>(reader: R) -> Result<Vec<BusEvent>, LogFileError> {
+    let mut interfaces: Vec<String> = Vec::new();
+    let mut events = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+
+        let Some(timestamp) = tokens.next() else {
+            continue;
+        };
+        let timestamp = timestamp
+            .strip_prefix('(')
+            .and_then(|t| t.strip_suffix(')'))
+            .ok_or_else(|| parse_error(line_no, "missing timestamp"))?;
+        let timestamp_us = parse_decimal_micros(line_no, timestamp, 1_000_000)?;
+
+        let interface = tokens
+            .next()
+            .ok_or_else(|| parse_error(line_no, "missing interface"))?;
+        let channel = match interfaces.iter().position(|i| i == interface) {
+            Some(position) => position + 1,
+            None => {
+                interfaces.push(String::from(interface));
+                interfaces.len()
+            }
+        };
+
+        let frame = tokens
+            .next()
+            .ok_or_else(|| parse_error(line_no, "missing frame"))?;
+        let mut event = parse_candump_frame(line_no, frame)?;
+        event.timestamp_us = timestamp_us;
+        event.bus_channel = channel as u8;
+        // candump marks transmitted frames with a trailing 'T'
+        event.tx = tokens.next() == Some("T");
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+fn parse_candump_frame(line_no: usize, frame: &str) -> Result<BusEvent, LogFileError> {
+    let (id, rest) = frame
+        .split_once('#')
+        .ok_or_else(|| parse_error(line_no, format!("invalid frame '{frame}'")))?;
+
+    let mut event = new_event(0, 1, BusEventKind::DataFrame);
+    let raw_id = parse_hex_u32(line_no, id)?;
+    event.extended = id.len() > 3;
+    if event.extended && raw_id & CAN_ERR_FLAG != 0 {
+        event.kind = BusEventKind::ErrorFrame;
+        event.can_id = raw_id & !CAN_ERR_FLAG;
+    } else {
+        event.can_id = raw_id;
+    }
+
+    let payload = if let Some(fd) = rest.strip_prefix('#') {
+        let mut chars = fd.chars();
+        let flags = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or_else(|| parse_error(line_no, format!("invalid CAN FD flags in '{frame}'")))?;
+        event.edl = true;
+        event.brs = flags & 0x01 != 0;
+        event.esi = flags & 0x02 != 0;
+        chars.as_str()
+    } else if let Some(remote) = rest.strip_prefix(['R', 'r']) {
+        event.kind = BusEventKind::RemoteFrame;
+        event.dlc = match remote {
+            "" => 0,
+            len => len
+                .parse()
+                .map_err(|_| parse_error(line_no, format!("invalid remote length '{len}'")))?,
+        };
+        return Ok(event);
+    } else {
+        rest
+    };
+
+    let digits: String = payload.chars().filter(|c| *c != '.').collect();
+    if !digits.is_ascii() {
+        return Err(parse_error(line_no, format!("invalid data in '{frame}'")));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(parse_error(
+            line_no,
+            format!("odd number of data digits in '{frame}'"),
+        ));
+    }
+    let data = (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| parse_error(line_no, format!("invalid data in '{frame}'")))?;
+
+    event.dlc = if event.edl {
+        CanFdFrame::calc_dlc(data.len())
+    } else {
+        data.len() as u8
+    };
+    event.data = data;
+    Ok(event)
+}
+
+/* Writers */
+
+fn write_trc<W: Write>(events: &[BusEvent], mut writer: W) -> Result<(), LogFileError> {
+    let start_us = events.first().map_or(0, |e| e.timestamp_us);
+    // OLE automation date: days since 1899-12-30
+    let start_time = 25_569.0 + start_us as f64 / DateTime::MICROS_PER_DAY as f64;
+
+    writeln!(writer, ";$FILEVERSION=2.1")?;
+    writeln!(writer, ";$STARTTIME={start_time:.10}")?;
+    writeln!(writer, ";$COLUMNS=N,O,T,B,I,d,R,L,D")?;
+    writeln!(writer, ";")?;
+    writeln!(writer, ";   Message   Time    Type ID     Rx/Tx")?;
+    writeln!(
+        writer,
+        ";   Number    Offset  |    Bus    [hex]     Reserved"
+    )?;
+    writeln!(
+        writer,
+        ";   |         [ms]    |    |      |      |  |   Data Length Code"
+    )?;
+    writeln!(
+        writer,
+        ";   |         |       |    |      |      |  |   |    Data [hex] ..."
+    )?;
+    writeln!(
+        writer,
+        ";---+-- ------+------ +- --+- ----+--- +- -+ -+- -+ -- -- -- -- -- -- --"
+    )?;
+
+    for (index, event) in events.iter().enumerate() {
+        let offset = event.timestamp_us.saturating_sub(start_us);
+        let kind = match event.kind {
+            BusEventKind::ErrorFrame => "ER",
+            BusEventKind::RemoteFrame => "RR",
+            BusEventKind::DataFrame => match (event.edl, event.brs, event.esi) {
+                (false, ..) => "DT",
+                (true, false, false) => "FD",
+                (true, true, false) => "FB",
+                (true, false, true) => "FE",
+                (true, true, true) => "BI",
+            },
+        };
+        let id = match (event.kind, event.extended) {
+            (BusEventKind::ErrorFrame, _) if event.can_id == 0 => String::from("-"),
+            (_, true) => format!("{:08X}", event.can_id),
+            (_, false) => format!("{:04X}", event.can_id),
+        };
+        let direction = if event.tx { "Tx" } else { "Rx" };
+        let (dlc, data) = match event.kind {
+            BusEventKind::RemoteFrame => (event.dlc, String::new()),
+            _ => (data_dlc(event), hex_bytes(&event.data)),
+        };
+
+        let line = format!(
+            "{:>7} {:>9}.{:03} {kind} {:>2} {id:>9} {direction} - {dlc:>2}    {data}",
+            index + 1,
+            offset / 1_000,
+            offset % 1_000,
+            event.bus_channel,
+        );
+        writeln!(writer, "{}", line.trim_end())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// `Tue Mar 12 10:00:00.000 am 2024`, as in the header of `.asc` files.
+fn asc_date(micros: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let time = DateTime::from_micros(micros);
+    let (hour, meridiem) = match time.hour {
+        0 => (12, "am"),
+        hour @ 1..=11 => (hour, "am"),
+        12 => (12, "pm"),
+        hour => (hour - 12, "pm"),
+    };
+    format!(
+        "{} {} {} {hour:02}:{:02}:{:02}.{:03} {meridiem} {}",
+        WEEKDAYS[time.weekday as usize],
+        MONTHS[time.month as usize - 1],
+        time.day,
+        time.minute,
+        time.second,
+        time.micros / 1_000,
+        time.year
+    )
+}
+
+fn write_asc<W: Write>(events: &[BusEvent], mut writer: W) -> Result<(), LogFileError> {
+    let start_us = events.first().map_or(0, |e| e.timestamp_us);
+    let date = asc_date(start_us);
+
+    writeln!(writer, "date {date}")?;
+    writeln!(writer, "base hex  timestamps absolute")?;
+    writeln!(writer, "internal events logged")?;
+    writeln!(writer, "Begin Triggerblock {date}")?;
+    writeln!(writer, "   0.000000 Start of measurement")?;
+
+    for event in events {
+        let time = format_seconds(event.timestamp_us.saturating_sub(start_us));
+        let channel = event.bus_channel;
+        let direction = if event.tx { "Tx" } else { "Rx" };
+        let id = if event.extended {
+            format!("{:X}x", event.can_id)
+        } else {
+            format!("{:X}", event.can_id)
+        };
+
+        match event.kind {
+            BusEventKind::ErrorFrame => writeln!(writer, "{time:>11} {channel:<2} ErrorFrame")?,
+            BusEventKind::RemoteFrame => writeln!(
+                writer,
+                "{time:>11} {channel:<2} {id:<15} {direction:<4} r {:X}",
+                event.dlc
+            )?,
+            BusEventKind::DataFrame if event.edl => {
+                // Message flags: EDL, BRS and ESI
+                let flags = 0x1000
+                    | if event.brs { 0x2000 } else { 0 }
+                    | if event.esi { 0x4000 } else { 0 };
+                writeln!(
+                    writer,
+                    "{time:>11} CANFD {channel:>3} {direction} {id:>10} {} {} {:x} {:>2} {} 0 0 {flags:>8x} 0 0 0 0 0",
+                    u8::from(event.brs),
+                    u8::from(event.esi),
+                    data_dlc(event),
+                    event.data.len(),
+                    hex_bytes(&event.data),
+                )?
+            }
+            BusEventKind::DataFrame => writeln!(
+                writer,
+                "{time:>11} {channel:<2} {id:<15} {direction:<4} d {:X} {}",
+                data_dlc(event),
+                hex_bytes(&event.data),
+            )?,
+        }
+    }
+
+    writeln!(writer, "End TriggerBlock")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_candump<W: Write>(events: &[BusEvent], mut writer: W) -> Result<(), LogFileError> {
+    for event in events {
+        let raw_id = match event.kind {
+            BusEventKind::ErrorFrame => event.can_id | CAN_ERR_FLAG,
+            _ => event.can_id,
+        };
+        let mut frame = if event.extended || event.kind == BusEventKind::ErrorFrame {
+            format!("{raw_id:08X}#")
+        } else {
+            format!("{raw_id:03X}#")
+        };
+        match event.kind {
+            BusEventKind::RemoteFrame if event.dlc > 0 => {
+                let _ = write!(frame, "R{}", event.dlc);
+            }
+            BusEventKind::RemoteFrame => frame.push('R'),
+            _ => {
+                if event.edl {
+                    let flags = u8::from(event.brs) | u8::from(event.esi) << 1;
+                    let _ = write!(frame, "#{flags:X}");
+                }
+                for byte in &event.data {
+                    let _ = write!(frame, "{byte:02X}");
+                }
+            }
+        }
+
+        write!(
+            writer,
+            "({}) can{} {frame}",
+            format_seconds(event.timestamp_us),
+            event.bus_channel.saturating_sub(1)
+        )?;
+        if event.tx {
+            write!(writer, " T")?;
+        }
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/* CSV */
+
+/// Header of the CSV files written by [write]. When reading, the columns may come in any
+/// order and only `timestamp` and `id` are required.
+///
+/// - `timestamp`: seconds with up to six decimals,
+/// - `channel`: bus channel starting at 1,
+/// - `direction`: `Rx` or `Tx`,
+/// - `kind`: `data`, `remote` or `error`,
+/// - `id`: hexadecimal identifier,
+/// - `extended`, `fd`, `brs`, `esi`: `0` or `1`,
+/// - `dlc`: decimal data length code,
+/// - `data`: payload as contiguous hex digits.
+pub const CSV_COLUMNS: &str = "timestamp,channel,direction,kind,id,extended,fd,brs,esi,dlc,data";
+
+fn parse_csv<R: BufRead>(reader: R) -> Result<Vec<BusEvent>, LogFileError> {
+    let mut columns: Vec<String> = Vec::new();
+    let mut events = Vec::new();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+        if columns.is_empty() {
+            columns = fields.iter().map(|f| f.to_ascii_lowercase()).collect();
+            for required in ["timestamp", "id"] {
+                if !columns.iter().any(|c| c == required) {
+                    return Err(parse_error(line_no, format!("missing column '{required}'")));
+                }
+            }
+            continue;
+        }
+
+        let mut event = new_event(0, 1, BusEventKind::DataFrame);
+        let mut dlc = None;
+        for (column, field) in columns.iter().zip(fields) {
+            let flag = || match field {
+                "1" | "true" => Ok(true),
+                "0" | "false" | "" => Ok(false),
+                _ => Err(parse_error(line_no, format!("invalid {column} '{field}'"))),
+            };
+            match column.as_str() {
+                "timestamp" => {
+                    event.timestamp_us = parse_decimal_micros(line_no, field, 1_000_000)?
+                }
+                "channel" => {
+                    event.bus_channel = field
+                        .parse()
+                        .map_err(|_| parse_error(line_no, format!("invalid channel '{field}'")))?
+                }
+                "direction" => event.tx = field.eq_ignore_ascii_case("tx"),
+                "kind" => {
+                    event.kind = match field.to_ascii_lowercase().as_str() {
+                        "data" | "" => BusEventKind::DataFrame,
+                        "remote" => BusEventKind::RemoteFrame,
+                        "error" => BusEventKind::ErrorFrame,
+                        _ => return Err(parse_error(line_no, format!("invalid kind '{field}'"))),
+                    }
+                }
+                "id" => {
+                    let digits = field.trim_start_matches("0x").trim_start_matches("0X");
+                    event.can_id = parse_hex_u32(line_no, digits)?;
+                }
+                "extended" => event.extended = flag()?,
+                "fd" => event.edl = flag()?,
+                "brs" => event.brs = flag()?,
+                "esi" => event.esi = flag()?,
+                "dlc" if !field.is_empty() => {
+                    dlc = Some(
+                        field
+                            .parse()
+                            .map_err(|_| parse_error(line_no, format!("invalid DLC '{field}'")))?,
+                    )
+                }
+                "data" => {
+                    if !field.is_ascii() || !field.len().is_multiple_of(2) {
+                        return Err(parse_error(line_no, format!("invalid data '{field}'")));
+                    }
+                    event.data = (0..field.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(&field[i..i + 2], 16))
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| parse_error(line_no, format!("invalid data '{field}'")))?;
+                }
+                _ => {}
+            }
+        }
+
+        event.dlc = match dlc {
+            Some(dlc) => dlc,
+            None if event.edl => CanFdFrame::calc_dlc(event.data.len()),
+            None => event.data.len() as u8,
+        };
+        if event.kind == BusEventKind::RemoteFrame {
+            event.data.clear();
+        }
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+fn write_csv<W: Write>(events: &[BusEvent], mut writer: W) -> Result<(), LogFileError> {
+    writeln!(writer, "{CSV_COLUMNS}")?;
+    for event in events {
+        let kind = match event.kind {
+            BusEventKind::DataFrame => "data",
+            BusEventKind::RemoteFrame => "remote",
+            BusEventKind::ErrorFrame => "error",
+        };
+        let data: String = event
+            .data
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        writeln!(
+            writer,
+            "{},{},{},{kind},{:X},{},{},{},{},{},{data}",
+            format_seconds(event.timestamp_us),
+            event.bus_channel,
+            if event.tx { "Tx" } else { "Rx" },
+            event.can_id,
+            u8::from(event.extended),
+            u8::from(event.edl),
+            u8::from(event.brs),
+            u8::from(event.esi),
+            event.dlc,
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trc_v1_1() {
+        let trc = ";$FILEVERSION=1.1\n\
+                   ;   Start time: 12.03.2024 10:00:00.000.0\n\
+                   ;---+--   ----+----  --+--  ----+---  +  -+ -- -- -- -- -- -- --\n     \
+                   1)      1059.9  Rx         0300  8  00 00 00 00 04 00 00 00\n     \
+                   2)      1283.2  Tx     18EFC000  2  01 02\n     \
+                   3)      1500.0  Rx         0100  4  RTR\n     \
+                   4)      1600.0  Warng  FFFFFFFF  4  00 00 00 08  BUSHEAVY\n";
+
+        let events = parse(LogFormat::Trc, trc.as_bytes()).unwrap();
+        assert_eq!(events.len(), 3);
+
+        assert_eq!(events[0].timestamp_us, 1_059_900);
+        assert_eq!(events[0].can_id, 0x300);
+        assert!(!events[0].extended);
+        assert_eq!(events[0].data, vec![0, 0, 0, 0, 4, 0, 0, 0]);
+
+        assert_eq!(events[1].can_id, 0x18EF_C000);
+        assert!(events[1].extended);
+        assert!(events[1].tx);
+
+        assert_eq!(events[2].kind, BusEventKind::RemoteFrame);
+        assert_eq!(events[2].dlc, 4);
+    }
+
+    #[test]
+    fn trc_v2_1() {
+        let trc = ";$FILEVERSION=2.1\n\
+                   ;$STARTTIME=43882.5465009259\n\
+                   ;$COLUMNS=N,O,T,B,I,d,R,L,D\n      \
+                   1      1059.900 DT 1      0300 Rx -  8    00 00 00 00 04 00 00 00\n      \
+                   2      1283.200 FB 2  18EFC000 Tx -  9    01 02 03 04 05 06 07 08 09 0A 0B 0C\n      \
+                   3      1300.000 RR 1      0100 Rx -  4\n      \
+                   4      1400.000 ST 1           Rx    00 00 00 08\n";
+
+        let events = parse(LogFormat::Trc, trc.as_bytes()).unwrap();
+        assert_eq!(events.len(), 3);
+
+        assert_eq!(events[0].bus_channel, 1);
+        assert_eq!(events[0].data.len(), 8);
+
+        assert_eq!(events[1].bus_channel, 2);
+        assert!(events[1].edl);
+        assert!(events[1].brs);
+        assert!(events[1].tx);
+        assert_eq!(events[1].dlc, 9);
+        assert_eq!(events[1].data.len(), 12);
+
+        assert_eq!(events[2].kind, BusEventKind::RemoteFrame);
+        assert_eq!(events[2].dlc, 4);
+    }
+
+    #[test]
+    fn asc_classic_and_fd() {
+        let asc = "date Tue Mar 12 10:00:00.000 am 2024\n\
+                   base hex  timestamps absolute\n\
+                   internal events logged\n\
+                   Begin Triggerblock Tue Mar 12 10:00:00.000 am 2024\n   \
+                   0.000000 Start of measurement\n   \
+                   0.015991 1  123             Rx   d 8 00 11 22 33 44 55 66 77  Length = 0 BitCount = 0\n   \
+                   0.020000 2  1ABCDEFx        Tx   d 2 AA BB\n   \
+                   0.030000 1  200             Rx   r 4\n   \
+                   0.040000 CANFD   1 Rx        7ff  MyMessage  1 0 9 12 01 02 03 04 05 06 07 08 09 0a 0b 0c   0 0  1000 0 0 0 0 0\n   \
+                   0.050000 1  ErrorFrame\n\
+                   End TriggerBlock\n";
+
+        let events = parse(LogFormat::Asc, asc.as_bytes()).unwrap();
+        assert_eq!(events.len(), 5);
+
+        assert_eq!(events[0].timestamp_us, 15_991);
+        assert_eq!(events[0].can_id, 0x123);
+        assert_eq!(
+            events[0].data,
+            vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]
+        );
+
+        assert_eq!(events[1].bus_channel, 2);
+        assert!(events[1].extended);
+        assert!(events[1].tx);
+
+        assert_eq!(events[2].kind, BusEventKind::RemoteFrame);
+
+        assert!(events[3].edl);
+        assert!(events[3].brs);
+        assert_eq!(events[3].can_id, 0x7FF);
+        assert_eq!(events[3].data.len(), 12);
+
+        assert_eq!(events[4].kind, BusEventKind::ErrorFrame);
+    }
+
+    #[test]
+    fn candump_log() {
+        let log = "(1436509052.249713) vcan0 044#2A366C2A\n\
+                   (1436509052.449847) vcan1 12345678#R\n\
+                   (1436509052.650004) vcan0 123##1DEADBEEF\n\
+                   (1436509052.850000) vcan0 20000004#0004000000000000\n";
+
+        let events = parse(LogFormat::Candump, log.as_bytes()).unwrap();
+        assert_eq!(events.len(), 4);
+
+        assert_eq!(events[0].timestamp_us, 1_436_509_052_249_713);
+        assert_eq!(events[0].bus_channel, 1);
+        assert_eq!(events[0].data, vec![0x2A, 0x36, 0x6C, 0x2A]);
+
+        assert_eq!(events[1].bus_channel, 2);
+        assert_eq!(events[1].kind, BusEventKind::RemoteFrame);
+        assert!(events[1].extended);
+
+        assert!(events[2].edl);
+        assert!(events[2].brs);
+        assert_eq!(events[2].data, vec![0xDE, 0xAD, 0xBE, 0xEF]);
+
+        assert_eq!(events[3].kind, BusEventKind::ErrorFrame);
+        assert_eq!(events[3].can_id, 0x04);
+    }
+
+    #[test]
+    fn parse_errors_report_line() {
+        let log = "(1436509052.249713) vcan0 044#2A366C2A\n(1436509052.249713) vcan0 044#2A3\n";
+        match parse(LogFormat::Candump, log.as_bytes()) {
+            Err(LogFileError::Parse { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        for log in ["(1.0) can0 123#€a\n", "(18446744073710.0) can0 123#00\n"] {
+            assert!(matches!(
+                parse(LogFormat::Candump, log.as_bytes()),
+                Err(LogFileError::Parse { line: 1, .. })
+            ));
+        }
+    }
+
+    fn sample_events() -> Vec<BusEvent> {
+        let mut events = vec![
+            new_event(1_710_237_600_015_991, 1, BusEventKind::DataFrame),
+            new_event(1_710_237_600_020_000, 2, BusEventKind::DataFrame),
+            new_event(1_710_237_600_030_000, 1, BusEventKind::RemoteFrame),
+            new_event(1_710_237_600_040_000, 1, BusEventKind::DataFrame),
+            new_event(1_710_237_600_050_000, 1, BusEventKind::ErrorFrame),
+        ];
+        events[0].can_id = 0x123;
+        events[0].dlc = 8;
+        events[0].data = vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+        events[1].can_id = 0x1ABC_DEF0;
+        events[1].extended = true;
+        events[1].tx = true;
+        events[1].dlc = 2;
+        events[1].data = vec![0xAA, 0xBB];
+        events[2].can_id = 0x200;
+        events[2].dlc = 4;
+        events[3].can_id = 0x7FF;
+        events[3].edl = true;
+        events[3].brs = true;
+        events[3].dlc = 9;
+        events[3].data = (1..=12).collect();
+        events
+    }
+
+    fn round_trip(format: LogFormat, events: &[BusEvent]) -> Vec<BusEvent> {
+        let mut file = Vec::new();
+        write(format, events, &mut file).unwrap();
+        parse(format, file.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn writers_round_trip() {
+        let events = sample_events();
+        let start = events[0].timestamp_us;
+        let relative: Vec<BusEvent> = events
+            .iter()
+            .cloned()
+            .map(|mut event| {
+                event.timestamp_us -= start;
+                event
+            })
+            .collect();
+
+        assert_eq!(round_trip(LogFormat::Trc, &events), relative);
+        assert_eq!(round_trip(LogFormat::Asc, &events), relative);
+        assert_eq!(round_trip(LogFormat::Csv, &events), events);
+
+        // candump error frames always use the 29 bit layout
+        let mut candump = events.clone();
+        candump[4].extended = true;
+        assert_eq!(round_trip(LogFormat::Candump, &events), candump);
+
+        let mut file = Vec::new();
+        write(LogFormat::Asc, &events, &mut file).unwrap();
+        let asc = String::from_utf8(file).unwrap();
+        assert!(asc.starts_with("date Tue Mar 12 10:00:00.015 am 2024\n"));
+
+        let mut file = Vec::new();
+        write(LogFormat::Candump, &events[..2], &mut file).unwrap();
+        assert_eq!(
+            String::from_utf8(file).unwrap(),
+            "(1710237600.015991) can0 123#0011223344556677\n\
+             (1710237600.020000) can1 1ABCDEF0#AABB T\n"
+        );
+    }
+
+    #[test]
+    fn csv_columns() {
+        let csv = "id,timestamp,data,kind\n\
+                   0x18DAF110,12.5,0201,data\n\
+                   7DF,12.75,,remote\n";
+        let events = parse(LogFormat::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].timestamp_us, 12_500_000);
+        assert_eq!(events[0].can_id, 0x18DA_F110);
+        assert_eq!(events[0].data, vec![0x02, 0x01]);
+        assert_eq!(events[0].dlc, 2);
+        assert_eq!(events[1].kind, BusEventKind::RemoteFrame);
+
+        assert!(matches!(
+            parse(LogFormat::Csv, "timestamp,data\n".as_bytes()),
+            Err(LogFileError::Parse { line: 1, .. })
+        ));
+        assert_eq!(LogFormat::from_path("bench.CSV"), Some(LogFormat::Csv));
+    }
+
+    #[test]
+    fn merge_by_timestamp() {
+        let events = sample_events();
+        let first = vec![events[0].clone(), events[3].clone()];
+        let second = vec![events[1].clone(), events[2].clone(), events[4].clone()];
+        assert_eq!(merge([first, second]), events);
+    }
+
+    #[test]
+    fn calendar() {
+        let time = DateTime::from_micros(1_710_237_600_015_991);
+        assert_eq!(
+            (time.year, time.month, time.day, time.weekday),
+            (2024, 3, 12, 2)
+        );
+        assert_eq!((time.hour, time.minute, time.second), (10, 0, 0));
+        assert_eq!(time.micros, 15_991);
+        assert_eq!(time.to_micros(), Some(1_710_237_600_015_991));
+
+        let leap = DateTime::from_micros(951_782_400_000_000);
+        assert_eq!((leap.year, leap.month, leap.day), (2000, 2, 29));
+        assert_eq!(DateTime::from_micros(0).to_micros(), Some(0));
+    }
+}
@@ -0,0 +1,1374 @@
+//! ASAM MDF4 bus logging.
+//!
+//! [Mdf4Writer] records CAN and CAN FD traffic into an MDF 4.10 file following the ASAM
+//! bus-logging conventions: one unsorted data group holding the `CAN_DataFrame`,
+//! `CAN_RemoteFrame` and `CAN_ErrorFrame` channel groups, each with a float64 `Timestamp`
+//! master channel (seconds) and a composed structure channel whose members carry the bus
+//! channel, identifier, DLC, flags and payload.
+//!
+//! [Mdf4Reader] reads such files back into [BusEvent]s. It understands unsorted and sorted
+//! data groups stored in plain `##DT` blocks, which covers files produced by this writer and
+//! most bus loggers that do not compress their data. Unfinished files (`UnFinMF`), as left
+//! behind by a logger that did not shut down cleanly, are read up to their last complete
+//! record.
+
+use crate::error::CanError;
+use crate::socket::{
+    CanFdFrame, CanFrame, FrameConstructionError, MessageType, RecvCan, RecvCanFd, Timestamp,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ID_BLOCK_SIZE: u64 = 64;
+const BLOCK_HEADER_SIZE: u64 = 24;
+
+const RECORD_ID_DATA_FRAME: u8 = 1;
+const RECORD_ID_REMOTE_FRAME: u8 = 2;
+const RECORD_ID_ERROR_FRAME: u8 = 3;
+
+const DATA_FRAME_BYTES: u32 = 80;
+const REMOTE_FRAME_BYTES: u32 = 16;
+const ERROR_FRAME_BYTES: u32 = 25;
+
+/* MDF constants (ASAM MDF 4.1) */
+
+const CN_TYPE_FIXED: u8 = 0;
+const CN_TYPE_MASTER: u8 = 2;
+const CN_SYNC_NONE: u8 = 0;
+const CN_SYNC_TIME: u8 = 1;
+const CN_DATA_UINT_LE: u8 = 0;
+const CN_DATA_FLOAT_LE: u8 = 4;
+const CN_DATA_BYTE_ARRAY: u8 = 10;
+
+const CG_FLAG_BUS_EVENT: u16 = 0x02;
+const CG_FLAG_PLAIN_BUS_EVENT: u16 = 0x04;
+
+const SI_TYPE_BUS: u8 = 2;
+const SI_BUS_CAN: u8 = 2;
+
+const UNFIN_CYCLE_COUNTERS: u16 = 0x01;
+const UNFIN_LAST_DT_LENGTH: u16 = 0x04;
+
+/// Errors raised while writing or reading MDF4 files.
+#[derive(Debug)]
+pub enum MdfError {
+    /// Underlying I/O failure.
+    Io(io::Error),
+    /// Failure reported by the CAN driver while recording.
+    Can(CanError),
+    /// The file is not a valid or supported MDF4 file.
+    InvalidFormat(String),
+    /// A stored record could not be turned back into a frame.
+    Frame(FrameConstructionError),
+}
+
+impl fmt::Display for MdfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdfError::Io(e) => write!(f, "{e}"),
+            MdfError::Can(e) => write!(f, "{e}"),
+            MdfError::InvalidFormat(s) => write!(f, "invalid mdf file: {s}"),
+            MdfError::Frame(e) => write!(f, "invalid frame: {e:?}"),
+        }
+    }
+}
+
+impl std::error::Error for MdfError {}
+
+impl From<io::Error> for MdfError {
+    fn from(value: io::Error) -> Self {
+        MdfError::Io(value)
+    }
+}
+
+impl From<CanError> for MdfError {
+    fn from(value: CanError) -> Self {
+        MdfError::Can(value)
+    }
+}
+
+impl From<FrameConstructionError> for MdfError {
+    fn from(value: FrameConstructionError) -> Self {
+        MdfError::Frame(value)
+    }
+}
+
+/// ASAM bus event type, one per channel group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BusEventKind {
+    DataFrame,
+    RemoteFrame,
+    ErrorFrame,
+}
+
+impl BusEventKind {
+    fn group_name(&self) -> &'static str {
+        match self {
+            BusEventKind::DataFrame => "CAN_DataFrame",
+            BusEventKind::RemoteFrame => "CAN_RemoteFrame",
+            BusEventKind::ErrorFrame => "CAN_ErrorFrame",
+        }
+    }
+
+    fn from_group_name(name: &str) -> Option<BusEventKind> {
+        match name {
+            "CAN_DataFrame" => Some(BusEventKind::DataFrame),
+            "CAN_RemoteFrame" => Some(BusEventKind::RemoteFrame),
+            "CAN_ErrorFrame" => Some(BusEventKind::ErrorFrame),
+            _ => None,
+        }
+    }
+}
+
+/// ASAM `CAN_ErrorFrame.ErrorType` values.
+pub mod error_type {
+    pub const UNKNOWN: u8 = 0;
+    pub const BIT_ERROR: u8 = 1;
+    pub const FORM_ERROR: u8 = 2;
+    pub const BIT_STUFFING_ERROR: u8 = 3;
+    pub const CRC_ERROR: u8 = 4;
+    pub const ACK_ERROR: u8 = 5;
+}
+
+/// One logged bus event, in the shape of the ASAM bus-logging channel groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusEvent {
+    /// Capture time in microseconds.
+    pub timestamp_us: u64,
+    /// Logical bus channel the event was captured on.
+    pub bus_channel: u8,
+    pub kind: BusEventKind,
+    pub can_id: u32,
+    pub extended: bool,
+    pub dlc: u8,
+    /// Payload bytes. Empty for remote frames.
+    pub data: Vec<u8>,
+    /// `true` for transmitted (echo) frames, `false` for received frames.
+    pub tx: bool,
+    /// Extended data length (CAN FD frame).
+    pub edl: bool,
+    /// Bit rate switch.
+    pub brs: bool,
+    /// Error state indicator.
+    pub esi: bool,
+    /// One of the [error_type] values. Only meaningful for error frames.
+    pub error_type: u8,
+}
+
+impl BusEvent {
+    /// Builds an event from a classic frame as returned by [RecvCan::recv].
+    pub fn from_frame(bus_channel: u8, frame: &CanFrame, timestamp: &Timestamp) -> BusEvent {
+        let kind = if frame.is_error_frame() {
+            BusEventKind::ErrorFrame
+        } else if frame.is_remote_frame() {
+            BusEventKind::RemoteFrame
+        } else {
+            BusEventKind::DataFrame
+        };
+
+        // Error frames report the error kind in the identifier (1 bit, 2 form, 4 stuff)
+        let error_type = match (kind, frame.can_id()) {
+            (BusEventKind::ErrorFrame, 1) => error_type::BIT_ERROR,
+            (BusEventKind::ErrorFrame, 2) => error_type::FORM_ERROR,
+            (BusEventKind::ErrorFrame, 4) => error_type::BIT_STUFFING_ERROR,
+            _ => error_type::UNKNOWN,
+        };
+
+        BusEvent {
+            timestamp_us: timestamp.as_micros(),
+            bus_channel,
+            kind,
+            can_id: frame.can_id(),
+            extended: frame.is_extended_frame(),
+            dlc: frame.dlc(),
+            data: match kind {
+                BusEventKind::RemoteFrame => Vec::new(),
+                _ => frame.data().to_vec(),
+            },
+            tx: frame.is_echo_frame(),
+            edl: false,
+            brs: false,
+            esi: false,
+            error_type,
+        }
+    }
+
+    /// Builds an event from a CAN FD frame as returned by [RecvCanFd::recv_fd].
+    pub fn from_fd_frame(bus_channel: u8, frame: &CanFdFrame, timestamp_us: u64) -> BusEvent {
+        let kind = if frame.is_error_frame() {
+            BusEventKind::ErrorFrame
+        } else {
+            BusEventKind::DataFrame
+        };
+
+        BusEvent {
+            timestamp_us,
+            bus_channel,
+            kind,
+            can_id: frame.can_id(),
+            extended: frame.is_extended_frame(),
+            dlc: frame.dlc(),
+            data: frame.data().to_vec(),
+            tx: frame.is_echo_frame(),
+            edl: frame.is_fd_frame(),
+            brs: frame.is_brs_frame(),
+            esi: frame.is_esi_frame(),
+            error_type: error_type::UNKNOWN,
+        }
+    }
+
+    fn msg_type(&self) -> MessageType {
+        if self.extended {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        }
+    }
+
+    /// Converts a classic data or remote frame event back into a [CanFrame].
+    pub fn to_frame(&self) -> Result<CanFrame, MdfError> {
+        match self.kind {
+            BusEventKind::RemoteFrame => Ok(CanFrame::new_remote(
+                self.can_id,
+                self.msg_type(),
+                self.dlc,
+            )?),
+            BusEventKind::DataFrame if !self.edl => {
+                Ok(CanFrame::new(self.can_id, self.msg_type(), &self.data)?)
+            }
+            _ => Err(MdfError::InvalidFormat(String::from(
+                "event is not a classic data or remote frame",
+            ))),
+        }
+    }
+
+    /// Converts a data frame event back into a [CanFdFrame].
+    pub fn to_fd_frame(&self) -> Result<CanFdFrame, MdfError> {
+        match self.kind {
+            BusEventKind::DataFrame => Ok(CanFdFrame::new(
+                self.can_id,
+                self.msg_type(),
+                &self.data,
+                self.edl,
+                self.brs,
+            )?),
+            _ => Err(MdfError::InvalidFormat(String::from(
+                "event is not a data frame",
+            ))),
+        }
+    }
+}
+
+/* Block encoding */
+
+/// In-memory image of the metadata section, with links patched after the fact.
+struct Layout {
+    buf: Vec<u8>,
+}
+
+impl Layout {
+    fn new() -> Layout {
+        Layout { buf: Vec::new() }
+    }
+
+    fn address(&self) -> u64 {
+        ID_BLOCK_SIZE + self.buf.len() as u64
+    }
+
+    fn push(&mut self, id: &[u8; 4], links: &[u64], data: &[u8]) -> u64 {
+        let address = self.address();
+        let length = BLOCK_HEADER_SIZE + 8 * links.len() as u64 + data.len() as u64;
+
+        self.buf.extend_from_slice(b"##");
+        self.buf.extend_from_slice(&id[2..]);
+        self.buf.extend_from_slice(&[0u8; 4]);
+        self.buf.extend_from_slice(&length.to_le_bytes());
+        self.buf
+            .extend_from_slice(&(links.len() as u64).to_le_bytes());
+        for link in links {
+            self.buf.extend_from_slice(&link.to_le_bytes());
+        }
+        self.buf.extend_from_slice(data);
+
+        // Blocks must start on 8 byte boundaries
+        while !self.buf.len().is_multiple_of(8) {
+            self.buf.push(0);
+        }
+        address
+    }
+
+    fn push_text(&mut self, id: &[u8; 4], text: &str) -> u64 {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        while !data.len().is_multiple_of(8) {
+            data.push(0);
+        }
+        self.push(id, &[], &data)
+    }
+
+    fn set_link(&mut self, block: u64, index: usize, target: u64) {
+        let offset = (block - ID_BLOCK_SIZE) as usize + BLOCK_HEADER_SIZE as usize + 8 * index;
+        self.buf[offset..offset + 8].copy_from_slice(&target.to_le_bytes());
+    }
+}
+
+fn id_block(finalized: bool) -> [u8; ID_BLOCK_SIZE as usize] {
+    let mut block = [0u8; ID_BLOCK_SIZE as usize];
+    if finalized {
+        block[0..8].copy_from_slice(b"MDF     ");
+    } else {
+        block[0..8].copy_from_slice(b"UnFinMF ");
+    }
+    block[8..16].copy_from_slice(b"4.10    ");
+    block[16..24].copy_from_slice(b"peakcan ");
+    block[28..30].copy_from_slice(&410u16.to_le_bytes());
+    if !finalized {
+        block[60..62].copy_from_slice(&(UNFIN_CYCLE_COUNTERS | UNFIN_LAST_DT_LENGTH).to_le_bytes());
+    }
+    block
+}
+
+/// Description of one fixed channel inside a record.
+struct ChannelSpec {
+    name: &'static str,
+    byte_offset: u32,
+    bit_offset: u8,
+    bit_count: u32,
+    data_type: u8,
+}
+
+impl ChannelSpec {
+    const fn new(
+        name: &'static str,
+        byte_offset: u32,
+        bit_offset: u8,
+        bit_count: u32,
+        data_type: u8,
+    ) -> Self {
+        ChannelSpec {
+            name,
+            byte_offset,
+            bit_offset,
+            bit_count,
+            data_type,
+        }
+    }
+}
+
+const DATA_FRAME_MEMBERS: &[ChannelSpec] = &[
+    ChannelSpec::new("BusChannel", 8, 0, 8, CN_DATA_UINT_LE),
+    ChannelSpec::new("ID", 9, 0, 29, CN_DATA_UINT_LE),
+    ChannelSpec::new("IDE", 12, 7, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("DLC", 13, 0, 4, CN_DATA_UINT_LE),
+    ChannelSpec::new("DataLength", 14, 0, 8, CN_DATA_UINT_LE),
+    ChannelSpec::new("Dir", 15, 0, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("EDL", 15, 1, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("BRS", 15, 2, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("ESI", 15, 3, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("DataBytes", 16, 0, 64 * 8, CN_DATA_BYTE_ARRAY),
+];
+
+const REMOTE_FRAME_MEMBERS: &[ChannelSpec] = &[
+    ChannelSpec::new("BusChannel", 8, 0, 8, CN_DATA_UINT_LE),
+    ChannelSpec::new("ID", 9, 0, 29, CN_DATA_UINT_LE),
+    ChannelSpec::new("IDE", 12, 7, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("DLC", 13, 0, 4, CN_DATA_UINT_LE),
+    ChannelSpec::new("DataLength", 14, 0, 8, CN_DATA_UINT_LE),
+    ChannelSpec::new("Dir", 15, 0, 1, CN_DATA_UINT_LE),
+];
+
+const ERROR_FRAME_MEMBERS: &[ChannelSpec] = &[
+    ChannelSpec::new("BusChannel", 8, 0, 8, CN_DATA_UINT_LE),
+    ChannelSpec::new("ID", 9, 0, 29, CN_DATA_UINT_LE),
+    ChannelSpec::new("IDE", 12, 7, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("DLC", 13, 0, 4, CN_DATA_UINT_LE),
+    ChannelSpec::new("DataLength", 14, 0, 8, CN_DATA_UINT_LE),
+    ChannelSpec::new("Dir", 15, 0, 1, CN_DATA_UINT_LE),
+    ChannelSpec::new("ErrorType", 16, 0, 8, CN_DATA_UINT_LE),
+    ChannelSpec::new("DataBytes", 17, 0, 8 * 8, CN_DATA_BYTE_ARRAY),
+];
+
+fn channel_data(
+    cn_type: u8,
+    sync_type: u8,
+    spec_data_type: u8,
+    byte_offset: u32,
+    bit_offset: u8,
+    bit_count: u32,
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(72);
+    data.push(cn_type);
+    data.push(sync_type);
+    data.push(spec_data_type);
+    data.push(bit_offset);
+    data.extend_from_slice(&byte_offset.to_le_bytes());
+    data.extend_from_slice(&bit_count.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes()); // cn_flags
+    data.extend_from_slice(&0u32.to_le_bytes()); // cn_inval_bit_pos
+    data.push(0); // cn_precision
+    data.push(0); // cn_reserved
+    data.extend_from_slice(&0u16.to_le_bytes()); // cn_attachment_count
+    for _ in 0..6 {
+        data.extend_from_slice(&0f64.to_le_bytes()); // ranges and limits
+    }
+    data
+}
+
+/// Pushes a CN block with links (next, composition, name, source, conversion, data, unit, comment).
+fn push_channel(layout: &mut Layout, name: &str, unit: u64, data: &[u8]) -> u64 {
+    let name = layout.push_text(b"##TX", name);
+    layout.push(b"##CN", &[0, 0, name, 0, 0, 0, unit, 0], data)
+}
+
+/// Pushes one ASAM bus-logging channel group and returns its address.
+fn push_channel_group(
+    layout: &mut Layout,
+    kind: BusEventKind,
+    record_id: u8,
+    data_bytes: u32,
+    members: &[ChannelSpec],
+    source: u64,
+    seconds: u64,
+) -> u64 {
+    let group = kind.group_name();
+
+    let timestamp = push_channel(
+        layout,
+        "Timestamp",
+        seconds,
+        &channel_data(CN_TYPE_MASTER, CN_SYNC_TIME, CN_DATA_FLOAT_LE, 0, 0, 64),
+    );
+
+    let structure = push_channel(
+        layout,
+        group,
+        0,
+        &channel_data(
+            CN_TYPE_FIXED,
+            CN_SYNC_NONE,
+            CN_DATA_BYTE_ARRAY,
+            8,
+            0,
+            (data_bytes - 8) * 8,
+        ),
+    );
+    layout.set_link(timestamp, 0, structure);
+
+    let mut previous: Option<u64> = None;
+    for member in members {
+        let name = format!("{}.{}", group, member.name);
+        let channel = push_channel(
+            layout,
+            &name,
+            0,
+            &channel_data(
+                CN_TYPE_FIXED,
+                CN_SYNC_NONE,
+                member.data_type,
+                member.byte_offset,
+                member.bit_offset,
+                member.bit_count,
+            ),
+        );
+        match previous {
+            Some(previous) => layout.set_link(previous, 0, channel),
+            None => layout.set_link(structure, 1, channel),
+        }
+        previous = Some(channel);
+    }
+
+    let acq_name = layout.push_text(b"##TX", group);
+
+    let mut data = Vec::with_capacity(32);
+    data.extend_from_slice(&(record_id as u64).to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes()); // cg_cycle_count, patched on finish
+    data.extend_from_slice(&(CG_FLAG_BUS_EVENT | CG_FLAG_PLAIN_BUS_EVENT).to_le_bytes());
+    data.extend_from_slice(&(b'.' as u16).to_le_bytes());
+    data.extend_from_slice(&[0u8; 4]);
+    data.extend_from_slice(&data_bytes.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+
+    layout.push(b"##CG", &[0, timestamp, acq_name, source, 0, 0], &data)
+}
+
+/* Writer */
+
+/// Streams bus events into an MDF4 file.
+///
+/// Records are appended to a single `##DT` block as they arrive. The cycle counters and the
+/// data block length are written by [finish](Mdf4Writer::finish); a file that was never
+/// finished keeps the `UnFinMF` marker so that MDF tools can still recover it.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use peak_can::bus::UsbBus;
+/// # use peak_can::mdf::Mdf4Writer;
+/// # use peak_can::socket::Baudrate;
+/// # use peak_can::socket::usb::UsbCanSocket;
+/// let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+/// let mut writer = Mdf4Writer::create("recording.mf4")?;
+/// for _ in 0..1000 {
+///     writer.record(&socket, 1)?;
+/// }
+/// writer.finish()?;
+/// # Ok::<(), peak_can::mdf::MdfError>(())
+/// ```
+pub struct Mdf4Writer<W: Write + Seek> {
+    inner: W,
+    cycle_counts: [u64; 3],
+    cycle_count_addresses: [u64; 3],
+    dt_address: u64,
+    dt_length: u64,
+}
+
+impl Mdf4Writer<BufWriter<File>> {
+    /// Creates (or truncates) the file at `path` and writes the MDF4 header.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, MdfError> {
+        let file = File::create(path)?;
+        Mdf4Writer::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write + Seek> Mdf4Writer<W> {
+    /// Writes the MDF4 header and channel layout to `inner`.
+    pub fn new(mut inner: W) -> Result<Self, MdfError> {
+        let start_time_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        let mut layout = Layout::new();
+
+        let mut hd_data = Vec::with_capacity(32);
+        hd_data.extend_from_slice(&start_time_ns.to_le_bytes());
+        hd_data.extend_from_slice(&0i16.to_le_bytes()); // tz offset
+        hd_data.extend_from_slice(&0i16.to_le_bytes()); // dst offset
+        hd_data.extend_from_slice(&[0u8; 4]); // time flags, time class, flags, reserved
+        hd_data.extend_from_slice(&0f64.to_le_bytes());
+        hd_data.extend_from_slice(&0f64.to_le_bytes());
+        let hd = layout.push(b"##HD", &[0; 6], &hd_data);
+
+        let comment = format!(
+            "<FHcomment><TX>Bus logging</TX><tool_id>peak-can</tool_id><tool_vendor>peak-can-rs</tool_vendor><tool_version>{}</tool_version></FHcomment>",
+            env!("CARGO_PKG_VERSION")
+        );
+        let md = layout.push_text(b"##MD", &comment);
+        let mut fh_data = Vec::with_capacity(16);
+        fh_data.extend_from_slice(&start_time_ns.to_le_bytes());
+        fh_data.extend_from_slice(&[0u8; 8]);
+        let fh = layout.push(b"##FH", &[0, md], &fh_data);
+        layout.set_link(hd, 1, fh);
+
+        let source_name = layout.push_text(b"##TX", "CAN");
+        let source = layout.push(
+            b"##SI",
+            &[source_name, 0, 0],
+            &[SI_TYPE_BUS, SI_BUS_CAN, 0, 0, 0, 0, 0, 0],
+        );
+        let seconds = layout.push_text(b"##TX", "s");
+
+        let groups = [
+            (
+                BusEventKind::DataFrame,
+                RECORD_ID_DATA_FRAME,
+                DATA_FRAME_BYTES,
+                DATA_FRAME_MEMBERS,
+            ),
+            (
+                BusEventKind::RemoteFrame,
+                RECORD_ID_REMOTE_FRAME,
+                REMOTE_FRAME_BYTES,
+                REMOTE_FRAME_MEMBERS,
+            ),
+            (
+                BusEventKind::ErrorFrame,
+                RECORD_ID_ERROR_FRAME,
+                ERROR_FRAME_BYTES,
+                ERROR_FRAME_MEMBERS,
+            ),
+        ];
+
+        let mut cycle_count_addresses = [0u64; 3];
+        let mut previous: Option<u64> = None;
+        let mut first_group = 0;
+        for (i, (kind, record_id, data_bytes, members)) in groups.into_iter().enumerate() {
+            let group = push_channel_group(
+                &mut layout,
+                kind,
+                record_id,
+                data_bytes,
+                members,
+                source,
+                seconds,
+            );
+            // cg_cycle_count follows the six links and cg_record_id
+            cycle_count_addresses[i] = group + BLOCK_HEADER_SIZE + 6 * 8 + 8;
+            match previous {
+                Some(previous) => layout.set_link(previous, 0, group),
+                None => first_group = group,
+            }
+            previous = Some(group);
+        }
+
+        let dg = layout.push(b"##DG", &[0, first_group, 0, 0], &[1, 0, 0, 0, 0, 0, 0, 0]);
+        layout.set_link(hd, 0, dg);
+
+        let dt_address = layout.address();
+        layout.set_link(dg, 2, dt_address);
+
+        inner.write_all(&id_block(false))?;
+        inner.write_all(&layout.buf)?;
+
+        // Data block header, the length is patched on finish
+        inner.write_all(b"##DT")?;
+        inner.write_all(&[0u8; 4])?;
+        inner.write_all(&BLOCK_HEADER_SIZE.to_le_bytes())?;
+        inner.write_all(&0u64.to_le_bytes())?;
+
+        Ok(Mdf4Writer {
+            inner,
+            cycle_counts: [0; 3],
+            cycle_count_addresses,
+            dt_address,
+            dt_length: BLOCK_HEADER_SIZE,
+        })
+    }
+
+    /// Appends one event to the recording.
+    pub fn write_event(&mut self, event: &BusEvent) -> Result<(), MdfError> {
+        let (record_id, size) = match event.kind {
+            BusEventKind::DataFrame => (RECORD_ID_DATA_FRAME, DATA_FRAME_BYTES),
+            BusEventKind::RemoteFrame => (RECORD_ID_REMOTE_FRAME, REMOTE_FRAME_BYTES),
+            BusEventKind::ErrorFrame => (RECORD_ID_ERROR_FRAME, ERROR_FRAME_BYTES),
+        };
+
+        let mut record = vec![0u8; size as usize + 1];
+        record[0] = record_id;
+        let body = &mut record[1..];
+
+        body[0..8].copy_from_slice(&(event.timestamp_us as f64 / 1_000_000.0).to_le_bytes());
+        body[8] = event.bus_channel;
+        let mut id = event.can_id & 0x1F_FF_FF_FF;
+        if event.extended {
+            id |= 0x80_00_00_00;
+        }
+        body[9..13].copy_from_slice(&id.to_le_bytes());
+        body[13] = event.dlc & 0x0F;
+        body[15] = event.tx as u8;
+
+        match event.kind {
+            BusEventKind::DataFrame => {
+                let len = event.data.len().min(64);
+                body[14] = len as u8;
+                body[15] |=
+                    (event.edl as u8) << 1 | (event.brs as u8) << 2 | (event.esi as u8) << 3;
+                body[16..16 + len].copy_from_slice(&event.data[..len]);
+            }
+            BusEventKind::RemoteFrame => {
+                body[14] = event.dlc;
+            }
+            BusEventKind::ErrorFrame => {
+                let len = event.data.len().min(8);
+                body[14] = len as u8;
+                body[16] = event.error_type;
+                body[17..17 + len].copy_from_slice(&event.data[..len]);
+            }
+        }
+
+        self.inner.write_all(&record)?;
+        self.dt_length += record.len() as u64;
+        self.cycle_counts[record_id as usize - 1] += 1;
+        Ok(())
+    }
+
+    /// Appends a classic frame as returned by [RecvCan::recv].
+    pub fn write_frame(
+        &mut self,
+        bus_channel: u8,
+        frame: &CanFrame,
+        timestamp: &Timestamp,
+    ) -> Result<(), MdfError> {
+        self.write_event(&BusEvent::from_frame(bus_channel, frame, timestamp))
+    }
+
+    /// Appends a CAN FD frame as returned by [RecvCanFd::recv_fd].
+    pub fn write_fd_frame(
+        &mut self,
+        bus_channel: u8,
+        frame: &CanFdFrame,
+        timestamp_us: u64,
+    ) -> Result<(), MdfError> {
+        self.write_event(&BusEvent::from_fd_frame(bus_channel, frame, timestamp_us))
+    }
+
+    /// Drains the receive queue of `socket`, logging every frame under `bus_channel`.
+    ///
+    /// Returns the number of frames written once the queue is empty.
+    pub fn record<S: RecvCan + ?Sized>(
+        &mut self,
+        socket: &S,
+        bus_channel: u8,
+    ) -> Result<usize, MdfError> {
+        let mut count = 0;
+        loop {
+            match socket.recv() {
+                Ok((frame, timestamp)) => {
+                    self.write_frame(bus_channel, &frame, &timestamp)?;
+                    count += 1;
+                }
+                Err(CanError::QrcvEmpty) => return Ok(count),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Drains the CAN FD receive queue of `socket`, logging every frame under `bus_channel`.
+    ///
+    /// Returns the number of frames written once the queue is empty.
+    pub fn record_fd<S: RecvCanFd + ?Sized>(
+        &mut self,
+        socket: &S,
+        bus_channel: u8,
+    ) -> Result<usize, MdfError> {
+        let mut count = 0;
+        loop {
+            match socket.recv_fd() {
+                Ok((frame, timestamp)) => {
+                    self.write_fd_frame(bus_channel, &frame, timestamp)?;
+                    count += 1;
+                }
+                Err(CanError::QrcvEmpty) => return Ok(count),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Number of events written so far.
+    pub fn event_count(&self) -> u64 {
+        self.cycle_counts.iter().sum()
+    }
+
+    /// Patches the cycle counters and data block length, marks the file finalized and
+    /// returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, MdfError> {
+        let end = self.inner.stream_position()?;
+
+        self.inner.seek(SeekFrom::Start(self.dt_address + 8))?;
+        self.inner.write_all(&self.dt_length.to_le_bytes())?;
+
+        for (address, count) in self
+            .cycle_count_addresses
+            .iter()
+            .zip(self.cycle_counts.iter())
+        {
+            self.inner.seek(SeekFrom::Start(*address))?;
+            self.inner.write_all(&count.to_le_bytes())?;
+        }
+
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.write_all(&id_block(true))?;
+
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/* Reader */
+
+struct Block {
+    id: [u8; 4],
+    links: Vec<u64>,
+    data: Vec<u8>,
+}
+
+/// Marks a block of a linked list as read, failing on links that lead back to it.
+fn visit(visited: &mut HashSet<u64>, address: u64) -> Result<(), MdfError> {
+    if visited.insert(address) {
+        Ok(())
+    } else {
+        Err(MdfError::InvalidFormat(format!(
+            "cyclic link to {address:#x}"
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    byte_offset: usize,
+    bit_offset: u8,
+    bit_count: u32,
+}
+
+impl Field {
+    fn read(&self, record: &[u8]) -> Option<u64> {
+        let bits = self.bit_offset as u32 + self.bit_count;
+        let bytes = bits.div_ceil(8) as usize;
+        if bits > 64 || self.byte_offset + bytes > record.len() {
+            return None;
+        }
+
+        let mut raw = [0u8; 8];
+        raw[..bytes].copy_from_slice(&record[self.byte_offset..self.byte_offset + bytes]);
+        let value = u64::from_le_bytes(raw) >> self.bit_offset;
+        if self.bit_count == 64 {
+            Some(value)
+        } else {
+            Some(value & ((1u64 << self.bit_count) - 1))
+        }
+    }
+
+    fn bytes<'a>(&self, record: &'a [u8]) -> Option<&'a [u8]> {
+        let len = (self.bit_count / 8) as usize;
+        record.get(self.byte_offset..self.byte_offset + len)
+    }
+}
+
+struct Group {
+    kind: Option<BusEventKind>,
+    data_bytes: usize,
+    fields: HashMap<String, Field>,
+}
+
+impl Group {
+    fn field(&self, name: &str) -> Option<&Field> {
+        let kind = self.kind?;
+        self.fields.get(&format!("{}.{}", kind.group_name(), name))
+    }
+
+    fn decode(&self, record: &[u8]) -> Result<Option<BusEvent>, MdfError> {
+        let Some(kind) = self.kind else {
+            return Ok(None);
+        };
+
+        let missing = |name: &str| {
+            MdfError::InvalidFormat(format!("missing channel {}.{}", kind.group_name(), name))
+        };
+        let value = |name: &str| -> Result<u64, MdfError> {
+            self.field(name)
+                .and_then(|f| f.read(record))
+                .ok_or_else(|| missing(name))
+        };
+        let optional = |name: &str| self.field(name).and_then(|f| f.read(record)).unwrap_or(0);
+
+        let timestamp = self
+            .fields
+            .get("Timestamp")
+            .and_then(|f| f.read(record))
+            .map(f64::from_bits)
+            .ok_or_else(|| MdfError::InvalidFormat(String::from("missing Timestamp channel")))?;
+
+        let data_length = optional("DataLength") as usize;
+        let data = match kind {
+            BusEventKind::RemoteFrame => Vec::new(),
+            _ => self
+                .field("DataBytes")
+                .and_then(|f| f.bytes(record))
+                .map(|bytes| bytes[..data_length.min(bytes.len())].to_vec())
+                .unwrap_or_default(),
+        };
+
+        let flags_ide = match self.field("IDE") {
+            Some(field) => field.read(record).unwrap_or(0) != 0,
+            None => false,
+        };
+
+        Ok(Some(BusEvent {
+            timestamp_us: (timestamp * 1_000_000.0).round() as u64,
+            bus_channel: optional("BusChannel") as u8,
+            kind,
+            can_id: value("ID")? as u32,
+            extended: flags_ide,
+            dlc: optional("DLC") as u8,
+            data,
+            tx: optional("Dir") != 0,
+            edl: optional("EDL") != 0,
+            brs: optional("BRS") != 0,
+            esi: optional("ESI") != 0,
+            error_type: optional("ErrorType") as u8,
+        }))
+    }
+}
+
+/// Reads ASAM bus-logging events back from an MDF4 file.
+pub struct Mdf4Reader<R: Read + Seek> {
+    inner: R,
+    file_size: u64,
+    /// `id_unfin_flags` of an unfinished file, zero for finalized ones.
+    unfinished: u16,
+}
+
+impl Mdf4Reader<BufReader<File>> {
+    /// Opens the MDF4 file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, MdfError> {
+        let file = File::open(path)?;
+        Mdf4Reader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> Mdf4Reader<R> {
+    /// Checks the identification block of `inner`.
+    pub fn new(mut inner: R) -> Result<Self, MdfError> {
+        let mut id = [0u8; ID_BLOCK_SIZE as usize];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut id)?;
+
+        if &id[0..8] != b"MDF     " && &id[0..8] != b"UnFinMF " {
+            return Err(MdfError::InvalidFormat(String::from(
+                "missing MDF identification",
+            )));
+        }
+        let version = u16::from_le_bytes([id[28], id[29]]);
+        if version < 400 {
+            return Err(MdfError::InvalidFormat(format!(
+                "unsupported MDF version {version}"
+            )));
+        }
+        let unfinished = match &id[0..8] {
+            b"UnFinMF " => u16::from_le_bytes([id[60], id[61]]),
+            _ => 0,
+        };
+        let file_size = inner.seek(SeekFrom::End(0))?;
+
+        Ok(Mdf4Reader {
+            inner,
+            file_size,
+            unfinished,
+        })
+    }
+
+    fn block(&mut self, address: u64) -> Result<Block, MdfError> {
+        self.inner.seek(SeekFrom::Start(address))?;
+        let mut header = [0u8; BLOCK_HEADER_SIZE as usize];
+        self.inner.read_exact(&mut header)?;
+
+        if &header[0..2] != b"##" {
+            return Err(MdfError::InvalidFormat(format!("no block at {address:#x}")));
+        }
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&header[0..4]);
+        let length = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let link_count = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        // Both come from the file, the buffers below must not be sized beyond it
+        let links_end = link_count
+            .checked_mul(8)
+            .and_then(|links| links.checked_add(BLOCK_HEADER_SIZE));
+        if links_end.is_none_or(|links_end| length < links_end)
+            || address
+                .checked_add(length)
+                .is_none_or(|end| end > self.file_size)
+        {
+            return Err(MdfError::InvalidFormat(format!(
+                "corrupt block at {address:#x}"
+            )));
+        }
+
+        let mut links = vec![0u64; link_count as usize];
+        for link in links.iter_mut() {
+            let mut raw = [0u8; 8];
+            self.inner.read_exact(&mut raw)?;
+            *link = u64::from_le_bytes(raw);
+        }
+
+        let mut data = vec![0u8; (length - BLOCK_HEADER_SIZE - 8 * link_count) as usize];
+        self.inner.read_exact(&mut data)?;
+
+        Ok(Block { id, links, data })
+    }
+
+    fn text(&mut self, address: u64) -> Result<String, MdfError> {
+        if address == 0 {
+            return Ok(String::new());
+        }
+        let block = self.block(address)?;
+        let end = block
+            .data
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(block.data.len());
+        Ok(String::from_utf8_lossy(&block.data[..end]).into_owned())
+    }
+
+    /// Reads the data of a `##DT` block, up to the end of the file for the last block of an
+    /// unfinished file whose length was never written.
+    fn data_block(&mut self, address: u64, last: bool) -> Result<Vec<u8>, MdfError> {
+        let dt = self.block(address)?;
+        if &dt.id != b"##DT" {
+            return Err(MdfError::InvalidFormat(String::from(
+                "only uncompressed ##DT data blocks are supported",
+            )));
+        }
+        if !last || self.unfinished & UNFIN_LAST_DT_LENGTH == 0 {
+            return Ok(dt.data);
+        }
+
+        let mut data = Vec::new();
+        self.inner
+            .seek(SeekFrom::Start(address + BLOCK_HEADER_SIZE))?;
+        self.inner.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn collect_fields(
+        &mut self,
+        mut address: u64,
+        fields: &mut HashMap<String, Field>,
+        visited: &mut HashSet<u64>,
+    ) -> Result<(), MdfError> {
+        while address != 0 {
+            visit(visited, address)?;
+            let block = self.block(address)?;
+            if &block.id != b"##CN" || block.links.len() < 8 || block.data.len() < 16 {
+                return Err(MdfError::InvalidFormat(format!(
+                    "expected channel at {address:#x}"
+                )));
+            }
+
+            let name = self.text(block.links[2])?;
+            let field = Field {
+                bit_offset: block.data[3],
+                byte_offset: u32::from_le_bytes(block.data[4..8].try_into().unwrap()) as usize,
+                bit_count: u32::from_le_bytes(block.data[8..12].try_into().unwrap()),
+            };
+            fields.insert(name, field);
+
+            let composition = block.links[1];
+            if composition != 0 && &self.block(composition)?.id == b"##CN" {
+                self.collect_fields(composition, fields, visited)?;
+            }
+
+            address = block.links[0];
+        }
+        Ok(())
+    }
+
+    /// Reads every bus event of the file in storage order.
+    ///
+    /// Records of channel groups that are not ASAM CAN bus events are skipped.
+    pub fn events(&mut self) -> Result<Vec<BusEvent>, MdfError> {
+        let hd = self.block(ID_BLOCK_SIZE)?;
+        if &hd.id != b"##HD" || hd.links.is_empty() {
+            return Err(MdfError::InvalidFormat(String::from(
+                "missing header block",
+            )));
+        }
+
+        let mut events = Vec::new();
+        let mut visited = HashSet::new();
+        let mut dg_address = hd.links[0];
+        while dg_address != 0 {
+            visit(&mut visited, dg_address)?;
+            let dg = self.block(dg_address)?;
+            if &dg.id != b"##DG" || dg.links.len() < 3 || dg.data.is_empty() {
+                return Err(MdfError::InvalidFormat(format!(
+                    "expected data group at {dg_address:#x}"
+                )));
+            }
+            let record_id_size = dg.data[0] as usize;
+
+            let mut groups: HashMap<u64, Group> = HashMap::new();
+            let mut cg_address = dg.links[1];
+            while cg_address != 0 {
+                visit(&mut visited, cg_address)?;
+                let cg = self.block(cg_address)?;
+                if &cg.id != b"##CG" || cg.links.len() < 4 || cg.data.len() < 32 {
+                    return Err(MdfError::InvalidFormat(format!(
+                        "expected channel group at {cg_address:#x}"
+                    )));
+                }
+                let record_id = u64::from_le_bytes(cg.data[0..8].try_into().unwrap());
+                let data_bytes = u32::from_le_bytes(cg.data[24..28].try_into().unwrap());
+                let inval_bytes = u32::from_le_bytes(cg.data[28..32].try_into().unwrap());
+
+                let mut fields = HashMap::new();
+                self.collect_fields(cg.links[1], &mut fields, &mut visited)?;
+
+                let name = self.text(cg.links[2])?;
+                let kind = BusEventKind::from_group_name(&name).or_else(|| {
+                    [
+                        BusEventKind::DataFrame,
+                        BusEventKind::RemoteFrame,
+                        BusEventKind::ErrorFrame,
+                    ]
+                    .into_iter()
+                    .find(|kind| fields.contains_key(kind.group_name()))
+                });
+
+                let record_bytes = data_bytes.checked_add(inval_bytes).ok_or_else(|| {
+                    MdfError::InvalidFormat(format!("invalid record size at {cg_address:#x}"))
+                })?;
+                groups.insert(
+                    record_id,
+                    Group {
+                        kind,
+                        data_bytes: record_bytes as usize,
+                        fields,
+                    },
+                );
+                cg_address = cg.links[0];
+            }
+
+            if dg.links[2] != 0 {
+                let last = dg.links[0] == 0;
+                let data = self.data_block(dg.links[2], last)?;
+                // A logger that stopped mid-write may leave a partial record behind
+                let partial = last && self.unfinished != 0;
+                self.decode_records(&data, record_id_size, &groups, partial, &mut events)?;
+            }
+
+            dg_address = dg.links[0];
+        }
+
+        Ok(events)
+    }
+
+    fn decode_records(
+        &self,
+        data: &[u8],
+        record_id_size: usize,
+        groups: &HashMap<u64, Group>,
+        partial: bool,
+        events: &mut Vec<BusEvent>,
+    ) -> Result<(), MdfError> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let record_id = match record_id_size {
+                0 => *groups.keys().next().unwrap_or(&0),
+                1 | 2 | 4 | 8 => {
+                    let mut raw = [0u8; 8];
+                    let Some(bytes) = data.get(offset..offset + record_id_size) else {
+                        if partial {
+                            break;
+                        }
+                        return Err(MdfError::InvalidFormat(String::from("truncated record id")));
+                    };
+                    raw[..record_id_size].copy_from_slice(bytes);
+                    offset += record_id_size;
+                    u64::from_le_bytes(raw)
+                }
+                _ => {
+                    return Err(MdfError::InvalidFormat(format!(
+                        "invalid record id size {record_id_size}"
+                    )));
+                }
+            };
+
+            let group = groups
+                .get(&record_id)
+                .ok_or_else(|| MdfError::InvalidFormat(format!("unknown record id {record_id}")))?;
+            // Without record ids an empty record would never advance through the data
+            if record_id_size == 0 && group.data_bytes == 0 {
+                return Err(MdfError::InvalidFormat(String::from("empty records")));
+            }
+            let Some(record) = data.get(offset..offset + group.data_bytes) else {
+                if partial {
+                    break;
+                }
+                return Err(MdfError::InvalidFormat(String::from("truncated record")));
+            };
+            offset += group.data_bytes;
+
+            if let Some(event) = group.decode(record)? {
+                events.push(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample_events() -> Vec<BusEvent> {
+        let classic = CanFrame::new(0x123, MessageType::Standard, &[1, 2, 3, 4]).unwrap();
+        let remote = CanFrame::new_remote(0x1AB_CDEF, MessageType::Extended, 6).unwrap();
+        let fd = CanFdFrame::new(
+            0x7FF,
+            MessageType::Standard,
+            &(0..20u8).collect::<Vec<_>>(),
+            true,
+            true,
+        )
+        .unwrap();
+
+        let mut error = BusEvent::from_frame(2, &classic, &Timestamp::from_micros(3_000_000));
+        error.kind = BusEventKind::ErrorFrame;
+        error.error_type = error_type::FORM_ERROR;
+
+        vec![
+            BusEvent::from_frame(1, &classic, &Timestamp::from_micros(1_000_250)),
+            BusEvent::from_frame(1, &remote, &Timestamp::from_micros(1_500_000)),
+            BusEvent::from_fd_frame(2, &fd, 2_000_001),
+            error,
+        ]
+    }
+
+    #[test]
+    fn mdf4_round_trip() {
+        let events = sample_events();
+
+        let mut writer = Mdf4Writer::new(Cursor::new(Vec::new())).unwrap();
+        for event in &events {
+            writer.write_event(event).unwrap();
+        }
+        assert_eq!(writer.event_count(), 4);
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(&bytes[0..8], b"MDF     ");
+        assert_eq!(&bytes[8..16], b"4.10    ");
+
+        let mut reader = Mdf4Reader::new(Cursor::new(bytes)).unwrap();
+        let read = reader.events().unwrap();
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn mdf4_round_trip_frames() {
+        let events = sample_events();
+
+        let mut writer = Mdf4Writer::new(Cursor::new(Vec::new())).unwrap();
+        for event in &events {
+            writer.write_event(event).unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+        let read = Mdf4Reader::new(Cursor::new(bytes))
+            .unwrap()
+            .events()
+            .unwrap();
+
+        let classic = read[0].to_frame().unwrap();
+        assert_eq!(classic.can_id(), 0x123);
+        assert_eq!(classic.data(), &[1, 2, 3, 4]);
+
+        let remote = read[1].to_frame().unwrap();
+        assert!(remote.is_remote_frame());
+        assert!(remote.is_extended_frame());
+        assert_eq!(remote.dlc(), 6);
+
+        let fd = read[2].to_fd_frame().unwrap();
+        assert!(fd.is_fd_frame());
+        assert!(fd.is_brs_frame());
+        assert_eq!(fd.len(), 20);
+    }
+
+    #[test]
+    fn mdf4_unfinished_file_is_readable() {
+        let events = sample_events();
+
+        let mut writer = Mdf4Writer::new(Cursor::new(Vec::new())).unwrap();
+        for event in &events[..3] {
+            writer.write_event(event).unwrap();
+        }
+        let mut bytes = writer.inner.get_ref().clone();
+        // The logger died while writing the fourth record
+        bytes.extend_from_slice(&[RECORD_ID_ERROR_FRAME, 0, 0, 0]);
+
+        assert_eq!(&bytes[0..8], b"UnFinMF ");
+        let mut reader = Mdf4Reader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.events().unwrap(), events[..3]);
+    }
+
+    fn finished_file() -> Vec<u8> {
+        let mut writer = Mdf4Writer::new(Cursor::new(Vec::new())).unwrap();
+        for event in &sample_events() {
+            writer.write_event(event).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn link(bytes: &[u8], block: u64, index: usize) -> u64 {
+        let at = (block + BLOCK_HEADER_SIZE) as usize + 8 * index;
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    fn set_field(bytes: &mut [u8], at: u64, value: u64) {
+        bytes[at as usize..at as usize + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn mdf4_rejects_corrupt_blocks() {
+        let events = |bytes: Vec<u8>| Mdf4Reader::new(Cursor::new(bytes)).unwrap().events();
+
+        // Link count and length of the header block beyond any file
+        let mut bytes = finished_file();
+        set_field(&mut bytes, ID_BLOCK_SIZE + 16, u64::MAX);
+        assert!(events(bytes).is_err());
+        let mut bytes = finished_file();
+        set_field(&mut bytes, ID_BLOCK_SIZE + 8, u64::MAX - 8);
+        assert!(events(bytes).is_err());
+
+        // A channel and a data group linking back to themselves
+        let mut bytes = finished_file();
+        let dg = link(&bytes, ID_BLOCK_SIZE, 0);
+        let cn = link(&bytes, link(&bytes, dg, 1), 1);
+        set_field(&mut bytes, cn + BLOCK_HEADER_SIZE, cn);
+        assert!(events(bytes).is_err());
+        let mut bytes = finished_file();
+        set_field(&mut bytes, dg + BLOCK_HEADER_SIZE, dg);
+        assert!(events(bytes).is_err());
+    }
+
+    /// Sets the record id size of the first data group and the record and invalidation
+    /// bytes of its channel groups.
+    fn set_record_layout(bytes: &mut [u8], record_id_size: u8, data_bytes: u32, inval_bytes: u32) {
+        let data = |bytes: &[u8], block: u64| {
+            let link_count =
+                u64::from_le_bytes(bytes[block as usize + 16..][..8].try_into().unwrap());
+            (block + BLOCK_HEADER_SIZE + 8 * link_count) as usize
+        };
+        let dg = link(bytes, ID_BLOCK_SIZE, 0);
+        bytes[data(bytes, dg)] = record_id_size;
+        let mut cg = link(bytes, dg, 1);
+        while cg != 0 {
+            let at = data(bytes, cg);
+            bytes[at + 24..at + 28].copy_from_slice(&data_bytes.to_le_bytes());
+            bytes[at + 28..at + 32].copy_from_slice(&inval_bytes.to_le_bytes());
+            cg = link(bytes, cg, 0);
+        }
+    }
+
+    #[test]
+    fn mdf4_rejects_invalid_records() {
+        let events = |bytes: Vec<u8>| Mdf4Reader::new(Cursor::new(bytes)).unwrap().events();
+
+        // Empty records without record ids
+        let mut bytes = finished_file();
+        set_record_layout(&mut bytes, 0, 0, 0);
+        let Err(MdfError::InvalidFormat(message)) = events(bytes) else {
+            panic!("accepted empty records");
+        };
+        assert_eq!(message, "empty records");
+
+        // Record and invalidation bytes beyond u32
+        let mut bytes = finished_file();
+        set_record_layout(&mut bytes, 1, u32::MAX, 1);
+        let Err(MdfError::InvalidFormat(message)) = events(bytes) else {
+            panic!("accepted an overflowing record size");
+        };
+        assert!(message.starts_with("invalid record size"));
+    }
+
+    #[test]
+    fn mdf4_rejects_other_files() {
+        let bytes = vec![0u8; 128];
+        assert!(Mdf4Reader::new(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn mdf4_blocks_are_aligned() {
+        let mut layout = Layout::new();
+        let a = layout.push_text(b"##TX", "abc");
+        let b = layout.push_text(b"##TX", "CAN_DataFrame.DataLength");
+        assert_eq!(a % 8, 0);
+        assert_eq!(b % 8, 0);
+        assert_eq!(layout.buf.len() % 8, 0);
+    }
+}
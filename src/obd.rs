@@ -0,0 +1,619 @@
+//! OBD-II (SAE J1979 / ISO 15031-5) scan tool.
+//!
+//! An [ObdScanner] sends diagnostic requests with ISO-TP framing either functionally to all
+//! emission related ECUs (0x7DF / 0x18DB33F1) or physically to one ECU, and collects the
+//! answers of every responding ECU until the response window closes. Multi-frame answers
+//! (for instance the VIN) of several ECUs are reassembled in parallel, each ECU receiving
+//! its own flow control frame.
+//!
+//! Services 01 (current data), 02 (freeze frame), 03 (stored DTCs), 04 (clear DTCs) and 09
+//! (vehicle information) are wrapped; [decode_pid] converts the data bytes of common PIDs
+//! to physical values with units.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::obd::{ObdAddressing, ObdScanner};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let scanner = ObdScanner::new(&socket, ObdAddressing::Standard);
+//!
+//! for (ecu, values) in scanner.read_pid(0x0C)? {
+//!     for value in values {
+//!         println!("ECU {ecu:X}: {} = {} {}", value.name, value.value, value.unit);
+//!     }
+//! }
+//! for (ecu, vin) in scanner.read_vin()? {
+//!     println!("ECU {ecu:X}: VIN {vin}");
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::socket::{self, CanFrame, FrameConstructionError, MessageType, RecvCan, SendCan};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const FUNCTIONAL_ID_11BIT: u32 = 0x7DF;
+pub const FUNCTIONAL_ID_29BIT: u32 = 0x18DB_33F1;
+
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+const RESPONSE_PENDING: u8 = 0x78;
+const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+
+/// Time an ECU may take to answer a request (P2 max of ISO 15765-4).
+const P2_MAX: Duration = Duration::from_millis(50);
+/// Time an ECU may take after a response pending answer (P2* max).
+const P2_STAR_MAX: Duration = Duration::from_millis(5000);
+/// Time allowed between consecutive frames (N_Cr).
+const N_CR: Duration = Duration::from_millis(150);
+
+#[derive(Debug)]
+pub enum ObdError {
+    Can(CanError),
+    Frame(FrameConstructionError),
+    /// No ECU answered the request.
+    NoResponse,
+    /// An ECU answered with a malformed message.
+    InvalidResponse {
+        ecu: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl fmt::Display for ObdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObdError::Can(e) => write!(f, "{e}"),
+            ObdError::Frame(e) => write!(f, "invalid frame: {e:?}"),
+            ObdError::NoResponse => write!(f, "no ECU responded"),
+            ObdError::InvalidResponse { ecu, data } => {
+                write!(f, "invalid response from ECU 0x{ecu:X}: {data:02X?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObdError {}
+
+impl From<CanError> for ObdError {
+    fn from(value: CanError) -> Self {
+        ObdError::Can(value)
+    }
+}
+
+impl From<FrameConstructionError> for ObdError {
+    fn from(value: FrameConstructionError) -> Self {
+        ObdError::Frame(value)
+    }
+}
+
+/// Identifier scheme of ISO 15765-4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObdAddressing {
+    /// Requests on 0x7DF / 0x7E0-0x7E7, responses on 0x7E8-0x7EF.
+    Standard,
+    /// Requests on 0x18DB33F1 / 0x18DAxxF1, responses on 0x18DAF1xx.
+    Extended,
+}
+
+impl ObdAddressing {
+    fn msg_type(&self) -> MessageType {
+        match self {
+            ObdAddressing::Standard => MessageType::Standard,
+            ObdAddressing::Extended => MessageType::Extended,
+        }
+    }
+
+    fn functional_id(&self) -> u32 {
+        match self {
+            ObdAddressing::Standard => FUNCTIONAL_ID_11BIT,
+            ObdAddressing::Extended => FUNCTIONAL_ID_29BIT,
+        }
+    }
+
+    /// Whether `can_id` is a response identifier of this scheme.
+    fn is_response(&self, can_id: u32, extended: bool) -> bool {
+        match self {
+            ObdAddressing::Standard => !extended && (0x7E8..=0x7EF).contains(&can_id),
+            ObdAddressing::Extended => extended && can_id & 0x1FFF_FF00 == 0x18DA_F100,
+        }
+    }
+
+    /// Physical request identifier of the ECU answering on `response_id`.
+    pub fn request_id(&self, response_id: u32) -> u32 {
+        match self {
+            ObdAddressing::Standard => response_id - 8,
+            ObdAddressing::Extended => 0x18DA_00F1 | (response_id & 0xFF) << 8,
+        }
+    }
+}
+
+/// Destination of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// All emission related ECUs.
+    Functional,
+    /// The ECU answering on this response identifier.
+    Physical(u32),
+}
+
+/// A physical value decoded from PID data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidValue {
+    pub name: &'static str,
+    pub value: f64,
+    pub unit: &'static str,
+}
+
+/// An emission related diagnostic trouble code, for instance P0301.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ObdDtc(pub u16);
+
+impl fmt::Display for ObdDtc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let system = ['P', 'C', 'B', 'U'][(self.0 >> 14) as usize];
+        write!(
+            f,
+            "{system}{:01X}{:03X}",
+            (self.0 >> 12) & 0x03,
+            self.0 & 0x0FFF
+        )
+    }
+}
+
+/// Decodes the data bytes of a service 01 / 02 PID.
+///
+/// Returns `None` for PIDs without a known formula or with too few data bytes.
+pub fn decode_pid(pid: u8, data: &[u8]) -> Option<Vec<PidValue>> {
+    let a = *data.first()? as f64;
+    let ab = || data.get(1).map(|b| a * 256.0 + *b as f64);
+    let value = |name, value, unit| Some(vec![PidValue { name, value, unit }]);
+
+    match pid {
+        0x01 => Some(vec![
+            PidValue {
+                name: "MIL",
+                value: (data[0] >> 7) as f64,
+                unit: "",
+            },
+            PidValue {
+                name: "DTC count",
+                value: (data[0] & 0x7F) as f64,
+                unit: "",
+            },
+        ]),
+        0x04 => value("Calculated engine load", a * 100.0 / 255.0, "%"),
+        0x05 => value("Engine coolant temperature", a - 40.0, "°C"),
+        0x06 => value(
+            "Short term fuel trim bank 1",
+            (a - 128.0) * 100.0 / 128.0,
+            "%",
+        ),
+        0x07 => value(
+            "Long term fuel trim bank 1",
+            (a - 128.0) * 100.0 / 128.0,
+            "%",
+        ),
+        0x08 => value(
+            "Short term fuel trim bank 2",
+            (a - 128.0) * 100.0 / 128.0,
+            "%",
+        ),
+        0x09 => value(
+            "Long term fuel trim bank 2",
+            (a - 128.0) * 100.0 / 128.0,
+            "%",
+        ),
+        0x0A => value("Fuel pressure", a * 3.0, "kPa"),
+        0x0B => value("Intake manifold absolute pressure", a, "kPa"),
+        0x0C => value("Engine speed", ab()? / 4.0, "rpm"),
+        0x0D => value("Vehicle speed", a, "km/h"),
+        0x0E => value("Timing advance", a / 2.0 - 64.0, "°"),
+        0x0F => value("Intake air temperature", a - 40.0, "°C"),
+        0x10 => value("Mass air flow rate", ab()? / 100.0, "g/s"),
+        0x11 => value("Throttle position", a * 100.0 / 255.0, "%"),
+        0x1F => value("Run time since engine start", ab()?, "s"),
+        0x21 => value("Distance traveled with MIL on", ab()?, "km"),
+        0x2F => value("Fuel tank level input", a * 100.0 / 255.0, "%"),
+        0x31 => value("Distance traveled since codes cleared", ab()?, "km"),
+        0x33 => value("Absolute barometric pressure", a, "kPa"),
+        0x42 => value("Control module voltage", ab()? / 1000.0, "V"),
+        0x46 => value("Ambient air temperature", a - 40.0, "°C"),
+        0x5C => value("Engine oil temperature", a - 40.0, "°C"),
+        0x5E => value("Engine fuel rate", ab()? / 20.0, "L/h"),
+        0xA6 if data.len() >= 4 => value(
+            "Odometer",
+            u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64 / 10.0,
+            "km",
+        ),
+        _ => None,
+    }
+}
+
+/// Decodes a "PIDs supported" bitmap answering PID `base` (0x00, 0x20, ...).
+pub fn decode_supported_pids(base: u8, data: &[u8]) -> Vec<u8> {
+    let bits = data
+        .iter()
+        .take(4)
+        .fold(0u32, |acc, b| acc << 8 | *b as u32);
+    (0..32)
+        .filter(|i| bits & (0x8000_0000 >> i) != 0)
+        .map(|i| base.wrapping_add(i as u8 + 1))
+        .collect()
+}
+
+/// Reassembly state of a multi-frame response.
+struct Session {
+    len: usize,
+    data: Vec<u8>,
+    sequence: u8,
+}
+
+pub struct ObdScanner<'a> {
+    tx: &'a dyn SendCan,
+    rx: &'a dyn RecvCan,
+    addressing: ObdAddressing,
+    /// Response window after the request, P2 max by default.
+    timeout: Duration,
+    padding: u8,
+}
+
+impl<'a> ObdScanner<'a> {
+    pub fn new<S: SendCan + RecvCan>(socket: &'a S, addressing: ObdAddressing) -> ObdScanner<'a> {
+        ObdScanner {
+            tx: socket,
+            rx: socket,
+            addressing,
+            timeout: P2_MAX,
+            padding: 0x00,
+        }
+    }
+
+    /// Sets the time to wait for answers after a request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the fill byte of transmitted frames, ISO 15765-4 requires 8 byte frames.
+    pub fn padding(mut self, padding: u8) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    fn send_frame(&self, can_id: u32, pdu: &[u8]) -> Result<(), ObdError> {
+        let mut data = pdu.to_vec();
+        data.resize(8, self.padding);
+        self.tx
+            .send(CanFrame::new(can_id, self.addressing.msg_type(), &data)?)?;
+        Ok(())
+    }
+
+    /// Sends a request and collects the responses of all ECUs, keyed by response id.
+    ///
+    /// Negative responses other than "response pending" are returned as well.
+    pub fn request(
+        &self,
+        target: Target,
+        request: &[u8],
+    ) -> Result<BTreeMap<u32, Vec<u8>>, ObdError> {
+        let can_id = match target {
+            Target::Functional => self.addressing.functional_id(),
+            Target::Physical(response_id) => self.addressing.request_id(response_id),
+        };
+        let mut pdu = vec![request.len() as u8];
+        pdu.extend_from_slice(request);
+        self.send_frame(can_id, &pdu)?;
+
+        let mut responses = BTreeMap::new();
+        let mut sessions: BTreeMap<u32, Session> = BTreeMap::new();
+        // ECUs in the middle of a transfer or that asked for more time extend the window
+        let mut waiting: BTreeMap<u32, Instant> = BTreeMap::new();
+        let window = Instant::now() + self.timeout;
+
+        loop {
+            let deadline = waiting.values().fold(window, |a, b| a.max(*b));
+            let Some(frame) = socket::poll_until(deadline, || self.rx.recv_frame())? else {
+                break;
+            };
+            let ecu = frame.can_id();
+            let data = frame.data();
+            if !self.addressing.is_response(ecu, frame.is_extended_frame())
+                || data.is_empty()
+                || matches!(target, Target::Physical(id) if id != ecu)
+            {
+                continue;
+            }
+
+            let complete = match data[0] >> 4 {
+                // Single frame
+                0 => {
+                    let len = (data[0] & 0x0F) as usize;
+                    (len > 0 && len < data.len()).then(|| data[1..1 + len].to_vec())
+                }
+                // First frame
+                1 if data.len() == 8 => {
+                    let len = ((data[0] & 0x0F) as usize) << 8 | data[1] as usize;
+                    sessions.insert(
+                        ecu,
+                        Session {
+                            len,
+                            data: data[2..].to_vec(),
+                            sequence: 1,
+                        },
+                    );
+                    self.send_frame(self.addressing.request_id(ecu), &[0x30, 0x00, 0x00])?;
+                    waiting.insert(ecu, Instant::now() + N_CR);
+                    None
+                }
+                // Consecutive frame
+                2 => match sessions.get_mut(&ecu) {
+                    Some(session) if data[0] & 0x0F == session.sequence => {
+                        let take = (session.len - session.data.len()).min(data.len() - 1);
+                        session.data.extend_from_slice(&data[1..1 + take]);
+                        session.sequence = (session.sequence + 1) & 0x0F;
+                        if session.data.len() == session.len {
+                            sessions.remove(&ecu).map(|s| s.data)
+                        } else {
+                            waiting.insert(ecu, Instant::now() + N_CR);
+                            None
+                        }
+                    }
+                    // Out of sequence, drop the transfer
+                    Some(_) => {
+                        sessions.remove(&ecu);
+                        waiting.remove(&ecu);
+                        None
+                    }
+                    None => None,
+                },
+                _ => None,
+            };
+
+            if let Some(response) = complete {
+                if matches!(response[..], [NEGATIVE_RESPONSE, _, RESPONSE_PENDING]) {
+                    waiting.insert(ecu, Instant::now() + P2_STAR_MAX);
+                    continue;
+                }
+                waiting.remove(&ecu);
+                responses.insert(ecu, response);
+                if let Target::Physical(_) = target {
+                    break;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+
+    /// Sends a request and keeps the positive responses, stripped of the service id.
+    fn positive(&self, target: Target, request: &[u8]) -> Result<BTreeMap<u32, Vec<u8>>, ObdError> {
+        let expected = request[0] + POSITIVE_RESPONSE_OFFSET;
+        let responses: BTreeMap<u32, Vec<u8>> = self
+            .request(target, request)?
+            .into_iter()
+            .filter(|(_, data)| data.first() == Some(&expected))
+            .map(|(ecu, data)| (ecu, data[1..].to_vec()))
+            .collect();
+        if responses.is_empty() {
+            return Err(ObdError::NoResponse);
+        }
+        Ok(responses)
+    }
+
+    /// Service 01: the PIDs each ECU supports.
+    pub fn supported_pids(&self) -> Result<BTreeMap<u32, Vec<u8>>, ObdError> {
+        let mut supported: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        let mut base = 0x00u8;
+        loop {
+            let responses = match self.positive(Target::Functional, &[0x01, base]) {
+                Ok(responses) => responses,
+                Err(ObdError::NoResponse) if base != 0 => break,
+                Err(err) => return Err(err),
+            };
+            let mut more = false;
+            for (ecu, data) in responses {
+                if data.first() != Some(&base) {
+                    continue;
+                }
+                let pids = decode_supported_pids(base, &data[1..]);
+                more |= pids.contains(&(base.wrapping_add(0x20)));
+                supported.entry(ecu).or_default().extend(pids);
+            }
+            if !more || base == 0xE0 {
+                break;
+            }
+            base += 0x20;
+        }
+        Ok(supported)
+    }
+
+    /// Service 01: reads the raw data bytes of a PID from every ECU supporting it.
+    pub fn read_pid_raw(&self, pid: u8) -> Result<BTreeMap<u32, Vec<u8>>, ObdError> {
+        let responses = self.positive(Target::Functional, &[0x01, pid])?;
+        Ok(responses
+            .into_iter()
+            .filter(|(_, data)| data.first() == Some(&pid))
+            .map(|(ecu, data)| (ecu, data[1..].to_vec()))
+            .collect())
+    }
+
+    /// Service 01: reads and decodes a PID from every ECU supporting it.
+    pub fn read_pid(&self, pid: u8) -> Result<BTreeMap<u32, Vec<PidValue>>, ObdError> {
+        self.read_pid_raw(pid)?
+            .into_iter()
+            .map(|(ecu, data)| match decode_pid(pid, &data) {
+                Some(values) => Ok((ecu, values)),
+                None => Err(ObdError::InvalidResponse { ecu, data }),
+            })
+            .collect()
+    }
+
+    /// Service 02: reads a PID of freeze frame `frame`.
+    pub fn read_freeze_frame(
+        &self,
+        pid: u8,
+        frame: u8,
+    ) -> Result<BTreeMap<u32, Vec<u8>>, ObdError> {
+        let responses = self.positive(Target::Functional, &[0x02, pid, frame])?;
+        Ok(responses
+            .into_iter()
+            .filter(|(_, data)| data.len() >= 2 && data[0] == pid && data[1] == frame)
+            .map(|(ecu, data)| (ecu, data[2..].to_vec()))
+            .collect())
+    }
+
+    /// Service 03: stored emission related DTCs of every ECU.
+    pub fn read_dtcs(&self) -> Result<BTreeMap<u32, Vec<ObdDtc>>, ObdError> {
+        let responses = self.positive(Target::Functional, &[0x03])?;
+        Ok(responses
+            .into_iter()
+            .map(|(ecu, data)| {
+                // On CAN the DTC count precedes the DTCs
+                let dtcs = data
+                    .get(1..)
+                    .unwrap_or_default()
+                    .chunks_exact(2)
+                    .map(|c| ObdDtc(u16::from_be_bytes([c[0], c[1]])))
+                    .filter(|dtc| dtc.0 != 0)
+                    .collect();
+                (ecu, dtcs)
+            })
+            .collect())
+    }
+
+    /// Service 04: clears DTCs and freeze frames, returns the ECUs that confirmed.
+    pub fn clear_dtcs(&self) -> Result<Vec<u32>, ObdError> {
+        Ok(self
+            .positive(Target::Functional, &[0x04])?
+            .into_keys()
+            .collect())
+    }
+
+    /// Service 09: reads vehicle information, without the leading item count.
+    pub fn vehicle_info(&self, info_type: u8) -> Result<BTreeMap<u32, Vec<u8>>, ObdError> {
+        let responses = self.positive(Target::Functional, &[0x09, info_type])?;
+        Ok(responses
+            .into_iter()
+            .filter(|(_, data)| data.len() >= 2 && data[0] == info_type)
+            .map(|(ecu, data)| (ecu, data[2..].to_vec()))
+            .collect())
+    }
+
+    /// Service 09 info type 02: the vehicle identification number.
+    pub fn read_vin(&self) -> Result<BTreeMap<u32, String>, ObdError> {
+        Ok(self
+            .vehicle_info(0x02)?
+            .into_iter()
+            .map(|(ecu, data)| {
+                let vin: String = data
+                    .iter()
+                    .filter(|b| b.is_ascii_graphic())
+                    .map(|b| *b as char)
+                    .collect();
+                (ecu, vin)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::mock_bus;
+
+    fn frame(can_id: u32, data: &[u8]) -> CanFrame {
+        let msg_type = if can_id > socket::STANDARD_MASK {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        };
+        CanFrame::new(can_id, msg_type, data).unwrap()
+    }
+
+    #[test]
+    fn obd_pid_decoding() {
+        let rpm = decode_pid(0x0C, &[0x1A, 0xF8]).unwrap();
+        assert_eq!(rpm[0].value, 1726.0);
+        assert_eq!(rpm[0].unit, "rpm");
+        assert_eq!(decode_pid(0x05, &[0x7B]).unwrap()[0].value, 83.0);
+        assert_eq!(
+            decode_pid(0x01, &[0x83, 0x07, 0xE5, 0x00]).unwrap()[1].value,
+            3.0
+        );
+        assert!(decode_pid(0x0C, &[0x1A]).is_none());
+
+        assert_eq!(
+            decode_supported_pids(0x00, &[0xBE, 0x1F, 0xA8, 0x13])[..4],
+            [0x01, 0x03, 0x04, 0x05]
+        );
+        assert_eq!(ObdDtc(0x0301).to_string(), "P0301");
+        assert_eq!(ObdDtc(0xC123).to_string(), "U0123");
+    }
+
+    #[test]
+    fn obd_multiple_ecus() {
+        let (a, b) = mock_bus();
+        b.send(frame(0x7E8, &[0x04, 0x41, 0x0C, 0x1A, 0xF8, 0, 0, 0]))
+            .unwrap();
+        b.send(frame(0x7E9, &[0x03, 0x7F, 0x01, 0x78, 0, 0, 0, 0]))
+            .unwrap();
+        b.send(frame(0x7E9, &[0x04, 0x41, 0x0C, 0x0F, 0xA0, 0, 0, 0]))
+            .unwrap();
+        b.send(frame(0x7E0, &[0x04, 0x41, 0x0C, 0x00, 0x00, 0, 0, 0]))
+            .unwrap();
+
+        let scanner = ObdScanner::new(&a, ObdAddressing::Standard);
+        let values = scanner.read_pid(0x0C).unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[&0x7E8][0].value, 1726.0);
+        assert_eq!(values[&0x7E9][0].value, 1000.0);
+
+        let request = b.recv_frame().unwrap();
+        assert_eq!(request.can_id(), 0x7DF);
+        assert_eq!(request.data(), &[0x02, 0x01, 0x0C, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn obd_vin_multi_frame() {
+        let (a, b) = mock_bus();
+        let id = 0x18DA_F110;
+        b.send(frame(id, &[0x10, 0x14, 0x49, 0x02, 0x01, b'1', b'G', b'1']))
+            .unwrap();
+        b.send(frame(id, &[0x21, b'J', b'C', b'5', b'4', b'4', b'4', b'R']))
+            .unwrap();
+        b.send(frame(id, &[0x22, b'7', b'2', b'5', b'2', b'3', b'6', b'7']))
+            .unwrap();
+
+        let scanner = ObdScanner::new(&a, ObdAddressing::Extended).padding(0xAA);
+        let vins = scanner.read_vin().unwrap();
+        assert_eq!(vins[&id], "1G1JC5444R7252367");
+
+        assert_eq!(b.recv_frame().unwrap().can_id(), FUNCTIONAL_ID_29BIT);
+        let flow_control = b.recv_frame().unwrap();
+        assert_eq!(flow_control.can_id(), 0x18DA_10F1);
+        assert_eq!(
+            flow_control.data(),
+            &[0x30, 0x00, 0x00, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA]
+        );
+    }
+
+    #[test]
+    fn obd_dtcs_and_no_response() {
+        let (a, b) = mock_bus();
+        b.send(frame(0x7E8, &[0x06, 0x43, 0x02, 0x03, 0x01, 0x01, 0x71, 0]))
+            .unwrap();
+
+        let scanner =
+            ObdScanner::new(&a, ObdAddressing::Standard).timeout(Duration::from_millis(20));
+        let dtcs = scanner.read_dtcs().unwrap();
+        assert_eq!(dtcs[&0x7E8], vec![ObdDtc(0x0301), ObdDtc(0x0171)]);
+
+        assert!(matches!(scanner.clear_dtcs(), Err(ObdError::NoResponse)));
+    }
+}
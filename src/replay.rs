@@ -0,0 +1,571 @@
+//! Trace replay with original timing.
+//!
+//! A [Replayer] sends previously recorded [BusEvent]s (for instance read with
+//! [logfile::read_file](crate::logfile::read_file)) onto one or more sockets while keeping
+//! the relative timing of the recording. Replay speed, looping, identifier remapping,
+//! channel mapping and identifier filtering are configured on the replayer before calling
+//! [run](Replayer::run).
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::logfile;
+//! # use peak_can::replay::{Repeat, ReplayTarget, Replayer};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let events = logfile::read_file("drive.trc")?;
+//!
+//! let report = Replayer::new(&events)
+//!     .speed(2.0)
+//!     .repeat(Repeat::Times(3))
+//!     .map_id(0x100, 0x200)
+//!     .target(1, ReplayTarget::Can(&socket))
+//!     .run()?;
+//! println!("sent {} frames", report.sent);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::mdf::{BusEvent, BusEventKind};
+use crate::socket::{
+    CanFdFrame, CanFrame, FrameConstructionError, MessageType, SendCan, SendCanFd,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Below this remaining delay the replayer yields instead of sleeping, as sleeps
+/// commonly overshoot by about a millisecond.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(2);
+
+/// Errors raised while replaying.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// A socket refused a frame.
+    Can(CanError),
+    /// A recorded event could not be turned into a frame.
+    Frame(FrameConstructionError),
+    /// The replayer was started without any target socket.
+    NoTarget,
+    /// The delay of an event at the configured speed exceeds the range of [Instant].
+    DelayOutOfRange,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Can(e) => write!(f, "{e}"),
+            ReplayError::Frame(e) => write!(f, "invalid frame: {e:?}"),
+            ReplayError::NoTarget => write!(f, "no replay target"),
+            ReplayError::DelayOutOfRange => write!(f, "replay delay out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<CanError> for ReplayError {
+    fn from(value: CanError) -> Self {
+        ReplayError::Can(value)
+    }
+}
+
+impl From<FrameConstructionError> for ReplayError {
+    fn from(value: FrameConstructionError) -> Self {
+        ReplayError::Frame(value)
+    }
+}
+
+/// How many times the recording is played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repeat {
+    Once,
+    Times(u32),
+    Forever,
+}
+
+/// Socket receiving replayed frames.
+pub enum ReplayTarget<'a> {
+    /// Classic CAN socket. CAN FD frames longer than 8 bytes are skipped.
+    Can(&'a dyn SendCan),
+    /// CAN FD socket. Remote frames are skipped, CAN FD sockets cannot send them.
+    Fd(&'a dyn SendCanFd),
+}
+
+/// Outcome of a replay run.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplayReport {
+    /// Frames handed to a socket.
+    pub sent: u64,
+    /// Events dropped by the filters, missing targets or unsupported frame kinds.
+    pub skipped: u64,
+    /// Completed passes over the recording. The run ends after a pass that sent nothing,
+    /// as every further pass would send nothing either.
+    pub loops: u32,
+    /// `true` if the run was interrupted through the stop handle.
+    pub stopped: bool,
+}
+
+/// Replays recorded events onto sockets.
+pub struct Replayer<'a> {
+    events: &'a [BusEvent],
+    speed: f64,
+    repeat: Repeat,
+    id_map: HashMap<u32, u32>,
+    channel_map: HashMap<u8, u8>,
+    include: HashSet<u32>,
+    exclude: HashSet<u32>,
+    skip_tx: bool,
+    targets: HashMap<u8, ReplayTarget<'a>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl<'a> Replayer<'a> {
+    /// Creates a replayer over `events`, which must be sorted by timestamp.
+    pub fn new(events: &'a [BusEvent]) -> Replayer<'a> {
+        Replayer {
+            events,
+            speed: 1.0,
+            repeat: Repeat::Once,
+            id_map: HashMap::new(),
+            channel_map: HashMap::new(),
+            include: HashSet::new(),
+            exclude: HashSet::new(),
+            skip_tx: false,
+            targets: HashMap::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Time scale factor: `2.0` plays twice as fast, `0.5` half as fast. A factor of zero
+    /// (or less) sends the frames back to back without any delay.
+    pub fn speed(mut self, factor: f64) -> Self {
+        self.speed = factor;
+        self
+    }
+
+    /// Number of passes over the recording.
+    pub fn repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    /// Sends frames recorded with identifier `from` with identifier `to` instead.
+    ///
+    /// Filters apply to the recorded identifier.
+    pub fn map_id(mut self, from: u32, to: u32) -> Self {
+        self.id_map.insert(from, to);
+        self
+    }
+
+    /// Routes events recorded on channel `from` to the target registered as `to`.
+    ///
+    /// Channels without a mapping go to the target with the same number.
+    pub fn map_channel(mut self, from: u8, to: u8) -> Self {
+        self.channel_map.insert(from, to);
+        self
+    }
+
+    /// Only replays the given identifiers. May be called several times.
+    pub fn only_ids<I: IntoIterator<Item = u32>>(mut self, ids: I) -> Self {
+        self.include.extend(ids);
+        self
+    }
+
+    /// Never replays the given identifiers. May be called several times.
+    pub fn exclude_ids<I: IntoIterator<Item = u32>>(mut self, ids: I) -> Self {
+        self.exclude.extend(ids);
+        self
+    }
+
+    /// Skips frames that were recorded as transmitted by the logging node.
+    pub fn skip_tx(mut self, skip: bool) -> Self {
+        self.skip_tx = skip;
+        self
+    }
+
+    /// Registers the socket receiving the events of `channel`.
+    pub fn target(mut self, channel: u8, target: ReplayTarget<'a>) -> Self {
+        self.targets.insert(channel, target);
+        self
+    }
+
+    /// Handle that interrupts [run](Replayer::run) when set to `true` from another thread.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    fn passes(&self) -> Option<u32> {
+        match self.repeat {
+            Repeat::Once => Some(1),
+            Repeat::Times(n) => Some(n),
+            Repeat::Forever => None,
+        }
+    }
+
+    fn accepts(&self, event: &BusEvent) -> bool {
+        if event.kind == BusEventKind::ErrorFrame {
+            return false;
+        }
+        if self.skip_tx && event.tx {
+            return false;
+        }
+        if !self.include.is_empty() && !self.include.contains(&event.can_id) {
+            return false;
+        }
+        !self.exclude.contains(&event.can_id)
+    }
+
+    fn target_for(&self, event: &BusEvent) -> Option<&ReplayTarget<'a>> {
+        let channel = self
+            .channel_map
+            .get(&event.bus_channel)
+            .unwrap_or(&event.bus_channel);
+        match self.targets.get(channel) {
+            Some(target) => Some(target),
+            // A single target without explicit mapping takes every channel
+            None if self.targets.len() == 1 && self.channel_map.is_empty() => {
+                self.targets.values().next()
+            }
+            None => None,
+        }
+    }
+
+    fn wait_until(&self, deadline: Instant) -> bool {
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            let remaining = deadline - now;
+            if remaining > SPIN_THRESHOLD {
+                thread::sleep((remaining - SPIN_THRESHOLD).min(Duration::from_millis(50)));
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+
+    /// Sends one event, returns `false` if the target cannot carry it.
+    fn send(&self, target: &ReplayTarget<'a>, event: &BusEvent) -> Result<bool, ReplayError> {
+        let can_id = *self.id_map.get(&event.can_id).unwrap_or(&event.can_id);
+        let msg_type = if event.extended {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        };
+
+        match target {
+            ReplayTarget::Can(socket) => {
+                let frame = match event.kind {
+                    BusEventKind::RemoteFrame => CanFrame::new_remote(can_id, msg_type, event.dlc)?,
+                    _ if event.data.len() > 8 => return Ok(false),
+                    _ => CanFrame::new(can_id, msg_type, &event.data)?,
+                };
+                socket.send(frame)?;
+            }
+            ReplayTarget::Fd(socket) => {
+                if event.kind == BusEventKind::RemoteFrame {
+                    return Ok(false);
+                }
+                let frame = CanFdFrame::new(can_id, msg_type, &event.data, event.edl, event.brs)?;
+                socket.send_fd(frame)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Replays the recording, blocking until all passes are done or the run is stopped.
+    pub fn run(&self) -> Result<ReplayReport, ReplayError> {
+        if self.targets.is_empty() {
+            return Err(ReplayError::NoTarget);
+        }
+
+        let mut report = ReplayReport::default();
+        let Some(first) = self.events.first() else {
+            return Ok(report);
+        };
+        let origin = first.timestamp_us;
+
+        // Each pass starts one average frame gap after the previous pass ended
+        let span = self
+            .events
+            .last()
+            .map(|e| e.timestamp_us.saturating_sub(origin))
+            .unwrap_or(0);
+        let gap = span / self.events.len().max(1) as u64;
+
+        let start = Instant::now();
+        let mut pass_offset = 0u64;
+
+        while self.passes().is_none_or(|passes| report.loops < passes) {
+            let sent = report.sent;
+            for event in self.events {
+                if self.stop.load(Ordering::Relaxed) {
+                    report.stopped = true;
+                    return Ok(report);
+                }
+
+                if !self.accepts(event) {
+                    report.skipped += 1;
+                    continue;
+                }
+                let Some(target) = self.target_for(event) else {
+                    report.skipped += 1;
+                    continue;
+                };
+
+                if self.speed > 0.0 {
+                    let offset = pass_offset + event.timestamp_us.saturating_sub(origin);
+                    // A tiny speed factor stretches late offsets beyond any Instant
+                    let deadline =
+                        Duration::try_from_secs_f64(offset as f64 / 1_000_000.0 / self.speed)
+                            .ok()
+                            .and_then(|delay| start.checked_add(delay))
+                            .ok_or(ReplayError::DelayOutOfRange)?;
+                    if !self.wait_until(deadline) {
+                        report.stopped = true;
+                        return Ok(report);
+                    }
+                }
+
+                if self.send(target, event)? {
+                    report.sent += 1;
+                } else {
+                    report.skipped += 1;
+                }
+            }
+
+            report.loops += 1;
+            if report.sent == sent {
+                break;
+            }
+            pass_offset += span + gap;
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mdf::error_type;
+    use crate::socket::mock::{MockEnd, mock_bus};
+    use crate::socket::{RecvCan, RecvCanFd, Timestamp};
+
+    fn drain(bus: &MockEnd) -> Vec<(CanFrame, Timestamp)> {
+        std::iter::from_fn(|| bus.recv().ok()).collect()
+    }
+
+    fn drain_fd(bus: &MockEnd) -> Vec<CanFdFrame> {
+        std::iter::from_fn(|| bus.recv_fd_frame().ok()).collect()
+    }
+
+    fn event(timestamp_us: u64, bus_channel: u8, can_id: u32, data: &[u8]) -> BusEvent {
+        BusEvent {
+            timestamp_us,
+            bus_channel,
+            kind: BusEventKind::DataFrame,
+            can_id,
+            extended: false,
+            dlc: data.len() as u8,
+            data: data.to_vec(),
+            tx: false,
+            edl: false,
+            brs: false,
+            esi: false,
+            error_type: error_type::UNKNOWN,
+        }
+    }
+
+    #[test]
+    fn replay_preserves_timing() {
+        let events = vec![
+            event(1_000_000, 1, 0x100, &[1]),
+            event(1_020_000, 1, 0x101, &[2]),
+            event(1_040_000, 1, 0x102, &[3]),
+        ];
+        let (socket, bus) = mock_bus();
+
+        let report = Replayer::new(&events)
+            .target(1, ReplayTarget::Can(&socket))
+            .run()
+            .unwrap();
+        assert_eq!(report.sent, 3);
+
+        let frames = drain(&bus);
+        let elapsed = Duration::from_micros(frames[2].1.as_micros() - frames[0].1.as_micros());
+        assert!(elapsed >= Duration::from_millis(39), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(200), "{elapsed:?}");
+    }
+
+    #[test]
+    fn replay_speed_factor() {
+        let events = vec![event(0, 1, 0x100, &[1]), event(100_000, 1, 0x101, &[2])];
+        let (socket, bus) = mock_bus();
+
+        let start = Instant::now();
+        Replayer::new(&events)
+            .speed(10.0)
+            .target(1, ReplayTarget::Can(&socket))
+            .run()
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(9), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(80), "{elapsed:?}");
+        assert_eq!(drain(&bus).len(), 2);
+    }
+
+    #[test]
+    fn replay_filters_and_remaps() {
+        let events = vec![
+            event(0, 1, 0x100, &[1]),
+            event(10, 1, 0x200, &[2]),
+            event(20, 1, 0x300, &[3]),
+            event(30, 1, 0x100, &[4]),
+        ];
+        let (socket, bus) = mock_bus();
+
+        let report = Replayer::new(&events)
+            .speed(0.0)
+            .only_ids([0x100, 0x200])
+            .exclude_ids([0x200])
+            .map_id(0x100, 0x555)
+            .target(1, ReplayTarget::Can(&socket))
+            .run()
+            .unwrap();
+
+        assert_eq!(report.sent, 2);
+        assert_eq!(report.skipped, 2);
+        let frames = drain(&bus);
+        assert!(frames.iter().all(|(f, _)| f.can_id() == 0x555));
+        assert_eq!(frames[1].0.data(), &[4]);
+    }
+
+    #[test]
+    fn replay_unsorted_events() {
+        let events = vec![event(50, 1, 0x100, &[1]), event(0, 1, 0x101, &[2])];
+        let (socket, bus) = mock_bus();
+
+        let report = Replayer::new(&events)
+            .repeat(Repeat::Times(2))
+            .target(1, ReplayTarget::Can(&socket))
+            .run()
+            .unwrap();
+        assert_eq!(report.sent, 4);
+        assert_eq!(drain(&bus).len(), 4);
+    }
+
+    #[test]
+    fn replay_channel_mapping_and_loops() {
+        let events = vec![
+            event(0, 1, 0x100, &[1]),
+            event(10, 2, 0x200, &[2]),
+            event(20, 3, 0x300, &[3]),
+        ];
+        let (first, first_bus) = mock_bus();
+        let (second, second_bus) = mock_bus();
+
+        let report = Replayer::new(&events)
+            .speed(0.0)
+            .repeat(Repeat::Times(2))
+            .map_channel(3, 1)
+            .target(1, ReplayTarget::Can(&first))
+            .target(2, ReplayTarget::Fd(&second))
+            .run()
+            .unwrap();
+
+        assert_eq!(report.loops, 2);
+        assert_eq!(report.sent, 6);
+        assert_eq!(drain(&first_bus).len(), 4);
+        let fd_frames = drain_fd(&second_bus);
+        assert_eq!(fd_frames.len(), 2);
+        assert_eq!(fd_frames[0].can_id(), 0x200);
+    }
+
+    #[test]
+    fn replay_skips_fd_payloads_on_classic_targets() {
+        let mut fd = event(0, 1, 0x100, &[0; 12]);
+        fd.edl = true;
+        let events = vec![fd, event(10, 1, 0x101, &[1])];
+        let (socket, bus) = mock_bus();
+
+        let report = Replayer::new(&events)
+            .speed(0.0)
+            .target(1, ReplayTarget::Can(&socket))
+            .run()
+            .unwrap();
+        assert_eq!(report.sent, 1);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(drain(&bus)[0].0.can_id(), 0x101);
+    }
+
+    #[test]
+    fn replay_stop_handle() {
+        let events = vec![event(0, 1, 0x100, &[1]), event(5_000_000, 1, 0x101, &[2])];
+        let (socket, bus) = mock_bus();
+        let replayer = Replayer::new(&events)
+            .repeat(Repeat::Forever)
+            .target(1, ReplayTarget::Can(&socket));
+
+        let stop = replayer.stop_handle();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let report = replayer.run().unwrap();
+        handle.join().unwrap();
+        assert!(report.stopped);
+        assert_eq!(report.sent, 1);
+        assert_eq!(drain(&bus).len(), 1);
+    }
+
+    #[test]
+    fn replay_ends_passes_without_frames() {
+        let events = vec![event(0, 1, 0x100, &[1]), event(10, 1, 0x101, &[2])];
+        let (socket, bus) = mock_bus();
+
+        let report = Replayer::new(&events)
+            .repeat(Repeat::Forever)
+            .exclude_ids([0x100, 0x101])
+            .target(1, ReplayTarget::Can(&socket))
+            .run()
+            .unwrap();
+        assert_eq!(report.loops, 1);
+        assert_eq!(report.skipped, 2);
+        assert!(drain(&bus).is_empty());
+    }
+
+    #[test]
+    fn replay_rejects_delays_out_of_range() {
+        let events = vec![event(0, 1, 0x100, &[1]), event(10, 1, 0x101, &[2])];
+        let (socket, bus) = mock_bus();
+
+        let result = Replayer::new(&events)
+            .speed(1e-300)
+            .target(1, ReplayTarget::Can(&socket))
+            .run();
+        assert!(matches!(result, Err(ReplayError::DelayOutOfRange)));
+        assert_eq!(drain(&bus).len(), 1);
+    }
+
+    #[test]
+    fn replay_requires_target() {
+        let events = vec![event(0, 1, 0x100, &[1])];
+        assert!(matches!(
+            Replayer::new(&events).run(),
+            Err(ReplayError::NoTarget)
+        ));
+    }
+}
@@ -0,0 +1,329 @@
+//! On-wire bit layout of classic CAN and CAN FD frames (ISO 11898-1:2015).
+//!
+//! [CanFrame::bitstream](crate::socket::CanFrame::bitstream) and
+//! [CanFdFrame::bitstream](crate::socket::CanFdFrame::bitstream) build the bits of a frame
+//! as they appear on the bus: the CRC-15 of classic frames, the CRC-17 / CRC-21 and the
+//! Gray coded stuff count of CAN FD frames, dynamic stuff bits after five equal bits and
+//! the fixed stuff bits of the CAN FD CRC field. The stream ends with the intermission and
+//! assumes an acknowledged frame.
+//!
+//! # Examples
+//!
+//! ```
+//! # use peak_can::socket::{CanFdFrame, CanFrame, MessageType};
+//! let frame = CanFrame::new(0x123, MessageType::Standard, &[0x01, 0x02])?;
+//! let bits = frame.bitstream();
+//! println!("{bits}");
+//! println!("{} bits, {} stuff bits", bits.len(), bits.stuff_bits());
+//! assert_eq!(frame.transmission_time(500_000), bits.duration(500_000, 500_000));
+//!
+//! let fd = CanFdFrame::new(0x123, MessageType::Standard, &[0; 64], true, true)?;
+//! println!("{:?}", fd.transmission_time(500_000, 2_000_000));
+//! # Ok::<(), peak_can::socket::FrameConstructionError>(())
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+/// CRC delimiter, ACK slot, ACK delimiter, end of frame and intermission, never stuffed.
+pub const TAIL_BITS: usize = 13;
+
+const CRC15_POLY: u32 = 0x4599;
+const CRC17_POLY: u32 = 0x1_685B;
+const CRC21_POLY: u32 = 0x10_2899;
+
+/// Bits of a frame as transmitted, from start of frame to the end of the intermission.
+///
+/// `true` is a recessive bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBits {
+    pub bits: Vec<bool>,
+    /// Indices of the dynamic and fixed stuff bits in `bits`.
+    pub stuff_positions: Vec<usize>,
+    /// Bits sent at the data bit rate, from after the BRS bit to the CRC delimiter.
+    pub data_bits: usize,
+    /// CRC-15, CRC-17 or CRC-21 of the frame.
+    pub crc: u32,
+}
+
+impl FrameBits {
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    pub fn stuff_bits(&self) -> usize {
+        self.stuff_positions.len()
+    }
+
+    /// Bits sent at the nominal bit rate.
+    pub fn nominal_bits(&self) -> usize {
+        self.bits.len() - self.data_bits
+    }
+
+    /// Time the frame occupies the bus, `data_bps` applies to the data phase of frames
+    /// with bit rate switch.
+    pub fn duration(&self, nominal_bps: u32, data_bps: u32) -> Duration {
+        let nanos = self.nominal_bits() as u64 * 1_000_000_000 / nominal_bps as u64
+            + self.data_bits as u64 * 1_000_000_000 / data_bps as u64;
+        Duration::from_nanos(nanos)
+    }
+}
+
+/// Writes the bits as `0` and `1`, stuff bits in brackets.
+impl fmt::Display for FrameBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut stuff = self.stuff_positions.iter().peekable();
+        for (i, bit) in self.bits.iter().enumerate() {
+            let digit = if *bit { '1' } else { '0' };
+            if stuff.next_if(|p| **p == i).is_some() {
+                write!(f, "[{digit}]")?;
+            } else {
+                write!(f, "{digit}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn push(bits: &mut Vec<bool>, value: u64, width: u32) {
+    bits.extend((0..width).rev().map(|i| value >> i & 1 != 0));
+}
+
+fn crc(bits: &[bool], poly: u32, width: u32, init: u32) -> u32 {
+    let top = 1 << (width - 1);
+    let mask = (1 << width) - 1;
+    bits.iter().fold(init, |crc, bit| {
+        let feedback = *bit ^ (crc & top != 0);
+        let crc = (crc << 1) & mask;
+        if feedback { crc ^ poly } else { crc }
+    })
+}
+
+/// Inserts a complementary bit after five consecutive equal bits.
+struct Stuffer {
+    out: Vec<bool>,
+    run: usize,
+    positions: Vec<usize>,
+}
+
+impl Stuffer {
+    fn new() -> Stuffer {
+        Stuffer {
+            out: Vec::with_capacity(700),
+            run: 0,
+            positions: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, bit: bool) {
+        match self.out.last() {
+            Some(last) if *last == bit => self.run += 1,
+            _ => self.run = 1,
+        }
+        self.out.push(bit);
+        if self.run == 5 {
+            self.positions.push(self.out.len());
+            self.out.push(!bit);
+            self.run = 1;
+        }
+    }
+}
+
+fn arbitration(bits: &mut Vec<bool>, id: u32, extended: bool, rtr_or_rrs: bool) {
+    bits.push(false); // SOF
+    if extended {
+        push(bits, (id >> 18) as u64, 11);
+        bits.push(true); // SRR
+        bits.push(true); // IDE
+        push(bits, (id & 0x3_FFFF) as u64, 18);
+    } else {
+        push(bits, id as u64, 11);
+    }
+    bits.push(rtr_or_rrs);
+    if !extended {
+        bits.push(false); // IDE
+    }
+}
+
+fn tail(bits: &mut Vec<bool>) {
+    bits.push(true); // CRC delimiter
+    bits.push(false); // ACK slot, driven by the receivers
+    bits.extend([true; TAIL_BITS - 2]);
+}
+
+/// Classic CAN data or remote frame.
+pub(crate) fn classic(id: u32, extended: bool, remote: bool, dlc: u8, data: &[u8]) -> FrameBits {
+    let mut raw = Vec::with_capacity(128);
+    arbitration(&mut raw, id, extended, remote);
+    if extended {
+        raw.push(false); // r1
+    }
+    raw.push(false); // r0
+    push(&mut raw, dlc as u64, 4);
+    if !remote {
+        data.iter().for_each(|b| push(&mut raw, *b as u64, 8));
+    }
+    let crc = crc(&raw, CRC15_POLY, 15, 0);
+    push(&mut raw, crc as u64, 15);
+
+    let mut stuffer = Stuffer::new();
+    raw.into_iter().for_each(|bit| stuffer.push(bit));
+    let mut bits = stuffer.out;
+    tail(&mut bits);
+    FrameBits {
+        bits,
+        stuff_positions: stuffer.positions,
+        data_bits: 0,
+        crc,
+    }
+}
+
+/// CAN FD data frame.
+pub(crate) fn fd(id: u32, extended: bool, brs: bool, esi: bool, dlc: u8, data: &[u8]) -> FrameBits {
+    let mut head = Vec::with_capacity(64);
+    arbitration(&mut head, id, extended, false);
+    head.push(true); // FDF
+    head.push(false); // res
+    head.push(brs);
+
+    let mut stuffer = Stuffer::new();
+    head.into_iter().for_each(|bit| stuffer.push(bit));
+    let switch = stuffer.out.len();
+
+    let mut rest = vec![esi];
+    push(&mut rest, dlc as u64, 4);
+    data.iter().for_each(|b| push(&mut rest, *b as u64, 8));
+    rest.into_iter().for_each(|bit| stuffer.push(bit));
+
+    // Gray coded stuff count modulo 8 with even parity
+    let gray =
+        [0b000, 0b001, 0b011, 0b010, 0b110, 0b111, 0b101, 0b100][stuffer.positions.len() % 8];
+    let mut tail_field = Vec::with_capacity(25);
+    push(&mut tail_field, gray, 3);
+    tail_field.push(gray.count_ones() % 2 == 1);
+
+    let mut crc_input = stuffer.out.clone();
+    crc_input.extend_from_slice(&tail_field);
+    let crc = if data.len() <= 16 {
+        let crc = crc(&crc_input, CRC17_POLY, 17, 1 << 16);
+        push(&mut tail_field, crc as u64, 17);
+        crc
+    } else {
+        let crc = crc(&crc_input, CRC21_POLY, 21, 1 << 20);
+        push(&mut tail_field, crc as u64, 21);
+        crc
+    };
+
+    // Fixed stuff bits before the stuff count and after every fourth bit
+    let mut bits = stuffer.out;
+    let mut stuff_positions = stuffer.positions;
+    stuff_positions.push(bits.len());
+    bits.push(!bits[bits.len() - 1]);
+    let len = tail_field.len();
+    for (i, bit) in tail_field.into_iter().enumerate() {
+        bits.push(bit);
+        if (i + 1).is_multiple_of(4) && i + 1 < len {
+            stuff_positions.push(bits.len());
+            bits.push(!bit);
+        }
+    }
+    let data_bits = if brs { bits.len() - switch } else { 0 };
+    tail(&mut bits);
+    FrameBits {
+        bits,
+        stuff_positions,
+        data_bits,
+        crc,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn longest_run(bits: &[bool]) -> usize {
+        bits.chunk_by(|a, b| a == b)
+            .map(|run| run.len())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn bitstream_classic_lengths() {
+        let frame = classic(0x555, false, false, 8, &[0xAA; 8]);
+        assert_eq!(frame.bits.len(), 111 + frame.stuff_bits());
+        assert_eq!(frame.nominal_bits(), frame.bits.len());
+
+        // Worst case stuffing of a standard frame with 8 data bytes
+        let frame = classic(0x000, false, false, 8, &[0x00; 8]);
+        assert!(frame.stuff_bits() <= 24);
+        assert!(longest_run(&frame.bits[..frame.bits.len() - TAIL_BITS]) <= 5);
+
+        let remote = classic(0x12345, true, true, 8, &[]);
+        assert_eq!(remote.bits.len(), 67 + remote.stuff_bits());
+    }
+
+    #[test]
+    fn bitstream_crc_residue() {
+        for (poly, width, init) in [
+            (CRC15_POLY, 15, 0),
+            (CRC17_POLY, 17, 1 << 16),
+            (CRC21_POLY, 21, 1 << 20),
+        ] {
+            let mut raw = Vec::new();
+            push(&mut raw, 0x1234_5678, 32);
+            let checksum = crc(&raw, poly, width, init);
+            assert!(checksum < 1 << width);
+            push(&mut raw, checksum as u64, width);
+            assert_eq!(crc(&raw, poly, width, init), 0);
+        }
+    }
+
+    #[test]
+    fn bitstream_stuff_positions() {
+        let frame = classic(0x000, false, false, 2, &[0x00, 0xFF]);
+        for p in &frame.stuff_positions {
+            assert_ne!(frame.bits[*p], frame.bits[*p - 1]);
+            assert!(
+                frame.bits[*p - 5..*p]
+                    .iter()
+                    .all(|b| *b == frame.bits[*p - 1])
+            );
+        }
+        // SOF and the first four identifier bits are dominant
+        let text = frame.to_string();
+        assert!(text.starts_with("00000[1]00000[1]0"));
+        assert_eq!(text.matches('[').count(), frame.stuff_bits());
+
+        let duration = frame.duration(500_000, 500_000);
+        assert_eq!(duration, Duration::from_micros(2 * frame.len() as u64));
+    }
+
+    #[test]
+    fn bitstream_fd_lengths() {
+        let slow = fd(0x123, false, false, false, 15, &[0x11; 64]);
+        let fast = fd(0x123, false, true, false, 15, &[0x11; 64]);
+        assert_eq!(slow.data_bits, 0);
+        assert!(fast.data_bits > 64 * 8 && fast.nominal_bits() < 40);
+
+        // CRC-17 field with 6 and CRC-21 field with 7 fixed stuff bits
+        let short = fd(0x123, false, true, false, 8, &[0x5A; 8]);
+        let dynamic = short.stuff_bits() - 6;
+        assert_eq!(short.bits.len(), 22 + 64 + 4 + 17 + 6 + dynamic + TAIL_BITS);
+        let crc17 = fd(0x123, false, true, false, 10, &[0x5A; 16]);
+        assert_eq!(
+            crc17.bits.len() - crc17.stuff_bits(),
+            22 + 128 + 4 + 17 + TAIL_BITS
+        );
+        let crc21 = fd(0x123, false, true, false, 11, &[0x5A; 20]);
+        assert_eq!(
+            crc21.bits.len() - crc21.stuff_bits(),
+            22 + 160 + 4 + 21 + TAIL_BITS
+        );
+        assert!(crc21.crc < 1 << 21 && crc21.stuff_bits() >= 7);
+    }
+}
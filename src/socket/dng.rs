@@ -20,6 +20,7 @@ use crate::info::{
 };
 use crate::peak_lib;
 use crate::socket::{Baudrate, HasRecvCan, HasSendCan, Socket};
+use crate::status::HasBusStatus;
 use crate::trace::{
     HasSetTraceConfigure, HasSetTraceLocation, HasSetTraceSize, HasSetTraceStatus,
     HasTraceConfigure, HasTraceLocation, HasTraceSize, HasTraceStatus,
@@ -78,6 +79,10 @@ impl HasSendCan for DngCanSocket {}
 // impl HasRecvCanFd for DngCanSocket {}
 // impl HasSendCanFd for DngCanSocket {}
 
+/* Status trait implementation */
+
+impl HasBusStatus for DngCanSocket {}
+
 /* HARDWARE IDENTIFICATION */
 
 impl HasHardwareName for DngCanSocket {}
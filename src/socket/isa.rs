@@ -20,6 +20,7 @@ use crate::info::{
 };
 use crate::peak_lib;
 use crate::socket::{Baudrate, HasRecvCan, HasSendCan, Socket};
+use crate::status::HasBusStatus;
 use crate::trace::{
     HasSetTraceConfigure, HasSetTraceLocation, HasSetTraceSize, HasSetTraceStatus,
     HasTraceConfigure, HasTraceLocation, HasTraceSize, HasTraceStatus,
@@ -77,6 +78,10 @@ impl HasSendCan for IsaCanSocket {}
 // impl HasRecvCanFd for IsaCanSocket {}
 // impl HasSendCanFd for IsaCanSocket {}
 
+/* Status trait implementation */
+
+impl HasBusStatus for IsaCanSocket {}
+
 /* HARDWARE IDENTIFICATION */
 
 impl HasHardwareName for IsaCanSocket {}
@@ -22,6 +22,7 @@ use crate::info::{
 };
 use crate::peak_lib;
 use crate::socket::{Baudrate, HasRecvCan, HasSendCan, Socket};
+use crate::status::HasBusStatus;
 use crate::trace::{
     HasSetTraceConfigure, HasSetTraceLocation, HasSetTraceSize, HasSetTraceStatus,
     HasTraceConfigure, HasTraceLocation, HasTraceSize, HasTraceStatus,
@@ -80,6 +81,10 @@ impl HasSendCan for LanCanSocket {}
 // impl HasRecvCanFd for LanCanSocket {}
 // impl HasSendCanFd for LanCanSocket {}
 
+/* Status trait implementation */
+
+impl HasBusStatus for LanCanSocket {}
+
 /* HARDWARE IDENTIFICATION */
 
 impl HasDeviceId for LanCanSocket {}
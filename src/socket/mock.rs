@@ -0,0 +1,88 @@
+//! In-memory bus for unit tests of the protocol layers.
+
+use crate::error::CanError;
+use crate::socket::{CanFdFrame, CanFrame, RecvCan, RecvCanFd, SendCan, SendCanFd, Timestamp};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+type Queue<T> = Arc<Mutex<VecDeque<(T, u64)>>>;
+
+/// One end of an in-memory bus: frames sent on one end are received on the other,
+/// timestamped in microseconds since the bus was created.
+pub(crate) struct MockEnd {
+    epoch: Instant,
+    rx: Queue<CanFrame>,
+    tx: Queue<CanFrame>,
+    rx_fd: Queue<CanFdFrame>,
+    tx_fd: Queue<CanFdFrame>,
+}
+
+pub(crate) fn mock_bus() -> (MockEnd, MockEnd) {
+    let a = MockEnd {
+        epoch: Instant::now(),
+        rx: Queue::default(),
+        tx: Queue::default(),
+        rx_fd: Queue::default(),
+        tx_fd: Queue::default(),
+    };
+    let b = MockEnd {
+        epoch: a.epoch,
+        rx: a.tx.clone(),
+        tx: a.rx.clone(),
+        rx_fd: a.tx_fd.clone(),
+        tx_fd: a.rx_fd.clone(),
+    };
+    (a, b)
+}
+
+impl MockEnd {
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+}
+
+impl SendCan for MockEnd {
+    fn send(&self, frame: CanFrame) -> Result<(), CanError> {
+        self.tx.lock().unwrap().push_back((frame, self.now()));
+        Ok(())
+    }
+}
+
+impl RecvCan for MockEnd {
+    fn recv(&self) -> Result<(CanFrame, Timestamp), CanError> {
+        let (frame, micros) = self
+            .rx
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(CanError::QrcvEmpty)?;
+        Ok((frame, Timestamp::from_micros(micros)))
+    }
+
+    fn recv_frame(&self) -> Result<CanFrame, CanError> {
+        self.recv().map(|(frame, _)| frame)
+    }
+}
+
+impl SendCanFd for MockEnd {
+    fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError> {
+        self.tx_fd.lock().unwrap().push_back((frame, self.now()));
+        Ok(())
+    }
+}
+
+impl RecvCanFd for MockEnd {
+    fn recv_fd(&self) -> Result<(CanFdFrame, u64), CanError> {
+        self.rx_fd
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(CanError::QrcvEmpty)
+    }
+
+    fn recv_fd_frame(&self) -> Result<CanFdFrame, CanError> {
+        self.recv_fd().map(|(frame, _)| frame)
+    }
+}
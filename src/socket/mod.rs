@@ -2,24 +2,34 @@
 //!
 //!
 
+pub mod bitstream;
 pub mod dng;
 pub mod isa;
 pub mod lan;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod pcc;
 pub mod pci;
+#[cfg(feature = "serde")]
+mod serialize;
 pub mod usb;
 
+use self::bitstream::FrameBits;
 use crate::bus::Bus;
 use crate::error::{CanError, CanOkError};
 use crate::peak_lib;
 use crate::peak_can;
 
+use std::fmt;
 use std::ops::Deref;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub const STANDARD_MASK: u32 = 0x07_FF;
 pub const EXTENDED_MASK: u32 = 0x1F_FF_FF_FF;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MessageType {
     Standard,
     Extended,
@@ -73,6 +83,29 @@ impl CanFrame {
         }
     }
 
+    /// Creates a remote transmission request frame asking for `dlc` bytes.
+    pub fn new_remote(
+        can_id: u32,
+        msg_type: MessageType,
+        dlc: u8,
+    ) -> Result<CanFrame, FrameConstructionError> {
+        if dlc as usize > Self::MAX_DLC {
+            return Err(FrameConstructionError::TooMuchData);
+        }
+
+        let mut frame = CanFrame::new(can_id, msg_type, &[])?;
+        frame.frame.MSGTYPE |= peak_can::PEAK_MESSAGE_RTR as u8;
+        frame.frame.LEN = dlc;
+        Ok(frame)
+    }
+
+    /// Creates an error frame as reported by the driver, `error_id` carrying the error type.
+    pub fn new_error(error_id: u32, data: &[u8]) -> Result<CanFrame, FrameConstructionError> {
+        let mut frame = CanFrame::new(error_id, MessageType::Standard, data)?;
+        frame.frame.MSGTYPE = peak_can::PEAK_MESSAGE_ERRFRAME as u8;
+        Ok(frame)
+    }
+
     pub fn is_standard_frame(&self) -> bool {
         // PEAK_MESSAGE_STANDARD flag is denoted as 0, so check for extended frame flag instead
         !self.is_extended_frame()
@@ -90,6 +123,15 @@ impl CanFrame {
         self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_ECHO as u8 != 0
     }
 
+    /// Status frames report a change of the controller state instead of bus traffic.
+    pub fn is_status_frame(&self) -> bool {
+        self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_STATUS as u8 != 0
+    }
+
+    pub fn is_remote_frame(&self) -> bool {
+        self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_RTR as u8 != 0
+    }
+
     pub fn can_id(&self) -> u32 {
         if self.is_standard_frame() {
             self.frame.ID & STANDARD_MASK
@@ -110,6 +152,27 @@ impl CanFrame {
         let dlc = self.dlc();
         &mut self.frame.DATA[0..dlc as usize]
     }
+
+    /// Bits of the frame as transmitted, see [bitstream].
+    pub fn bitstream(&self) -> FrameBits {
+        bitstream::classic(
+            self.can_id(),
+            self.is_extended_frame(),
+            self.is_remote_frame(),
+            self.dlc(),
+            self.data(),
+        )
+    }
+
+    /// Number of bits on the wire, stuff bits and intermission included.
+    pub fn bit_length(&self) -> usize {
+        self.bitstream().len()
+    }
+
+    /// Time the frame occupies the bus at `bps`.
+    pub fn transmission_time(&self, bps: u32) -> Duration {
+        self.bitstream().duration(bps, bps)
+    }
 }
 
 impl Default for CanFrame {
@@ -188,6 +251,13 @@ impl CanFdFrame {
         }
     }
 
+    /// Creates an error frame as reported by the driver, `error_id` carrying the error type.
+    pub fn new_error(error_id: u32, data: &[u8]) -> Result<CanFdFrame, FrameConstructionError> {
+        let mut frame = CanFdFrame::new(error_id, MessageType::Standard, data, false, false)?;
+        frame.frame.MSGTYPE = peak_can::PEAK_MESSAGE_ERRFRAME as u8;
+        Ok(frame)
+    }
+
     pub fn is_standard_frame(&self) -> bool {
         self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_STANDARD as u8 != 0
     }
@@ -208,10 +278,23 @@ impl CanFdFrame {
         self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_ECHO as u8 != 0
     }
 
+    /// Status frames report a change of the controller state instead of bus traffic.
+    pub fn is_status_frame(&self) -> bool {
+        self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_STATUS as u8 != 0
+    }
+
     pub fn is_fd_frame(&self) -> bool {
         self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_FD as u8 != 0
     }
 
+    pub fn is_brs_frame(&self) -> bool {
+        self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_BRS as u8 != 0
+    }
+
+    pub fn is_esi_frame(&self) -> bool {
+        self.frame.MSGTYPE & peak_can::PEAK_MESSAGE_ESI as u8 != 0
+    }
+
     pub fn can_id(&self) -> u32 {
         if self.is_standard_frame() {
             self.frame.ID & STANDARD_MASK
@@ -228,12 +311,45 @@ impl CanFdFrame {
         &self.frame.DATA[0..self.len() as usize]
     }
 
+    /// Bits of the frame as transmitted, see [bitstream].
+    pub fn bitstream(&self) -> FrameBits {
+        if self.is_fd_frame() {
+            bitstream::fd(
+                self.can_id(),
+                self.is_extended_frame(),
+                self.is_brs_frame(),
+                self.is_esi_frame(),
+                self.dlc(),
+                self.data(),
+            )
+        } else {
+            bitstream::classic(
+                self.can_id(),
+                self.is_extended_frame(),
+                false,
+                self.dlc(),
+                self.data(),
+            )
+        }
+    }
+
+    /// Number of bits on the wire, stuff bits and intermission included.
+    pub fn bit_length(&self) -> usize {
+        self.bitstream().len()
+    }
+
+    /// Time the frame occupies the bus, `data_bps` applies to frames with bit rate switch.
+    pub fn transmission_time(&self, nominal_bps: u32, data_bps: u32) -> Duration {
+        self.bitstream().duration(nominal_bps, data_bps)
+    }
+
     pub fn mut_data(&mut self) -> &mut [u8] {
         let len = self.len();
         &mut self.frame.DATA[0..len as usize]
     }
 
-    fn calc_dlc(len: usize) -> u8 {
+    /// Smallest CAN FD DLC able to hold `len` data bytes.
+    pub fn calc_dlc(len: usize) -> u8 {
         match len {
             0..=8 => len as u8,
             9..=12 => 9,
@@ -247,9 +363,10 @@ impl CanFdFrame {
         }
     }
 
-    pub fn len(&self) -> usize {
-        match self.dlc() {
-            0..=8 => self.dlc() as usize,
+    /// Number of data bytes encoded by a CAN FD DLC.
+    pub fn dlc_to_len(dlc: u8) -> usize {
+        match dlc {
+            0..=8 => dlc as usize,
             9 => 12,
             10 => 16,
             11 => 20,
@@ -261,6 +378,10 @@ impl CanFdFrame {
         }
     }
 
+    pub fn len(&self) -> usize {
+        Self::dlc_to_len(self.dlc())
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -299,6 +420,27 @@ pub struct Timestamp {
     timestamp: peak_can::TPEAKTimestamp,
 }
 
+impl Timestamp {
+    /// Builds a timestamp from a microsecond count, splitting it into the driver's
+    /// millis / overflow / micros representation.
+    pub fn from_micros(micros: u64) -> Timestamp {
+        let millis = micros / 1_000;
+        Timestamp {
+            timestamp: peak_can::TPEAKTimestamp {
+                micros: (micros % 1_000) as u16,
+                millis: (millis & 0xFF_FF_FF_FF) as _,
+                millis_overflow: (millis >> 32) as u16,
+            },
+        }
+    }
+
+    /// Total number of microseconds represented by this timestamp.
+    pub fn as_micros(&self) -> u64 {
+        let millis = ((self.timestamp.millis_overflow as u64) << 32) + self.timestamp.millis as u64;
+        millis * 1_000 + self.timestamp.micros as u64
+    }
+}
+
 impl Deref for Timestamp {
     type Target = peak_can::TPEAKTimestamp;
 
@@ -383,11 +525,43 @@ pub trait SendCanFd {
 
 trait Socket {
     fn handle(&self) -> u16;
+
+    /// Whether the channel was initialized for CAN FD, [SendCanFd] fails early otherwise.
+    fn is_fd(&self) -> bool {
+        false
+    }
+}
+
+/* Polling */
+
+/// Pause between two reads of an empty receive queue.
+const POLL_INTERVAL: Duration = Duration::from_micros(200);
+
+/// Calls `recv` until it yields something other than [CanError::QrcvEmpty].
+///
+/// Returns `Ok(None)` once `deadline` has passed with an empty receive queue.
+pub(crate) fn poll_until<T>(
+    deadline: Instant,
+    mut recv: impl FnMut() -> Result<T, CanError>,
+) -> Result<Option<T>, CanError> {
+    loop {
+        match recv() {
+            Ok(value) => return Ok(Some(value)),
+            Err(CanError::QrcvEmpty) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                thread::sleep(POLL_INTERVAL.min(deadline - now));
+            }
+            Err(err) => return Err(err),
+        }
+    }
 }
 
 /* Baudrate */
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Baudrate {
     Baud1M,
     Baud800K,
@@ -427,6 +601,97 @@ impl From<Baudrate> for u16 {
     }
 }
 
+impl Baudrate {
+    /// Nominal bit rate in bits per second.
+    pub fn bits_per_second(&self) -> u32 {
+        match self {
+            Baudrate::Baud1M => 1_000_000,
+            Baudrate::Baud800K => 800_000,
+            Baudrate::Baud500K => 500_000,
+            Baudrate::Baud250K => 250_000,
+            Baudrate::Baud125K => 125_000,
+            Baudrate::Baud100K => 100_000,
+            Baudrate::Baud95K => 95_238,
+            Baudrate::Baud83K => 83_333,
+            Baudrate::Baud50K => 50_000,
+            Baudrate::Baud47K => 47_619,
+            Baudrate::Baud33K => 33_333,
+            Baudrate::Baud20K => 20_000,
+            Baudrate::Baud10K => 10_000,
+            Baudrate::Baud5K => 5_000,
+        }
+    }
+
+    /// Short name such as `"500K"`.
+    pub fn name(&self) -> &'static str {
+        BAUDRATES
+            .iter()
+            .find(|(baudrate, _)| baudrate == self)
+            .map(|(_, name)| *name)
+            .expect("every baud rate is named")
+    }
+}
+
+const BAUDRATES: [(Baudrate, &str); 14] = [
+    (Baudrate::Baud1M, "1M"),
+    (Baudrate::Baud800K, "800K"),
+    (Baudrate::Baud500K, "500K"),
+    (Baudrate::Baud250K, "250K"),
+    (Baudrate::Baud125K, "125K"),
+    (Baudrate::Baud100K, "100K"),
+    (Baudrate::Baud95K, "95K"),
+    (Baudrate::Baud83K, "83K"),
+    (Baudrate::Baud50K, "50K"),
+    (Baudrate::Baud47K, "47K"),
+    (Baudrate::Baud33K, "33K"),
+    (Baudrate::Baud20K, "20K"),
+    (Baudrate::Baud10K, "10K"),
+    (Baudrate::Baud5K, "5K"),
+];
+
+impl fmt::Display for Baudrate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses a name such as `"500K"` or `"Baud500K"` (case-insensitive) or the exact bits per
+/// second, e.g. `"500000"`.
+impl FromStr for Baudrate {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        if let Ok(bits_per_second) = name.parse::<u32>() {
+            return BAUDRATES
+                .iter()
+                .map(|(baudrate, _)| *baudrate)
+                .find(|baudrate| baudrate.bits_per_second() == bits_per_second)
+                .ok_or(());
+        }
+
+        let name = name
+            .get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("baud"))
+            .map_or(name, |_| &name[4..]);
+        BAUDRATES
+            .iter()
+            .find(|(_, known)| known.eq_ignore_ascii_case(name))
+            .map(|(baudrate, _)| *baudrate)
+            .ok_or(())
+    }
+}
+
+/// Controller clock frequency in Hz the BTR0BTR1 register values refer to.
+pub(crate) const BTR0BTR1_CLOCK_HZ: u32 = 8_000_000;
+
+/// CAN FD controller clock frequency in Hz for PEAK USB devices.
+/// 
+/// This represents the base clock frequency (80 MHz) used by the CAN FD controller
+/// on PEAK USB hardware. This value is used when configuring custom bit timing
+/// parameters for CAN FD communication.
+pub(crate) const CANFD_CLOCK_HZ: u32 = 80_000_000;
+
 /// Hardware-specific timing parameter boundaries for classical CAN 2.0 bit timing.
 ///
 /// These boundaries define the valid ranges for CAN bit timing parameters and are
@@ -582,6 +847,7 @@ pub const CANFD_TIMING_BOUNDARIES: FdTimingBoundaries = FdTimingBoundaries {
     data_tseg2_max: 16,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CanBitTiming {
     pub prescaler: u16,
     pub sjw: u8,
@@ -605,6 +871,12 @@ impl CanBitTiming {
         }
     }
 
+    /// Bit rate in bits per second: one synchronization segment plus both time segments.
+    pub fn bits_per_second(&self) -> u32 {
+        let quanta = 1 + self.tseg1 as u32 + self.tseg2 as u32;
+        BTR0BTR1_CLOCK_HZ / (self.prescaler as u32 * quanta)
+    }
+
     fn validate(timing: &CanBitTiming) -> bool {
         if timing.prescaler < CAN_TIMING_BOUNDARIES.prescaler_min
             || timing.prescaler > CAN_TIMING_BOUNDARIES.prescaler_max
@@ -630,6 +902,7 @@ impl CanBitTiming {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CanFdBitTiming {
     pub nom_prescaler: u16,
     pub nom_sjw: u8,
@@ -661,6 +934,18 @@ impl CanFdBitTiming {
         }
     }
 
+    /// Arbitration phase bit rate in bits per second.
+    pub fn nominal_bits_per_second(&self) -> u32 {
+        let quanta = 1 + self.nom_tseg1 as u32 + self.nom_tseg2 as u32;
+        CANFD_CLOCK_HZ / (self.nom_prescaler as u32 * quanta)
+    }
+
+    /// Data phase bit rate in bits per second.
+    pub fn data_bits_per_second(&self) -> u32 {
+        let quanta = 1 + self.data_tseg1 as u32 + self.data_tseg2 as u32;
+        CANFD_CLOCK_HZ / (self.data_prescaler as u32 * quanta)
+    }
+
     fn validate(timing: &CanFdBitTiming) -> bool {
         if timing.nom_prescaler < CANFD_TIMING_BOUNDARIES.nom_prescaler_min
             || timing.nom_prescaler > CANFD_TIMING_BOUNDARIES.nom_prescaler_max
@@ -809,6 +1094,9 @@ impl<T: HasSendCan + Socket> SendCan for T {
 
 impl<T: HasSendCanFd + Socket> SendCanFd for T {
     fn send_fd(&self, frame: CanFdFrame) -> Result<(), CanError> {
+        if !self.is_fd() {
+            return Err(CanError::IllOperation);
+        }
         let mut frame = frame;
         let error_code = unsafe {
             peak_lib()?.CAN_WriteFD(self.handle(), &mut frame.frame as *mut peak_can::TPEAKMsgFD)
@@ -1205,4 +1493,23 @@ mod tests {
         assert!(CanFdBitTiming::new(1, 1, 1, 1, 1, 1, 1, 0).is_err());
         assert!(CanFdBitTiming::new(1, 1, 1, 1, 1, 1, 1, 17).is_err());
     }
+
+    #[test]
+    fn bit_rates() {
+        assert_eq!(Baudrate::Baud83K.bits_per_second(), 83_333);
+        assert_eq!(CanBitTiming::new(1, 1, 13, 2).unwrap().bits_per_second(), 500_000);
+
+        let timing = CanFdBitTiming::new(10, 4, 13, 2, 5, 2, 6, 1).unwrap();
+        assert_eq!(timing.nominal_bits_per_second(), 500_000);
+        assert_eq!(timing.data_bits_per_second(), 2_000_000);
+    }
+
+    #[test]
+    fn baudrate_names() {
+        assert_eq!("500k".parse(), Ok(Baudrate::Baud500K));
+        assert_eq!("Baud1M".parse(), Ok(Baudrate::Baud1M));
+        assert_eq!("83333".parse(), Ok(Baudrate::Baud83K));
+        assert_eq!("2M".parse::<Baudrate>(), Err(()));
+        assert_eq!(Baudrate::Baud47K.to_string(), "47K");
+    }
 }
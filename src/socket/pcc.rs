@@ -20,6 +20,7 @@ use crate::info::{
 };
 use crate::peak_lib;
 use crate::socket::{Baudrate, HasRecvCan, HasSendCan, Socket};
+use crate::status::HasBusStatus;
 use crate::special::{HasFiveVoltsPower, HasSetFiveVoltsPower};
 use crate::trace::{
     HasSetTraceConfigure, HasSetTraceLocation, HasSetTraceSize, HasSetTraceStatus,
@@ -79,6 +80,10 @@ impl HasSendCan for PccCanSocket {}
 // impl HasRecvCanFd for PccCanSocket {}
 // impl HasSendCanFd for PccCanSocket {}
 
+/* Status trait implementation */
+
+impl HasBusStatus for PccCanSocket {}
+
 /* HARDWARE IDENTIFICATION */
 
 impl HasHardwareName for PccCanSocket {}
@@ -22,6 +22,7 @@ use crate::info::{
 };
 use crate::peak_lib;
 use crate::socket::{Baudrate, HasRecvCan, HasSendCan, Socket};
+use crate::status::HasBusStatus;
 use crate::trace::{
     HasSetTraceConfigure, HasSetTraceLocation, HasSetTraceSize, HasSetTraceStatus,
     HasTraceConfigure, HasTraceLocation, HasTraceSize, HasTraceStatus,
@@ -80,6 +81,10 @@ impl HasSendCan for PciCanSocket {}
 // impl HasRecvCanFd for PciCanSocket {}
 // impl HasSendCanFd for PciCanSocket {}
 
+/* Status trait implementation */
+
+impl HasBusStatus for PciCanSocket {}
+
 /* HARDWARE IDENTIFICATION */
 
 impl HasDeviceId for PciCanSocket {}
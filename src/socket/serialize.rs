@@ -0,0 +1,478 @@
+//! [serde](https://serde.rs) support, enabled with the `serde` feature.
+//!
+//! Representations are meant to be read and written by hand and stay stable across
+//! releases: identifiers are hex strings (`"0x18DAF110"`), payloads are hex strings
+//! (`"DEADBEEF"`), message type bits are named booleans, baud rates are their names
+//! (`"500K"`) and timestamps are microseconds. Missing flags default to `false`, hex
+//! input may be of either case and contain whitespace, identifiers and baud rates are
+//! also accepted as plain numbers.
+//!
+//! ```json
+//! { "id": "0x123", "extended": false, "remote": false, "error": false,
+//!   "status": false, "echo": false, "dlc": 3, "data": "0102FF" }
+//! ```
+
+use super::{
+    BAUDRATES, Baudrate, CanBitTiming, CanFdBitTiming, CanFdFrame, CanFrame, EXTENDED_MASK,
+    MessageType, STANDARD_MASK, Timestamp,
+};
+use crate::peak_can;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+use std::fmt;
+use std::fmt::Write;
+
+/* Hex fields */
+
+struct HexId(u32);
+
+impl Serialize for HexId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:#X}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for HexId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct IdVisitor;
+
+        impl Visitor<'_> for IdVisitor {
+            type Value = HexId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a hex string or an integer CAN identifier")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<HexId, E> {
+                u32::try_from(value)
+                    .map(HexId)
+                    .map_err(|_| E::custom("CAN identifier out of range"))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<HexId, E> {
+                let value = value.trim();
+                let digits = value
+                    .strip_prefix("0x")
+                    .or_else(|| value.strip_prefix("0X"))
+                    .unwrap_or(value);
+                u32::from_str_radix(digits, 16)
+                    .map(HexId)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(value), &self))
+            }
+        }
+
+        deserializer.deserialize_any(IdVisitor)
+    }
+}
+
+#[derive(Default)]
+struct HexData(Vec<u8>);
+
+impl Serialize for HexData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut hex = String::with_capacity(self.0.len() * 2);
+        for byte in &self.0 {
+            let _ = write!(hex, "{byte:02X}");
+        }
+        serializer.serialize_str(&hex)
+    }
+}
+
+impl<'de> Deserialize<'de> for HexData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let digits: Vec<u8> = text.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+        if !digits.len().is_multiple_of(2) {
+            return Err(de::Error::custom("hex payload has an odd number of digits"));
+        }
+        digits
+            .chunks(2)
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| de::Error::custom(format!("invalid hex payload `{text}`")))
+            })
+            .collect::<Result<_, _>>()
+            .map(HexData)
+    }
+}
+
+fn checked_id<E: de::Error>(id: u32, extended: bool) -> Result<(u32, MessageType), E> {
+    let (mask, kind) = if extended {
+        (EXTENDED_MASK, MessageType::Extended)
+    } else {
+        (STANDARD_MASK, MessageType::Standard)
+    };
+    if id & !mask != 0 {
+        return Err(E::custom(format!(
+            "CAN identifier {id:#X} exceeds its frame format"
+        )));
+    }
+    Ok((id, kind))
+}
+
+fn flag(set: bool, bit: u32) -> u8 {
+    if set { bit as u8 } else { 0 }
+}
+
+/* Frames */
+
+#[derive(Serialize, Deserialize)]
+struct CanFrameRepr {
+    id: HexId,
+    #[serde(default)]
+    extended: bool,
+    #[serde(default)]
+    remote: bool,
+    #[serde(default)]
+    error: bool,
+    #[serde(default)]
+    status: bool,
+    #[serde(default)]
+    echo: bool,
+    #[serde(default)]
+    dlc: Option<u8>,
+    #[serde(default)]
+    data: HexData,
+}
+
+impl Serialize for CanFrame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CanFrameRepr {
+            id: HexId(self.can_id()),
+            extended: self.is_extended_frame(),
+            remote: self.is_remote_frame(),
+            error: self.is_error_frame(),
+            status: self.is_status_frame(),
+            echo: self.is_echo_frame(),
+            dlc: Some(self.dlc()),
+            data: HexData(self.data().to_vec()),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Remote frames carry their requested length in `dlc`, data frames may omit it.
+impl<'de> Deserialize<'de> for CanFrame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = CanFrameRepr::deserialize(deserializer)?;
+        let (id, kind) = checked_id(repr.id.0, repr.extended)?;
+        let mut frame = if repr.remote {
+            CanFrame::new_remote(id, kind, repr.dlc.unwrap_or(0))
+        } else {
+            if repr
+                .dlc
+                .is_some_and(|dlc| dlc as usize != repr.data.0.len())
+            {
+                return Err(de::Error::custom("dlc does not match the payload length"));
+            }
+            CanFrame::new(id, kind, &repr.data.0)
+        }
+        .map_err(|_| de::Error::custom("classic CAN frames carry at most 8 bytes"))?;
+
+        frame.frame.MSGTYPE |= flag(repr.error, peak_can::PEAK_MESSAGE_ERRFRAME)
+            | flag(repr.status, peak_can::PEAK_MESSAGE_STATUS)
+            | flag(repr.echo, peak_can::PEAK_MESSAGE_ECHO);
+        Ok(frame)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CanFdFrameRepr {
+    id: HexId,
+    #[serde(default)]
+    extended: bool,
+    #[serde(default)]
+    fd: bool,
+    #[serde(default)]
+    brs: bool,
+    #[serde(default)]
+    esi: bool,
+    #[serde(default)]
+    error: bool,
+    #[serde(default)]
+    status: bool,
+    #[serde(default)]
+    echo: bool,
+    #[serde(default)]
+    data: HexData,
+}
+
+impl Serialize for CanFdFrame {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CanFdFrameRepr {
+            id: HexId(self.can_id()),
+            extended: self.is_extended_frame(),
+            fd: self.is_fd_frame(),
+            brs: self.is_brs_frame(),
+            esi: self.is_esi_frame(),
+            error: self.is_error_frame(),
+            status: self.is_status_frame(),
+            echo: self.is_echo_frame(),
+            data: HexData(self.data().to_vec()),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Payload lengths must be representable by a CAN FD DLC, they are not padded.
+impl<'de> Deserialize<'de> for CanFdFrame {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = CanFdFrameRepr::deserialize(deserializer)?;
+        let (id, kind) = checked_id(repr.id.0, repr.extended)?;
+        let len = repr.data.0.len();
+        if !repr.fd && len > 8 {
+            return Err(de::Error::custom(
+                "classic CAN frames carry at most 8 bytes",
+            ));
+        }
+        if len > 64 || CanFdFrame::dlc_to_len(CanFdFrame::calc_dlc(len)) != len {
+            return Err(de::Error::custom(format!(
+                "{len} bytes is not a CAN FD payload length"
+            )));
+        }
+
+        let mut frame = CanFdFrame::new(id, kind, &repr.data.0, repr.fd, repr.brs)
+            .map_err(|_| de::Error::custom("CAN FD frames carry at most 64 bytes"))?;
+        frame.frame.MSGTYPE |= flag(repr.esi, peak_can::PEAK_MESSAGE_ESI)
+            | flag(repr.error, peak_can::PEAK_MESSAGE_ERRFRAME)
+            | flag(repr.status, peak_can::PEAK_MESSAGE_STATUS)
+            | flag(repr.echo, peak_can::PEAK_MESSAGE_ECHO);
+        Ok(frame)
+    }
+}
+
+/* Timestamp */
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.as_micros())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(Timestamp::from_micros)
+    }
+}
+
+/* Baudrate */
+
+impl Serialize for Baudrate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Baudrate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BaudrateVisitor;
+
+        impl Visitor<'_> for BaudrateVisitor {
+            type Value = Baudrate;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a baud rate name such as \"500K\" or its bits per second")
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Baudrate, E> {
+                BAUDRATES
+                    .iter()
+                    .map(|(baudrate, _)| *baudrate)
+                    .find(|baudrate| baudrate.bits_per_second() as u64 == value)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(value), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Baudrate, E> {
+                value
+                    .parse()
+                    .map_err(|_| E::unknown_variant(value, &["1M", "800K", "500K", "..."]))
+            }
+        }
+
+        deserializer.deserialize_any(BaudrateVisitor)
+    }
+}
+
+/* Bit timing */
+
+#[derive(Serialize, Deserialize)]
+struct CanBitTimingRepr {
+    prescaler: u16,
+    sjw: u8,
+    tseg1: u8,
+    tseg2: u8,
+}
+
+impl Serialize for CanBitTiming {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CanBitTimingRepr {
+            prescaler: self.prescaler,
+            sjw: self.sjw,
+            tseg1: self.tseg1,
+            tseg2: self.tseg2,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Parameters are checked against [CAN_TIMING_BOUNDARIES](super::CAN_TIMING_BOUNDARIES).
+impl<'de> Deserialize<'de> for CanBitTiming {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = CanBitTimingRepr::deserialize(deserializer)?;
+        CanBitTiming::new(repr.prescaler, repr.sjw, repr.tseg1, repr.tseg2)
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CanFdBitTimingRepr {
+    nom_prescaler: u16,
+    nom_sjw: u8,
+    nom_tseg1: u16,
+    nom_tseg2: u8,
+    data_prescaler: u16,
+    data_sjw: u8,
+    data_tseg1: u8,
+    data_tseg2: u8,
+}
+
+impl Serialize for CanFdBitTiming {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        CanFdBitTimingRepr {
+            nom_prescaler: self.nom_prescaler,
+            nom_sjw: self.nom_sjw,
+            nom_tseg1: self.nom_tseg1,
+            nom_tseg2: self.nom_tseg2,
+            data_prescaler: self.data_prescaler,
+            data_sjw: self.data_sjw,
+            data_tseg1: self.data_tseg1,
+            data_tseg2: self.data_tseg2,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Parameters are checked against [CANFD_TIMING_BOUNDARIES](super::CANFD_TIMING_BOUNDARIES).
+impl<'de> Deserialize<'de> for CanFdBitTiming {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = CanFdBitTimingRepr::deserialize(deserializer)?;
+        CanFdBitTiming::new(
+            repr.nom_prescaler,
+            repr.nom_sjw,
+            repr.nom_tseg1,
+            repr.nom_tseg2,
+            repr.data_prescaler,
+            repr.data_sjw,
+            repr.data_tseg1,
+            repr.data_tseg2,
+        )
+        .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::{from_str, json, to_value};
+
+    #[test]
+    fn frame_representation() {
+        let frame = CanFrame::new(0x123, MessageType::Standard, &[0x01, 0x02, 0xFF]).unwrap();
+        assert_eq!(
+            to_value(frame).unwrap(),
+            json!({
+                "id": "0x123", "extended": false, "remote": false, "error": false,
+                "status": false, "echo": false, "dlc": 3, "data": "0102FF"
+            })
+        );
+        assert_eq!(
+            from_str::<CanFrame>(r#"{"id": "0x123", "data": "01 02 ff"}"#).unwrap(),
+            frame
+        );
+        assert_eq!(
+            from_str::<CanFrame>(r#"{"id": 291, "data": "0102FF"}"#).unwrap(),
+            frame
+        );
+
+        let remote = CanFrame::new_remote(0x18DA_F110, MessageType::Extended, 8).unwrap();
+        let text = serde_json::to_string(&remote).unwrap();
+        assert!(text.contains(r#""id":"0x18DAF110""#) && text.contains(r#""remote":true"#));
+        assert_eq!(from_str::<CanFrame>(&text).unwrap(), remote);
+
+        let mut echo = frame;
+        echo.frame.MSGTYPE |= peak_can::PEAK_MESSAGE_ECHO as u8;
+        let back: CanFrame = from_str(&serde_json::to_string(&echo).unwrap()).unwrap();
+        assert!(back.is_echo_frame() && back == echo);
+
+        assert!(from_str::<CanFrame>(r#"{"id": "0x800"}"#).is_err());
+        assert!(from_str::<CanFrame>(r#"{"id": "0x1", "data": "010"}"#).is_err());
+        assert!(from_str::<CanFrame>(r#"{"id": "0x1", "dlc": 2, "data": "01"}"#).is_err());
+        assert!(from_str::<CanFrame>(r#"{"id": "0x1", "data": "000000000000000000"}"#).is_err());
+
+        let fd = CanFdFrame::new(0x7E0, MessageType::Standard, &[0xAA; 12], true, true).unwrap();
+        let value = to_value(fd).unwrap();
+        assert_eq!(value["fd"], json!(true));
+        assert_eq!(value["esi"], json!(false));
+        assert_eq!(value["data"], json!("AA".repeat(12)));
+        assert_eq!(serde_json::from_value::<CanFdFrame>(value).unwrap(), fd);
+        assert!(
+            from_str::<CanFdFrame>(r#"{"id": "0x1", "fd": true, "data": "00000000000000000000"}"#)
+                .is_err()
+        );
+        assert!(from_str::<CanFdFrame>(r#"{"id": "0x1", "data": "000000000000000000"}"#).is_err());
+    }
+
+    #[test]
+    fn settings_representation() {
+        assert_eq!(to_value(Baudrate::Baud500K).unwrap(), json!("500K"));
+        assert_eq!(from_str::<Baudrate>(r#""1m""#).unwrap(), Baudrate::Baud1M);
+        assert_eq!(
+            from_str::<Baudrate>(r#""Baud83K""#).unwrap(),
+            Baudrate::Baud83K
+        );
+        assert_eq!(from_str::<Baudrate>("250000").unwrap(), Baudrate::Baud250K);
+        assert!(from_str::<Baudrate>(r#""42K""#).is_err());
+
+        assert_eq!(
+            to_value(Timestamp::from_micros(5_000_123)).unwrap(),
+            json!(5_000_123)
+        );
+        assert_eq!(
+            from_str::<Timestamp>("5000123").unwrap(),
+            Timestamp::from_micros(5_000_123)
+        );
+
+        let timing = CanBitTiming::new(1, 1, 13, 2).unwrap();
+        let value = to_value(timing).unwrap();
+        assert_eq!(
+            value,
+            json!({"prescaler": 1, "sjw": 1, "tseg1": 13, "tseg2": 2})
+        );
+        assert_eq!(
+            serde_json::from_value::<CanBitTiming>(value).unwrap().tseg1,
+            13
+        );
+        assert!(
+            from_str::<CanBitTiming>(r#"{"prescaler": 0, "sjw": 1, "tseg1": 13, "tseg2": 2}"#)
+                .is_err()
+        );
+
+        let fd = CanFdBitTiming::new(1, 16, 63, 16, 4, 4, 7, 2).unwrap();
+        let back: CanFdBitTiming = serde_json::from_value(to_value(fd).unwrap()).unwrap();
+        assert_eq!(back.data_bits_per_second(), fd.data_bits_per_second());
+
+        assert_eq!(
+            to_value(crate::log::TraceFile::Segmented).unwrap(),
+            json!("segmented")
+        );
+        assert_eq!(
+            from_str::<crate::io::IOConfig>(r#""in_out""#).unwrap(),
+            crate::io::IOConfig::InOut
+        );
+    }
+}
@@ -19,7 +19,7 @@ use crate::hw::{
     HasSetControllerNumber, HasSetDeviceId,
 };
 use crate::info::{
-    HasBitrateInfo, HasChannelFeatures, HasChannelVersion, HasDataBusSpeed, HasFirmwareVersion,
+    ChannelFeatures, HasBitrateInfo, HasChannelFeatures, HasChannelVersion, HasDataBusSpeed, HasFirmwareVersion,
     HasNominalBusSpeed,
 };
 use crate::io::{
@@ -27,7 +27,8 @@ use crate::io::{
     HasSetDigitalConfiguration, HasSetDigitalSet, HasSetDigitalValue,
 };
 use crate::peak_lib;
-use crate::socket::{Baudrate, CanBitTiming, CanFdBitTiming, HasRecvCan, HasRecvCanFd, HasSendCan, HasSendCanFd, Socket};
+use crate::socket::{Baudrate, CanBitTiming, CanFdBitTiming, CANFD_CLOCK_HZ, HasRecvCan, HasRecvCanFd, HasSendCan, HasSendCanFd, Socket};
+use crate::status::HasBusStatus;
 use crate::special::{
     HasBusOffAutoreset, HasFiveVoltsPower, HasInterframeDelay, HasListenOnly,
     HasSetBusOffAutoreset, HasSetFiveVoltsPower, HasSetInterframeDelay, HasSetListenOnly,
@@ -37,13 +38,6 @@ use crate::trace::{
     HasTraceConfigure, HasTraceLocation, HasTraceSize, HasTraceStatus,
 };
 
-/// CAN FD controller clock frequency in Hz for PEAK USB devices.
-/// 
-/// This represents the base clock frequency (80 MHz) used by the CAN FD controller
-/// on PEAK USB hardware. This value is used when configuring custom bit timing
-/// parameters for CAN FD communication.
-const CANFD_CLOCK_HZ: u32 = 80_000_000;
-
 /// Helper function to calculate BTR0BTR1 value
 fn calculate_btr0btr1(timing: &CanBitTiming) -> u16 {
     ((((timing.tseg2 - 1) & 0x07) as u16) << 4)
@@ -71,6 +65,7 @@ fn build_timing_string(timing: &CanFdBitTiming) -> String {
 #[derive(Debug, PartialEq)]
 pub struct UsbCanSocket {
     handle: u16,
+    fd: bool,
 }
 
 impl UsbCanSocket {
@@ -95,15 +90,18 @@ impl UsbCanSocket {
         let code = unsafe { peak_lib()?.CAN_Initialize(handle, baud.into(), 0, 0, 0) };
 
         match CanOkError::try_from(code) {
-            Ok(CanOkError::Ok) => Ok(UsbCanSocket { handle }),
+            Ok(CanOkError::Ok) => Ok(UsbCanSocket { handle, fd: false }),
             Ok(CanOkError::Err(err)) => Err(err),
             Err(_) => Err(CanError::Unknown),
         }
     }
 
+    /// Attaches to a channel initialized elsewhere, CAN FD sends are allowed if the
+    /// hardware is FD capable.
     pub fn open_with_usb_bus(bus: UsbBus) -> UsbCanSocket {
+        let fd = bus.is_fd_capable().unwrap_or(true);
         let handle = bus.into();
-        UsbCanSocket { handle }
+        UsbCanSocket { handle, fd }
     }
 
     /// Opens a CAN socket with custom bit timing.
@@ -126,13 +124,14 @@ impl UsbCanSocket {
         let code = unsafe { peak_lib()?.CAN_Initialize(handle, btr0btr1, 0, 0, 0) };
 
         match CanOkError::try_from(code) {
-            Ok(CanOkError::Ok) => Ok(UsbCanSocket { handle }),
+            Ok(CanOkError::Ok) => Ok(UsbCanSocket { handle, fd: false }),
             Ok(CanOkError::Err(err)) => Err(err),
             Err(_) => Err(CanError::Unknown),
         }
     }
 
-    /// Opens a CAN FD socket with custom timing for nominal and data phases.
+    /// Opens a CAN FD socket with custom timing for nominal and data phases. Fails with
+    /// [CanError::IllOperation] if the channel is not CAN FD capable.
     ///
     /// # Examples
     ///
@@ -148,6 +147,9 @@ impl UsbCanSocket {
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
     pub fn open_fd_with_timing(bus: UsbBus, timing: &CanFdBitTiming) -> Result<UsbCanSocket, CanError> {
+        if matches!(bus.is_fd_capable(), Ok(false)) {
+            return Err(CanError::IllOperation);
+        }
         let handle = bus.into();
         let timing_str = build_timing_string(timing);
 
@@ -158,7 +160,7 @@ impl UsbCanSocket {
         let code = unsafe { peak_lib()?.CAN_InitializeFD(handle, timing_bytes.as_mut_ptr().cast()) };
 
         match CanOkError::try_from(code) {
-            Ok(CanOkError::Ok) => Ok(UsbCanSocket { handle }),
+            Ok(CanOkError::Ok) => Ok(UsbCanSocket { handle, fd: true }),
             Ok(CanOkError::Err(err)) => Err(err),
             Err(_) => Err(CanError::Unknown),
         }
@@ -182,6 +184,10 @@ impl Socket for UsbCanSocket {
     fn handle(&self) -> u16 {
         self.handle
     }
+
+    fn is_fd(&self) -> bool {
+        self.fd
+    }
 }
 
 /* Channel trait implementation */
@@ -200,6 +206,10 @@ impl HasSendCan for UsbCanSocket {}
 impl HasRecvCanFd for UsbCanSocket {}
 impl HasSendCanFd for UsbCanSocket {}
 
+/* Status trait implementation */
+
+impl HasBusStatus for UsbCanSocket {}
+
 /* HARDWARE IDENTIFICATION */
 
 impl HasChannelIdentifying for UsbCanSocket {}
@@ -298,6 +308,16 @@ impl HasAnalogValue for UsbCanSocket {}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::socket::{CanFdFrame, MessageType, SendCanFd};
+
+    #[test]
+    fn fd_send_requires_fd_mode() {
+        let socket = UsbCanSocket { handle: UsbBus::USB1.into(), fd: false };
+        let frame = CanFdFrame::new(0x123, MessageType::Standard, &[0; 16], true, true).unwrap();
+        assert!(matches!(socket.send_fd(frame), Err(CanError::IllOperation)));
+        // Never opened, nothing to uninitialize
+        std::mem::forget(socket);
+    }
 
     #[test]
     fn btr0btr1_encoding() {
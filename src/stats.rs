@@ -0,0 +1,384 @@
+//! Bus load and traffic statistics.
+//!
+//! A [BusStatistics] collector is fed with received and transmitted frames and their
+//! timestamps. The bus time of every frame is derived from its exact length on the wire,
+//! stuff bits included, at the nominal and, for CAN FD frames with bit rate switch, the data
+//! bit rate. Load, frame rates, periods and jitter are computed over a rolling window that
+//! ends at the newest recorded timestamp.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::socket::{Baudrate, RecvCan};
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use peak_can::stats::{BusStatistics, Direction};
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let mut stats = BusStatistics::from_baudrate(&Baudrate::Baud500K);
+//!
+//! loop {
+//!     if let Ok((frame, timestamp)) = socket.recv() {
+//!         stats.record(&frame, timestamp.as_micros(), Direction::Rx);
+//!         if stats.bus_load() > 70.0 {
+//!             println!("bus overloaded: {:.1} %", stats.bus_load());
+//!         }
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::socket::{Baudrate, CanBitTiming, CanFdBitTiming, CanFdFrame, CanFrame};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// Error flag, error delimiter and intermission of an error frame without superposition.
+const ERROR_FRAME_BITS: u64 = 6 + 8 + 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// Traffic of one identifier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdStatistics {
+    pub id: u32,
+    pub extended: bool,
+    /// Frames since the collector was created or reset.
+    pub count: u64,
+    /// Frames per second in the window.
+    pub frames_per_second: f64,
+    /// Mean interval between frames in the window.
+    pub period: Option<Duration>,
+    /// Difference between the longest and the shortest interval in the window.
+    pub jitter: Option<Duration>,
+    pub min_dlc: u8,
+    pub max_dlc: u8,
+    /// Timestamp of the newest frame in microseconds.
+    pub last_timestamp: u64,
+}
+
+struct IdState {
+    count: u64,
+    min_dlc: u8,
+    max_dlc: u8,
+    timestamps: VecDeque<u64>,
+}
+
+pub struct BusStatistics {
+    nominal_bps: u32,
+    data_bps: u32,
+    window_us: u64,
+    /// Timestamp and bus time in nanoseconds of the frames in the window.
+    frames: VecDeque<(u64, u64)>,
+    /// Timestamps of the error frames in the window.
+    errors: VecDeque<u64>,
+    ids: BTreeMap<(bool, u32), IdState>,
+    rx_frames: u64,
+    tx_frames: u64,
+    error_frames: u64,
+    now: u64,
+}
+
+impl BusStatistics {
+    /// Collector for a bus running at `nominal_bps`, with a window of one second.
+    pub fn new(nominal_bps: u32) -> BusStatistics {
+        BusStatistics {
+            nominal_bps,
+            data_bps: nominal_bps,
+            window_us: 1_000_000,
+            frames: VecDeque::new(),
+            errors: VecDeque::new(),
+            ids: BTreeMap::new(),
+            rx_frames: 0,
+            tx_frames: 0,
+            error_frames: 0,
+            now: 0,
+        }
+    }
+
+    pub fn from_baudrate(baudrate: &Baudrate) -> BusStatistics {
+        Self::new(baudrate.bits_per_second())
+    }
+
+    pub fn from_bit_timing(timing: &CanBitTiming) -> BusStatistics {
+        Self::new(timing.bits_per_second())
+    }
+
+    pub fn from_fd_bit_timing(timing: &CanFdBitTiming) -> BusStatistics {
+        Self::new(timing.nominal_bits_per_second()).data_rate(timing.data_bits_per_second())
+    }
+
+    /// Sets the data phase bit rate of CAN FD frames with bit rate switch.
+    pub fn data_rate(mut self, data_bps: u32) -> Self {
+        self.data_bps = data_bps;
+        self
+    }
+
+    /// Sets the length of the rolling window.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window_us = (window.as_micros() as u64).max(1);
+        self
+    }
+
+    /// Forgets all recorded frames.
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.errors.clear();
+        self.ids.clear();
+        self.rx_frames = 0;
+        self.tx_frames = 0;
+        self.error_frames = 0;
+        self.now = 0;
+    }
+
+    /// Records a classic frame with its timestamp in microseconds. Echo frames always count
+    /// as transmitted.
+    pub fn record(&mut self, frame: &CanFrame, timestamp: u64, direction: Direction) {
+        if frame.is_error_frame() {
+            self.record_error(timestamp);
+            return;
+        }
+        let busy = frame.transmission_time(self.nominal_bps);
+        let direction = if frame.is_echo_frame() {
+            Direction::Tx
+        } else {
+            direction
+        };
+        let key = (frame.is_extended_frame(), frame.can_id());
+        self.add(key, frame.dlc(), busy, timestamp, direction);
+    }
+
+    /// Records a CAN FD or classic frame received on an FD channel.
+    pub fn record_fd(&mut self, frame: &CanFdFrame, timestamp: u64, direction: Direction) {
+        if frame.is_error_frame() {
+            self.record_error(timestamp);
+            return;
+        }
+        let extended = frame.is_extended_frame();
+        let busy = frame.transmission_time(self.nominal_bps, self.data_bps);
+        let direction = if frame.is_echo_frame() {
+            Direction::Tx
+        } else {
+            direction
+        };
+        self.add(
+            (extended, frame.can_id()),
+            frame.dlc(),
+            busy,
+            timestamp,
+            direction,
+        );
+    }
+
+    fn record_error(&mut self, timestamp: u64) {
+        self.advance(timestamp);
+        self.error_frames += 1;
+        self.errors.push_back(timestamp);
+        let busy = ERROR_FRAME_BITS * 1_000_000_000 / self.nominal_bps as u64;
+        self.frames.push_back((timestamp, busy));
+    }
+
+    fn add(
+        &mut self,
+        key: (bool, u32),
+        dlc: u8,
+        busy: Duration,
+        timestamp: u64,
+        direction: Direction,
+    ) {
+        self.advance(timestamp);
+        match direction {
+            Direction::Rx => self.rx_frames += 1,
+            Direction::Tx => self.tx_frames += 1,
+        }
+        self.frames.push_back((timestamp, busy.as_nanos() as u64));
+
+        let cutoff = self.cutoff();
+        let state = self.ids.entry(key).or_insert(IdState {
+            count: 0,
+            min_dlc: dlc,
+            max_dlc: dlc,
+            timestamps: VecDeque::new(),
+        });
+        state.count += 1;
+        state.min_dlc = state.min_dlc.min(dlc);
+        state.max_dlc = state.max_dlc.max(dlc);
+        state.timestamps.push_back(timestamp);
+        while state.timestamps.front().is_some_and(|t| *t < cutoff) {
+            state.timestamps.pop_front();
+        }
+    }
+
+    fn cutoff(&self) -> u64 {
+        self.now.saturating_sub(self.window_us)
+    }
+
+    /// Moves the end of the window to `timestamp` and drops what falls out of it.
+    fn advance(&mut self, timestamp: u64) {
+        self.now = self.now.max(timestamp);
+        let cutoff = self.cutoff();
+        while self.frames.front().is_some_and(|(t, _)| *t < cutoff) {
+            self.frames.pop_front();
+        }
+        while self.errors.front().is_some_and(|t| *t < cutoff) {
+            self.errors.pop_front();
+        }
+    }
+
+    fn per_second(&self, count: usize) -> f64 {
+        count as f64 * 1_000_000.0 / self.window_us as f64
+    }
+
+    /// Share of the window the bus was busy, in percent.
+    pub fn bus_load(&self) -> f64 {
+        let busy: u64 = self.frames.iter().map(|(_, busy)| busy).sum();
+        busy as f64 / (self.window_us as f64 * 1000.0) * 100.0
+    }
+
+    /// Frames per second of all identifiers in the window, error frames excluded.
+    pub fn frames_per_second(&self) -> f64 {
+        self.per_second(self.frames.len() - self.errors.len())
+    }
+
+    pub fn error_frames_per_second(&self) -> f64 {
+        self.per_second(self.errors.len())
+    }
+
+    pub fn rx_frames(&self) -> u64 {
+        self.rx_frames
+    }
+
+    pub fn tx_frames(&self) -> u64 {
+        self.tx_frames
+    }
+
+    pub fn error_frames(&self) -> u64 {
+        self.error_frames
+    }
+
+    fn id_statistics_of(&self, (extended, id): (bool, u32), state: &IdState) -> IdStatistics {
+        let cutoff = self.cutoff();
+        let in_window: Vec<u64> = state
+            .timestamps
+            .iter()
+            .copied()
+            .filter(|t| *t >= cutoff)
+            .collect();
+        let intervals: Vec<u64> = in_window
+            .windows(2)
+            .map(|w| w[1].saturating_sub(w[0]))
+            .collect();
+        let (period, jitter) = match (intervals.iter().min(), intervals.iter().max()) {
+            (Some(min), Some(max)) => {
+                let mean = intervals.iter().sum::<u64>() / intervals.len() as u64;
+                (
+                    Some(Duration::from_micros(mean)),
+                    Some(Duration::from_micros(max - min)),
+                )
+            }
+            _ => (None, None),
+        };
+        IdStatistics {
+            id,
+            extended,
+            count: state.count,
+            frames_per_second: self.per_second(in_window.len()),
+            period,
+            jitter,
+            min_dlc: state.min_dlc,
+            max_dlc: state.max_dlc,
+            last_timestamp: state.timestamps.back().copied().unwrap_or_default(),
+        }
+    }
+
+    /// Statistics of one identifier.
+    pub fn id(&self, id: u32, extended: bool) -> Option<IdStatistics> {
+        self.ids
+            .get(&(extended, id))
+            .map(|state| self.id_statistics_of((extended, id), state))
+    }
+
+    /// Statistics of all identifiers seen, standard identifiers first.
+    pub fn id_statistics(&self) -> Vec<IdStatistics> {
+        self.ids
+            .iter()
+            .map(|(key, state)| self.id_statistics_of(*key, state))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::MessageType;
+
+    #[test]
+    fn stats_periodic_traffic() {
+        let mut stats = BusStatistics::from_baudrate(&Baudrate::Baud500K);
+        let frame = CanFrame::new(0x100, MessageType::Standard, &[0x55; 8]).unwrap();
+        let bits = frame.bit_length();
+
+        for i in 0..=100 {
+            stats.record(&frame, 10_000 * i, Direction::Rx);
+        }
+        let id = stats.id(0x100, false).unwrap();
+        assert_eq!(id.count, 101);
+        assert_eq!(id.frames_per_second, 101.0);
+        assert_eq!(id.period, Some(Duration::from_millis(10)));
+        assert_eq!(id.jitter, Some(Duration::ZERO));
+        assert_eq!((id.min_dlc, id.max_dlc), (8, 8));
+
+        // 2 µs per bit at 500 kbit/s
+        let expected = 101.0 * bits as f64 * 2.0 / 1_000_000.0 * 100.0;
+        assert!((stats.bus_load() - expected).abs() < 1e-9);
+        assert_eq!(stats.rx_frames(), 101);
+    }
+
+    #[test]
+    fn stats_rolling_window_and_jitter() {
+        let mut stats = BusStatistics::new(250_000).window(Duration::from_millis(100));
+        let short = CanFrame::new(0x7FF, MessageType::Standard, &[1]).unwrap();
+        let long = CanFrame::new(0x7FF, MessageType::Standard, &[1, 2, 3, 4]).unwrap();
+
+        stats.record(&short, 0, Direction::Tx);
+        stats.record(&long, 8_000, Direction::Tx);
+        stats.record(&short, 20_000, Direction::Tx);
+        let id = stats.id(0x7FF, false).unwrap();
+        assert_eq!(id.period, Some(Duration::from_millis(10)));
+        assert_eq!(id.jitter, Some(Duration::from_millis(4)));
+        assert_eq!((id.min_dlc, id.max_dlc), (1, 4));
+
+        stats.record(&short, 500_000, Direction::Tx);
+        let id = stats.id(0x7FF, false).unwrap();
+        assert_eq!(id.count, 4);
+        assert_eq!(id.frames_per_second, 10.0);
+        assert_eq!(id.period, None);
+        assert_eq!(stats.frames_per_second(), 10.0);
+        assert_eq!(stats.tx_frames(), 4);
+    }
+
+    #[test]
+    fn stats_fd_and_errors() {
+        let timing = CanFdBitTiming::new(10, 4, 13, 2, 5, 2, 6, 1).unwrap();
+        let mut stats = BusStatistics::from_fd_bit_timing(&timing);
+        let slow = CanFdFrame::new(0x10, MessageType::Standard, &[0; 64], true, false).unwrap();
+        let fast = CanFdFrame::new(0x11, MessageType::Standard, &[0; 64], true, true).unwrap();
+
+        stats.record_fd(&slow, 1_000, Direction::Rx);
+        let slow_load = stats.bus_load();
+        stats.reset();
+        stats.record_fd(&fast, 1_000, Direction::Rx);
+        assert!(stats.bus_load() < slow_load / 2.0);
+
+        let error = CanFrame::new_error(0x01, &[0, 0, 0, 1]).unwrap();
+        stats.record(&error, 2_000, Direction::Rx);
+        stats.record(&error, 3_000, Direction::Rx);
+        assert_eq!(stats.error_frames(), 2);
+        assert_eq!(stats.error_frames_per_second(), 2.0);
+        assert_eq!(stats.frames_per_second(), 1.0);
+        assert_eq!(stats.id_statistics().len(), 1);
+    }
+}
@@ -0,0 +1,443 @@
+//! Controller error state tracking.
+//!
+//! A CAN controller moves between error active, error warning (a counter reached 96), error
+//! passive (a counter reached 128) and bus off (the transmit error counter exceeded 255).
+//! [ErrorMonitor] follows these states from error frames, which carry the transmit and
+//! receive error counters, from status frames and from [BusStatus::bus_status] polls, keeps
+//! a timestamped history of the transitions and forwards every transition to its
+//! subscribers.
+//!
+//! Error and status frames are only delivered when enabled with
+//! [SetAllowErrorFrames](crate::df::SetAllowErrorFrames) and
+//! [SetAllowStatusFrames](crate::df::SetAllowStatusFrames).
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::df::SetAllowErrorFrames;
+//! # use peak_can::socket::{Baudrate, RecvCan};
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use peak_can::status::{BusStatus, ErrorMonitor};
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! socket.allow_error_frames(true)?;
+//!
+//! let mut monitor = ErrorMonitor::new();
+//! let alarms = monitor.subscribe();
+//! std::thread::spawn(move || {
+//!     for transition in alarms {
+//!         eprintln!("{} -> {} at {} µs", transition.from, transition.to, transition.timestamp);
+//!     }
+//! });
+//!
+//! loop {
+//!     if let Ok((frame, timestamp)) = socket.recv() {
+//!         monitor.process(&frame, timestamp.as_micros());
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::channel::Channel;
+use crate::error::{CanError, CanOkError};
+use crate::peak_can;
+use crate::peak_lib;
+use crate::socket::{CanFdFrame, CanFrame};
+
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+const WARNING_LIMIT: u16 = 96;
+const PASSIVE_LIMIT: u16 = 128;
+const BUS_OFF_LIMIT: u16 = 256;
+
+const BUS_STATUS_MASK: u32 = peak_can::PEAK_ERROR_BUSLIGHT
+    | peak_can::PEAK_ERROR_BUSWARNING
+    | peak_can::PEAK_ERROR_BUSPASSIVE
+    | peak_can::PEAK_ERROR_BUSOFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorState {
+    Active,
+    Warning,
+    Passive,
+    BusOff,
+}
+
+impl ErrorState {
+    /// State of a controller with the given transmit and receive error counters.
+    pub fn from_counters(tec: u16, rec: u16) -> ErrorState {
+        if tec >= BUS_OFF_LIMIT {
+            ErrorState::BusOff
+        } else if tec >= PASSIVE_LIMIT || rec >= PASSIVE_LIMIT {
+            ErrorState::Passive
+        } else if tec >= WARNING_LIMIT || rec >= WARNING_LIMIT {
+            ErrorState::Warning
+        } else {
+            ErrorState::Active
+        }
+    }
+
+    /// State encoded in a PCAN status value, `None` if it holds other errors.
+    pub fn from_status(status: u32) -> Option<ErrorState> {
+        if status & !BUS_STATUS_MASK != 0 {
+            None
+        } else if status & peak_can::PEAK_ERROR_BUSOFF != 0 {
+            Some(ErrorState::BusOff)
+        } else if status & peak_can::PEAK_ERROR_BUSPASSIVE != 0 {
+            Some(ErrorState::Passive)
+        } else if status & (peak_can::PEAK_ERROR_BUSWARNING | peak_can::PEAK_ERROR_BUSLIGHT) != 0 {
+            Some(ErrorState::Warning)
+        } else {
+            Some(ErrorState::Active)
+        }
+    }
+}
+
+impl fmt::Display for ErrorState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorState::Active => write!(f, "error active"),
+            ErrorState::Warning => write!(f, "error warning"),
+            ErrorState::Passive => write!(f, "error passive"),
+            ErrorState::BusOff => write!(f, "bus off"),
+        }
+    }
+}
+
+/* Bus Status */
+
+pub(crate) trait HasBusStatus {}
+
+pub trait BusStatus {
+    /// Error state reported by the driver.
+    fn bus_status(&self) -> Result<ErrorState, CanError>;
+}
+
+impl<T: HasBusStatus + Channel> BusStatus for T {
+    fn bus_status(&self) -> Result<ErrorState, CanError> {
+        let code = unsafe { peak_lib()?.CAN_GetStatus(self.channel()) };
+
+        if let Some(state) = ErrorState::from_status(code) {
+            return Ok(state);
+        }
+        match CanOkError::try_from(code) {
+            Ok(CanOkError::Ok) => Ok(ErrorState::Active),
+            Ok(CanOkError::Err(err)) => Err(err),
+            Err(_) => Err(CanError::Unknown),
+        }
+    }
+}
+
+/* Error frames */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Bit,
+    Form,
+    Stuff,
+    Other(u32),
+}
+
+/// Content of an error frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorFrame {
+    pub kind: ErrorKind,
+    /// Whether the error was detected while receiving.
+    pub rx: bool,
+    /// Error code capture register of the controller.
+    pub ecc: u8,
+    pub rec: u8,
+    pub tec: u8,
+}
+
+impl ErrorFrame {
+    fn decode(id: u32, data: &[u8]) -> Option<ErrorFrame> {
+        let [direction, ecc, rec, tec, ..] = *data else {
+            return None;
+        };
+        let kind = match id {
+            1 => ErrorKind::Bit,
+            2 => ErrorKind::Form,
+            4 => ErrorKind::Stuff,
+            other => ErrorKind::Other(other),
+        };
+        Some(ErrorFrame {
+            kind,
+            rx: direction != 0,
+            ecc,
+            rec,
+            tec,
+        })
+    }
+
+    /// Decodes an error frame, `None` for other frames.
+    pub fn from_frame(frame: &CanFrame) -> Option<ErrorFrame> {
+        if !frame.is_error_frame() {
+            return None;
+        }
+        Self::decode(frame.can_id(), frame.data())
+    }
+
+    pub fn from_fd_frame(frame: &CanFdFrame) -> Option<ErrorFrame> {
+        if !frame.is_error_frame() {
+            return None;
+        }
+        Self::decode(frame.can_id(), frame.data())
+    }
+}
+
+/* Monitor */
+
+/// A change of the error state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateTransition {
+    pub from: ErrorState,
+    pub to: ErrorState,
+    /// Timestamp in microseconds.
+    pub timestamp: u64,
+    pub tec: u8,
+    pub rec: u8,
+}
+
+pub struct ErrorMonitor {
+    state: ErrorState,
+    since: u64,
+    tec: u8,
+    rec: u8,
+    error_frames: u64,
+    kinds: [u64; 4],
+    history: Vec<StateTransition>,
+    history_limit: usize,
+    subscribers: Vec<Sender<StateTransition>>,
+}
+
+impl Default for ErrorMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorMonitor {
+    /// Monitor of an error active controller, keeping the last 1000 transitions.
+    pub fn new() -> ErrorMonitor {
+        ErrorMonitor {
+            state: ErrorState::Active,
+            since: 0,
+            tec: 0,
+            rec: 0,
+            error_frames: 0,
+            kinds: [0; 4],
+            history: Vec::new(),
+            history_limit: 1000,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Sets the number of transitions kept in the history.
+    pub fn history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    /// Receives every following transition. Dropped receivers are removed.
+    pub fn subscribe(&mut self) -> Receiver<StateTransition> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    pub fn state(&self) -> ErrorState {
+        self.state
+    }
+
+    /// Timestamp of the transition into the current state.
+    pub fn since(&self) -> u64 {
+        self.since
+    }
+
+    /// Transmit error counter of the last error frame.
+    pub fn tec(&self) -> u8 {
+        self.tec
+    }
+
+    /// Receive error counter of the last error frame.
+    pub fn rec(&self) -> u8 {
+        self.rec
+    }
+
+    pub fn error_frames(&self) -> u64 {
+        self.error_frames
+    }
+
+    /// Error frames of one kind, all [ErrorKind::Other] kinds together.
+    pub fn error_frames_of(&self, kind: ErrorKind) -> u64 {
+        match kind {
+            ErrorKind::Bit => self.kinds[0],
+            ErrorKind::Form => self.kinds[1],
+            ErrorKind::Stuff => self.kinds[2],
+            ErrorKind::Other(_) => self.kinds[3],
+        }
+    }
+
+    /// Transitions seen so far, oldest first.
+    pub fn history(&self) -> &[StateTransition] {
+        &self.history
+    }
+
+    fn transition(&mut self, to: ErrorState, timestamp: u64) -> Option<StateTransition> {
+        if to == self.state {
+            return None;
+        }
+        let transition = StateTransition {
+            from: self.state,
+            to,
+            timestamp,
+            tec: self.tec,
+            rec: self.rec,
+        };
+        self.state = to;
+        self.since = timestamp;
+
+        if self.history_limit > 0 {
+            if self.history.len() == self.history_limit {
+                self.history.remove(0);
+            }
+            self.history.push(transition);
+        }
+        self.subscribers.retain(|tx| tx.send(transition).is_ok());
+        Some(transition)
+    }
+
+    /// Updates the error counters, the state follows from them.
+    pub fn update_counters(&mut self, tec: u8, rec: u8, timestamp: u64) -> Option<StateTransition> {
+        self.tec = tec;
+        self.rec = rec;
+        self.transition(ErrorState::from_counters(tec as u16, rec as u16), timestamp)
+    }
+
+    /// Sets the state reported by the driver.
+    pub fn update_state(&mut self, state: ErrorState, timestamp: u64) -> Option<StateTransition> {
+        if state == ErrorState::Active {
+            self.tec = 0;
+            self.rec = 0;
+        }
+        self.transition(state, timestamp)
+    }
+
+    fn error_frame(&mut self, error: ErrorFrame, timestamp: u64) -> Option<StateTransition> {
+        self.error_frames += 1;
+        self.kinds[match error.kind {
+            ErrorKind::Bit => 0,
+            ErrorKind::Form => 1,
+            ErrorKind::Stuff => 2,
+            ErrorKind::Other(_) => 3,
+        }] += 1;
+        self.update_counters(error.tec, error.rec, timestamp)
+    }
+
+    /// Status frames carry the PCAN status value in their first four bytes, MSB first.
+    fn status_frame(&mut self, data: &[u8], timestamp: u64) -> Option<StateTransition> {
+        let status = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+        self.update_state(ErrorState::from_status(status)?, timestamp)
+    }
+
+    /// Feeds a received frame, other than error and status frames are ignored.
+    pub fn process(&mut self, frame: &CanFrame, timestamp: u64) -> Option<StateTransition> {
+        if let Some(error) = ErrorFrame::from_frame(frame) {
+            self.error_frame(error, timestamp)
+        } else if frame.is_status_frame() {
+            self.status_frame(frame.data(), timestamp)
+        } else {
+            None
+        }
+    }
+
+    pub fn process_fd(&mut self, frame: &CanFdFrame, timestamp: u64) -> Option<StateTransition> {
+        if let Some(error) = ErrorFrame::from_fd_frame(frame) {
+            self.error_frame(error, timestamp)
+        } else if frame.is_status_frame() {
+            self.status_frame(frame.data(), timestamp)
+        } else {
+            None
+        }
+    }
+
+    /// Reads the state from the driver.
+    pub fn poll<S: BusStatus>(
+        &mut self,
+        socket: &S,
+        timestamp: u64,
+    ) -> Result<Option<StateTransition>, CanError> {
+        let state = socket.bus_status()?;
+        Ok(self.update_state(state, timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_frame(kind: u32, tec: u8, rec: u8) -> CanFrame {
+        CanFrame::new_error(kind, &[1, 0, rec, tec]).unwrap()
+    }
+
+    #[test]
+    fn status_states() {
+        assert_eq!(ErrorState::from_counters(95, 0), ErrorState::Active);
+        assert_eq!(ErrorState::from_counters(0, 96), ErrorState::Warning);
+        assert_eq!(ErrorState::from_counters(128, 0), ErrorState::Passive);
+        assert_eq!(ErrorState::from_counters(256, 0), ErrorState::BusOff);
+
+        assert_eq!(ErrorState::from_status(0), Some(ErrorState::Active));
+        let status = peak_can::PEAK_ERROR_BUSOFF | peak_can::PEAK_ERROR_BUSPASSIVE;
+        assert_eq!(ErrorState::from_status(status), Some(ErrorState::BusOff));
+        assert_eq!(
+            ErrorState::from_status(peak_can::PEAK_ERROR_QRCVEMPTY),
+            None
+        );
+    }
+
+    #[test]
+    fn status_monitor_transitions() {
+        let mut monitor = ErrorMonitor::new();
+        let alarms = monitor.subscribe();
+        drop(monitor.subscribe());
+
+        assert_eq!(monitor.process(&error_frame(4, 8, 0), 100), None);
+        let warning = monitor.process(&error_frame(1, 96, 0), 200).unwrap();
+        assert_eq!(
+            (warning.from, warning.to),
+            (ErrorState::Active, ErrorState::Warning)
+        );
+        monitor.process(&error_frame(1, 136, 0), 300);
+        assert_eq!(
+            monitor.update_state(ErrorState::BusOff, 400).unwrap().tec,
+            136
+        );
+        monitor.update_state(ErrorState::Active, 900);
+
+        let received: Vec<_> = alarms.try_iter().map(|t| (t.to, t.timestamp)).collect();
+        assert_eq!(
+            received,
+            [
+                (ErrorState::Warning, 200),
+                (ErrorState::Passive, 300),
+                (ErrorState::BusOff, 400),
+                (ErrorState::Active, 900),
+            ]
+        );
+        assert_eq!(monitor.history().len(), 4);
+        assert_eq!(
+            (monitor.state(), monitor.since()),
+            (ErrorState::Active, 900)
+        );
+        assert_eq!((monitor.tec(), monitor.rec()), (0, 0));
+        assert_eq!(monitor.error_frames(), 3);
+        assert_eq!(monitor.error_frames_of(ErrorKind::Bit), 2);
+        assert_eq!(monitor.error_frames_of(ErrorKind::Stuff), 1);
+
+        let data = CanFrame::new(0x100, crate::socket::MessageType::Standard, &[1, 2, 3, 4]);
+        assert_eq!(monitor.process(&data.unwrap(), 1000), None);
+    }
+}
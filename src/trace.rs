@@ -200,7 +200,9 @@ pub fn set_default_trace_size<T: SetTraceSize>(value: &T) -> Result<(), CanError
 
 /* TRACE CONFIGURE traits */
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum TraceFile {
     Single,
     Segmented,
@@ -0,0 +1,834 @@
+//! UDS (ISO 14229) diagnostic client.
+//!
+//! A [UdsClient] sends diagnostic requests over an [IsoTpChannel] and waits for the
+//! matching response. Negative responses are returned as
+//! [UdsError::NegativeResponse] with a decoded [NegativeResponseCode]; a "response
+//! pending" (0x78) answer extends the wait to the P2* timeout reported by the ECU.
+//!
+//! The client does not spawn threads: the tester present keep-alive is sent by
+//! [keep_alive](UdsClient::keep_alive) or [idle](UdsClient::idle) whenever the
+//! configured interval has elapsed since the last request.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::isotp::{IsoTpChannel, IsoTpConfig, IsoTpLink};
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use peak_can::uds::{Session, UdsClient};
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let channel = IsoTpChannel::new(IsoTpLink::can(&socket), IsoTpConfig::new(0x7E0, 0x7E8))?;
+//! let client = UdsClient::new(channel);
+//!
+//! client.diagnostic_session_control(Session::Extended)?;
+//! client.security_access(0x01, &|_level: u8, seed: &[u8]| seed.iter().map(|b| b ^ 0x5A).collect())?;
+//! let vin = client.read_data_by_identifier(0xF190)?;
+//! println!("VIN {}", String::from_utf8_lossy(&vin));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::isotp::{IsoTpChannel, IsoTpError};
+
+use std::cell::Cell;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const SID_DIAGNOSTIC_SESSION_CONTROL: u8 = 0x10;
+pub const SID_ECU_RESET: u8 = 0x11;
+pub const SID_CLEAR_DIAGNOSTIC_INFORMATION: u8 = 0x14;
+pub const SID_READ_DTC_INFORMATION: u8 = 0x19;
+pub const SID_READ_DATA_BY_IDENTIFIER: u8 = 0x22;
+pub const SID_SECURITY_ACCESS: u8 = 0x27;
+pub const SID_WRITE_DATA_BY_IDENTIFIER: u8 = 0x2E;
+pub const SID_ROUTINE_CONTROL: u8 = 0x31;
+pub const SID_REQUEST_DOWNLOAD: u8 = 0x34;
+pub const SID_TRANSFER_DATA: u8 = 0x36;
+pub const SID_REQUEST_TRANSFER_EXIT: u8 = 0x37;
+pub const SID_TESTER_PRESENT: u8 = 0x3E;
+
+const NEGATIVE_RESPONSE: u8 = 0x7F;
+const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+const SUPPRESS_POSITIVE_RESPONSE: u8 = 0x80;
+
+/// Negative response codes of ISO 14229-1 annex A.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegativeResponseCode {
+    GeneralReject,
+    ServiceNotSupported,
+    SubFunctionNotSupported,
+    IncorrectMessageLengthOrInvalidFormat,
+    ResponseTooLong,
+    BusyRepeatRequest,
+    ConditionsNotCorrect,
+    RequestSequenceError,
+    NoResponseFromSubnetComponent,
+    FailurePreventsExecutionOfRequestedAction,
+    RequestOutOfRange,
+    SecurityAccessDenied,
+    InvalidKey,
+    ExceededNumberOfAttempts,
+    RequiredTimeDelayNotExpired,
+    UploadDownloadNotAccepted,
+    TransferDataSuspended,
+    GeneralProgrammingFailure,
+    WrongBlockSequenceCounter,
+    RequestCorrectlyReceivedResponsePending,
+    SubFunctionNotSupportedInActiveSession,
+    ServiceNotSupportedInActiveSession,
+    RpmTooHigh,
+    RpmTooLow,
+    EngineIsRunning,
+    EngineIsNotRunning,
+    EngineRunTimeTooLow,
+    TemperatureTooHigh,
+    TemperatureTooLow,
+    VehicleSpeedTooHigh,
+    VehicleSpeedTooLow,
+    ThrottlePedalTooHigh,
+    ThrottlePedalTooLow,
+    TransmissionRangeNotInNeutral,
+    TransmissionRangeNotInGear,
+    BrakeSwitchNotClosed,
+    ShifterLeverNotInPark,
+    TorqueConverterClutchLocked,
+    VoltageTooHigh,
+    VoltageTooLow,
+    Other(u8),
+}
+
+impl From<u8> for NegativeResponseCode {
+    fn from(value: u8) -> Self {
+        use NegativeResponseCode::*;
+        match value {
+            0x10 => GeneralReject,
+            0x11 => ServiceNotSupported,
+            0x12 => SubFunctionNotSupported,
+            0x13 => IncorrectMessageLengthOrInvalidFormat,
+            0x14 => ResponseTooLong,
+            0x21 => BusyRepeatRequest,
+            0x22 => ConditionsNotCorrect,
+            0x24 => RequestSequenceError,
+            0x25 => NoResponseFromSubnetComponent,
+            0x26 => FailurePreventsExecutionOfRequestedAction,
+            0x31 => RequestOutOfRange,
+            0x33 => SecurityAccessDenied,
+            0x35 => InvalidKey,
+            0x36 => ExceededNumberOfAttempts,
+            0x37 => RequiredTimeDelayNotExpired,
+            0x70 => UploadDownloadNotAccepted,
+            0x71 => TransferDataSuspended,
+            0x72 => GeneralProgrammingFailure,
+            0x73 => WrongBlockSequenceCounter,
+            0x78 => RequestCorrectlyReceivedResponsePending,
+            0x7E => SubFunctionNotSupportedInActiveSession,
+            0x7F => ServiceNotSupportedInActiveSession,
+            0x81 => RpmTooHigh,
+            0x82 => RpmTooLow,
+            0x83 => EngineIsRunning,
+            0x84 => EngineIsNotRunning,
+            0x85 => EngineRunTimeTooLow,
+            0x86 => TemperatureTooHigh,
+            0x87 => TemperatureTooLow,
+            0x88 => VehicleSpeedTooHigh,
+            0x89 => VehicleSpeedTooLow,
+            0x8A => ThrottlePedalTooHigh,
+            0x8B => ThrottlePedalTooLow,
+            0x8C => TransmissionRangeNotInNeutral,
+            0x8D => TransmissionRangeNotInGear,
+            0x8F => BrakeSwitchNotClosed,
+            0x90 => ShifterLeverNotInPark,
+            0x91 => TorqueConverterClutchLocked,
+            0x92 => VoltageTooHigh,
+            0x93 => VoltageTooLow,
+            other => Other(other),
+        }
+    }
+}
+
+impl fmt::Display for NegativeResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NegativeResponseCode::Other(code) => write!(f, "negative response 0x{code:02X}"),
+            known => write!(f, "{known:?}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UdsError {
+    IsoTp(IsoTpError),
+    /// The ECU rejected the request.
+    NegativeResponse {
+        service: u8,
+        code: NegativeResponseCode,
+    },
+    /// No response within P2 (or P2* after a response pending).
+    Timeout,
+    /// The response does not match the request.
+    UnexpectedResponse(Vec<u8>),
+    InvalidArgument(String),
+}
+
+impl fmt::Display for UdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UdsError::IsoTp(e) => write!(f, "{e}"),
+            UdsError::NegativeResponse { service, code } => {
+                write!(f, "service 0x{service:02X} rejected: {code}")
+            }
+            UdsError::Timeout => write!(f, "no response from ECU"),
+            UdsError::UnexpectedResponse(data) => write!(f, "unexpected response {data:02X?}"),
+            UdsError::InvalidArgument(s) => write!(f, "invalid argument: {s}"),
+        }
+    }
+}
+
+impl std::error::Error for UdsError {}
+
+impl From<IsoTpError> for UdsError {
+    fn from(value: IsoTpError) -> Self {
+        UdsError::IsoTp(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    Default,
+    Programming,
+    Extended,
+    SafetySystem,
+    Other(u8),
+}
+
+impl From<Session> for u8 {
+    fn from(value: Session) -> Self {
+        match value {
+            Session::Default => 0x01,
+            Session::Programming => 0x02,
+            Session::Extended => 0x03,
+            Session::SafetySystem => 0x04,
+            Session::Other(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+    Hard,
+    KeyOffOn,
+    Soft,
+    Other(u8),
+}
+
+impl From<ResetType> for u8 {
+    fn from(value: ResetType) -> Self {
+        match value {
+            ResetType::Hard => 0x01,
+            ResetType::KeyOffOn => 0x02,
+            ResetType::Soft => 0x03,
+            ResetType::Other(value) => value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutineControlType {
+    Start,
+    Stop,
+    RequestResults,
+}
+
+impl From<RoutineControlType> for u8 {
+    fn from(value: RoutineControlType) -> Self {
+        match value {
+            RoutineControlType::Start => 0x01,
+            RoutineControlType::Stop => 0x02,
+            RoutineControlType::RequestResults => 0x03,
+        }
+    }
+}
+
+/// Session timing reported by the ECU in the session control response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionTiming {
+    pub p2: Duration,
+    pub p2_star: Duration,
+}
+
+/// A diagnostic trouble code with its status byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dtc {
+    /// Three byte DTC number.
+    pub code: u32,
+    pub status: u8,
+}
+
+/// Memory area of a download request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRange {
+    address: u64,
+    size: u64,
+    address_len: u8,
+    size_len: u8,
+}
+
+impl MemoryRange {
+    /// Range with four byte address and size fields.
+    pub fn new(address: u32, size: u32) -> MemoryRange {
+        MemoryRange {
+            address: address as u64,
+            size: size as u64,
+            address_len: 4,
+            size_len: 4,
+        }
+    }
+
+    /// Range whose address and size are encoded in `address_len` and `size_len` bytes,
+    /// 1 to 8 each, large enough to hold the values.
+    pub fn with_lengths(
+        address: u64,
+        size: u64,
+        address_len: u8,
+        size_len: u8,
+    ) -> Result<MemoryRange, UdsError> {
+        let fits = |value: u64, len: u8| {
+            (1..=8).contains(&len) && (len == 8 || value >> (8 * len as u32) == 0)
+        };
+        if !fits(address, address_len) {
+            return Err(UdsError::InvalidArgument(format!(
+                "address {address:#X} in {address_len} bytes"
+            )));
+        }
+        if !fits(size, size_len) {
+            return Err(UdsError::InvalidArgument(format!(
+                "size {size:#X} in {size_len} bytes"
+            )));
+        }
+        Ok(MemoryRange {
+            address,
+            size,
+            address_len,
+            size_len,
+        })
+    }
+
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.size_len << 4 | self.address_len];
+        data.extend_from_slice(&self.address.to_be_bytes()[8 - self.address_len as usize..]);
+        data.extend_from_slice(&self.size.to_be_bytes()[8 - self.size_len as usize..]);
+        data
+    }
+}
+
+/// Computes the security access key for a seed.
+pub trait SeedKey {
+    /// `level` is the odd request seed sub-function.
+    fn compute_key(&self, level: u8, seed: &[u8]) -> Vec<u8>;
+}
+
+impl<F: Fn(u8, &[u8]) -> Vec<u8>> SeedKey for F {
+    fn compute_key(&self, level: u8, seed: &[u8]) -> Vec<u8> {
+        self(level, seed)
+    }
+}
+
+pub struct UdsClient<'a> {
+    channel: IsoTpChannel<'a>,
+    p2: Cell<Duration>,
+    p2_star: Cell<Duration>,
+    keep_alive: Cell<Option<Duration>>,
+    last_request: Cell<Instant>,
+}
+
+impl<'a> UdsClient<'a> {
+    /// Client with the default P2 of 50 ms and P2* of 5 s.
+    pub fn new(channel: IsoTpChannel<'a>) -> UdsClient<'a> {
+        UdsClient {
+            channel,
+            p2: Cell::new(Duration::from_millis(50)),
+            p2_star: Cell::new(Duration::from_millis(5000)),
+            keep_alive: Cell::new(None),
+            last_request: Cell::new(Instant::now()),
+        }
+    }
+
+    pub fn channel(&self) -> &IsoTpChannel<'a> {
+        &self.channel
+    }
+
+    pub fn timing(&self) -> SessionTiming {
+        SessionTiming {
+            p2: self.p2.get(),
+            p2_star: self.p2_star.get(),
+        }
+    }
+
+    /// Overrides the response timeouts, until the next session change.
+    pub fn set_timing(&self, timing: SessionTiming) {
+        self.p2.set(timing.p2);
+        self.p2_star.set(timing.p2_star);
+    }
+
+    /// Sets the tester present interval, `None` to disable the keep-alive.
+    pub fn set_keep_alive(&self, interval: Option<Duration>) {
+        self.keep_alive.set(interval);
+    }
+
+    /// Sends a tester present without response if the keep-alive interval has elapsed
+    /// since the last request.
+    pub fn keep_alive(&self) -> Result<(), UdsError> {
+        match self.keep_alive.get() {
+            Some(interval) if self.last_request.get().elapsed() >= interval => {
+                self.tester_present(true)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for `duration` while keeping the session alive.
+    pub fn idle(&self, duration: Duration) -> Result<(), UdsError> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.keep_alive()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            let mut pause = deadline - now;
+            if let Some(interval) = self.keep_alive.get() {
+                let due = (self.last_request.get() + interval).saturating_duration_since(now);
+                pause = pause.min(due);
+            }
+            thread::sleep(pause);
+        }
+    }
+
+    /// Sends a raw request without waiting for a response.
+    pub fn send(&self, request: &[u8]) -> Result<(), UdsError> {
+        self.channel.send(request)?;
+        self.last_request.set(Instant::now());
+        Ok(())
+    }
+
+    /// Sends a raw request and returns the positive response, including the service id.
+    pub fn request(&self, request: &[u8]) -> Result<Vec<u8>, UdsError> {
+        let Some(&service) = request.first() else {
+            return Err(UdsError::InvalidArgument(String::from("empty request")));
+        };
+        self.send(request)?;
+
+        let mut timeout = self.p2.get();
+        loop {
+            let Some(response) = self.channel.recv(timeout)? else {
+                return Err(UdsError::Timeout);
+            };
+
+            match response.as_slice() {
+                [NEGATIVE_RESPONSE, sid, code, ..] if *sid == service => {
+                    match NegativeResponseCode::from(*code) {
+                        NegativeResponseCode::RequestCorrectlyReceivedResponsePending => {
+                            timeout = self.p2_star.get();
+                        }
+                        code => return Err(UdsError::NegativeResponse { service, code }),
+                    }
+                }
+                [sid, ..] if *sid == service.wrapping_add(POSITIVE_RESPONSE_OFFSET) => {
+                    return Ok(response);
+                }
+                _ => return Err(UdsError::UnexpectedResponse(response)),
+            }
+        }
+    }
+
+    /// Checks the echoed bytes after the service id and strips them.
+    fn expect_echo(response: Vec<u8>, echo: &[u8]) -> Result<Vec<u8>, UdsError> {
+        if response.len() < 1 + echo.len() || &response[1..1 + echo.len()] != echo {
+            return Err(UdsError::UnexpectedResponse(response));
+        }
+        Ok(response[1 + echo.len()..].to_vec())
+    }
+
+    /// Changes the diagnostic session and adopts the P2 / P2* timing of the ECU.
+    pub fn diagnostic_session_control(&self, session: Session) -> Result<SessionTiming, UdsError> {
+        let session = u8::from(session);
+        let response = self.request(&[SID_DIAGNOSTIC_SESSION_CONTROL, session])?;
+        let record = Self::expect_echo(response, &[session])?;
+
+        let mut timing = self.timing();
+        if let [p2_hi, p2_lo, star_hi, star_lo, ..] = record[..] {
+            timing = SessionTiming {
+                p2: Duration::from_millis(u16::from_be_bytes([p2_hi, p2_lo]) as u64),
+                p2_star: Duration::from_millis(u16::from_be_bytes([star_hi, star_lo]) as u64 * 10),
+            };
+            self.set_timing(timing);
+        }
+        Ok(timing)
+    }
+
+    pub fn ecu_reset(&self, reset: ResetType) -> Result<(), UdsError> {
+        let reset = u8::from(reset);
+        let response = self.request(&[SID_ECU_RESET, reset])?;
+        Self::expect_echo(response, &[reset]).map(|_| ())
+    }
+
+    /// Unlocks the security level `level` (odd request seed sub-function).
+    ///
+    /// A zero seed means the level is already unlocked and no key is sent.
+    pub fn security_access(&self, level: u8, seed_key: &dyn SeedKey) -> Result<(), UdsError> {
+        // The send key sub-function is level + 1
+        if level.is_multiple_of(2) || level == 0xFF {
+            return Err(UdsError::InvalidArgument(format!(
+                "security level {level:#04X} is not a request seed sub-function"
+            )));
+        }
+        let response = self.request(&[SID_SECURITY_ACCESS, level])?;
+        let seed = Self::expect_echo(response, &[level])?;
+        if seed.iter().all(|b| *b == 0) {
+            return Ok(());
+        }
+
+        let key = seed_key.compute_key(level, &seed);
+        let mut request = vec![SID_SECURITY_ACCESS, level + 1];
+        request.extend_from_slice(&key);
+        let response = self.request(&request)?;
+        Self::expect_echo(response, &[level + 1]).map(|_| ())
+    }
+
+    pub fn read_data_by_identifier(&self, did: u16) -> Result<Vec<u8>, UdsError> {
+        let did = did.to_be_bytes();
+        let response = self.request(&[SID_READ_DATA_BY_IDENTIFIER, did[0], did[1]])?;
+        Self::expect_echo(response, &did)
+    }
+
+    pub fn write_data_by_identifier(&self, did: u16, data: &[u8]) -> Result<(), UdsError> {
+        let did = did.to_be_bytes();
+        let mut request = vec![SID_WRITE_DATA_BY_IDENTIFIER, did[0], did[1]];
+        request.extend_from_slice(data);
+        let response = self.request(&request)?;
+        Self::expect_echo(response, &did).map(|_| ())
+    }
+
+    /// Starts, stops or queries a routine and returns the routine status record.
+    pub fn routine_control(
+        &self,
+        control: RoutineControlType,
+        routine: u16,
+        data: &[u8],
+    ) -> Result<Vec<u8>, UdsError> {
+        let control = u8::from(control);
+        let routine = routine.to_be_bytes();
+        let mut request = vec![SID_ROUTINE_CONTROL, control, routine[0], routine[1]];
+        request.extend_from_slice(data);
+        let response = self.request(&request)?;
+        Self::expect_echo(response, &[control, routine[0], routine[1]])
+    }
+
+    /// Reads the DTCs matching `status_mask` (report DTC by status mask).
+    ///
+    /// Returns the status availability mask of the ECU and the DTCs.
+    pub fn read_dtc_by_status_mask(&self, status_mask: u8) -> Result<(u8, Vec<Dtc>), UdsError> {
+        const REPORT_DTC_BY_STATUS_MASK: u8 = 0x02;
+
+        let response = self.request(&[
+            SID_READ_DTC_INFORMATION,
+            REPORT_DTC_BY_STATUS_MASK,
+            status_mask,
+        ])?;
+        let record = Self::expect_echo(response, &[REPORT_DTC_BY_STATUS_MASK])?;
+        let Some((availability, dtcs)) = record.split_first() else {
+            return Err(UdsError::UnexpectedResponse(record));
+        };
+
+        let dtcs = dtcs
+            .chunks_exact(4)
+            .map(|c| Dtc {
+                code: u32::from_be_bytes([0, c[0], c[1], c[2]]),
+                status: c[3],
+            })
+            .collect();
+        Ok((*availability, dtcs))
+    }
+
+    /// Clears the DTCs of a group, `0xFFFFFF` for all groups.
+    pub fn clear_diagnostic_information(&self, group: u32) -> Result<(), UdsError> {
+        let group = group.to_be_bytes();
+        self.request(&[
+            SID_CLEAR_DIAGNOSTIC_INFORMATION,
+            group[1],
+            group[2],
+            group[3],
+        ])
+        .map(|_| ())
+    }
+
+    /// Sends a tester present. With `suppress` the ECU does not answer.
+    pub fn tester_present(&self, suppress: bool) -> Result<(), UdsError> {
+        if suppress {
+            self.send(&[SID_TESTER_PRESENT, SUPPRESS_POSITIVE_RESPONSE])
+        } else {
+            let response = self.request(&[SID_TESTER_PRESENT, 0x00])?;
+            Self::expect_echo(response, &[0x00]).map(|_| ())
+        }
+    }
+
+    /// Requests a download and returns the maximum length of a transfer data request.
+    pub fn request_download(&self, range: MemoryRange, data_format: u8) -> Result<usize, UdsError> {
+        let mut request = vec![SID_REQUEST_DOWNLOAD, data_format];
+        request.extend_from_slice(&range.encode());
+        let response = self.request(&request)?;
+
+        let length_len = response.get(1).map_or(0, |b| (b >> 4) as usize);
+        if length_len == 0 || length_len > 8 || response.len() < 2 + length_len {
+            return Err(UdsError::UnexpectedResponse(response));
+        }
+        let max_block = response[2..2 + length_len]
+            .iter()
+            .fold(0usize, |acc, b| acc << 8 | *b as usize);
+        Ok(max_block)
+    }
+
+    pub fn transfer_data(&self, sequence: u8, data: &[u8]) -> Result<Vec<u8>, UdsError> {
+        let mut request = vec![SID_TRANSFER_DATA, sequence];
+        request.extend_from_slice(data);
+        let response = self.request(&request)?;
+        Self::expect_echo(response, &[sequence])
+    }
+
+    pub fn request_transfer_exit(&self, data: &[u8]) -> Result<Vec<u8>, UdsError> {
+        let mut request = vec![SID_REQUEST_TRANSFER_EXIT];
+        request.extend_from_slice(data);
+        self.request(&request)
+            .map(|response| response[1..].to_vec())
+    }
+
+    /// Downloads `image` to the address of `range`: request download, transfer data in blocks
+    /// of the size accepted by the ECU and request transfer exit.
+    pub fn download(
+        &self,
+        range: MemoryRange,
+        data_format: u8,
+        image: &[u8],
+    ) -> Result<(), UdsError> {
+        let max_block = self.request_download(range, data_format)?;
+        // The block length includes service id and sequence counter
+        let chunk = max_block.saturating_sub(2).max(1);
+
+        let mut sequence = 1u8;
+        for block in image.chunks(chunk) {
+            self.transfer_data(sequence, block)?;
+            sequence = sequence.wrapping_add(1);
+        }
+        self.request_transfer_exit(&[]).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isotp::{IsoTpConfig, IsoTpLink};
+    use crate::socket::mock::{MockEnd, mock_bus};
+
+    /// Runs `tester` against an ECU answering each expected request with its responses.
+    fn with_ecu(script: Vec<(Vec<u8>, Vec<Vec<u8>>)>, tester: impl FnOnce(&UdsClient)) {
+        let (a, b) = mock_bus();
+        thread::scope(|scope| {
+            scope.spawn(|| ecu(&b, script));
+
+            let channel =
+                IsoTpChannel::new(IsoTpLink::can(&a), IsoTpConfig::new(0x7E0, 0x7E8)).unwrap();
+            tester(&UdsClient::new(channel));
+        });
+    }
+
+    fn ecu(end: &MockEnd, script: Vec<(Vec<u8>, Vec<Vec<u8>>)>) {
+        let channel =
+            IsoTpChannel::new(IsoTpLink::can(end), IsoTpConfig::new(0x7E8, 0x7E0)).unwrap();
+        for (expected, responses) in script {
+            let request = channel.recv(Duration::from_secs(2)).unwrap().unwrap();
+            assert_eq!(request, expected);
+            for response in responses {
+                channel.send(&response).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn uds_session_and_read_data() {
+        let vin = b"WVWZZZ1JZXW000001".to_vec();
+        let mut read = vec![0x62, 0xF1, 0x90];
+        read.extend_from_slice(&vin);
+
+        with_ecu(
+            vec![
+                (
+                    vec![0x10, 0x03],
+                    vec![vec![0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]],
+                ),
+                (vec![0x22, 0xF1, 0x90], vec![read]),
+                (vec![0x2E, 0xF1, 0x98, 1, 2], vec![vec![0x6E, 0xF1, 0x98]]),
+            ],
+            |client| {
+                let timing = client
+                    .diagnostic_session_control(Session::Extended)
+                    .unwrap();
+                assert_eq!(timing.p2, Duration::from_millis(50));
+                assert_eq!(timing.p2_star, Duration::from_millis(5000));
+                assert_eq!(client.read_data_by_identifier(0xF190).unwrap(), vin);
+                client.write_data_by_identifier(0xF198, &[1, 2]).unwrap();
+            },
+        );
+    }
+
+    #[test]
+    fn uds_negative_response_and_pending() {
+        with_ecu(
+            vec![
+                (
+                    vec![0x31, 0x01, 0xFF, 0x00],
+                    vec![
+                        vec![0x7F, 0x31, 0x78],
+                        vec![0x7F, 0x31, 0x78],
+                        vec![0x71, 0x01, 0xFF, 0x00, 0x02],
+                    ],
+                ),
+                (vec![0x11, 0x01], vec![vec![0x7F, 0x11, 0x22]]),
+                (vec![0x22, 0x12, 0x34], vec![vec![0x7F, 0x22, 0xF0]]),
+            ],
+            |client| {
+                let status = client
+                    .routine_control(RoutineControlType::Start, 0xFF00, &[])
+                    .unwrap();
+                assert_eq!(status, vec![0x02]);
+
+                assert!(matches!(
+                    client.ecu_reset(ResetType::Hard),
+                    Err(UdsError::NegativeResponse {
+                        service: 0x11,
+                        code: NegativeResponseCode::ConditionsNotCorrect
+                    })
+                ));
+                assert!(matches!(
+                    client.read_data_by_identifier(0x1234),
+                    Err(UdsError::NegativeResponse {
+                        code: NegativeResponseCode::Other(0xF0),
+                        ..
+                    })
+                ));
+            },
+        );
+    }
+
+    #[test]
+    fn uds_security_access() {
+        with_ecu(
+            vec![
+                (vec![0x27, 0x01], vec![vec![0x67, 0x01, 0x12, 0x34]]),
+                (vec![0x27, 0x02, 0x48, 0x6E], vec![vec![0x67, 0x02]]),
+                (vec![0x27, 0x03], vec![vec![0x67, 0x03, 0x00, 0x00]]),
+            ],
+            |client| {
+                let xor = |_level: u8, seed: &[u8]| seed.iter().map(|b| b ^ 0x5A).collect();
+                client.security_access(0x01, &xor).unwrap();
+                client.security_access(0x03, &xor).unwrap();
+            },
+        );
+    }
+
+    #[test]
+    fn uds_read_dtcs() {
+        with_ecu(
+            vec![(
+                vec![0x19, 0x02, 0x08],
+                vec![vec![
+                    0x59, 0x02, 0xFF, 0x01, 0x23, 0x45, 0x08, 0xC1, 0x00, 0x01, 0x2F,
+                ]],
+            )],
+            |client| {
+                let (availability, dtcs) = client.read_dtc_by_status_mask(0x08).unwrap();
+                assert_eq!(availability, 0xFF);
+                assert_eq!(
+                    dtcs,
+                    vec![
+                        Dtc {
+                            code: 0x012345,
+                            status: 0x08
+                        },
+                        Dtc {
+                            code: 0xC10001,
+                            status: 0x2F
+                        },
+                    ]
+                );
+            },
+        );
+    }
+
+    #[test]
+    fn uds_download() {
+        let image: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let mut script = vec![(
+            vec![
+                0x34, 0x00, 0x44, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x02, 0x58,
+            ],
+            vec![vec![0x74, 0x20, 0x01, 0x02]],
+        )];
+        for (i, block) in image.chunks(256).enumerate() {
+            let mut request = vec![0x36, i as u8 + 1];
+            request.extend_from_slice(block);
+            script.push((request, vec![vec![0x76, i as u8 + 1]]));
+        }
+        script.push((vec![0x37], vec![vec![0x77]]));
+
+        with_ecu(script, |client| {
+            client
+                .download(MemoryRange::new(0x0001_0000, 600), 0x00, &image)
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn uds_invalid_arguments() {
+        let range = MemoryRange::with_lengths(0x1_0000, 600, 3, 2).unwrap();
+        assert_eq!(range.encode(), [0x23, 0x01, 0x00, 0x00, 0x02, 0x58]);
+        assert!(MemoryRange::with_lengths(u64::MAX, 1, 8, 1).is_ok());
+        assert!(MemoryRange::with_lengths(0, 1, 0, 4).is_err());
+        assert!(MemoryRange::with_lengths(0, 1, 4, 9).is_err());
+        assert!(MemoryRange::with_lengths(0x1_0000, 1, 2, 1).is_err());
+
+        with_ecu(vec![], |client| {
+            assert!(matches!(
+                client.request(&[]),
+                Err(UdsError::InvalidArgument(_))
+            ));
+            let xor = |_level: u8, seed: &[u8]| seed.to_vec();
+            for level in [0x00, 0x02, 0xFF] {
+                assert!(matches!(
+                    client.security_access(level, &xor),
+                    Err(UdsError::InvalidArgument(_))
+                ));
+            }
+        });
+    }
+
+    #[test]
+    fn uds_tester_present_keep_alive() {
+        with_ecu(
+            vec![(vec![0x3E, 0x80], vec![]), (vec![0x3E, 0x80], vec![])],
+            |client| {
+                client.set_keep_alive(Some(Duration::from_millis(20)));
+                client.keep_alive().unwrap();
+                client.idle(Duration::from_millis(50)).unwrap();
+            },
+        );
+    }
+}
@@ -0,0 +1,1159 @@
+//! XCP on CAN master (ASAM MCD-1 XCP 1.x) for measurement and calibration.
+//!
+//! [XcpMaster] talks to one XCP slave over a pair of identifiers: command packets (CTO) go
+//! out on the master identifier, responses, events and DAQ packets (DTO) come back on the
+//! slave identifier. Over a [XcpLink::fd] link the packets may be as long as the slave's
+//! MAX_CTO / MAX_DTO allow, up to 64 bytes.
+//!
+//! Supported are session management (CONNECT, GET_STATUS, SYNCH), seed & key unlocking,
+//! memory access (SHORT_UPLOAD, SHORT_DOWNLOAD and MTA based UPLOAD / DOWNLOAD of any
+//! length) and dynamic DAQ configuration with decoding of the measured values and slave
+//! timestamps. DAQ packets must use the absolute ODT number identification field.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # use peak_can::bus::UsbBus;
+//! # use peak_can::socket::Baudrate;
+//! # use peak_can::socket::usb::UsbCanSocket;
+//! # use peak_can::xcp::{DaqList, OdtEntry, XcpLink, XcpMaster};
+//! # use std::time::Duration;
+//! let socket = UsbCanSocket::open(UsbBus::USB1, Baudrate::Baud500K)?;
+//! let master = XcpMaster::new(XcpLink::can(&socket), 0x7F0, 0x7F1);
+//!
+//! let slave = master.connect()?;
+//! println!("MAX_CTO {} MAX_DTO {}", slave.max_cto, slave.max_dto);
+//! let value = master.short_upload(0x2000_1000, 0, 4)?;
+//!
+//! master.configure_daq(&[DaqList {
+//!     event_channel: 0,
+//!     prescaler: 1,
+//!     priority: 0,
+//!     timestamp: true,
+//!     odts: vec![vec![OdtEntry { address: 0x2000_1000, extension: 0, size: 4 }]],
+//! }])?;
+//! master.start_daq()?;
+//! while let Some(packet) = master.recv_daq(Duration::from_secs(1))? {
+//!     println!("{:?} {:02X?}", packet.timestamp, packet.values);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::error::CanError;
+use crate::socket::{
+    self, CanFdFrame, CanFrame, FrameConstructionError, MessageType, RecvCan, RecvCanFd, SendCan,
+    SendCanFd,
+};
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/* Command codes */
+
+pub const CMD_CONNECT: u8 = 0xFF;
+pub const CMD_DISCONNECT: u8 = 0xFE;
+pub const CMD_GET_STATUS: u8 = 0xFD;
+pub const CMD_SYNCH: u8 = 0xFC;
+pub const CMD_GET_SEED: u8 = 0xF8;
+pub const CMD_UNLOCK: u8 = 0xF7;
+pub const CMD_SET_MTA: u8 = 0xF6;
+pub const CMD_UPLOAD: u8 = 0xF5;
+pub const CMD_SHORT_UPLOAD: u8 = 0xF4;
+pub const CMD_DOWNLOAD: u8 = 0xF0;
+pub const CMD_SHORT_DOWNLOAD: u8 = 0xED;
+pub const CMD_SET_DAQ_PTR: u8 = 0xE2;
+pub const CMD_WRITE_DAQ: u8 = 0xE1;
+pub const CMD_SET_DAQ_LIST_MODE: u8 = 0xE0;
+pub const CMD_START_STOP_DAQ_LIST: u8 = 0xDE;
+pub const CMD_START_STOP_SYNCH: u8 = 0xDD;
+pub const CMD_GET_DAQ_PROCESSOR_INFO: u8 = 0xDA;
+pub const CMD_GET_DAQ_RESOLUTION_INFO: u8 = 0xD9;
+pub const CMD_FREE_DAQ: u8 = 0xD6;
+pub const CMD_ALLOC_DAQ: u8 = 0xD5;
+pub const CMD_ALLOC_ODT: u8 = 0xD4;
+pub const CMD_ALLOC_ODT_ENTRY: u8 = 0xD3;
+
+const PID_RESPONSE: u8 = 0xFF;
+const PID_ERROR: u8 = 0xFE;
+const PID_EVENT: u8 = 0xFD;
+const PID_SERVICE: u8 = 0xFC;
+
+const ERR_CMD_SYNCH: u8 = 0x00;
+const DAQ_LIST_MODE_TIMESTAMP: u8 = 0x10;
+
+/// Error code of a negative response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XcpErrorCode(pub u8);
+
+impl XcpErrorCode {
+    pub fn description(&self) -> &'static str {
+        match self.0 {
+            0x00 => "command processor synchronization",
+            0x10 => "command was not executed, slave busy",
+            0x11 => "command rejected because DAQ is running",
+            0x12 => "command rejected because PGM is running",
+            0x20 => "unknown command or not implemented optional command",
+            0x21 => "command syntax invalid",
+            0x22 => "command syntax valid but command parameter(s) out of range",
+            0x23 => "the memory location is write protected",
+            0x24 => "the memory location is not accessible",
+            0x25 => "access denied, seed & key is required",
+            0x26 => "selected page not available",
+            0x27 => "selected page mode not available",
+            0x28 => "selected segment not valid",
+            0x29 => "sequence error",
+            0x2A => "DAQ configuration not valid",
+            0x30 => "memory overflow error",
+            0x31 => "generic error",
+            0x32 => "the slave internal program verify routine detects an error",
+            _ => "unknown error code",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum XcpError {
+    Can(CanError),
+    Frame(FrameConstructionError),
+    /// The slave did not answer within the timeout.
+    Timeout,
+    /// The command needs a connected session.
+    NotConnected,
+    /// The slave rejected `command`.
+    Command {
+        command: u8,
+        code: XcpErrorCode,
+    },
+    InvalidArgument(String),
+    /// The slave uses an option this master does not implement.
+    Unsupported(String),
+    Protocol(String),
+}
+
+impl fmt::Display for XcpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XcpError::Can(e) => write!(f, "{e}"),
+            XcpError::Frame(e) => write!(f, "invalid frame: {e:?}"),
+            XcpError::Timeout => write!(f, "XCP slave did not respond"),
+            XcpError::NotConnected => write!(f, "not connected to an XCP slave"),
+            XcpError::Command { command, code } => write!(
+                f,
+                "command 0x{command:02X} failed with 0x{:02X}: {}",
+                code.0,
+                code.description()
+            ),
+            XcpError::InvalidArgument(s) => write!(f, "invalid argument: {s}"),
+            XcpError::Unsupported(s) => write!(f, "unsupported: {s}"),
+            XcpError::Protocol(s) => write!(f, "protocol error: {s}"),
+        }
+    }
+}
+
+impl std::error::Error for XcpError {}
+
+impl From<CanError> for XcpError {
+    fn from(value: CanError) -> Self {
+        XcpError::Can(value)
+    }
+}
+
+impl From<FrameConstructionError> for XcpError {
+    fn from(value: FrameConstructionError) -> Self {
+        XcpError::Frame(value)
+    }
+}
+
+/// Sockets an XCP master sends and receives packets on.
+#[derive(Clone, Copy)]
+pub enum XcpLink<'a> {
+    Can {
+        tx: &'a dyn SendCan,
+        rx: &'a dyn RecvCan,
+    },
+    Fd {
+        tx: &'a dyn SendCanFd,
+        rx: &'a dyn RecvCanFd,
+        brs: bool,
+    },
+}
+
+impl<'a> XcpLink<'a> {
+    /// Classic CAN link, packets of up to 8 bytes.
+    pub fn can<S: SendCan + RecvCan>(socket: &'a S) -> XcpLink<'a> {
+        XcpLink::Can {
+            tx: socket,
+            rx: socket,
+        }
+    }
+
+    /// CAN FD link, packets of up to 64 bytes.
+    pub fn fd<S: SendCanFd + RecvCanFd>(socket: &'a S, brs: bool) -> XcpLink<'a> {
+        XcpLink::Fd {
+            tx: socket,
+            rx: socket,
+            brs,
+        }
+    }
+
+    fn max_packet_len(&self) -> usize {
+        match self {
+            XcpLink::Can { .. } => 8,
+            XcpLink::Fd { .. } => 64,
+        }
+    }
+
+    /// Receives one frame as (identifier, data, timestamp in microseconds).
+    fn recv(&self) -> Result<(u32, Vec<u8>, u64), CanError> {
+        match self {
+            XcpLink::Can { rx, .. } => rx
+                .recv()
+                .map(|(frame, ts)| (frame.can_id(), frame.data().to_vec(), ts.as_micros())),
+            XcpLink::Fd { rx, .. } => rx
+                .recv_fd()
+                .map(|(frame, ts)| (frame.can_id(), frame.data().to_vec(), ts)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Intel,
+    Motorola,
+}
+
+/// Slave properties reported by CONNECT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlaveInfo {
+    /// Available resources (CAL/PAG, DAQ, STIM, PGM bits).
+    pub resources: u8,
+    pub byte_order: ByteOrder,
+    /// Bytes per memory element: 1, 2 or 4.
+    pub address_granularity: usize,
+    pub max_cto: usize,
+    pub max_dto: usize,
+    pub protocol_version: u8,
+    pub transport_version: u8,
+}
+
+/// Answer to GET_STATUS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlaveStatus {
+    pub session_status: u8,
+    /// Resources that are still protected by seed & key.
+    pub protection: u8,
+    pub session_config_id: u16,
+}
+
+/// Computes the unlock key for a seed.
+pub trait XcpSeedKey {
+    /// `resource` is the resource mask being unlocked.
+    fn compute_key(&self, resource: u8, seed: &[u8]) -> Vec<u8>;
+}
+
+impl<F: Fn(u8, &[u8]) -> Vec<u8>> XcpSeedKey for F {
+    fn compute_key(&self, resource: u8, seed: &[u8]) -> Vec<u8> {
+        self(resource, seed)
+    }
+}
+
+/* DAQ */
+
+/// One measured memory location of an ODT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OdtEntry {
+    pub address: u32,
+    pub extension: u8,
+    /// Size in bytes.
+    pub size: u8,
+}
+
+/// A dynamic DAQ list: ODTs sampled on one event channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaqList {
+    pub event_channel: u16,
+    pub prescaler: u8,
+    pub priority: u8,
+    /// Request a slave timestamp in the first ODT of every sample.
+    pub timestamp: bool,
+    pub odts: Vec<Vec<OdtEntry>>,
+}
+
+/// A decoded DAQ packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaqPacket {
+    /// DAQ list number.
+    pub daq: u16,
+    /// ODT number relative to the DAQ list.
+    pub odt: u8,
+    /// Slave timestamp, only in the first ODT of lists with timestamps.
+    pub timestamp: Option<Duration>,
+    /// Receive timestamp of the frame in microseconds.
+    pub received: u64,
+    /// Data of each ODT entry.
+    pub values: Vec<Vec<u8>>,
+}
+
+/// Timestamp format of the DAQ processor.
+#[derive(Debug, Clone, Copy)]
+struct TimestampFormat {
+    size: usize,
+    /// Duration of one tick in nanoseconds.
+    tick_ns: u64,
+}
+
+/// A configured DAQ list.
+struct DaqState {
+    number: u16,
+    list: DaqList,
+}
+
+pub struct XcpMaster<'a> {
+    link: XcpLink<'a>,
+    master_id: u32,
+    slave_id: u32,
+    msg_type: MessageType,
+    timeout: Cell<Duration>,
+    padding: Cell<Option<u8>>,
+    slave: Cell<Option<SlaveInfo>>,
+    timestamp_format: Cell<Option<TimestampFormat>>,
+    daq_lists: RefCell<Vec<DaqState>>,
+    /// Absolute ODT number to index into `daq_lists` and ODT.
+    odt_map: RefCell<BTreeMap<u8, (usize, u8)>>,
+    /// DAQ packets received while waiting for a command response.
+    pending: RefCell<VecDeque<(Vec<u8>, u64)>>,
+}
+
+impl<'a> XcpMaster<'a> {
+    /// Creates a master sending commands on `master_id` and receiving on `slave_id`.
+    ///
+    /// Identifiers above 0x7FF select 29 bit frames.
+    pub fn new(link: XcpLink<'a>, master_id: u32, slave_id: u32) -> XcpMaster<'a> {
+        let msg_type = if master_id > socket::STANDARD_MASK || slave_id > socket::STANDARD_MASK {
+            MessageType::Extended
+        } else {
+            MessageType::Standard
+        };
+        XcpMaster {
+            link,
+            master_id,
+            slave_id,
+            msg_type,
+            timeout: Cell::new(Duration::from_millis(25)),
+            padding: Cell::new(None),
+            slave: Cell::new(None),
+            timestamp_format: Cell::new(None),
+            daq_lists: RefCell::new(Vec::new()),
+            odt_map: RefCell::new(BTreeMap::new()),
+            pending: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Time to wait for a command response (T1), 25 ms by default.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout.set(timeout);
+    }
+
+    /// Pads classic frames to 8 bytes with `padding`. CAN FD frames are always padded to the
+    /// next valid length, with zeros by default.
+    pub fn set_padding(&self, padding: Option<u8>) {
+        self.padding.set(padding);
+    }
+
+    /// Slave properties of the current session.
+    pub fn slave(&self) -> Option<SlaveInfo> {
+        self.slave.get()
+    }
+
+    fn session(&self) -> Result<SlaveInfo, XcpError> {
+        self.slave.get().ok_or(XcpError::NotConnected)
+    }
+
+    /* Transport */
+
+    fn send_packet(&self, packet: &[u8]) -> Result<(), XcpError> {
+        let mut data = packet.to_vec();
+        match self.link {
+            XcpLink::Can { tx, .. } => {
+                if let Some(padding) = self.padding.get() {
+                    data.resize(8, padding);
+                }
+                tx.send(CanFrame::new(self.master_id, self.msg_type, &data)?)?;
+            }
+            XcpLink::Fd { tx, brs, .. } => {
+                let len = CanFdFrame::dlc_to_len(CanFdFrame::calc_dlc(data.len()));
+                data.resize(len, self.padding.get().unwrap_or(0));
+                tx.send_fd(CanFdFrame::new(
+                    self.master_id,
+                    self.msg_type,
+                    &data,
+                    true,
+                    brs,
+                )?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a command and waits for its positive response.
+    fn command(&self, command: &[u8]) -> Result<Vec<u8>, XcpError> {
+        let max_cto = self
+            .slave
+            .get()
+            .map_or(self.link.max_packet_len(), |s| s.max_cto);
+        if command.len() > max_cto {
+            return Err(XcpError::InvalidArgument(format!(
+                "command of {} bytes exceeds MAX_CTO {max_cto}",
+                command.len()
+            )));
+        }
+        self.send_packet(command)?;
+
+        let deadline = Instant::now() + self.timeout.get();
+        loop {
+            let Some((can_id, data, ts)) = socket::poll_until(deadline, || self.link.recv())?
+            else {
+                return Err(XcpError::Timeout);
+            };
+            if can_id != self.slave_id || data.is_empty() {
+                continue;
+            }
+            match data[0] {
+                PID_RESPONSE => return Ok(data),
+                PID_ERROR => {
+                    return Err(XcpError::Command {
+                        command: command[0],
+                        code: XcpErrorCode(data.get(1).copied().unwrap_or(0x31)),
+                    });
+                }
+                PID_EVENT | PID_SERVICE => continue,
+                _ => self.pending.borrow_mut().push_back((data, ts)),
+            }
+        }
+    }
+
+    fn response(data: &[u8], len: usize) -> Result<(), XcpError> {
+        if data.len() < len {
+            return Err(XcpError::Protocol(format!(
+                "response too short: {data:02X?}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        match self.slave.get().map(|s| s.byte_order) {
+            Some(ByteOrder::Motorola) => value.to_be_bytes(),
+            _ => value.to_le_bytes(),
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        match self.slave.get().map(|s| s.byte_order) {
+            Some(ByteOrder::Motorola) => value.to_be_bytes(),
+            _ => value.to_le_bytes(),
+        }
+    }
+
+    fn read_uint(&self, data: &[u8]) -> u64 {
+        let fold = |acc: u64, b: &u8| acc << 8 | *b as u64;
+        match self.slave.get().map(|s| s.byte_order) {
+            Some(ByteOrder::Motorola) => data.iter().fold(0, fold),
+            _ => data.iter().rev().fold(0, fold),
+        }
+    }
+
+    /* Session */
+
+    /// Opens a session in normal mode.
+    pub fn connect(&self) -> Result<SlaveInfo, XcpError> {
+        self.slave.set(None);
+        let r = self.command(&[CMD_CONNECT, 0x00])?;
+        Self::response(&r, 8)?;
+
+        let comm_mode = r[2];
+        let byte_order = if comm_mode & 0x01 != 0 {
+            ByteOrder::Motorola
+        } else {
+            ByteOrder::Intel
+        };
+        let max_dto = match byte_order {
+            ByteOrder::Intel => u16::from_le_bytes([r[4], r[5]]),
+            ByteOrder::Motorola => u16::from_be_bytes([r[4], r[5]]),
+        } as usize;
+        let info = SlaveInfo {
+            resources: r[1],
+            byte_order,
+            address_granularity: match (comm_mode >> 1) & 0x03 {
+                0 => 1,
+                1 => 2,
+                2 => 4,
+                _ => return Err(XcpError::Protocol("invalid address granularity".into())),
+            },
+            max_cto: r[3] as usize,
+            max_dto,
+            protocol_version: r[6],
+            transport_version: r[7],
+        };
+        let max = self.link.max_packet_len();
+        if info.max_cto < 8 || info.max_cto > max || info.max_dto < 8 || info.max_dto > max {
+            return Err(XcpError::Unsupported(format!(
+                "MAX_CTO {} / MAX_DTO {} on a link of {max} bytes",
+                info.max_cto, info.max_dto
+            )));
+        }
+        self.slave.set(Some(info));
+        Ok(info)
+    }
+
+    pub fn disconnect(&self) -> Result<(), XcpError> {
+        self.session()?;
+        self.command(&[CMD_DISCONNECT])?;
+        self.slave.set(None);
+        self.odt_map.borrow_mut().clear();
+        Ok(())
+    }
+
+    pub fn get_status(&self) -> Result<SlaveStatus, XcpError> {
+        self.session()?;
+        let r = self.command(&[CMD_GET_STATUS])?;
+        Self::response(&r, 6)?;
+        Ok(SlaveStatus {
+            session_status: r[1],
+            protection: r[2],
+            session_config_id: self.read_uint(&r[4..6]) as u16,
+        })
+    }
+
+    /// Resynchronizes the command processor after a timeout.
+    pub fn synch(&self) -> Result<(), XcpError> {
+        match self.command(&[CMD_SYNCH]) {
+            Err(XcpError::Command { code, .. }) if code.0 == ERR_CMD_SYNCH => Ok(()),
+            Ok(r) => Err(XcpError::Protocol(format!(
+                "unexpected response to SYNCH: {r:02X?}"
+            ))),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Unlocks `resource` with seed & key and returns the still protected resources.
+    pub fn unlock(&self, resource: u8, seed_key: &dyn XcpSeedKey) -> Result<u8, XcpError> {
+        let info = self.session()?;
+        let r = self.command(&[CMD_GET_SEED, 0x00, resource])?;
+        Self::response(&r, 2)?;
+        let len = r[1] as usize;
+        if len == 0 {
+            return Ok(self.get_status()?.protection);
+        }
+        let mut seed = r[2..r.len().min(2 + len)].to_vec();
+        while seed.len() < len {
+            let r = self.command(&[CMD_GET_SEED, 0x01, resource])?;
+            Self::response(&r, 3)?;
+            let remaining = (r[1] as usize).min(len - seed.len());
+            seed.extend_from_slice(&r[2..r.len().min(2 + remaining)]);
+        }
+
+        let key = seed_key.compute_key(resource, &seed);
+        if key.is_empty() || key.len() > 0xFF {
+            return Err(XcpError::InvalidArgument(format!(
+                "key of {} bytes",
+                key.len()
+            )));
+        }
+        let mut protection = 0;
+        let mut sent = 0;
+        for chunk in key.chunks(info.max_cto - 2) {
+            let mut command = vec![CMD_UNLOCK, (key.len() - sent) as u8];
+            command.extend_from_slice(chunk);
+            let r = self.command(&command)?;
+            Self::response(&r, 2)?;
+            protection = r[1];
+            sent += chunk.len();
+        }
+        Ok(protection)
+    }
+
+    /* Memory */
+
+    fn check_aligned(info: &SlaveInfo, len: usize) -> Result<(), XcpError> {
+        if !len.is_multiple_of(info.address_granularity) {
+            return Err(XcpError::InvalidArgument(format!(
+                "{len} bytes is not a multiple of the address granularity {}",
+                info.address_granularity
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn set_mta(&self, address: u32, extension: u8) -> Result<(), XcpError> {
+        self.session()?;
+        let mut command = vec![CMD_SET_MTA, 0, 0, extension];
+        command.extend_from_slice(&self.u32_bytes(address));
+        self.command(&command)?;
+        Ok(())
+    }
+
+    /// Reads `len` bytes in one command.
+    pub fn short_upload(
+        &self,
+        address: u32,
+        extension: u8,
+        len: usize,
+    ) -> Result<Vec<u8>, XcpError> {
+        let info = self.session()?;
+        Self::check_aligned(&info, len)?;
+        let ag = info.address_granularity;
+        if len > info.max_cto - ag {
+            return Err(XcpError::InvalidArgument(format!(
+                "{len} bytes do not fit a response of MAX_CTO {}",
+                info.max_cto
+            )));
+        }
+        let mut command = vec![CMD_SHORT_UPLOAD, (len / ag) as u8, 0, extension];
+        command.extend_from_slice(&self.u32_bytes(address));
+        let r = self.command(&command)?;
+        Self::response(&r, ag + len)?;
+        Ok(r[ag..ag + len].to_vec())
+    }
+
+    /// Writes `data` in one command, the packet needs 8 bytes of header.
+    pub fn short_download(&self, address: u32, extension: u8, data: &[u8]) -> Result<(), XcpError> {
+        let info = self.session()?;
+        Self::check_aligned(&info, data.len())?;
+        if data.len() > info.max_cto - 8 {
+            return Err(XcpError::InvalidArgument(format!(
+                "{} bytes do not fit a command of MAX_CTO {}",
+                data.len(),
+                info.max_cto
+            )));
+        }
+        let mut command = vec![
+            CMD_SHORT_DOWNLOAD,
+            (data.len() / info.address_granularity) as u8,
+            0,
+            extension,
+        ];
+        command.extend_from_slice(&self.u32_bytes(address));
+        command.extend_from_slice(data);
+        self.command(&command)?;
+        Ok(())
+    }
+
+    /// Reads any number of bytes with SET_MTA and repeated UPLOAD commands.
+    pub fn upload(&self, address: u32, extension: u8, len: usize) -> Result<Vec<u8>, XcpError> {
+        let info = self.session()?;
+        Self::check_aligned(&info, len)?;
+        let ag = info.address_granularity;
+        self.set_mta(address, extension)?;
+
+        // The response starts with the PID and alignment bytes up to one element
+        let chunk = (info.max_cto - ag) / ag * ag;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let n = chunk.min(len - data.len());
+            let r = self.command(&[CMD_UPLOAD, (n / ag) as u8])?;
+            Self::response(&r, ag + n)?;
+            data.extend_from_slice(&r[ag..ag + n]);
+        }
+        Ok(data)
+    }
+
+    /// Writes any number of bytes with SET_MTA and repeated DOWNLOAD commands.
+    pub fn download(&self, address: u32, extension: u8, data: &[u8]) -> Result<(), XcpError> {
+        let info = self.session()?;
+        Self::check_aligned(&info, data.len())?;
+        let ag = info.address_granularity;
+        self.set_mta(address, extension)?;
+
+        // Command code, element count and alignment bytes precede the data
+        let header = ag.max(2);
+        let chunk = (info.max_cto - header) / ag * ag;
+        for part in data.chunks(chunk) {
+            let mut command = vec![CMD_DOWNLOAD, (part.len() / ag) as u8];
+            command.resize(header, 0);
+            command.extend_from_slice(part);
+            self.command(&command)?;
+        }
+        Ok(())
+    }
+
+    /* DAQ */
+
+    /// Replaces the dynamic DAQ configuration of the slave with `lists`.
+    pub fn configure_daq(&self, lists: &[DaqList]) -> Result<(), XcpError> {
+        let info = self.session()?;
+        self.odt_map.borrow_mut().clear();
+        self.daq_lists.borrow_mut().clear();
+
+        let r = self.command(&[CMD_GET_DAQ_PROCESSOR_INFO])?;
+        Self::response(&r, 8)?;
+        if r[1] & 0x01 == 0 {
+            return Err(XcpError::Unsupported("static DAQ configuration".into()));
+        }
+        if (r[7] >> 6) & 0x03 != 0 {
+            return Err(XcpError::Unsupported(
+                "DAQ identification field other than absolute ODT number".into(),
+            ));
+        }
+        let min_daq = r[6] as u16;
+
+        let timestamp_size = if lists.iter().any(|l| l.timestamp) {
+            let r = self.command(&[CMD_GET_DAQ_RESOLUTION_INFO])?;
+            Self::response(&r, 8)?;
+            let size = match r[5] & 0x07 {
+                size @ (1 | 2 | 4) => size as usize,
+                _ => return Err(XcpError::Unsupported("slave has no DAQ timestamps".into())),
+            };
+            let ticks = self.read_uint(&r[6..8]).max(1);
+            self.timestamp_format.set(Some(TimestampFormat {
+                size,
+                tick_ns: ticks * 10u64.pow((r[5] >> 4).min(9) as u32),
+            }));
+            size
+        } else {
+            0
+        };
+
+        let mut absolute_odts = 0;
+        for list in lists {
+            if list.odts.is_empty() || list.odts.iter().any(|odt| odt.is_empty()) {
+                return Err(XcpError::InvalidArgument("empty DAQ list or ODT".into()));
+            }
+            for (i, odt) in list.odts.iter().enumerate() {
+                let header = 1 + if i == 0 && list.timestamp {
+                    timestamp_size
+                } else {
+                    0
+                };
+                let len = header + odt.iter().map(|e| e.size as usize).sum::<usize>();
+                if len > info.max_dto {
+                    return Err(XcpError::InvalidArgument(format!(
+                        "ODT of {len} bytes exceeds MAX_DTO {}",
+                        info.max_dto
+                    )));
+                }
+            }
+            absolute_odts += list.odts.len();
+        }
+        if absolute_odts > 0xFC {
+            return Err(XcpError::InvalidArgument(format!(
+                "{absolute_odts} ODTs exceed the identification field"
+            )));
+        }
+
+        self.command(&[CMD_FREE_DAQ])?;
+        let mut command = vec![CMD_ALLOC_DAQ, 0];
+        command.extend_from_slice(&self.u16_bytes(lists.len() as u16));
+        self.command(&command)?;
+
+        let number = |i: usize| min_daq + i as u16;
+        for (i, list) in lists.iter().enumerate() {
+            let mut command = vec![CMD_ALLOC_ODT, 0];
+            command.extend_from_slice(&self.u16_bytes(number(i)));
+            command.push(list.odts.len() as u8);
+            self.command(&command)?;
+        }
+        for (i, list) in lists.iter().enumerate() {
+            for (odt, entries) in list.odts.iter().enumerate() {
+                let mut command = vec![CMD_ALLOC_ODT_ENTRY, 0];
+                command.extend_from_slice(&self.u16_bytes(number(i)));
+                command.extend_from_slice(&[odt as u8, entries.len() as u8]);
+                self.command(&command)?;
+            }
+        }
+        for (i, list) in lists.iter().enumerate() {
+            for (odt, entries) in list.odts.iter().enumerate() {
+                let mut command = vec![CMD_SET_DAQ_PTR, 0];
+                command.extend_from_slice(&self.u16_bytes(number(i)));
+                command.extend_from_slice(&[odt as u8, 0]);
+                self.command(&command)?;
+
+                for entry in entries {
+                    let mut command = vec![CMD_WRITE_DAQ, 0xFF, entry.size, entry.extension];
+                    command.extend_from_slice(&self.u32_bytes(entry.address));
+                    self.command(&command)?;
+                }
+            }
+
+            let mode = if list.timestamp {
+                DAQ_LIST_MODE_TIMESTAMP
+            } else {
+                0
+            };
+            let mut command = vec![CMD_SET_DAQ_LIST_MODE, mode];
+            command.extend_from_slice(&self.u16_bytes(number(i)));
+            command.extend_from_slice(&self.u16_bytes(list.event_channel));
+            command.extend_from_slice(&[list.prescaler, list.priority]);
+            self.command(&command)?;
+        }
+
+        *self.daq_lists.borrow_mut() = lists
+            .iter()
+            .enumerate()
+            .map(|(i, list)| DaqState {
+                number: number(i),
+                list: list.clone(),
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Starts all configured DAQ lists synchronously.
+    pub fn start_daq(&self) -> Result<(), XcpError> {
+        self.session()?;
+        let mut odt_map = BTreeMap::new();
+        for (i, state) in self.daq_lists.borrow().iter().enumerate() {
+            let mut command = vec![CMD_START_STOP_DAQ_LIST, 0x02];
+            command.extend_from_slice(&self.u16_bytes(state.number));
+            let r = self.command(&command)?;
+            Self::response(&r, 2)?;
+            for odt in 0..state.list.odts.len() {
+                odt_map.insert(r[1].wrapping_add(odt as u8), (i, odt as u8));
+            }
+        }
+        if odt_map.is_empty() {
+            return Err(XcpError::InvalidArgument("no DAQ list configured".into()));
+        }
+        *self.odt_map.borrow_mut() = odt_map;
+        self.command(&[CMD_START_STOP_SYNCH, 0x01])?;
+        Ok(())
+    }
+
+    /// Stops all running DAQ lists.
+    pub fn stop_daq(&self) -> Result<(), XcpError> {
+        self.session()?;
+        self.command(&[CMD_START_STOP_SYNCH, 0x00])?;
+        self.odt_map.borrow_mut().clear();
+        self.pending.borrow_mut().clear();
+        Ok(())
+    }
+
+    fn decode_daq(&self, data: &[u8], received: u64) -> Option<DaqPacket> {
+        let (index, odt) = *self.odt_map.borrow().get(data.first()?)?;
+        let lists = self.daq_lists.borrow();
+        let state = &lists[index];
+
+        let mut offset = 1;
+        let timestamp = match self.timestamp_format.get() {
+            Some(format) if odt == 0 && state.list.timestamp => {
+                let raw = self.read_uint(data.get(offset..offset + format.size)?);
+                offset += format.size;
+                Some(Duration::from_nanos(raw.wrapping_mul(format.tick_ns)))
+            }
+            _ => None,
+        };
+        let mut values = Vec::new();
+        for entry in &state.list.odts[odt as usize] {
+            let size = entry.size as usize;
+            values.push(data.get(offset..offset + size)?.to_vec());
+            offset += size;
+        }
+        Some(DaqPacket {
+            daq: state.number,
+            odt,
+            timestamp,
+            received,
+            values,
+        })
+    }
+
+    /// Waits for the next DAQ packet, `Ok(None)` when none arrives within `timeout`.
+    pub fn recv_daq(&self, timeout: Duration) -> Result<Option<DaqPacket>, XcpError> {
+        while let Some((data, received)) = self.pending.borrow_mut().pop_front() {
+            if let Some(packet) = self.decode_daq(&data, received) {
+                return Ok(Some(packet));
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let Some((can_id, data, received)) = socket::poll_until(deadline, || self.link.recv())?
+            else {
+                return Ok(None);
+            };
+            if can_id != self.slave_id {
+                continue;
+            }
+            if let Some(packet) = self.decode_daq(&data, received) {
+                return Ok(Some(packet));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::mock::{MockEnd, mock_bus};
+
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    const MASTER: u32 = 0x7F0;
+    const SLAVE: u32 = 0x7F1;
+
+    /// A little endian, byte granular slave with 256 bytes of memory.
+    struct Slave {
+        fd: bool,
+        max_cto: u8,
+        memory: Mutex<Vec<u8>>,
+        commands: Mutex<Vec<Vec<u8>>>,
+        done: AtomicBool,
+    }
+
+    impl Slave {
+        fn new(fd: bool) -> Slave {
+            Slave {
+                fd,
+                max_cto: if fd { 64 } else { 8 },
+                memory: Mutex::new((0..=255).collect()),
+                commands: Mutex::new(Vec::new()),
+                done: AtomicBool::new(false),
+            }
+        }
+
+        fn send(&self, end: &MockEnd, data: &[u8]) {
+            if self.fd {
+                let len = CanFdFrame::dlc_to_len(CanFdFrame::calc_dlc(data.len()));
+                let mut data = data.to_vec();
+                data.resize(len, 0);
+                let frame = CanFdFrame::new(SLAVE, MessageType::Standard, &data, true, false);
+                end.send_fd(frame.unwrap()).unwrap();
+            } else {
+                let frame = CanFrame::new(SLAVE, MessageType::Standard, data).unwrap();
+                end.send(frame).unwrap();
+            }
+        }
+
+        fn run(&self, end: &MockEnd) {
+            let mut mta = 0usize;
+            let mut key = Vec::new();
+            while !self.done.load(Ordering::Relaxed) {
+                let received = if self.fd {
+                    end.recv_fd_frame().map(|f| f.data().to_vec())
+                } else {
+                    end.recv_frame().map(|f| f.data().to_vec())
+                };
+                let Ok(c) = received else {
+                    thread::sleep(Duration::from_micros(100));
+                    continue;
+                };
+                self.commands.lock().unwrap().push(c.clone());
+                let address = |c: &[u8]| u32::from_le_bytes([c[4], c[5], c[6], c[7]]) as usize;
+                let mut memory = self.memory.lock().unwrap();
+                let response = match c[0] {
+                    CMD_CONNECT => vec![0xFF, 0x15, 0x00, self.max_cto, self.max_cto, 0, 1, 1],
+                    CMD_GET_STATUS => vec![0xFF, 0x00, 0x01, 0x00, 0x34, 0x12],
+                    CMD_SYNCH => vec![0xFE, 0x00],
+                    CMD_SET_MTA => {
+                        mta = address(&c);
+                        vec![0xFF]
+                    }
+                    CMD_UPLOAD => {
+                        let n = c[1] as usize;
+                        let mut r = vec![0xFF];
+                        r.extend_from_slice(&memory[mta..mta + n]);
+                        mta += n;
+                        r
+                    }
+                    CMD_SHORT_UPLOAD => {
+                        let (a, n) = (address(&c), c[1] as usize);
+                        let mut r = vec![0xFF];
+                        r.extend_from_slice(&memory[a..a + n]);
+                        r
+                    }
+                    CMD_DOWNLOAD => {
+                        let n = c[1] as usize;
+                        memory[mta..mta + n].copy_from_slice(&c[2..2 + n]);
+                        mta += n;
+                        vec![0xFF]
+                    }
+                    CMD_SHORT_DOWNLOAD => {
+                        let (a, n) = (address(&c), c[1] as usize);
+                        memory[a..a + n].copy_from_slice(&c[8..8 + n]);
+                        vec![0xFF]
+                    }
+                    // A 10 byte seed, sent in two parts on classic CAN
+                    CMD_GET_SEED if c[1] == 0 => vec![0xFF, 10, 0, 1, 2, 3, 4, 5],
+                    CMD_GET_SEED => vec![0xFF, 4, 6, 7, 8, 9],
+                    CMD_UNLOCK => {
+                        key.extend_from_slice(&c[2..(2 + c[1] as usize).min(c.len())]);
+                        let expected: Vec<u8> = (0..10).map(|b| b ^ 0x5A).collect();
+                        if key.len() < 10 {
+                            vec![0xFF, 0x01]
+                        } else if key == expected {
+                            vec![0xFF, 0x00]
+                        } else {
+                            vec![0xFE, 0x25]
+                        }
+                    }
+                    // Dynamic DAQ, absolute ODT numbers, 2 byte timestamps of 10 µs
+                    CMD_GET_DAQ_PROCESSOR_INFO => vec![0xFF, 0x11, 0, 0, 1, 0, 0, 0],
+                    CMD_GET_DAQ_RESOLUTION_INFO => vec![0xFF, 1, 7, 1, 7, 0x32, 10, 0],
+                    CMD_START_STOP_DAQ_LIST => vec![0xFF, 0x10 + c[2] * 4],
+                    _ => vec![0xFF],
+                };
+                self.send(end, &response);
+            }
+        }
+    }
+
+    fn with_slave(fd: bool, master: impl FnOnce(&XcpMaster, &Slave, &MockEnd)) {
+        let (a, b) = mock_bus();
+        let slave = Slave::new(fd);
+        let link = if fd {
+            XcpLink::fd(&a, true)
+        } else {
+            XcpLink::can(&a)
+        };
+        let xcp = XcpMaster::new(link, MASTER, SLAVE);
+        xcp.set_timeout(Duration::from_millis(500));
+        thread::scope(|s| {
+            s.spawn(|| slave.run(&b));
+            master(&xcp, &slave, &b);
+            slave.done.store(true, Ordering::Relaxed);
+        });
+    }
+
+    #[test]
+    fn xcp_connect_and_status() {
+        with_slave(false, |xcp, _, _| {
+            assert!(matches!(xcp.get_status(), Err(XcpError::NotConnected)));
+            let info = xcp.connect().unwrap();
+            assert_eq!(info.byte_order, ByteOrder::Intel);
+            assert_eq!((info.max_cto, info.max_dto), (8, 8));
+            assert_eq!(info.address_granularity, 1);
+
+            let status = xcp.get_status().unwrap();
+            assert_eq!(status.protection, 0x01);
+            assert_eq!(status.session_config_id, 0x1234);
+            xcp.synch().unwrap();
+        });
+    }
+
+    #[test]
+    fn xcp_memory_transfer() {
+        with_slave(false, |xcp, slave, _| {
+            xcp.connect().unwrap();
+            assert_eq!(
+                xcp.short_upload(0x10, 0, 4).unwrap(),
+                [0x10, 0x11, 0x12, 0x13]
+            );
+            assert!(matches!(
+                xcp.short_upload(0x10, 0, 8),
+                Err(XcpError::InvalidArgument(_))
+            ));
+            assert!(xcp.short_download(0x10, 0, &[1]).is_err());
+
+            assert_eq!(
+                xcp.upload(0x20, 0, 20).unwrap(),
+                (0x20..0x34).collect::<Vec<u8>>()
+            );
+            let image: Vec<u8> = (0..15).map(|b| 0xA0 + b).collect();
+            xcp.download(0x80, 0, &image).unwrap();
+            assert_eq!(slave.memory.lock().unwrap()[0x80..0x8F], image[..]);
+
+            // Three uploads of 7 bytes and three downloads of 6 bytes after SET_MTA
+            let commands = slave.commands.lock().unwrap();
+            let count = |cmd| commands.iter().filter(|c| c[0] == cmd).count();
+            assert_eq!((count(CMD_UPLOAD), count(CMD_DOWNLOAD)), (3, 3));
+        });
+    }
+
+    #[test]
+    fn xcp_fd_short_download() {
+        with_slave(true, |xcp, slave, _| {
+            let info = xcp.connect().unwrap();
+            assert_eq!(info.max_cto, 64);
+            let data = [0x55; 56];
+            xcp.short_download(0x40, 0, &data).unwrap();
+            assert_eq!(slave.memory.lock().unwrap()[0x40..0x78], data);
+            assert_eq!(xcp.short_upload(0x40, 0, 63).unwrap()[..56], data);
+        });
+    }
+
+    #[test]
+    fn xcp_seed_and_key() {
+        with_slave(false, |xcp, _, _| {
+            xcp.connect().unwrap();
+            let key = |resource: u8, seed: &[u8]| -> Vec<u8> {
+                assert_eq!(resource, 0x01);
+                seed.iter().map(|b| b ^ 0x5A).collect()
+            };
+            assert_eq!(xcp.unlock(0x01, &key).unwrap(), 0x00);
+        });
+    }
+
+    #[test]
+    fn xcp_daq_measurement() {
+        with_slave(false, |xcp, slave, bus| {
+            xcp.connect().unwrap();
+            let entry = |address, size| OdtEntry {
+                address,
+                extension: 0,
+                size,
+            };
+            assert!(matches!(
+                xcp.configure_daq(&[DaqList {
+                    event_channel: 0,
+                    prescaler: 1,
+                    priority: 0,
+                    timestamp: true,
+                    odts: vec![vec![entry(0x100, 6)]],
+                }]),
+                Err(XcpError::InvalidArgument(_))
+            ));
+            xcp.configure_daq(&[
+                DaqList {
+                    event_channel: 1,
+                    prescaler: 1,
+                    priority: 0,
+                    timestamp: true,
+                    odts: vec![
+                        vec![entry(0x100, 4)],
+                        vec![entry(0x200, 2), entry(0x300, 1)],
+                    ],
+                },
+                DaqList {
+                    event_channel: 2,
+                    prescaler: 1,
+                    priority: 0,
+                    timestamp: false,
+                    odts: vec![vec![entry(0x400, 7)]],
+                },
+            ])
+            .unwrap();
+            {
+                let commands = slave.commands.lock().unwrap();
+                let writes: Vec<_> = commands.iter().filter(|c| c[0] == CMD_WRITE_DAQ).collect();
+                assert_eq!(writes.len(), 4);
+                assert_eq!(writes[1][..], [CMD_WRITE_DAQ, 0xFF, 2, 0, 0x00, 0x02, 0, 0]);
+            }
+            xcp.start_daq().unwrap();
+
+            // First PIDs are 0x10 and 0x14
+            slave.send(bus, &[0x10, 0x64, 0x00, 1, 2, 3, 4]);
+            slave.send(bus, &[0x11, 0xAA, 0xBB, 0xCC]);
+            slave.send(bus, &[0x14, 1, 2, 3, 4, 5, 6, 7]);
+
+            let first = xcp.recv_daq(Duration::from_millis(100)).unwrap().unwrap();
+            assert_eq!((first.daq, first.odt), (0, 0));
+            assert_eq!(first.timestamp, Some(Duration::from_millis(1)));
+            assert_eq!(first.values, vec![vec![1, 2, 3, 4]]);
+
+            let second = xcp.recv_daq(Duration::from_millis(100)).unwrap().unwrap();
+            assert_eq!((second.odt, second.timestamp), (1, None));
+            assert_eq!(second.values, vec![vec![0xAA, 0xBB], vec![0xCC]]);
+
+            let third = xcp.recv_daq(Duration::from_millis(100)).unwrap().unwrap();
+            assert_eq!(third.daq, 1);
+            assert_eq!(third.values[0], [1, 2, 3, 4, 5, 6, 7]);
+
+            xcp.stop_daq().unwrap();
+            assert!(xcp.recv_daq(Duration::from_millis(10)).unwrap().is_none());
+        });
+    }
+}